@@ -0,0 +1,25 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("Failed to read cbindgen.toml");
+
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=SICOM_UPDATE_HEADER");
+
+    let bindings = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Failed to generate C bindings");
+    bindings.write_to_file(out_dir.join("sicom.h"));
+
+    // The checked-in header is only rewritten on request, never by a plain build
+    if env::var_os("SICOM_UPDATE_HEADER").is_some() {
+        bindings.write_to_file(crate_dir.join("include/sicom.h"));
+    }
+}
@@ -0,0 +1,54 @@
+#ifndef SICOM_H
+#define SICOM_H
+
+/* Generated by cbindgen from ffi/src/lib.rs - do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Status returned on success; failures return the CLI exit codes
+#define SICOM_OK 0
+
+// Called after each pack entry with the number of entries done and the total
+typedef void (*SicomProgressCallback)(uint64_t done, uint64_t total, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Compress a pack
+//
+// `output` may be null to write `<name>_compressed.siq` next to the input.
+// `options_json` may be null for defaults, or hold a JSON object of
+// `CompressOptions` fields; missing fields use the CLI defaults. `progress`
+// may be null; it is called on the calling thread with `user_data` passed
+// through.
+//
+// Returns 0 on success, or the CLI exit code of the failure class (see the
+// README); [`sicom_last_error`] then describes the failure.
+//
+// # Safety
+// `input`, `output` and `options_json` must each be null or point to a
+// NUL-terminated string that stays valid for the duration of the call.
+int32_t sicom_compress_pack(const char *input,
+                            const char *output,
+                            const char *options_json,
+                            SicomProgressCallback progress,
+                            void *user_data);
+
+// Message of the last failed call on this thread, or null
+//
+// The string is owned by the library and stays valid until the next call
+// to [`sicom_compress_pack`] on the same thread.
+const char *sicom_last_error(void);
+
+// Library version as a static NUL-terminated string
+const char *sicom_version(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SICOM_H */
@@ -0,0 +1,274 @@
+//! C ABI for compressing packs from mobile apps
+//!
+//! Android (JNI) and iOS (Swift) companion apps link the static or shared
+//! library and call [`sicom_compress_pack`] to shrink a pack on-device before
+//! uploading it. The build script generates the C header into `OUT_DIR` and
+//! only refreshes the checked-in `ffi/include/sicom.h` when
+//! `SICOM_UPDATE_HEADER=1` is set.
+
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use sicom::SicomError;
+use sicom::options::CompressOptions;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+/// Status returned on success; failures return the CLI exit codes
+pub const SICOM_OK: i32 = 0;
+
+/// Called after each pack entry with the number of entries done and the total
+pub type SicomProgressCallback =
+    Option<extern "C" fn(done: u64, total: u64, user_data: *mut c_void)>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Opaque caller pointer handed back to the progress callback
+struct UserData(*mut c_void);
+
+// The pointer is only passed back to the caller, never dereferenced here
+unsafe impl Send for UserData {}
+
+impl UserData {
+    const fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Read a nullable C string argument
+///
+/// # Safety
+/// `value` must be null or point to a NUL-terminated string.
+unsafe fn read_str<'a>(value: *const c_char, name: &str) -> Result<Option<&'a str>, SicomError> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    unsafe { CStr::from_ptr(value) }
+        .to_str()
+        .map(Some)
+        .map_err(|_| SicomError::InvalidOptions(format!("{name} is not valid UTF-8")))
+}
+
+fn parse_options(json: Option<&str>) -> Result<CompressOptions, SicomError> {
+    json.map_or_else(
+        || Ok(CompressOptions::default()),
+        |json| {
+            serde_json::from_str(json)
+                .map_err(|e| SicomError::InvalidOptions(format!("Invalid options JSON: {e}")))
+        },
+    )
+}
+
+fn compress(
+    input: &str,
+    output: Option<&str>,
+    options: CompressOptions,
+    progress: SicomProgressCallback,
+    user_data: UserData,
+) -> anyhow::Result<()> {
+    let on_progress = progress.map(|callback| {
+        Box::new(move |done, total| callback(done, total, user_data.get()))
+            as sicom::progress::ProgressCallback
+    });
+
+    sicom::pipeline::compress_pack_with_progress(
+        PathBuf::from(input),
+        output.map(PathBuf::from),
+        options,
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        on_progress,
+    )
+    .map(drop)
+}
+
+/// Compress a pack
+///
+/// `output` may be null to write `<name>_compressed.siq` next to the input.
+/// `options_json` may be null for defaults, or hold a JSON object of
+/// `CompressOptions` fields; missing fields use the CLI defaults. `progress`
+/// may be null; it is called on the calling thread with `user_data` passed
+/// through.
+///
+/// Returns 0 on success, or the CLI exit code of the failure class (see the
+/// README); [`sicom_last_error`] then describes the failure.
+///
+/// # Safety
+/// `input`, `output` and `options_json` must each be null or point to a
+/// NUL-terminated string that stays valid for the duration of the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sicom_compress_pack(
+    input: *const c_char,
+    output: *const c_char,
+    options_json: *const c_char,
+    progress: SicomProgressCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+
+    let arguments = (|| {
+        let input = unsafe { read_str(input, "input") }?
+            .ok_or_else(|| SicomError::InvalidOptions("input path is null".to_string()))?;
+        let output = unsafe { read_str(output, "output") }?;
+        let options = parse_options(unsafe { read_str(options_json, "options") }?)?;
+        Ok::<_, SicomError>((input, output, options))
+    })();
+    let (input, output, options) = match arguments {
+        Ok(arguments) => arguments,
+        Err(e) => {
+            let code = e.exit_code();
+            set_last_error(e.to_string());
+            return code;
+        }
+    };
+
+    let user_data = UserData(user_data);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        compress(input, output, options, progress, user_data)
+    }));
+
+    match result {
+        Ok(Ok(())) => SICOM_OK,
+        Ok(Err(e)) => {
+            set_last_error(format!("{e:#}"));
+            sicom::error::classify(&e).map_or(1, SicomError::exit_code)
+        }
+        Err(_) => {
+            set_last_error("Internal error: compression panicked".to_string());
+            1
+        }
+    }
+}
+
+/// Message of the last failed call on this thread, or null
+///
+/// The string is owned by the library and stays valid until the next call
+/// to [`sicom_compress_pack`] on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn sicom_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Library version as a static NUL-terminated string
+#[unsafe(no_mangle)]
+pub extern "C" fn sicom_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::ptr;
+
+    fn last_error() -> String {
+        let message = sicom_last_error();
+        assert!(!message.is_null());
+        unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    extern "C" fn count_progress(done: u64, total: u64, user_data: *mut c_void) {
+        let calls = unsafe { &mut *user_data.cast::<Vec<(u64, u64)>>() };
+        calls.push((done, total));
+    }
+
+    #[test]
+    fn test_missing_input() {
+        let input = CString::new("/nonexistent/pack.siq").unwrap();
+        let status = unsafe {
+            sicom_compress_pack(
+                input.as_ptr(),
+                ptr::null(),
+                ptr::null(),
+                None,
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!(status, 3);
+        assert!(last_error().contains("does not exist"));
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        let status = unsafe {
+            sicom_compress_pack(ptr::null(), ptr::null(), ptr::null(), None, ptr::null_mut())
+        };
+        assert_eq!(status, 2);
+
+        let input = CString::new("pack.siq").unwrap();
+        let options = CString::new("{\"image_quality\": \"high\"}").unwrap();
+        let status = unsafe {
+            sicom_compress_pack(
+                input.as_ptr(),
+                ptr::null(),
+                options.as_ptr(),
+                None,
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!(status, 2);
+        assert!(last_error().contains("Invalid options JSON"));
+    }
+
+    #[test]
+    fn test_compress_reports_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let input_path = dir.path().join("pack.siq");
+        let output_path = dir.path().join("small.siq");
+
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&input_path).unwrap());
+        let options = zip::write::FileOptions::default();
+        zip.start_file("content.xml", options).unwrap();
+        zip.write_all(b"<package name=\"Test\"></package>").unwrap();
+        zip.start_file("Texts/authors.xml", options).unwrap();
+        zip.write_all(b"<authors/>").unwrap();
+        zip.finish().unwrap();
+
+        let input = CString::new(input_path.to_str().unwrap()).unwrap();
+        let output = CString::new(output_path.to_str().unwrap()).unwrap();
+        let options = CString::new("{\"skip_video\": true}").unwrap();
+        let mut calls: Vec<(u64, u64)> = Vec::new();
+        let status = unsafe {
+            sicom_compress_pack(
+                input.as_ptr(),
+                output.as_ptr(),
+                options.as_ptr(),
+                Some(count_progress),
+                (&raw mut calls).cast(),
+            )
+        };
+
+        assert_eq!(status, SICOM_OK);
+        assert!(sicom_last_error().is_null());
+        assert!(output_path.exists());
+        assert_eq!(calls.last(), Some(&(2, 2)));
+    }
+
+    #[test]
+    fn test_version() {
+        let version = unsafe { CStr::from_ptr(sicom_version()) };
+        assert_eq!(version.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_header_up_to_date() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/sicom.h"));
+        let checked_in = include_str!("../include/sicom.h");
+        assert!(
+            generated == checked_in,
+            "include/sicom.h is stale; rebuild with SICOM_UPDATE_HEADER=1"
+        );
+    }
+}
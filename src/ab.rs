@@ -0,0 +1,268 @@
+use crate::audio::{self, AudioMode};
+use crate::error::SicomError;
+use crate::explain::find_entry_name;
+use crate::options::CompressOptions;
+use crate::{format_size, open_pack, prepare_ffmpeg};
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// File the answer key is written to in the output directory
+pub const ANSWER_KEY: &str = "answers.json";
+
+/// Which quality is behind each blind sample
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnswerKey {
+    pub pack: String,
+    pub files: Vec<AbFile>,
+}
+
+/// Samples of one pack entry, kept in a directory of its own
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AbFile {
+    pub entry: String,
+    pub directory: String,
+    pub samples: Vec<AbSample>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AbSample {
+    pub name: String,
+    /// Encoding quality, or none for the original file
+    pub quality: Option<u8>,
+    pub size: u64,
+}
+
+/// Label of the n-th sample: A, B, ..., Z, AA, AB, ...
+fn sample_label(mut index: usize) -> String {
+    let mut label = Vec::new();
+    loop {
+        label.push(b'A' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    label.reverse();
+    String::from_utf8(label).unwrap_or_default()
+}
+
+fn extension(entry_name: &str) -> &str {
+    Path::new(entry_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+}
+
+/// Directory for an entry's samples, named after its decoded filename stem
+fn sample_directory(entry_name: &str, taken: &mut BTreeSet<String>) -> String {
+    let decoded = urlencoding::decode(entry_name).unwrap_or_else(|_| entry_name.into());
+    let stem = Path::new(decoded.as_ref())
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or_else(|| "audio".to_string());
+    let directory = (1..)
+        .map(|n| match n {
+            1 => stem.clone(),
+            n => format!("{stem}-{n}"),
+        })
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap_or(stem);
+    taken.insert(directory.clone());
+    directory
+}
+
+/// Encode pack audio entries at several qualities for a blind listening test
+///
+/// Each entry gets a directory of samples named `A`, `B`, ... in random order,
+/// with the original among them when it has the same extension as the encodes
+/// (a different one would give it away). Which quality is behind which sample
+/// is written to [`ANSWER_KEY`]; the output defaults to `<name>_ab` next to the pack.
+pub fn ab_samples(
+    input_pack: &Path,
+    files: &[String],
+    qualities: &[u8],
+    mut options: CompressOptions,
+    output_dir: Option<PathBuf>,
+) -> Result<AnswerKey> {
+    if !input_pack.exists() {
+        return Err(SicomError::InputNotFound(input_pack.to_path_buf()).into());
+    }
+    if files.is_empty() || qualities.is_empty() {
+        return Err(SicomError::InvalidOptions(
+            "A/B samples need at least one file and one quality".to_string(),
+        )
+        .into());
+    }
+    if let Some(quality) = qualities.iter().find(|q| !(1..=100).contains(*q)) {
+        return Err(SicomError::InvalidOptions(format!(
+            "Audio quality must be between 1 and 100, got {quality}"
+        ))
+        .into());
+    }
+    if options.audio.mode == AudioMode::Repack {
+        return Err(SicomError::InvalidOptions(
+            "A/B samples compare re-encodes; repack mode ignores the quality".to_string(),
+        )
+        .into());
+    }
+    options.validate()?;
+    prepare_ffmpeg(&mut options);
+
+    let output_dir = output_dir.unwrap_or_else(|| {
+        let stem = input_pack.file_stem().unwrap_or_default().to_string_lossy();
+        input_pack.with_file_name(format!("{stem}_ab"))
+    });
+    let mut qualities = qualities.to_vec();
+    qualities.sort_unstable();
+    qualities.dedup();
+
+    let mut archive = open_pack(input_pack)?;
+    let mut directories = BTreeSet::new();
+    let mut key = AnswerKey {
+        pack: input_pack.display().to_string(),
+        files: Vec::new(),
+    };
+    for file in files {
+        let entry = find_entry_name(archive.file_names(), file)
+            .ok_or_else(|| anyhow!("Entry not found in pack: {file}"))?;
+        if !audio::is_supported_audio(&entry) {
+            return Err(anyhow!("Not a supported audio file: {entry}"));
+        }
+        let mut data = Vec::new();
+        archive
+            .by_name(&entry)
+            .with_context(|| format!("Failed to read entry: {entry}"))?
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read entry data: {entry}"))?;
+
+        let mut versions = Vec::new();
+        for &quality in &qualities {
+            let audio_options = options.audio.quality(quality);
+            let encoded = audio::compress_audio(
+                &data,
+                &entry,
+                &audio_options,
+                options.ffmpeg_path.as_deref(),
+            )
+            .with_context(|| format!("Failed to encode {entry} at quality {quality}"))?;
+            let extension = extension(&audio::output_filename(&entry, &audio_options)).to_string();
+            versions.push((Some(quality), extension, encoded));
+        }
+        let encoded_extension = versions[0].1.clone();
+        if extension(&entry).eq_ignore_ascii_case(&encoded_extension) {
+            versions.push((None, encoded_extension, data));
+        } else {
+            warn!("Leaving the original of {entry} out: its extension would give it away");
+        }
+        fastrand::shuffle(&mut versions);
+
+        let directory = sample_directory(&entry, &mut directories);
+        let path = output_dir.join(&directory);
+        fs::create_dir_all(&path).map_err(|source| SicomError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        let mut samples = Vec::new();
+        for (index, (quality, extension, data)) in versions.into_iter().enumerate() {
+            let name = format!("{}.{extension}", sample_label(index));
+            let sample_path = path.join(&name);
+            fs::write(&sample_path, &data).map_err(|source| SicomError::Io {
+                path: sample_path,
+                source,
+            })?;
+            samples.push(AbSample {
+                name,
+                quality,
+                size: data.len() as u64,
+            });
+        }
+        info!("{entry}: {} samples in {}", samples.len(), path.display());
+        key.files.push(AbFile {
+            entry,
+            directory,
+            samples,
+        });
+    }
+
+    let key_path = output_dir.join(ANSWER_KEY);
+    fs::write(&key_path, serde_json::to_vec_pretty(&key)?).map_err(|source| SicomError::Io {
+        path: key_path.clone(),
+        source,
+    })?;
+    let total: u64 = key
+        .files
+        .iter()
+        .flat_map(|file| &file.samples)
+        .map(|sample| sample.size)
+        .sum();
+    info!(
+        "Wrote {} of samples, answer key in {}",
+        format_size(total),
+        key_path.display()
+    );
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_label() {
+        assert_eq!(sample_label(0), "A");
+        assert_eq!(sample_label(25), "Z");
+        assert_eq!(sample_label(26), "AA");
+        assert_eq!(sample_label(27), "AB");
+
+        let mut taken = BTreeSet::new();
+        assert_eq!(
+            sample_directory("Audio/%D0%B3%D0%B8%D0%BC%D0%BD.mp3", &mut taken),
+            "гимн"
+        );
+        assert_eq!(sample_directory("Other/гимн.wav", &mut taken), "гимн-2");
+    }
+
+    #[cfg(feature = "audio")]
+    #[test]
+    fn test_ab_samples() {
+        use crate::test_support::write_pack;
+
+        let dir = tempfile::tempdir().unwrap();
+        let pack = dir.path().join("pack.siq");
+        write_pack(
+            &pack,
+            &[
+                ("content.xml", b"<package/>"),
+                ("Audio/tone.wav", &audio::synthetic_wav(1)),
+            ],
+        );
+
+        let files = ["Audio/tone.wav".to_string()];
+        let key = ab_samples(&pack, &files, &[80, 20, 80], CompressOptions::new(), None).unwrap();
+        let out = dir.path().join("pack_ab");
+        let samples = &key.files[0].samples;
+        // WAV originals can't hide among MP3s
+        let mut qualities: Vec<Option<u8>> = samples.iter().map(|s| s.quality).collect();
+        qualities.sort();
+        assert_eq!(qualities, [Some(20), Some(80)]);
+        let names: Vec<&str> = samples.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["A.mp3", "B.mp3"]);
+        for sample in samples {
+            let path = out.join("tone").join(&sample.name);
+            assert_eq!(fs::metadata(path).unwrap().len(), sample.size);
+        }
+        let written: serde_json::Value =
+            serde_json::from_slice(&fs::read(out.join(ANSWER_KEY)).unwrap()).unwrap();
+        assert_eq!(written["files"][0]["entry"], "Audio/tone.wav");
+
+        assert!(ab_samples(&pack, &files, &[0], CompressOptions::new(), None).is_err());
+        let missing = ["Audio/none.mp3".to_string()];
+        assert!(ab_samples(&pack, &missing, &[40], CompressOptions::new(), None).is_err());
+    }
+}
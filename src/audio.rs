@@ -1,34 +1,619 @@
+// Without the codecs, the helpers they share have no callers
+#![cfg_attr(
+    not(all(feature = "audio", feature = "ffmpeg")),
+    allow(dead_code, unused_imports)
+)]
+
+use crate::error::SicomError;
+#[cfg(feature = "audio")]
+use crate::fingerprint::Fingerprint;
+#[cfg(feature = "audio")]
+use crate::loudness::{Loudness, TARGET_LUFS};
+use crate::media::{
+    LongMediaPenalty, MediaInfo, MediaKind, MediaOutput, MediaPlan, MediaProcessor,
+};
+use crate::progress::ProgressLogger;
 use anyhow::{Context, Result, anyhow};
-use mp3lame_encoder::{Bitrate, Builder, FlushNoGap, InterleavedPcm};
-use std::path::Path;
+#[cfg(feature = "ffmpeg")]
+use ffmpeg_sidecar::command::FfmpegCommand;
+#[cfg(feature = "ffmpeg")]
+use ffmpeg_sidecar::event::{FfmpegEvent, LogLevel};
+use log::{debug, warn};
+#[cfg(feature = "audio")]
+use mp3lame_encoder::{Bitrate, Builder, FlushNoGap, InterleavedPcm, Mode};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "audio")]
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "audio")]
 use symphonia::core::audio::{AudioBufferRef, Signal};
-use symphonia::core::codecs::{CODEC_TYPE_NULL, DecoderOptions};
+#[cfg(feature = "audio")]
+use symphonia::core::codecs::{CODEC_TYPE_NULL, Decoder, DecoderOptions};
+#[cfg(feature = "audio")]
 use symphonia::core::errors::Error as SymphoniaError;
-use symphonia::core::formats::FormatOptions;
+#[cfg(feature = "audio")]
+use symphonia::core::formats::{FormatOptions, FormatReader};
+#[cfg(feature = "audio")]
 use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
-use symphonia::core::meta::MetadataOptions;
+#[cfg(feature = "audio")]
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+#[cfg(feature = "audio")]
 use symphonia::core::probe::Hint;
 
 /// MP3 frame size in samples
 const SAMPLES_PER_FRAME: usize = 1152;
 
+/// Maximum allowed difference between source and re-encoded duration in seconds
+/// (covers encoder delay, padding and the LAME info frame)
+const DURATION_TOLERANCE_SECONDS: f64 = 0.5;
+
+/// Minimum bitrate for short clips - artifacts on buzzers and stingers are very noticeable
+#[cfg(feature = "audio")]
+const SHORT_CLIP_MIN_BITRATE: Bitrate = Bitrate::Kbps128;
+
+/// Sample rates MPEG audio can be encoded at
+pub const MP3_SAMPLE_RATES: &[u32] = &[
+    8_000, 11_025, 12_000, 16_000, 22_050, 24_000, 32_000, 44_100, 48_000,
+];
+
+/// Sources at or below this bitrate aren't re-encoded any lower without `allow_degrade`
+const LOW_SOURCE_BITRATE_KBPS: u32 = 128;
+
+/// Slack for measured source bitrates (VBR averages, frame overhead)
+const BITRATE_TOLERANCE_KBPS: u32 = 8;
+
+/// Thresholds for very short clips (buzzer sounds, stingers)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct ShortClipPolicy {
+    /// Clips shorter than this are treated as short
+    pub max_seconds: f64,
+    /// Short clips are kept as-is when the estimated savings are below this
+    pub min_savings_bytes: u64,
+}
+
+impl Default for ShortClipPolicy {
+    fn default() -> Self {
+        Self {
+            max_seconds: 3.0,
+            min_savings_bytes: 10 * 1024,
+        }
+    }
+}
+
+impl ShortClipPolicy {
+    pub const fn max_seconds(mut self, seconds: f64) -> Self {
+        self.max_seconds = seconds;
+        self
+    }
+
+    pub const fn min_savings_bytes(mut self, bytes: u64) -> Self {
+        self.min_savings_bytes = bytes;
+        self
+    }
+}
+
+/// How audio files are processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum AudioMode {
+    /// Decode and re-encode at the bitrate mapped from quality
+    #[default]
+    Reencode,
+    /// Lossless MP3 repackaging: drops frame padding, ancillary data, tag padding,
+    /// duplicate tags and junk between frames
+    Repack,
+}
+
+/// Backend used to re-encode audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum AudioEngine {
+    /// Symphonia decoder with the built-in LAME encoder
+    #[default]
+    Native,
+    /// External ffmpeg with libmp3lame (same integration as video)
+    Ffmpeg,
+}
+
+/// Format re-encoded audio is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum AudioCodec {
+    #[default]
+    Mp3,
+    /// Opus in an Ogg container (.opus) through ffmpeg's libopus, at half the MP3 bitrate
+    Opus,
+}
+
+/// LAME channel mode for stereo sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum StereoMode {
+    /// Joint stereo (mid/side) - much better quality per bit at low bitrates
+    Joint,
+    /// Independent left/right channels
+    Stereo,
+}
+
+/// Settings for audio compression
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct AudioOptions {
+    pub quality: u8,
+    pub mode: AudioMode,
+    pub engine: AudioEngine,
+    /// Output format of re-encoded audio (repacked audio stays MP3)
+    pub codec: AudioCodec,
+    pub short_clip: ShortClipPolicy,
+    /// LAME channel mode (derived from the target bitrate when None)
+    pub stereo_mode: Option<StereoMode>,
+    /// LAME lowpass frequency in Hz (derived from the target bitrate when None)
+    pub lowpass_hz: Option<u32>,
+    /// Warn when the fingerprint similarity of source and output drops below this (0-1)
+    pub verify_similarity: Option<f32>,
+    /// Lower quality for long tracks (off when None)
+    pub long_media: Option<LongMediaPenalty>,
+    /// Re-encode at a bitrate above the source's, which only adds size
+    pub allow_upcoding: bool,
+    /// Re-encode already low-bitrate sources at an even lower bitrate
+    pub allow_degrade: bool,
+    /// Keep lossy sources averaging this bitrate or less (the target bitrate when None)
+    pub min_bitrate_kbps: Option<u32>,
+    /// Bring re-encoded tracks to the same loudness (EBU R128, -16 LUFS)
+    pub normalize_loudness: bool,
+    /// Downmix re-encoded tracks to mono
+    pub mono: bool,
+    /// Resample re-encoded MP3s to this rate in Hz (one of [`MP3_SAMPLE_RATES`]);
+    /// sources already at or below it keep theirs
+    pub sample_rate: Option<u32>,
+    /// Drop tags (title, artist, ...) instead of carrying them over to the output
+    pub strip_metadata: bool,
+}
+
+impl Default for AudioOptions {
+    fn default() -> Self {
+        Self {
+            quality: 85,
+            mode: AudioMode::default(),
+            engine: AudioEngine::default(),
+            codec: AudioCodec::default(),
+            short_clip: ShortClipPolicy::default(),
+            stereo_mode: None,
+            lowpass_hz: None,
+            verify_similarity: None,
+            long_media: None,
+            allow_upcoding: false,
+            allow_degrade: false,
+            min_bitrate_kbps: None,
+            normalize_loudness: false,
+            mono: false,
+            sample_rate: None,
+            strip_metadata: false,
+        }
+    }
+}
+
+impl AudioOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub const fn quality(mut self, quality: u8) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    pub const fn mode(mut self, mode: AudioMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub const fn engine(mut self, engine: AudioEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub const fn codec(mut self, codec: AudioCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    pub const fn short_clip(mut self, policy: ShortClipPolicy) -> Self {
+        self.short_clip = policy;
+        self
+    }
+
+    pub const fn stereo_mode(mut self, stereo_mode: Option<StereoMode>) -> Self {
+        self.stereo_mode = stereo_mode;
+        self
+    }
+
+    pub const fn lowpass_hz(mut self, lowpass_hz: Option<u32>) -> Self {
+        self.lowpass_hz = lowpass_hz;
+        self
+    }
+
+    pub const fn verify_similarity(mut self, threshold: Option<f32>) -> Self {
+        self.verify_similarity = threshold;
+        self
+    }
+
+    pub const fn long_media(mut self, penalty: Option<LongMediaPenalty>) -> Self {
+        self.long_media = penalty;
+        self
+    }
+
+    pub const fn allow_upcoding(mut self, allow: bool) -> Self {
+        self.allow_upcoding = allow;
+        self
+    }
+
+    pub const fn allow_degrade(mut self, allow: bool) -> Self {
+        self.allow_degrade = allow;
+        self
+    }
+
+    pub const fn min_bitrate_kbps(mut self, kbps: Option<u32>) -> Self {
+        self.min_bitrate_kbps = kbps;
+        self
+    }
+
+    pub const fn normalize_loudness(mut self, normalize: bool) -> Self {
+        self.normalize_loudness = normalize;
+        self
+    }
+
+    pub const fn mono(mut self, mono: bool) -> Self {
+        self.mono = mono;
+        self
+    }
+
+    pub const fn sample_rate(mut self, sample_rate: Option<u32>) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub const fn strip_metadata(mut self, strip: bool) -> Self {
+        self.strip_metadata = strip;
+        self
+    }
+}
+
+/// LAME encoder settings resolved for a target bitrate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LameSettings {
+    pub bitrate_kbps: u16,
+    pub stereo_mode: StereoMode,
+    pub lowpass_hz: u32,
+    /// Downmix stereo sources to mono
+    pub mono: bool,
+    /// Output sample rate, None to keep the source's
+    pub sample_rate: Option<u32>,
+}
+
+#[cfg(feature = "audio")]
+impl AudioOptions {
+    /// Resolve LAME settings for a bitrate, applying the stereo/lowpass overrides
+    pub fn lame_settings(&self, bitrate: Bitrate) -> LameSettings {
+        let lowpass_hz = self
+            .lowpass_hz
+            .unwrap_or_else(|| default_lowpass_hz(bitrate));
+        LameSettings {
+            bitrate_kbps: bitrate as u16,
+            stereo_mode: self
+                .stereo_mode
+                .unwrap_or_else(|| default_stereo_mode(bitrate)),
+            // Nothing above half the sample rate survives resampling anyway
+            lowpass_hz: self
+                .sample_rate
+                .map_or(lowpass_hz, |rate| lowpass_hz.min(rate / 2)),
+            mono: self.mono,
+            sample_rate: self.sample_rate,
+        }
+    }
+}
+
+impl LameSettings {
+    /// Human-readable encoder settings
+    pub fn describe(&self) -> String {
+        let stereo = match self.stereo_mode {
+            _ if self.mono => "mono",
+            StereoMode::Joint => "joint stereo",
+            StereoMode::Stereo => "stereo",
+        };
+        let mut description = format!(
+            "MP3 {} kbps CBR, {stereo}, lowpass {} Hz",
+            self.bitrate_kbps, self.lowpass_hz
+        );
+        if let Some(rate) = self.sample_rate {
+            description.push_str(&format!(", resampled to {rate} Hz"));
+        }
+        description
+    }
+}
+
+/// Describe what the audio settings mean in encoder terms
+#[cfg(feature = "audio")]
+pub fn describe_quality(options: &AudioOptions) -> String {
+    match options.mode {
+        AudioMode::Repack => "lossless MP3 repack (quality not used)".to_string(),
+        AudioMode::Reencode => {
+            let floor_kbps = match options.codec {
+                AudioCodec::Mp3 => u32::from(SHORT_CLIP_MIN_BITRATE as u16),
+                AudioCodec::Opus => opus_kbps(SHORT_CLIP_MIN_BITRATE),
+            };
+            format!(
+                "{} (clips under {}s: at least {floor_kbps} kbps)",
+                describe_encoder(options, quality_to_mp3_bitrate(options.quality)),
+                options.short_clip.max_seconds,
+            )
+        }
+    }
+}
+
+/// Describe what the audio settings mean in encoder terms
+#[cfg(not(feature = "audio"))]
+pub fn describe_quality(_options: &AudioOptions) -> String {
+    "unavailable (built without the audio feature)".to_string()
+}
+
+/// Opus bitrate matching the quality of an MP3 bitrate (Opus needs about half)
+#[cfg(feature = "audio")]
+pub fn opus_kbps(bitrate: Bitrate) -> u32 {
+    u32::from(bitrate as u16) / 2
+}
+
+/// Encoder settings for a target MP3 bitrate, in the configured codec
+#[cfg(feature = "audio")]
+fn describe_encoder(options: &AudioOptions, bitrate: Bitrate) -> String {
+    match options.codec {
+        AudioCodec::Mp3 => format!(
+            "{}, {:?} engine",
+            options.lame_settings(bitrate).describe(),
+            options.engine
+        ),
+        AudioCodec::Opus => format!("Opus {} kbps VBR, libopus via ffmpeg", opus_kbps(bitrate)),
+    }
+}
+
+/// Entry name of re-encoded audio: `.opus` for Opus, `.mp3` for MP3 (WAV, Ogg
+/// and FLAC sources get the new extension, MP3 names are kept as they are)
+pub fn output_filename(filename: &str, options: &AudioOptions) -> String {
+    let extension = match options.codec {
+        AudioCodec::Mp3 => "mp3",
+        AudioCodec::Opus => "opus",
+    };
+    let path = Path::new(filename);
+    let unchanged = options.mode == AudioMode::Repack
+        || path
+            .extension()
+            .and_then(|s| s.to_str())
+            .is_some_and(|current| current.eq_ignore_ascii_case(extension));
+    if unchanged {
+        filename.to_string()
+    } else {
+        path.with_extension(extension)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Audio pipeline: MP3, WAV, Ogg Vorbis and FLAC re-encode, or lossless MP3 repack
+pub struct AudioProcessor {
+    pub options: AudioOptions,
+    pub ffmpeg_path: Option<PathBuf>,
+}
+
+#[cfg(feature = "audio")]
+impl MediaProcessor for AudioProcessor {
+    fn kind(&self) -> MediaKind {
+        MediaKind::Audio
+    }
+
+    fn supported(&self, filename: &str) -> bool {
+        is_supported_audio(filename)
+    }
+
+    fn analyze(&self, data: &[u8], filename: &str) -> MediaInfo {
+        let format = detect_audio_format(filename);
+        let source = format.map(|format| SourceAudio::scan(data, format));
+        MediaInfo {
+            format: format.map(|format| format!("{format:?}")),
+            duration_seconds: source.and_then(|source| source.duration_seconds),
+            bitrate_kbps: source.and_then(|source| source.bitrate_kbps()),
+            ..Default::default()
+        }
+    }
+
+    fn plan(&self, data: &[u8], filename: &str) -> Result<MediaPlan> {
+        let format = detect_audio_format(filename)
+            .ok_or_else(|| anyhow!("Unsupported audio format: {}", filename))?;
+
+        let (encoder, estimated_size) = match self.options.mode {
+            // Repacking is cheap - report the exact size
+            AudioMode::Repack => (
+                describe_quality(&self.options),
+                repack_audio(data, format)?.len() as u64,
+            ),
+            AudioMode::Reencode => {
+                let source = SourceAudio::scan(data, format);
+                let Some(duration_seconds) = source.duration_seconds else {
+                    return Err(match format {
+                        AudioFormat::Mp3 => anyhow!("No MPEG audio frames found"),
+                        _ => anyhow!("Unreadable {} header", format.name()),
+                    });
+                };
+                let bitrate = select_target_bitrate(&source, &self.options)?;
+                let estimated_size = estimate_encoded_size(duration_seconds, bitrate);
+                (
+                    describe_encoder(&self.options, bitrate),
+                    match self.options.codec {
+                        AudioCodec::Mp3 => estimated_size,
+                        AudioCodec::Opus => estimated_size / 2,
+                    },
+                )
+            }
+        };
+
+        Ok(MediaPlan {
+            filename: output_filename(filename, &self.options),
+            encoder,
+            estimated_size: Some(estimated_size),
+        })
+    }
+
+    fn check(&self, data: &[u8], _filename: &str) -> Result<()> {
+        let mut decoder = AudioDecoder::new(data)?;
+        let mut samples = Vec::new();
+        let mut decoded = 0;
+        while decoder.next_chunk(&mut samples)? {
+            decoded += samples.len();
+        }
+        if decoded == 0 {
+            return Err(anyhow!("No audio samples decoded"));
+        }
+        Ok(())
+    }
+
+    fn compress(
+        &self,
+        data: &[u8],
+        filename: &str,
+        _progress: &mut ProgressLogger,
+    ) -> Result<MediaOutput> {
+        let compressed =
+            compress_audio(data, filename, &self.options, self.ffmpeg_path.as_deref())?;
+        if let Some(threshold) = self.options.verify_similarity {
+            verify_fingerprint(data, &compressed, filename, threshold);
+        }
+        let codec = match (self.options.mode, self.options.codec) {
+            (AudioMode::Reencode, AudioCodec::Opus) => "Opus",
+            _ => "MP3",
+        };
+        Ok(MediaOutput {
+            data: compressed,
+            filename: output_filename(filename, &self.options),
+            codec,
+            renditions: Vec::new(),
+        })
+    }
+}
+
+/// Without the `audio` feature only the format is known; everything else fails
+#[cfg(not(feature = "audio"))]
+impl MediaProcessor for AudioProcessor {
+    fn kind(&self) -> MediaKind {
+        MediaKind::Audio
+    }
+
+    fn supported(&self, filename: &str) -> bool {
+        is_supported_audio(filename)
+    }
+
+    fn analyze(&self, _data: &[u8], filename: &str) -> MediaInfo {
+        MediaInfo {
+            format: detect_audio_format(filename).map(|format| format!("{format:?}")),
+            ..Default::default()
+        }
+    }
+
+    fn plan(&self, _data: &[u8], _filename: &str) -> Result<MediaPlan> {
+        Err(SicomError::MissingFeature("audio").into())
+    }
+
+    fn check(&self, _data: &[u8], _filename: &str) -> Result<()> {
+        Err(SicomError::MissingFeature("audio").into())
+    }
+
+    fn compress(
+        &self,
+        _data: &[u8],
+        _filename: &str,
+        _progress: &mut ProgressLogger,
+    ) -> Result<MediaOutput> {
+        Err(SicomError::MissingFeature("audio").into())
+    }
+}
+
+/// Warn when the re-encoded audio no longer sounds like the source
+///
+/// Size and duration checks can't see decode bugs like swapped channels or
+/// garbled output; comparing loudness envelopes does.
+#[cfg(feature = "audio")]
+fn verify_fingerprint(source: &[u8], encoded: &[u8], filename: &str, threshold: f32) {
+    let similarity = Fingerprint::from_audio(source)
+        .and_then(|source| Ok(source.similarity(&Fingerprint::from_audio(encoded)?)));
+    match similarity {
+        Ok(similarity) if similarity < f64::from(threshold) => warn!(
+            "  Audio fingerprint mismatch for {filename}: similarity {similarity:.2} is below {threshold}"
+        ),
+        Ok(similarity) => debug!("  Audio fingerprint similarity: {similarity:.2}"),
+        Err(e) => debug!("  Could not fingerprint {filename}: {e}"),
+    }
+}
+
 /// Supported audio formats
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AudioFormat {
     Mp3,
+    /// Uncompressed PCM, always re-encoded
+    Wav,
+    /// Vorbis in an Ogg container, always re-encoded
+    OggVorbis,
+    /// Lossless FLAC, always re-encoded
+    Flac,
     // Future formats to be added:
-    // Wav,
-    // OggVorbis,
     // Opus,
-    // Flac,
 }
 
+impl AudioFormat {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Mp3 => "MP3",
+            Self::Wav => "WAV",
+            Self::OggVorbis => "Ogg Vorbis",
+            Self::Flac => "FLAC",
+        }
+    }
+
+    /// Extension of the format's files, with the dot
+    const fn suffix(self) -> &'static str {
+        match self {
+            Self::Mp3 => ".mp3",
+            Self::Wav => ".wav",
+            Self::OggVorbis => ".ogg",
+            Self::Flac => ".flac",
+        }
+    }
+
+    /// Whether re-encoding can't lose anything the source still has
+    const fn is_lossless(self) -> bool {
+        matches!(self, Self::Wav | Self::Flac)
+    }
+}
+
+/// Audio file extensions (lowercase) the pipeline compresses
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["mp3", "wav", "ogg", "flac"];
+
 /// Check if an audio file format is supported
 pub fn is_supported_audio(filename: &str) -> bool {
     let path = Path::new(filename);
     path.extension()
         .and_then(|s| s.to_str())
-        .is_some_and(|ext| matches!(ext.to_lowercase().as_str(), "mp3"))
+        .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Whether `filename` is audio from a lossless source (WAV, FLAC)
+pub fn is_lossless_audio(filename: &str) -> bool {
+    detect_audio_format(filename).is_some_and(AudioFormat::is_lossless)
 }
 
 /// Detect audio format from file extension
@@ -37,11 +622,11 @@ fn detect_audio_format(filename: &str) -> Option<AudioFormat> {
     path.extension().and_then(|s| s.to_str()).and_then(|ext| {
         match ext.to_lowercase().as_str() {
             "mp3" => Some(AudioFormat::Mp3),
+            "wav" => Some(AudioFormat::Wav),
+            "ogg" => Some(AudioFormat::OggVorbis),
+            "flac" => Some(AudioFormat::Flac),
             // Future formats:
-            // "wav" => Some(AudioFormat::Wav),
-            // "ogg" => Some(AudioFormat::OggVorbis),
             // "opus" => Some(AudioFormat::Opus),
-            // "flac" => Some(AudioFormat::Flac),
             _ => None,
         }
     })
@@ -49,7 +634,8 @@ fn detect_audio_format(filename: &str) -> Option<AudioFormat> {
 
 /// Map quality (1-100) to MP3 bitrate enum
 /// Based on real-world data: 64-320 kbps range, 215 kbps average
-fn quality_to_mp3_bitrate(quality: u8) -> Bitrate {
+#[cfg(feature = "audio")]
+pub fn quality_to_mp3_bitrate(quality: u8) -> Bitrate {
     // Ensure quality is in valid range
     let quality = quality.clamp(1, 100);
 
@@ -78,193 +664,732 @@ fn quality_to_mp3_bitrate(quality: u8) -> Bitrate {
     }
 }
 
-/// Decode audio data using Symphonia
-fn decode_audio_data(data: &[u8]) -> Result<(Vec<f32>, u32, u32)> {
-    // Create a media source from the byte data (copy to owned Vec to fix lifetime)
-    let data_owned = data.to_vec();
-    let cursor = std::io::Cursor::new(data_owned);
-    let media_source =
-        MediaSourceStream::new(Box::new(cursor), MediaSourceStreamOptions::default());
-
-    // Create a probe hint (we'll let Symphonia auto-detect the format)
-    let hint = Hint::new();
-
-    // Use the default options
-    let format_opts = FormatOptions::default();
-    let metadata_opts = MetadataOptions::default();
-    let decoder_opts = DecoderOptions::default();
-
-    // Probe the media source
-    let probed = symphonia::default::get_probe()
-        .format(&hint, media_source, &format_opts, &metadata_opts)
-        .with_context(|| "Failed to probe audio format")?;
-
-    let mut format = probed.format;
-
-    // Find the first audio track
-    let track = format
-        .tracks()
-        .iter()
-        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-        .ok_or_else(|| anyhow!("No audio track found"))?;
-
-    let track_id = track.id;
-
-    // Create a decoder for the track
-    let mut audio_decoder = symphonia::default::get_codecs()
-        .make(&track.codec_params, &decoder_opts)
-        .with_context(|| "Failed to create audio decoder")?;
-
-    let mut audio_data = Vec::new();
-    let sample_rate = track.codec_params.sample_rate.unwrap_or(44100);
-    let channels = u32::try_from(track.codec_params.channels.map_or(2, |c| c.count())).unwrap_or(2);
-
-    // Decode all packets
-    loop {
-        let packet = match format.next_packet() {
-            Ok(packet) => packet,
-            Err(SymphoniaError::ResetRequired) => {
-                // The track list has been changed. Re-examine it and create a new set of decoders,
-                // then restart the decode loop. This is an advanced feature that most applications
-                // do not need.
-                unimplemented!();
+/// Default channel mode for a bitrate
+/// Joint stereo wins everywhere except at the very top where both modes are transparent
+#[cfg(feature = "audio")]
+fn default_stereo_mode(bitrate: Bitrate) -> StereoMode {
+    if (bitrate as u16) < 320 {
+        StereoMode::Joint
+    } else {
+        StereoMode::Stereo
+    }
+}
+
+/// Default lowpass frequency in Hz for a bitrate
+/// Cutting inaudible highs at low bitrates leaves more bits for the audible band
+#[cfg(feature = "audio")]
+fn default_lowpass_hz(bitrate: Bitrate) -> u32 {
+    match bitrate as u16 {
+        0..=64 => 13_000,
+        65..=80 => 14_000,
+        81..=96 => 15_000,
+        97..=128 => 17_000,
+        129..=160 => 17_500,
+        161..=192 => 19_000,
+        193..=224 => 19_500,
+        225..=256 => 20_000,
+        _ => 20_500,
+    }
+}
+
+/// Estimate encoded size in bytes for a CBR stream of the given duration
+#[cfg(feature = "audio")]
+fn estimate_encoded_size(duration_seconds: f64, bitrate: Bitrate) -> u64 {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    {
+        (duration_seconds * f64::from(bitrate as u16) * 1000.0 / 8.0) as u64
+    }
+}
+
+/// Pick the bitrate for a short clip, or fail when re-encoding is not worth it
+/// Short clips never go below 128 kbps and are skipped when savings would be negligible
+#[cfg(feature = "audio")]
+fn short_clip_bitrate(
+    duration_seconds: f64,
+    original_size: u64,
+    target_bitrate: Bitrate,
+    policy: &ShortClipPolicy,
+) -> Result<Bitrate> {
+    let bitrate = if (target_bitrate as u16) < (SHORT_CLIP_MIN_BITRATE as u16) {
+        SHORT_CLIP_MIN_BITRATE
+    } else {
+        target_bitrate
+    };
+
+    let estimated_size = estimate_encoded_size(duration_seconds, bitrate);
+    let savings = original_size.saturating_sub(estimated_size);
+    if savings < policy.min_savings_bytes {
+        return Err(anyhow!(
+            "Short clip ({duration_seconds:.1}s): estimated savings of {savings} bytes not worth re-encoding"
+        ));
+    }
+
+    Ok(bitrate)
+}
+
+/// What the bitrate decisions need to know about a source file
+#[cfg(feature = "audio")]
+#[derive(Debug, Clone, Copy)]
+struct SourceAudio {
+    format: AudioFormat,
+    size: u64,
+    /// Playback time, None when the file can't be read
+    duration_seconds: Option<f64>,
+    /// Bytes of audio data the bitrate is measured over (MPEG frames, whole file otherwise)
+    audio_bytes: u64,
+    /// None when the file can't be read
+    sample_rate: Option<u32>,
+}
+
+#[cfg(feature = "audio")]
+impl SourceAudio {
+    /// Read the duration from the MPEG frames of MP3s, or from the container of
+    /// other formats (WAV header, last Ogg page, FLAC STREAMINFO)
+    fn scan(data: &[u8], format: AudioFormat) -> Self {
+        let (duration_seconds, audio_bytes, sample_rate) = match format {
+            AudioFormat::Mp3 => {
+                let summary = crate::mp3::scan_frames(data);
+                (
+                    (summary.frames > 0).then_some(summary.duration_seconds),
+                    summary.audio_bytes,
+                    (summary.frames > 0).then_some(summary.sample_rate),
+                )
             }
-            Err(SymphoniaError::IoError(err)) => {
-                // The packet reader has reached EOF, or a fatal error has occurred.
-                match err.kind() {
-                    std::io::ErrorKind::UnexpectedEof => break,
-                    _ => return Err(anyhow!("IO error during decoding: {}", err)),
-                }
+            AudioFormat::Wav | AudioFormat::OggVorbis | AudioFormat::Flac => {
+                let decoder = AudioDecoder::new(data).ok();
+                (
+                    decoder
+                        .as_ref()
+                        .and_then(|decoder| decoder.duration_seconds),
+                    data.len() as u64,
+                    decoder.map(|decoder| decoder.sample_rate),
+                )
             }
-            Err(err) => return Err(anyhow!("Decode error: {}", err)),
         };
+        Self {
+            format,
+            size: data.len() as u64,
+            duration_seconds: duration_seconds.filter(|&seconds| seconds > 0.0),
+            audio_bytes,
+            sample_rate,
+        }
+    }
+
+    /// Average bitrate over the duration
+    fn bitrate_kbps(&self) -> Option<u32> {
+        self.duration_seconds
+            .map(|seconds| (self.audio_bytes as f64 * 8.0 / seconds / 1000.0) as u32)
+    }
 
-        // Only decode packets for our selected track
-        if packet.track_id() != track_id {
+    /// LAME settings for this source, never resampling it to a higher rate
+    fn lame_settings(&self, bitrate: Bitrate, options: &AudioOptions) -> LameSettings {
+        let mut settings = options.lame_settings(bitrate);
+        if let (Some(target), Some(source)) = (settings.sample_rate, self.sample_rate) {
+            if target >= source {
+                settings.sample_rate = None;
+            }
+        }
+        settings
+    }
+}
+
+/// Refuse bitrates that can only lose quality: above the source's (a bigger file
+/// that sounds no better), for a source already at or below the threshold, or
+/// below an already low source bitrate. Without an explicit threshold, sources
+/// below the target are left to the upcoding check
+#[cfg(feature = "audio")]
+fn check_bitrate_loss(source_kbps: u32, bitrate: Bitrate, options: &AudioOptions) -> Result<()> {
+    let target_kbps = u32::from(bitrate as u16);
+    let upcoding = target_kbps > source_kbps + BITRATE_TOLERANCE_KBPS;
+    if upcoding && !options.allow_upcoding {
+        return Err(anyhow!(
+            "Source is {source_kbps} kbps: re-encoding at {target_kbps} kbps adds size without adding quality (--allow-upcoding)"
+        ));
+    }
+    let threshold_kbps = match options.min_bitrate_kbps {
+        Some(threshold_kbps) => Some(threshold_kbps),
+        None if !upcoding => Some(target_kbps),
+        None => None,
+    };
+    if let Some(threshold_kbps) = threshold_kbps.filter(|&threshold| source_kbps <= threshold) {
+        return Err(anyhow!(
+            "Source is already {source_kbps} kbps, at or below {threshold_kbps} kbps: re-encoding only loses quality (--audio-min-bitrate-kbps)"
+        ));
+    }
+    if source_kbps <= LOW_SOURCE_BITRATE_KBPS + BITRATE_TOLERANCE_KBPS
+        && target_kbps + BITRATE_TOLERANCE_KBPS < source_kbps
+        && !options.allow_degrade
+    {
+        return Err(anyhow!(
+            "Source is already {source_kbps} kbps: re-encoding at {target_kbps} kbps would audibly degrade it (--allow-degrade)"
+        ));
+    }
+    Ok(())
+}
+
+/// Append a decoded buffer to `out` as interleaved f32 samples
+#[cfg(feature = "audio")]
+fn append_interleaved(buffer: AudioBufferRef<'_>, out: &mut Vec<f32>) -> Result<()> {
+    match buffer {
+        AudioBufferRef::F32(buf) => {
+            // Interleave channels if stereo
+            if buf.spec().channels.count() == 1 {
+                out.extend_from_slice(buf.chan(0));
+            } else {
+                let left = buf.chan(0);
+                let right = buf.chan(1);
+                for (l, r) in left.iter().zip(right.iter()) {
+                    out.push(*l);
+                    out.push(*r);
+                }
+            }
+        }
+        AudioBufferRef::U8(buf) => {
+            // Convert u8 to f32 - interleave channels
+            if buf.spec().channels.count() == 1 {
+                for &sample in buf.chan(0) {
+                    let f_sample = (f32::from(sample) - 128.0) / 128.0;
+                    out.push(f_sample);
+                }
+            } else {
+                let left = buf.chan(0);
+                let right = buf.chan(1);
+                for (l, r) in left.iter().zip(right.iter()) {
+                    let f_l = (f32::from(*l) - 128.0) / 128.0;
+                    let f_r = (f32::from(*r) - 128.0) / 128.0;
+                    out.push(f_l);
+                    out.push(f_r);
+                }
+            }
+        }
+        AudioBufferRef::U16(buf) => {
+            // Convert u16 to f32 - interleave channels
+            if buf.spec().channels.count() == 1 {
+                for &sample in buf.chan(0) {
+                    let f_sample = (f32::from(sample) - 32768.0) / 32768.0;
+                    out.push(f_sample);
+                }
+            } else {
+                let left = buf.chan(0);
+                let right = buf.chan(1);
+                for (l, r) in left.iter().zip(right.iter()) {
+                    let f_l = (f32::from(*l) - 32768.0) / 32768.0;
+                    let f_r = (f32::from(*r) - 32768.0) / 32768.0;
+                    out.push(f_l);
+                    out.push(f_r);
+                }
+            }
+        }
+        AudioBufferRef::S16(buf) => {
+            // Convert s16 to f32 - interleave channels
+            if buf.spec().channels.count() == 1 {
+                for &sample in buf.chan(0) {
+                    let f_sample = f32::from(sample) / 32768.0;
+                    out.push(f_sample);
+                }
+            } else {
+                let left = buf.chan(0);
+                let right = buf.chan(1);
+                for (l, r) in left.iter().zip(right.iter()) {
+                    let f_l = f32::from(*l) / 32768.0;
+                    let f_r = f32::from(*r) / 32768.0;
+                    out.push(f_l);
+                    out.push(f_r);
+                }
+            }
+        }
+        AudioBufferRef::S32(buf) => {
+            // Convert s32 to f32 - interleave channels
+            if buf.spec().channels.count() == 1 {
+                for &sample in buf.chan(0) {
+                    #[allow(clippy::cast_precision_loss)]
+                    let f_sample = sample as f32 / 2_147_483_648.0;
+                    out.push(f_sample);
+                }
+            } else {
+                let left = buf.chan(0);
+                let right = buf.chan(1);
+                for (l, r) in left.iter().zip(right.iter()) {
+                    #[allow(clippy::cast_precision_loss)]
+                    let f_l = *l as f32 / 2_147_483_648.0;
+                    #[allow(clippy::cast_precision_loss)]
+                    let f_r = *r as f32 / 2_147_483_648.0;
+                    out.push(f_l);
+                    out.push(f_r);
+                }
+            }
+        }
+        _ => {
+            return Err(anyhow!("Unsupported audio buffer format"));
+        }
+    }
+    Ok(())
+}
+
+/// Streaming audio decoder built on Symphonia
+/// Yields interleaved f32 samples one packet at a time so memory stays bounded
+/// regardless of track length
+#[cfg(feature = "audio")]
+pub(crate) struct AudioDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    pub(crate) sample_rate: u32,
+    pub(crate) channels: u32,
+    /// Track length from the container, when it records one
+    pub(crate) duration_seconds: Option<f64>,
+    /// Text tags as ID3v2 frames (frame ID, text)
+    pub(crate) tags: Vec<(&'static str, String)>,
+}
+
+/// ID3v2 frame carrying a tag
+#[cfg(feature = "audio")]
+const fn id3_frame_id(key: StandardTagKey) -> Option<&'static str> {
+    Some(match key {
+        StandardTagKey::TrackTitle => "TIT2",
+        StandardTagKey::Artist => "TPE1",
+        StandardTagKey::AlbumArtist => "TPE2",
+        StandardTagKey::Album => "TALB",
+        StandardTagKey::Composer => "TCOM",
+        StandardTagKey::Date => "TDRC",
+        StandardTagKey::Genre => "TCON",
+        StandardTagKey::TrackNumber => "TRCK",
+        StandardTagKey::Copyright => "TCOP",
+        _ => return None,
+    })
+}
+
+/// Add the text tags of a metadata revision not seen yet
+#[cfg(feature = "audio")]
+fn collect_tags(revision: &MetadataRevision, tags: &mut Vec<(&'static str, String)>) {
+    for tag in revision.tags() {
+        let Some(id) = tag.std_key.and_then(id3_frame_id) else {
             continue;
+        };
+        let text = tag.value.to_string();
+        if !text.is_empty() && !tags.iter().any(|(seen, _)| *seen == id) {
+            tags.push((id, text));
         }
+    }
+}
 
-        // Decode the packet
-        match audio_decoder.decode(&packet) {
-            Ok(decoded_buffer) => {
-                // Convert decoded audio to f32 samples
-                match decoded_buffer {
-                    AudioBufferRef::F32(buf) => {
-                        // Interleave channels if stereo
-                        if buf.spec().channels.count() == 1 {
-                            audio_data.extend_from_slice(buf.chan(0));
-                        } else {
-                            let left = buf.chan(0);
-                            let right = buf.chan(1);
-                            for (l, r) in left.iter().zip(right.iter()) {
-                                audio_data.push(*l);
-                                audio_data.push(*r);
-                            }
-                        }
-                    }
-                    AudioBufferRef::U8(buf) => {
-                        // Convert u8 to f32 - interleave channels
-                        if buf.spec().channels.count() == 1 {
-                            for &sample in buf.chan(0) {
-                                let f_sample = (f32::from(sample) - 128.0) / 128.0;
-                                audio_data.push(f_sample);
-                            }
-                        } else {
-                            let left = buf.chan(0);
-                            let right = buf.chan(1);
-                            for (l, r) in left.iter().zip(right.iter()) {
-                                let f_l = (f32::from(*l) - 128.0) / 128.0;
-                                let f_r = (f32::from(*r) - 128.0) / 128.0;
-                                audio_data.push(f_l);
-                                audio_data.push(f_r);
-                            }
-                        }
-                    }
-                    AudioBufferRef::U16(buf) => {
-                        // Convert u16 to f32 - interleave channels
-                        if buf.spec().channels.count() == 1 {
-                            for &sample in buf.chan(0) {
-                                let f_sample = (f32::from(sample) - 32768.0) / 32768.0;
-                                audio_data.push(f_sample);
-                            }
-                        } else {
-                            let left = buf.chan(0);
-                            let right = buf.chan(1);
-                            for (l, r) in left.iter().zip(right.iter()) {
-                                let f_l = (f32::from(*l) - 32768.0) / 32768.0;
-                                let f_r = (f32::from(*r) - 32768.0) / 32768.0;
-                                audio_data.push(f_l);
-                                audio_data.push(f_r);
-                            }
-                        }
-                    }
-                    AudioBufferRef::S16(buf) => {
-                        // Convert s16 to f32 - interleave channels
-                        if buf.spec().channels.count() == 1 {
-                            for &sample in buf.chan(0) {
-                                let f_sample = f32::from(sample) / 32768.0;
-                                audio_data.push(f_sample);
-                            }
-                        } else {
-                            let left = buf.chan(0);
-                            let right = buf.chan(1);
-                            for (l, r) in left.iter().zip(right.iter()) {
-                                let f_l = f32::from(*l) / 32768.0;
-                                let f_r = f32::from(*r) / 32768.0;
-                                audio_data.push(f_l);
-                                audio_data.push(f_r);
-                            }
-                        }
-                    }
-                    AudioBufferRef::S32(buf) => {
-                        // Convert s32 to f32 - interleave channels
-                        if buf.spec().channels.count() == 1 {
-                            for &sample in buf.chan(0) {
-                                #[allow(clippy::cast_precision_loss)]
-                                let f_sample = sample as f32 / 2_147_483_648.0;
-                                audio_data.push(f_sample);
-                            }
-                        } else {
-                            let left = buf.chan(0);
-                            let right = buf.chan(1);
-                            for (l, r) in left.iter().zip(right.iter()) {
-                                #[allow(clippy::cast_precision_loss)]
-                                let f_l = *l as f32 / 2_147_483_648.0;
-                                #[allow(clippy::cast_precision_loss)]
-                                let f_r = *r as f32 / 2_147_483_648.0;
-                                audio_data.push(f_l);
-                                audio_data.push(f_r);
-                            }
-                        }
-                    }
-                    _ => {
-                        return Err(anyhow!("Unsupported audio buffer format"));
+#[cfg(feature = "audio")]
+impl AudioDecoder {
+    pub(crate) fn new(data: &[u8]) -> Result<Self> {
+        // Create a media source from the byte data (copy to owned Vec to fix lifetime)
+        let data_owned = data.to_vec();
+        let cursor = std::io::Cursor::new(data_owned);
+        let media_source =
+            MediaSourceStream::new(Box::new(cursor), MediaSourceStreamOptions::default());
+
+        // Create a probe hint (we'll let Symphonia auto-detect the format)
+        let hint = Hint::new();
+
+        // Use the default options
+        let format_opts = FormatOptions::default();
+        let metadata_opts = MetadataOptions::default();
+        let decoder_opts = DecoderOptions::default();
+
+        // Probe the media source
+        let mut probed = symphonia::default::get_probe()
+            .format(&hint, media_source, &format_opts, &metadata_opts)
+            .with_context(|| "Failed to probe audio format")?;
+
+        // Tags come before the container (ID3v2) or inside it (Vorbis comments, RIFF INFO)
+        let mut tags = Vec::new();
+        if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+            collect_tags(revision, &mut tags);
+        }
+        let mut format = probed.format;
+        if let Some(revision) = format.metadata().current() {
+            collect_tags(revision, &mut tags);
+        }
+
+        // Find the first audio track
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| anyhow!("No audio track found"))?;
+
+        // Create a decoder for the track
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &decoder_opts)
+            .with_context(|| "Failed to create audio decoder")?;
+
+        let sample_rate = track.codec_params.sample_rate.unwrap_or(44100);
+        Ok(Self {
+            track_id: track.id,
+            sample_rate,
+            duration_seconds: track
+                .codec_params
+                .n_frames
+                .map(|frames| frames as f64 / f64::from(sample_rate)),
+            channels: u32::try_from(track.codec_params.channels.map_or(2, |c| c.count()))
+                .unwrap_or(2),
+            tags,
+            format,
+            decoder,
+        })
+    }
+
+    /// Switch to the first audio track of a new track list with a fresh decoder.
+    /// The samples are appended to the previous track's, so it must keep the
+    /// sample rate and channel count
+    fn reset_track(&mut self) -> Result<()> {
+        let track = self
+            .format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| anyhow!("No audio track found in chained stream"))?;
+        let sample_rate = track.codec_params.sample_rate.unwrap_or(self.sample_rate);
+        let channels = track
+            .codec_params
+            .channels
+            .map_or(self.channels, |c| u32::try_from(c.count()).unwrap_or(2));
+        if sample_rate != self.sample_rate || channels != self.channels {
+            return Err(anyhow!(
+                "Chained stream changes from {} Hz, {} channels to {sample_rate} Hz, {channels} channels",
+                self.sample_rate,
+                self.channels
+            ));
+        }
+
+        self.decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .with_context(|| "Failed to create audio decoder")?;
+        self.track_id = track.id;
+        Ok(())
+    }
+
+    /// Decode the next packet of the selected track, replacing the contents of `out`
+    /// with its interleaved samples. Returns false at the end of the stream
+    pub(crate) fn next_chunk(&mut self, out: &mut Vec<f32>) -> Result<bool> {
+        out.clear();
+
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::ResetRequired) => {
+                    // A chained stream (concatenated Ogg files) starts a new track list
+                    self.reset_track()?;
+                    continue;
+                }
+                Err(SymphoniaError::IoError(err)) => {
+                    // The packet reader has reached EOF, or a fatal error has occurred.
+                    match err.kind() {
+                        std::io::ErrorKind::UnexpectedEof => return Ok(false),
+                        _ => return Err(anyhow!("IO error during decoding: {}", err)),
                     }
                 }
+                Err(err) => return Err(anyhow!("Decode error: {}", err)),
+            };
+
+            // Only decode packets for our selected track
+            if packet.track_id() != self.track_id {
+                continue;
             }
-            Err(SymphoniaError::IoError(_)) => {
-                // The packet reader has reached EOF
-                break;
+
+            // Decode the packet
+            match self.decoder.decode(&packet) {
+                Ok(decoded_buffer) => {
+                    append_interleaved(decoded_buffer, out)?;
+                    return Ok(true);
+                }
+                Err(SymphoniaError::IoError(_)) => {
+                    // The packet reader has reached EOF
+                    return Ok(false);
+                }
+                Err(SymphoniaError::DecodeError(_)) => {
+                    // Decode errors are not fatal. Skip the packet and continue.
+                }
+                Err(err) => {
+                    return Err(anyhow!("Fatal decode error: {}", err));
+                }
             }
-            Err(SymphoniaError::DecodeError(_)) => {
-                // Decode errors are not fatal. Skip the packet and continue.
+        }
+    }
+}
+
+/// Calculate duration in seconds from interleaved PCM sample count
+fn pcm_duration_seconds(sample_count: usize, sample_rate: u32, channels: u32) -> f64 {
+    if sample_rate == 0 || channels == 0 {
+        return 0.0;
+    }
+    sample_count as f64 / f64::from(channels) / f64::from(sample_rate)
+}
+
+/// Ensure the re-encoded audio has the same duration as the decoded source
+fn check_duration(source_seconds: f64, encoded_seconds: f64) -> Result<()> {
+    if (source_seconds - encoded_seconds).abs() > DURATION_TOLERANCE_SECONDS {
+        return Err(anyhow!(
+            "Duration mismatch after re-encode: {encoded_seconds:.2}s vs {source_seconds:.2}s source"
+        ));
+    }
+    Ok(())
+}
+
+/// Pick the target bitrate from quality and the short clip policy
+/// Shared by both engines so they make identical decisions
+#[cfg(feature = "audio")]
+fn select_target_bitrate(source: &SourceAudio, options: &AudioOptions) -> Result<Bitrate> {
+    let mut quality = options.quality;
+    if let Some(penalty) = options.long_media {
+        if source.duration_seconds.is_some() {
+            quality = penalty.apply(quality, source.duration_seconds);
+        }
+    }
+    if quality != options.quality {
+        debug!(
+            "  Long track ({:.0}s): quality {} -> {quality}",
+            source.duration_seconds.unwrap_or_default(),
+            options.quality
+        );
+    }
+    let target_bitrate = quality_to_mp3_bitrate(quality);
+    let short_clip = &options.short_clip;
+
+    // Short clips get a bitrate floor (or are skipped) based on the frame-level duration
+    let bitrate = match source.duration_seconds {
+        Some(seconds) if seconds < short_clip.max_seconds => {
+            short_clip_bitrate(seconds, source.size, target_bitrate, short_clip)?
+        }
+        _ => target_bitrate,
+    };
+
+    // Uncompressed sources can't lose quality to a lossy bitrate
+    if !source.format.is_lossless() {
+        if let Some(source_kbps) = source.bitrate_kbps() {
+            check_bitrate_loss(source_kbps, bitrate, options)?;
+        }
+    }
+    Ok(bitrate)
+}
+
+/// Gain applied to the decoded samples, None unless loudness normalization is on
+///
+/// Integrated loudness needs the whole track, so the source is decoded an
+/// extra time to measure it. Both engines apply the same measured gain.
+#[cfg(feature = "audio")]
+fn loudness_gain(data: &[u8], options: &AudioOptions) -> Result<Option<f32>> {
+    if !options.normalize_loudness {
+        return Ok(None);
+    }
+    let loudness = Loudness::from_audio(data)?;
+    let gain = loudness.gain(TARGET_LUFS);
+    match loudness.integrated_lufs {
+        Some(lufs) => debug!(
+            "  Loudness {lufs:.1} LUFS, peak {:.2}: gain {:+.1} dB",
+            loudness.peak,
+            20.0 * f64::from(gain).log10()
+        ),
+        None => debug!("  Loudness could not be measured, leaving the level alone"),
+    }
+    Ok(Some(gain))
+}
+
+/// ffmpeg output arguments dropping the source's tags, which it copies by default
+fn ffmpeg_metadata_args(options: &AudioOptions) -> Vec<String> {
+    if options.strip_metadata {
+        vec!["-map_metadata".to_string(), "-1".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// ffmpeg filter arguments applying a loudness `gain`
+#[cfg(feature = "audio")]
+fn ffmpeg_gain_args(gain: Option<f32>) -> Vec<String> {
+    gain.map_or_else(Vec::new, |gain| {
+        vec!["-af".to_string(), format!("volume={gain:.6}")]
+    })
+}
+
+/// Build the ffmpeg libmp3lame arguments matching the native encoder settings
+#[cfg(feature = "audio")]
+fn ffmpeg_mp3_args(settings: &LameSettings) -> Vec<String> {
+    let mut args = vec![
+        "-vn".to_string(), // Drop embedded cover art streams
+        "-c:a".to_string(),
+        "libmp3lame".to_string(),
+        "-b:a".to_string(),
+        format!("{}k", settings.bitrate_kbps),
+        "-joint_stereo".to_string(),
+        u8::from(settings.stereo_mode == StereoMode::Joint).to_string(),
+        "-cutoff".to_string(),
+        settings.lowpass_hz.to_string(),
+    ];
+    if settings.mono {
+        args.extend(["-ac".to_string(), "1".to_string()]);
+    }
+    if let Some(rate) = settings.sample_rate {
+        args.extend(["-ar".to_string(), rate.to_string()]);
+    }
+    args.extend(["-f".to_string(), "mp3".to_string(), "-y".to_string()]);
+    args
+}
+
+/// Build the ffmpeg libopus arguments for Opus in an Ogg container
+///
+/// Opus always runs at 48 kHz internally, so only the channel downmix applies.
+fn ffmpeg_opus_args(kbps: u32, mono: bool) -> Vec<String> {
+    let mut args = vec![
+        "-vn".to_string(), // Drop embedded cover art streams
+        "-c:a".to_string(),
+        "libopus".to_string(),
+        "-b:a".to_string(),
+        format!("{kbps}k"),
+    ];
+    if mono {
+        args.extend(["-ac".to_string(), "1".to_string()]);
+    }
+    args.extend(["-f".to_string(), "ogg".to_string(), "-y".to_string()]);
+    args
+}
+
+/// Compress audio to MP3 through an external ffmpeg process
+#[cfg(all(feature = "audio", feature = "ffmpeg"))]
+fn compress_mp3_with_ffmpeg(
+    data: &[u8],
+    format: AudioFormat,
+    options: &AudioOptions,
+    ffmpeg_path: Option<&Path>,
+) -> Result<Vec<u8>> {
+    let source = SourceAudio::scan(data, format);
+    let target_bitrate = select_target_bitrate(&source, options)?;
+    let mut args = ffmpeg_gain_args(loudness_gain(data, options)?);
+    args.extend(ffmpeg_metadata_args(options));
+    args.extend(ffmpeg_mp3_args(
+        &source.lame_settings(target_bitrate, options),
+    ));
+    let mp3_buffer = run_ffmpeg(data, format, &args, ".mp3", ffmpeg_path)?;
+
+    // Same truncation guard as the native engine, based on the source duration
+    if let Some(source_seconds) = source.duration_seconds {
+        let encoded_seconds = crate::mp3::scan_frames(&mp3_buffer).duration_seconds;
+        check_duration(source_seconds, encoded_seconds)?;
+    }
+
+    Ok(mp3_buffer)
+}
+
+/// Re-encode audio to Opus through an external ffmpeg process
+///
+/// The bitrate follows the same quality, long track, short clip and quality
+/// loss rules as MP3, then halves. There's no Opus decoder to check the
+/// output duration with, so the truncation guard doesn't apply.
+#[cfg(all(feature = "audio", feature = "ffmpeg"))]
+fn compress_opus_with_ffmpeg(
+    data: &[u8],
+    format: AudioFormat,
+    options: &AudioOptions,
+    ffmpeg_path: Option<&Path>,
+) -> Result<Vec<u8>> {
+    let source = SourceAudio::scan(data, format);
+    let target_bitrate = select_target_bitrate(&source, options)?;
+    let mut args = ffmpeg_gain_args(loudness_gain(data, options)?);
+    args.extend(ffmpeg_metadata_args(options));
+    args.extend(ffmpeg_opus_args(opus_kbps(target_bitrate), options.mono));
+    run_ffmpeg(data, format, &args, ".opus", ffmpeg_path)
+}
+
+/// Run ffmpeg on `data` with output `args`, returning the encoded file
+#[cfg(feature = "ffmpeg")]
+fn run_ffmpeg(
+    data: &[u8],
+    format: AudioFormat,
+    args: &[String],
+    output_suffix: &str,
+    ffmpeg_path: Option<&Path>,
+) -> Result<Vec<u8>> {
+    // ffmpeg works on files - stage input and output in temporary files
+    let mut input_temp = tempfile::NamedTempFile::with_suffix(format.suffix())
+        .context("Failed to create temporary input file")?;
+    input_temp
+        .write_all(data)
+        .context("Failed to write input data to temporary file")?;
+    input_temp
+        .flush()
+        .context("Failed to flush input data to temporary file")?;
+    let output_temp = tempfile::NamedTempFile::with_suffix(output_suffix)
+        .context("Failed to create temporary output file")?;
+
+    let mut ffmpeg_cmd = ffmpeg_path.map_or_else(FfmpegCommand::new, |path| {
+        FfmpegCommand::new_with_path(path)
+    });
+    ffmpeg_cmd
+        .input(input_temp.path().to_string_lossy())
+        .args(args)
+        .output(output_temp.path().to_string_lossy());
+
+    let mut child = ffmpeg_cmd
+        .spawn()
+        .context("Failed to spawn ffmpeg process")?;
+    let iter = child.iter().context("Failed to create event iterator")?;
+
+    let mut error_message = None;
+    for event in iter {
+        match event {
+            FfmpegEvent::Log(LogLevel::Warning | LogLevel::Error | LogLevel::Fatal, message) => {
+                debug!("FFmpeg: {}", message.trim());
             }
-            Err(err) => {
-                return Err(anyhow!("Fatal decode error: {}", err));
+            // Ignore spurious "No streams found" error that occurs after successful processing
+            FfmpegEvent::Error(error_msg) if error_msg.trim() != "No streams found" => {
+                warn!("FFmpeg Error: {}", error_msg.trim());
+                error_message = Some(error_msg);
             }
+            FfmpegEvent::Done => break,
+            _ => {}
         }
     }
 
-    Ok((audio_data, sample_rate, channels))
+    if let Some(error_message) = error_message {
+        return Err(anyhow!("FFmpeg execution failed: {}", error_message));
+    }
+
+    std::fs::read(output_temp.path()).context("Failed to read compressed audio data")
 }
 
-/// Compress MP3 audio file
-fn compress_mp3_file(data: &[u8], quality: u8) -> Result<Vec<u8>> {
-    // Get target bitrate from quality
-    let target_bitrate = quality_to_mp3_bitrate(quality);
+/// Convert interleaved f32 PCM to the interleaved i16 stereo layout LAME expects
+/// Mono samples are duplicated into both channels
+fn to_stereo_i16(samples: &[f32], channels: u32, out: &mut Vec<i16>) {
+    out.clear();
+    for &sample in samples {
+        // Clamp to prevent overflow and convert to i16
+        let sample_clamped = sample.clamp(-1.0, 1.0);
+        #[allow(clippy::cast_possible_truncation)]
+        let sample_i16 = (sample_clamped * 32767.0) as i16;
+        out.push(sample_i16);
+        if channels == 1 {
+            out.push(sample_i16);
+        }
+    }
+}
+
+#[cfg(feature = "audio")]
+thread_local! {
+    /// Per-packet PCM buffers (decoded, stereo i16), kept by each worker thread
+    /// between files so long-running modes don't regrow them for every track
+    ///
+    /// LAME encoders aren't kept: a flushed encoder still holds the previous
+    /// track's psychoacoustic state, which would bleed into the next file.
+    static PCM_SCRATCH: RefCell<(Vec<f32>, Vec<i16>)> = RefCell::default();
+}
 
-    // First, decode the original MP3 to get PCM data
-    let (pcm_data, sample_rate, channels) = decode_audio_data(data)?;
+/// Compress an audio file to MP3
+/// Decoding and encoding are interleaved packet by packet, so only the compressed
+/// output grows with track length
+#[cfg(feature = "audio")]
+fn compress_mp3_file(data: &[u8], format: AudioFormat, options: &AudioOptions) -> Result<Vec<u8>> {
+    let source = SourceAudio::scan(data, format);
+    let target_bitrate = select_target_bitrate(&source, options)?;
+    let gain = loudness_gain(data, options)?;
+
+    // Open the source for streaming decode
+    let mut decoder = AudioDecoder::new(data)?;
+    let sample_rate = decoder.sample_rate;
+    let channels = decoder.channels;
 
     // Create and configure LAME encoder
     let mut builder =
@@ -280,111 +1405,215 @@ fn compress_mp3_file(data: &[u8], quality: u8) -> Result<Vec<u8>> {
         .set_brate(target_bitrate)
         .map_err(|e| anyhow!("Failed to set bitrate: {}", e))?;
 
+    // Channel mode and lowpass tuned for the target bitrate instead of library defaults
+    let settings = source.lame_settings(target_bitrate, options);
+    if channels == 2 {
+        builder
+            .set_mode(match settings.stereo_mode {
+                // LAME mixes stereo input down itself
+                _ if settings.mono => Mode::Mono,
+                StereoMode::Joint => Mode::JointStereo,
+                StereoMode::Stereo => Mode::Stereo,
+            })
+            .map_err(|e| anyhow!("Failed to set stereo mode: {}", e))?;
+    }
+    if let Some(rate) = settings.sample_rate {
+        // SAFETY: the builder owns a valid LAME handle until build() consumes it
+        let result = unsafe {
+            mp3lame_encoder::ffi::lame_set_out_samplerate(
+                builder.as_ptr(),
+                i32::try_from(rate).unwrap_or(i32::MAX),
+            )
+        };
+        if result != 0 {
+            return Err(anyhow!("Failed to set output sample rate: {rate} Hz"));
+        }
+    }
+    let lowpass_hz = settings.lowpass_hz;
+    // SAFETY: the builder owns a valid LAME handle until build() consumes it
+    let lowpass_result = unsafe {
+        mp3lame_encoder::ffi::lame_set_lowpassfreq(
+            builder.as_ptr(),
+            i32::try_from(lowpass_hz).unwrap_or(i32::MAX),
+        )
+    };
+    if lowpass_result != 0 {
+        return Err(anyhow!("Failed to set lowpass frequency: {lowpass_hz} Hz"));
+    }
+
     let mut encoder = builder
         .build()
         .map_err(|e| anyhow!("Failed to build MP3 encoder: {}", e))?;
 
-    // Convert f32 PCM to i16 PCM (LAME expects i16)
-    let pcm_i16: Vec<i16> = pcm_data
-        .iter()
-        .map(|&sample| {
-            // Clamp to prevent overflow and convert to i16
-            let sample_clamped = sample.clamp(-1.0, 1.0);
-            #[allow(clippy::cast_possible_truncation)]
-            {
-                (sample_clamped * 32767.0) as i16
+    // Estimate output size from the target bitrate to avoid repeated reallocation
+    let mut mp3_buffer = Vec::with_capacity(
+        usize::try_from(estimate_encoded_size(
+            source.duration_seconds.unwrap_or_default(),
+            target_bitrate,
+        ))
+        .unwrap_or(0),
+    );
+
+    let decoded_samples = PCM_SCRATCH.with_borrow_mut(|(pcm_chunk, stereo_chunk)| {
+        let mut decoded_samples = 0;
+        while decoder.next_chunk(pcm_chunk)? {
+            decoded_samples += pcm_chunk.len();
+            if let Some(gain) = gain {
+                pcm_chunk.iter_mut().for_each(|sample| *sample *= gain);
             }
-        })
-        .collect();
+            to_stereo_i16(pcm_chunk, channels, stereo_chunk);
 
-    // Ensure stereo format (duplicate mono channels if needed)
-    let stereo_pcm = if channels == 1 {
-        // Mono: duplicate samples for stereo encoding
-        let mut stereo_data = Vec::with_capacity(pcm_i16.len() * 2);
-        for &sample in &pcm_i16 {
-            stereo_data.push(sample);
-            stereo_data.push(sample);
+            // Encode in frame-sized chunks (stereo samples)
+            for chunk in stereo_chunk.chunks(SAMPLES_PER_FRAME * 2) {
+                mp3_buffer.reserve(mp3lame_encoder::max_required_buffer_size(chunk.len() / 2));
+                encoder
+                    .encode_to_vec(InterleavedPcm(chunk), &mut mp3_buffer)
+                    .map_err(|e| anyhow!("Failed to encode MP3 chunk: {}", e))?;
+            }
         }
-        stereo_data
-    } else {
-        // Already stereo
-        pcm_i16
-    };
-
-    // Calculate required buffer size and prepare output
-    let samples_per_channel = stereo_pcm.len() / 2;
-    let mp3_buffer_size = mp3lame_encoder::max_required_buffer_size(samples_per_channel);
-    let mut mp3_buffer: Vec<std::mem::MaybeUninit<u8>> = Vec::with_capacity(mp3_buffer_size);
-
-    // Process audio in chunks that fit the encoder's expectations
-    let chunk_size = SAMPLES_PER_FRAME * 2; // Stereo samples
-    let mut input_pos = 0;
-    let mut total_encoded = 0;
-
-    while input_pos < stereo_pcm.len() {
-        let chunk_end = std::cmp::min(input_pos + chunk_size, stereo_pcm.len());
-        let chunk = &stereo_pcm[input_pos..chunk_end];
-
-        // Create InterleavedPcm from chunk
-        let interleaved_pcm = InterleavedPcm(chunk);
-
-        // Reserve space and encode
-        mp3_buffer.resize(
-            total_encoded + mp3_buffer_size,
-            std::mem::MaybeUninit::uninit(),
-        );
-
-        let encoded_size = encoder
-            .encode(interleaved_pcm, &mut mp3_buffer[total_encoded..])
-            .map_err(|e| anyhow!("Failed to encode MP3 chunk: {}", e))?;
-
-        total_encoded += encoded_size;
-        input_pos = chunk_end;
-    }
+        Ok::<_, anyhow::Error>(decoded_samples)
+    })?;
 
     // Flush encoder to get any remaining data
-    mp3_buffer.resize(
-        total_encoded + mp3_buffer_size,
-        std::mem::MaybeUninit::uninit(),
-    );
-
-    let flush_size = encoder
-        .flush::<FlushNoGap>(&mut mp3_buffer[total_encoded..])
+    mp3_buffer.reserve(mp3lame_encoder::max_required_buffer_size(SAMPLES_PER_FRAME));
+    encoder
+        .flush_to_vec::<FlushNoGap>(&mut mp3_buffer)
         .map_err(|e| anyhow!("Failed to flush MP3 encoder: {}", e))?;
 
-    total_encoded += flush_size;
+    // Decoder edge cases can silently truncate clips - verify frame count x frame duration
+    let source_seconds = pcm_duration_seconds(decoded_samples, sample_rate, channels);
+    let encoded_seconds = crate::mp3::scan_frames(&mp3_buffer).duration_seconds;
+    check_duration(source_seconds, encoded_seconds)?;
 
-    // Convert MaybeUninit<u8> to u8 for the final result
-    mp3_buffer.truncate(total_encoded);
-    let final_buffer: Vec<u8> = mp3_buffer
-        .into_iter()
-        .map(|b| unsafe { b.assume_init() })
-        .collect();
+    // LAME writes no tags of its own; carry over the source's text tags
+    if !options.strip_metadata && !decoder.tags.is_empty() {
+        let mut tagged = crate::mp3::id3v2_text_tag(&decoder.tags);
+        tagged.append(&mut mp3_buffer);
+        mp3_buffer = tagged;
+    }
 
-    Ok(final_buffer)
+    Ok(mp3_buffer)
 }
 
-/// Compress audio file based on format and quality
-pub fn compress_audio_file(
+/// Compress an audio file, returning the encoded file
+///
+/// The format is detected from the `filename` extension (MP3, WAV, Ogg Vorbis
+/// or FLAC). Everything but MP3 is always re-encoded, so the entry name changes;
+/// see [`output_filename`].
+/// `ffmpeg_path` is used by [`AudioEngine::Ffmpeg`] and [`AudioCodec::Opus`]; `None`
+/// runs `ffmpeg` from PATH. Opus output is an Ogg file.
+#[cfg(feature = "audio")]
+pub fn compress_audio(
     data: &[u8],
     filename: &str,
-    quality: u8,
-) -> Result<(Vec<u8>, u64, u64)> {
-    let original_size = data.len() as u64;
+    options: &AudioOptions,
+    ffmpeg_path: Option<&Path>,
+) -> Result<Vec<u8>> {
+    if !(1..=100).contains(&options.quality) {
+        return Err(SicomError::InvalidOptions(
+            "Audio quality must be between 1 and 100".to_string(),
+        )
+        .into());
+    }
+    if let Some(message) = check_sample_rate(options.sample_rate) {
+        return Err(SicomError::InvalidOptions(message).into());
+    }
 
     let format = detect_audio_format(filename)
         .ok_or_else(|| anyhow!("Unsupported audio format: {}", filename))?;
 
-    let compressed_data = match format {
-        AudioFormat::Mp3 => compress_mp3_file(data, quality)?,
-        // Future formats will be added here
-    };
+    match options.mode {
+        AudioMode::Reencode => match (options.codec, options.engine) {
+            (AudioCodec::Mp3, AudioEngine::Native) => compress_mp3_file(data, format, options),
+            #[cfg(feature = "ffmpeg")]
+            (AudioCodec::Opus, _) => compress_opus_with_ffmpeg(data, format, options, ffmpeg_path),
+            #[cfg(feature = "ffmpeg")]
+            (AudioCodec::Mp3, AudioEngine::Ffmpeg) => {
+                compress_mp3_with_ffmpeg(data, format, options, ffmpeg_path)
+            }
+            #[cfg(not(feature = "ffmpeg"))]
+            (AudioCodec::Opus, _) | (AudioCodec::Mp3, AudioEngine::Ffmpeg) => {
+                let _ = ffmpeg_path;
+                Err(SicomError::MissingFeature("ffmpeg").into())
+            }
+        },
+        AudioMode::Repack => {
+            let repacked = repack_audio(data, format)?;
+            if options.strip_metadata {
+                Ok(crate::mp3::strip_tags(&repacked).to_vec())
+            } else {
+                Ok(repacked)
+            }
+        }
+    }
+}
+
+/// Compress an audio file, which needs the `audio` feature
+#[cfg(not(feature = "audio"))]
+pub fn compress_audio(
+    _data: &[u8],
+    _filename: &str,
+    _options: &AudioOptions,
+    _ffmpeg_path: Option<&Path>,
+) -> Result<Vec<u8>> {
+    Err(SicomError::MissingFeature("audio").into())
+}
+
+/// Explain why a requested output sample rate can't be used
+pub fn check_sample_rate(sample_rate: Option<u32>) -> Option<String> {
+    sample_rate
+        .filter(|rate| !MP3_SAMPLE_RATES.contains(rate))
+        .map(|rate| {
+            let rates: Vec<String> = MP3_SAMPLE_RATES.iter().map(u32::to_string).collect();
+            format!(
+                "Audio sample rate {rate} Hz is not one of {}",
+                rates.join(", ")
+            )
+        })
+}
+
+/// Lossless repack, which only MP3 has
+fn repack_audio(data: &[u8], format: AudioFormat) -> Result<Vec<u8>> {
+    match format {
+        AudioFormat::Mp3 => crate::mp3::repack(data),
+        _ => Err(anyhow!(
+            "{} can't be repacked losslessly; use --audio-mode reencode",
+            format.name()
+        )),
+    }
+}
 
-    let compressed_size = compressed_data.len() as u64;
-    Ok((compressed_data, original_size, compressed_size))
+/// 16-bit mono PCM WAV of a 440 Hz tone, for tests and benchmarks
+#[cfg(feature = "audio")]
+pub(crate) fn synthetic_wav(seconds: u32) -> Vec<u8> {
+    let sample_rate = 44100u32;
+    let samples: Vec<i16> = (0..sample_rate * seconds)
+        .map(|i| {
+            let t = f64::from(i) / f64::from(sample_rate);
+            ((t * 440.0 * std::f64::consts::TAU).sin() * 8000.0) as i16
+        })
+        .collect();
+    let data_size = samples.len() as u32 * 2;
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "audio"))]
 mod tests {
     use super::*;
 
@@ -392,8 +1621,8 @@ mod tests {
     fn test_is_supported_audio() {
         assert!(is_supported_audio("Audio/test.mp3"));
         assert!(is_supported_audio("Audio/test.MP3"));
-        assert!(!is_supported_audio("Audio/test.wav"));
-        assert!(!is_supported_audio("Audio/test.ogg"));
+        assert!(is_supported_audio("Audio/test.wav"));
+        assert!(is_supported_audio("Audio/test.ogg"));
         assert!(!is_supported_audio("Audio/test.txt"));
         assert!(!is_supported_audio("Images/test.jpg"));
     }
@@ -406,10 +1635,446 @@ mod tests {
             detect_audio_format("Audio/song.mp3"),
             Some(AudioFormat::Mp3)
         );
-        assert_eq!(detect_audio_format("test.wav"), None);
+        assert_eq!(detect_audio_format("test.WAV"), Some(AudioFormat::Wav));
+        assert_eq!(
+            detect_audio_format("Audio/theme.ogg"),
+            Some(AudioFormat::OggVorbis)
+        );
         assert_eq!(detect_audio_format("test.txt"), None);
     }
 
+    #[test]
+    fn test_ffmpeg_mp3_args() {
+        let args = ffmpeg_mp3_args(&AudioOptions::default().lame_settings(Bitrate::Kbps128));
+        let joined = args.join(" ");
+        assert!(joined.contains("-c:a libmp3lame"));
+        assert!(joined.contains("-b:a 128k"));
+        assert!(joined.contains("-joint_stereo 1"));
+        assert!(joined.contains("-cutoff 17000"));
+
+        // Explicit overrides are passed through
+        let options = AudioOptions {
+            stereo_mode: Some(StereoMode::Stereo),
+            lowpass_hz: Some(16000),
+            ..Default::default()
+        };
+        let joined = ffmpeg_mp3_args(&options.lame_settings(Bitrate::Kbps128)).join(" ");
+        assert!(joined.contains("-joint_stereo 0"));
+        assert!(joined.contains("-cutoff 16000"));
+        assert!(!joined.contains("-ac") && !joined.contains("-ar"));
+
+        // Downmix and resample; the lowpass can't exceed the new Nyquist frequency
+        let options = AudioOptions::new().mono(true).sample_rate(Some(22_050));
+        let settings = options.lame_settings(Bitrate::Kbps128);
+        assert_eq!(settings.lowpass_hz, 11_025);
+        assert_eq!(
+            settings.describe(),
+            "MP3 128 kbps CBR, mono, lowpass 11025 Hz, resampled to 22050 Hz"
+        );
+        let joined = ffmpeg_mp3_args(&settings).join(" ");
+        assert!(joined.contains("-ac 1 -ar 22050 -f mp3"));
+    }
+
+    #[test]
+    fn test_opus_output() {
+        let joined = ffmpeg_opus_args(opus_kbps(Bitrate::Kbps192), false).join(" ");
+        assert!(joined.contains("-c:a libopus -b:a 96k -f ogg"));
+
+        let options = AudioOptions::new().codec(AudioCodec::Opus);
+        assert_eq!(output_filename("Audio/a b.mp3", &options), "Audio/a b.opus");
+        assert!(describe_quality(&options).starts_with("Opus 128 kbps"));
+        // Repacking stays MP3
+        let repack = options.mode(AudioMode::Repack);
+        assert_eq!(output_filename("Audio/a.mp3", &repack), "Audio/a.mp3");
+    }
+
+    /// Stereo 16-bit FLAC at 44.1 kHz with verbatim subframes, `frames` blocks of 4096 samples
+    fn synthetic_flac(frames: u32) -> Vec<u8> {
+        fn crc8(data: &[u8]) -> u8 {
+            data.iter().fold(0u8, |crc, &byte| {
+                (0..8).fold(crc ^ byte, |crc, _| {
+                    if crc & 0x80 == 0 {
+                        crc << 1
+                    } else {
+                        (crc << 1) ^ 0x07
+                    }
+                })
+            })
+        }
+        fn crc16(data: &[u8]) -> u16 {
+            data.iter().fold(0u16, |crc, &byte| {
+                (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+                    if crc & 0x8000 == 0 {
+                        crc << 1
+                    } else {
+                        (crc << 1) ^ 0x8005
+                    }
+                })
+            })
+        }
+
+        const BLOCK: u32 = 4096;
+        let mut flac = b"fLaC".to_vec();
+        // Last metadata block, STREAMINFO, 34 bytes
+        flac.extend_from_slice(&[0x80, 0, 0, 34]);
+        flac.extend_from_slice(&(BLOCK as u16).to_be_bytes());
+        flac.extend_from_slice(&(BLOCK as u16).to_be_bytes());
+        flac.extend_from_slice(&[0; 6]); // Frame sizes unknown
+        // Sample rate (20 bits), channels - 1 (3), bits per sample - 1 (5), total samples (36)
+        let total = u64::from(frames * BLOCK);
+        flac.extend_from_slice(&((44_100u64 << 44) | (1 << 41) | (15 << 36) | total).to_be_bytes());
+        flac.extend_from_slice(&[0; 16]); // No MD5
+
+        for number in 0..frames {
+            let start = flac.len();
+            // Fixed blocksize sync, 4096 samples at 44.1 kHz, two channels of 16 bits
+            flac.extend_from_slice(&[0xFF, 0xF8, 0xC9, 0x18]);
+            let mut utf8 = [0; 4];
+            let coded = char::from_u32(number).unwrap().encode_utf8(&mut utf8);
+            flac.extend_from_slice(coded.as_bytes());
+            flac.push(crc8(&flac[start..]));
+            let samples: Vec<i16> = (0..BLOCK)
+                .map(|i| {
+                    let t = f64::from(number * BLOCK + i) / 44_100.0;
+                    ((t * 440.0 * std::f64::consts::TAU).sin() * 8000.0) as i16
+                })
+                .collect();
+            for _channel in 0..2 {
+                flac.push(0x02); // Verbatim subframe
+                for sample in &samples {
+                    flac.extend_from_slice(&sample.to_be_bytes());
+                }
+            }
+            let crc = crc16(&flac[start..]);
+            flac.extend_from_slice(&crc.to_be_bytes());
+        }
+        flac
+    }
+
+    /// Wrap a native FLAC stream from `synthetic_flac` in Ogg pages, one frame per page
+    fn synthetic_ogg_flac(flac: &[u8], serial: u32) -> Vec<u8> {
+        fn crc32(data: &[u8]) -> u32 {
+            data.iter().fold(0u32, |crc, &byte| {
+                (0..8).fold(crc ^ (u32::from(byte) << 24), |crc, _| {
+                    if crc & 0x8000_0000 == 0 {
+                        crc << 1
+                    } else {
+                        (crc << 1) ^ 0x04C1_1DB7
+                    }
+                })
+            })
+        }
+
+        // Identification packet: mapping 1.0, header count unknown, then STREAMINFO
+        let mut packets = vec![[&[0x7F][..], b"FLAC\x01\x00\x00\x00", &flac[..42]].concat()];
+        let frame_len = 4 + 1 + 1 + 2 * (1 + 4096 * 2) + 2;
+        packets.extend(flac[42..].chunks(frame_len).map(<[u8]>::to_vec));
+
+        let mut ogg = Vec::new();
+        let last = packets.len() - 1;
+        for (sequence, packet) in packets.iter().enumerate() {
+            let start = ogg.len();
+            let header_type = match sequence {
+                0 => 0x02,
+                _ if sequence == last => 0x04,
+                _ => 0,
+            };
+            ogg.extend_from_slice(b"OggS\x00");
+            ogg.push(header_type);
+            ogg.extend_from_slice(&(sequence as u64 * 4096).to_le_bytes());
+            ogg.extend_from_slice(&serial.to_le_bytes());
+            ogg.extend_from_slice(&(sequence as u32).to_le_bytes());
+            ogg.extend_from_slice(&[0; 4]);
+            let mut lacing = vec![255; packet.len() / 255];
+            lacing.push((packet.len() % 255) as u8);
+            ogg.push(lacing.len() as u8);
+            ogg.extend_from_slice(&lacing);
+            ogg.extend_from_slice(packet);
+            let crc = crc32(&ogg[start..]);
+            ogg[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+        }
+        ogg
+    }
+
+    #[test]
+    fn test_chained_ogg() {
+        let chained = [
+            synthetic_ogg_flac(&synthetic_flac(3), 1),
+            synthetic_ogg_flac(&synthetic_flac(2), 2),
+        ]
+        .concat();
+        let mut decoder = AudioDecoder::new(&chained).unwrap();
+        let mut chunk = Vec::new();
+        let mut samples = 0;
+        while decoder.next_chunk(&mut chunk).unwrap() {
+            samples += chunk.len();
+        }
+        assert_eq!(samples, 5 * 4096 * 2);
+    }
+
+    #[test]
+    fn test_flac_reencode() {
+        let flac = synthetic_flac(54);
+        let source = SourceAudio::scan(&flac, AudioFormat::Flac);
+        let seconds = f64::from(54 * 4096) / 44_100.0;
+        assert!((source.duration_seconds.unwrap() - seconds).abs() < 0.01);
+
+        let mp3 = compress_audio(&flac, "Audio/take.flac", &AudioOptions::new(), None).unwrap();
+        let summary = crate::mp3::scan_frames(&mp3);
+        assert!((summary.duration_seconds - seconds).abs() < 0.1);
+        assert!(mp3.len() < flac.len() / 4);
+        assert!(is_lossless_audio("Audio/take.FLAC"));
+        assert!(!is_lossless_audio("Audio/take.ogg"));
+    }
+
+    #[test]
+    fn test_mono_downmix_and_resample() {
+        let flac = synthetic_flac(54);
+        let seconds = f64::from(54 * 4096) / 44_100.0;
+        let options = AudioOptions::new().mono(true).sample_rate(Some(32_000));
+        let mp3 = compress_audio(&flac, "Audio/take.flac", &options, None).unwrap();
+
+        let decoder = AudioDecoder::new(&mp3).unwrap();
+        assert_eq!(decoder.channels, 1);
+        assert_eq!(decoder.sample_rate, 32_000);
+        let summary = crate::mp3::scan_frames(&mp3);
+        assert!((summary.duration_seconds - seconds).abs() < 0.1);
+
+        // Never resampled upwards
+        let wav = synthetic_wav(2);
+        let upsample = AudioOptions::new().sample_rate(Some(48_000));
+        let mp3 = compress_audio(&wav, "Audio/tone.wav", &upsample, None).unwrap();
+        assert_eq!(AudioDecoder::new(&mp3).unwrap().sample_rate, 44_100);
+
+        let invalid = AudioOptions::new().sample_rate(Some(30_000));
+        assert!(compress_audio(&wav, "Audio/tone.wav", &invalid, None).is_err());
+    }
+
+    #[test]
+    fn test_wav_reencode() {
+        let wav = synthetic_wav(5);
+        let options = AudioOptions::new();
+        let source = SourceAudio::scan(&wav, AudioFormat::Wav);
+        assert!((source.duration_seconds.unwrap() - 5.0).abs() < 0.01);
+        assert_eq!(source.bitrate_kbps(), Some(705));
+
+        let mp3 = compress_audio(&wav, "Audio/tone.wav", &options, None).unwrap();
+        let summary = crate::mp3::scan_frames(&mp3);
+        assert!((summary.duration_seconds - 5.0).abs() < 0.1);
+        assert!(mp3.len() < wav.len() / 2);
+
+        // The reference follows the new extension; MP3 names are kept as they are
+        assert_eq!(
+            output_filename("Audio/tone.wav", &options),
+            "Audio/tone.mp3"
+        );
+        assert_eq!(
+            output_filename("Audio/tone.MP3", &options),
+            "Audio/tone.MP3"
+        );
+        assert_eq!(
+            output_filename("Audio/tone.wav", &options.codec(AudioCodec::Opus)),
+            "Audio/tone.opus"
+        );
+    }
+
+    #[test]
+    fn test_tags_carried_over() {
+        let mut mp3 = crate::mp3::id3v2_text_tag(&[
+            ("TIT2", "Гимн".to_string()),
+            ("TPE1", "Оркестр".to_string()),
+        ]);
+        let options = AudioOptions::new();
+        mp3.extend(compress_audio(&synthetic_wav(3), "Audio/tone.wav", &options, None).unwrap());
+        let tags = AudioDecoder::new(&mp3).unwrap().tags;
+        assert_eq!(
+            tags,
+            [
+                ("TIT2", "Гимн".to_string()),
+                ("TPE1", "Оркестр".to_string())
+            ]
+        );
+
+        let lower = options.quality(40).allow_degrade(true);
+        let reencoded = compress_audio(&mp3, "Audio/tone.mp3", &lower, None).unwrap();
+        assert_eq!(AudioDecoder::new(&reencoded).unwrap().tags, tags);
+
+        let stripped =
+            compress_audio(&mp3, "Audio/tone.mp3", &lower.strip_metadata(true), None).unwrap();
+        assert_eq!(crate::mp3::id3v2_tag_len(&stripped), 0);
+        let repacked = options.mode(AudioMode::Repack).strip_metadata(true);
+        let repacked = compress_audio(&mp3, "Audio/tone.mp3", &repacked, None).unwrap();
+        assert_eq!(crate::mp3::id3v2_tag_len(&repacked), 0);
+    }
+
+    #[test]
+    fn test_loudness_normalization() {
+        let wav = synthetic_wav(5);
+        let options = AudioOptions::new().normalize_loudness(true);
+        let mp3 = compress_audio(&wav, "Audio/tone.wav", &options, None).unwrap();
+
+        let loudness = Loudness::from_audio(&mp3).unwrap();
+        let lufs = loudness.integrated_lufs.unwrap();
+        assert!((lufs - TARGET_LUFS).abs() < 0.5, "{lufs}");
+        assert!(loudness.peak < 0.95);
+        assert!((crate::mp3::scan_frames(&mp3).duration_seconds - 5.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_to_stereo_i16() {
+        let mut out = Vec::new();
+
+        // Stereo passes through, clamped and scaled
+        to_stereo_i16(&[0.0, 1.0, -1.0, 2.0], 2, &mut out);
+        assert_eq!(out, vec![0, 32767, -32767, 32767]);
+
+        // Mono is duplicated into both channels, buffer is reused
+        to_stereo_i16(&[0.5, -0.5], 1, &mut out);
+        assert_eq!(out, vec![16383, 16383, -16383, -16383]);
+    }
+
+    #[test]
+    fn test_pcm_duration_seconds() {
+        assert!((pcm_duration_seconds(88200, 44100, 2) - 1.0).abs() < f64::EPSILON);
+        assert!((pcm_duration_seconds(44100, 44100, 1) - 1.0).abs() < f64::EPSILON);
+        assert!((pcm_duration_seconds(0, 44100, 2)).abs() < f64::EPSILON);
+        assert!((pcm_duration_seconds(1000, 0, 2)).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_check_duration() {
+        assert!(check_duration(10.0, 10.0).is_ok());
+        assert!(check_duration(10.0, 10.05).is_ok()); // Encoder padding
+        assert!(check_duration(10.0, 9.6).is_ok());
+        assert!(check_duration(10.0, 5.0).is_err()); // Truncated
+        assert!(check_duration(10.0, 20.0).is_err()); // Half-speed output
+    }
+
+    #[test]
+    fn test_default_stereo_mode() {
+        assert_eq!(default_stereo_mode(Bitrate::Kbps64), StereoMode::Joint);
+        assert_eq!(default_stereo_mode(Bitrate::Kbps256), StereoMode::Joint);
+        assert_eq!(default_stereo_mode(Bitrate::Kbps320), StereoMode::Stereo);
+    }
+
+    #[test]
+    fn test_default_lowpass_hz() {
+        assert_eq!(default_lowpass_hz(Bitrate::Kbps64), 13_000);
+        assert_eq!(default_lowpass_hz(Bitrate::Kbps96), 15_000);
+        assert_eq!(default_lowpass_hz(Bitrate::Kbps128), 17_000);
+        assert_eq!(default_lowpass_hz(Bitrate::Kbps192), 19_000);
+        assert_eq!(default_lowpass_hz(Bitrate::Kbps320), 20_500);
+    }
+
+    #[test]
+    fn test_estimate_encoded_size() {
+        assert_eq!(estimate_encoded_size(1.0, Bitrate::Kbps128), 16_000);
+        assert_eq!(estimate_encoded_size(2.5, Bitrate::Kbps64), 20_000);
+        assert_eq!(estimate_encoded_size(0.0, Bitrate::Kbps320), 0);
+    }
+
+    #[test]
+    fn test_long_track_bitrate() {
+        let source = |duration_seconds| SourceAudio {
+            format: AudioFormat::Mp3,
+            size: 20_000_000,
+            duration_seconds: Some(duration_seconds),
+            audio_bytes: 20_000_000,
+            sample_rate: Some(44_100),
+        };
+        let options = AudioOptions::new().quality(60).long_media(Some(
+            LongMediaPenalty::default()
+                .min_seconds(300)
+                .quality_reduction(30),
+        ));
+
+        let short = select_target_bitrate(&source(120.0), &options).unwrap();
+        assert_eq!(short as u16, quality_to_mp3_bitrate(60) as u16);
+        let long = select_target_bitrate(&source(400.0), &options).unwrap();
+        assert_eq!(long as u16, quality_to_mp3_bitrate(30) as u16);
+    }
+
+    #[test]
+    fn test_bitrate_loss_guard() {
+        let options = AudioOptions::new();
+
+        // 128 kbps source at 256 kbps only grows
+        assert!(check_bitrate_loss(128, Bitrate::Kbps256, &options).is_err());
+        assert!(check_bitrate_loss(128, Bitrate::Kbps256, &options.allow_upcoding(true)).is_ok());
+        // A source already at the target is kept unless the threshold is lowered
+        assert!(check_bitrate_loss(124, Bitrate::Kbps128, &options).is_err());
+        assert!(check_bitrate_loss(128, Bitrate::Kbps128, &options).is_err());
+        assert!(
+            check_bitrate_loss(124, Bitrate::Kbps128, &options.min_bitrate_kbps(Some(112))).is_ok()
+        );
+        // A raised threshold keeps sources above the target too
+        assert!(
+            check_bitrate_loss(192, Bitrate::Kbps128, &options.min_bitrate_kbps(Some(192)))
+                .is_err()
+        );
+        assert!(check_bitrate_loss(193, Bitrate::Kbps128, &options).is_ok());
+
+        // Each guard has its own setting: allowing upcoding keeps the threshold,
+        // lowering the threshold keeps the upcoding check
+        let upcoding = options.allow_upcoding(true);
+        assert!(check_bitrate_loss(124, Bitrate::Kbps128, &upcoding).is_err());
+        assert!(
+            check_bitrate_loss(128, Bitrate::Kbps256, &upcoding.min_bitrate_kbps(Some(160)))
+                .is_err()
+        );
+        assert!(
+            check_bitrate_loss(128, Bitrate::Kbps256, &options.min_bitrate_kbps(Some(96))).is_err()
+        );
+        assert!(
+            check_bitrate_loss(128, Bitrate::Kbps256, &upcoding.min_bitrate_kbps(Some(96))).is_ok()
+        );
+
+        // 96 kbps source at 64 kbps degrades further
+        assert!(check_bitrate_loss(96, Bitrate::Kbps64, &options).is_err());
+        assert!(check_bitrate_loss(96, Bitrate::Kbps64, &options.allow_degrade(true)).is_ok());
+        // High-bitrate sources may go as low as asked
+        assert!(check_bitrate_loss(320, Bitrate::Kbps64, &options).is_ok());
+
+        // Ogg Vorbis is lossy too, so the guard applies; WAV is skipped
+        let source = |format| SourceAudio {
+            format,
+            size: 1_000_000,
+            duration_seconds: Some(100.0),
+            audio_bytes: 1_000_000,
+            sample_rate: Some(44_100),
+        };
+        let high = AudioOptions::new().quality(100);
+        assert!(select_target_bitrate(&source(AudioFormat::OggVorbis), &high).is_err());
+        assert!(select_target_bitrate(&source(AudioFormat::Wav), &high).is_ok());
+        assert!(repack_audio(b"OggS", AudioFormat::OggVorbis).is_err());
+    }
+
+    #[test]
+    fn test_short_clip_bitrate() {
+        let policy = ShortClipPolicy::default();
+
+        // Low target bitrate is raised to 128 kbps
+        let bitrate = short_clip_bitrate(2.0, 200_000, Bitrate::Kbps64, &policy).unwrap();
+        assert_eq!(bitrate as u16, 128);
+
+        // Higher target bitrate is kept
+        let bitrate = short_clip_bitrate(2.0, 200_000, Bitrate::Kbps256, &policy).unwrap();
+        assert_eq!(bitrate as u16, 256);
+
+        // 1s at 320 kbps source (~40 KB) -> 128 kbps (~16 KB) saves enough
+        assert!(short_clip_bitrate(1.0, 40_000, Bitrate::Kbps96, &policy).is_ok());
+
+        // 1s at 128 kbps source (~16 KB) saves nothing
+        assert!(short_clip_bitrate(1.0, 16_000, Bitrate::Kbps64, &policy).is_err());
+
+        // Savings threshold is configurable
+        let lenient = ShortClipPolicy {
+            max_seconds: 3.0,
+            min_savings_bytes: 0,
+        };
+        assert!(short_clip_bitrate(1.0, 16_000, Bitrate::Kbps64, &lenient).is_ok());
+    }
+
     #[test]
     fn test_quality_to_mp3_bitrate() {
         // Since Bitrate doesn't implement PartialEq or Debug, we'll test the function
@@ -444,4 +2109,50 @@ mod tests {
             std::mem::discriminant(&result1_second)
         );
     }
+
+    #[test]
+    fn test_audio_processor_analyze() {
+        let processor = AudioProcessor {
+            options: AudioOptions::default(),
+            ffmpeg_path: None,
+        };
+        let data = crate::mp3::tests::synthetic_mp3(100, 9);
+        let info = processor.analyze(&data, "Audio/test.mp3");
+        assert_eq!(info.format.as_deref(), Some("Mp3"));
+        assert!((info.duration_seconds.unwrap() - 2.61).abs() < 0.01);
+        assert!((127..=128).contains(&info.bitrate_kbps.unwrap()));
+
+        let info = processor.analyze(b"garbage", "Audio/test.mp3");
+        assert_eq!(info.duration_seconds, None);
+    }
+
+    #[test]
+    fn test_compress_audio_repack() {
+        let data = crate::mp3::tests::synthetic_mp3(50, 9);
+        let options = AudioOptions::new().mode(AudioMode::Repack);
+        let repacked = compress_audio(&data, "intro.mp3", &options, None).unwrap();
+        assert_eq!(crate::mp3::scan_frames(&repacked).frames, 50);
+
+        assert!(compress_audio(&data, "intro.wav", &options, None).is_err());
+        assert!(compress_audio(&data, "intro.mp3", &options.quality(101), None).is_err());
+    }
+
+    #[test]
+    fn test_repack_keeps_audio() {
+        let decode = |data: &[u8]| {
+            let mut decoder = AudioDecoder::new(data).unwrap();
+            let (mut samples, mut chunk) = (Vec::new(), Vec::new());
+            while decoder.next_chunk(&mut chunk).unwrap() {
+                samples.extend_from_slice(&chunk);
+            }
+            samples
+        };
+        let mp3 =
+            compress_audio(&synthetic_wav(3), "tone.wav", &AudioOptions::new(), None).unwrap();
+        let options = AudioOptions::new().mode(AudioMode::Repack);
+        let repacked = compress_audio(&mp3, "tone.mp3", &options, None).unwrap();
+        // Padding and ancillary data go, the decoded audio stays identical
+        assert!(repacked.len() < mp3.len());
+        assert_eq!(decode(&repacked), decode(&mp3));
+    }
 }
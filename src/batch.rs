@@ -0,0 +1,355 @@
+use crate::collection::{self, CollectionFormat};
+use crate::error::SicomError;
+use crate::format_size;
+use crate::options::CompressOptions;
+use crate::pipeline::{compress_pack, default_output_path};
+use crate::stats::reduction_percent;
+use anyhow::{Context, Result, anyhow};
+use indicatif::MultiProgress;
+use log::{info, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Result for one pack of a batch run
+#[derive(Debug, Serialize)]
+pub struct BatchEntry {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub input_size: u64,
+    /// None when compression failed
+    pub output_size: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Shell-style match of `*` (any run of characters) and `?` (one character)
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it currently covers up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    backtrack = Some((star_p, star_n + 1));
+                    p = star_p;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether a file looks like the default output of an earlier run
+fn is_compressed_output(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .is_some_and(|stem| stem.ends_with("_compressed"))
+}
+
+/// Packs in a directory, or files matching a glob pattern in its last component
+/// (e.g. `packs/*.siq`), sorted by name
+///
+/// Directories yield their .siq files (any file with `generic_zip`), skipping
+/// `*_compressed.*` outputs of earlier runs.
+pub fn find_packs(source: &Path, generic_zip: bool) -> Result<Vec<PathBuf>> {
+    let (directory, pattern) = if source.is_dir() {
+        (source, None)
+    } else {
+        let pattern = source
+            .file_name()
+            .and_then(|s| s.to_str())
+            .filter(|name| name.contains(['*', '?']))
+            .ok_or_else(|| SicomError::InputNotFound(source.to_path_buf()))?;
+        let directory = match source.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        (directory, Some(pattern))
+    };
+
+    let entries = fs::read_dir(directory).map_err(|source| SicomError::Io {
+        path: directory.to_path_buf(),
+        source,
+    })?;
+    let mut packs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default();
+            match pattern {
+                Some(pattern) => wildcard_match(pattern, &name),
+                None => {
+                    !name.starts_with('.')
+                        && !is_compressed_output(path)
+                        && (generic_zip || path.extension().and_then(|s| s.to_str()) == Some("siq"))
+                }
+            }
+        })
+        .collect();
+    packs.sort();
+    Ok(packs)
+}
+
+/// Whether `source` is a collection archive (.zip, .7z or .rar of packs)
+fn is_collection(source: &Path) -> bool {
+    source.is_file() && CollectionFormat::detect(source).is_some()
+}
+
+/// Packs a batch run over `source` would compress, without compressing them
+pub fn list_packs(source: &Path, generic_zip: bool) -> Result<Vec<String>> {
+    if is_collection(source) {
+        return collection::list_packs(source);
+    }
+    Ok(find_packs(source, generic_zip)?
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect())
+}
+
+/// Compress every pack found by `find_packs`, carrying on past failures
+///
+/// Outputs go to `output_dir` under the input's name, or next to each input
+/// with the usual `_compressed` suffix. A collection archive as `source` is
+/// extracted first; its packs keep their paths inside the archive and go to
+/// [`collection::default_output_dir`] by default.
+pub fn compress_batch(
+    source: &Path,
+    output_dir: Option<&Path>,
+    options: &CompressOptions,
+    multi_progress: &MultiProgress,
+) -> Result<Vec<BatchEntry>> {
+    let extracted;
+    let (packs, collection_root) = if is_collection(source) {
+        extracted = tempfile::tempdir().context("Failed to create extraction directory")?;
+        info!("Extracting packs from {}", source.display());
+        let packs = collection::extract_packs(source, extracted.path())?;
+        (packs, Some(extracted.path()))
+    } else {
+        (find_packs(source, options.generic_zip)?, None)
+    };
+    if packs.is_empty() {
+        return Err(anyhow!("No packs found in {}", source.display()));
+    }
+    let default_dir = collection_root.map(|_| collection::default_output_dir(source));
+    let output_dir = output_dir.or(default_dir.as_deref());
+    if let Some(directory) = output_dir {
+        fs::create_dir_all(directory).map_err(|source| SicomError::Io {
+            path: directory.to_path_buf(),
+            source,
+        })?;
+    }
+
+    let total = packs.len();
+    let mut entries = Vec::with_capacity(total);
+    for (i, input) in packs.into_iter().enumerate() {
+        // Packs of a collection are reported by their path inside it
+        let (reported, output) = match (collection_root, output_dir) {
+            (Some(root), Some(directory)) => {
+                let relative = input.strip_prefix(root).unwrap_or(&input);
+                let output = directory.join(relative);
+                if let Some(parent) = output.parent() {
+                    fs::create_dir_all(parent).map_err(|source| SicomError::Io {
+                        path: parent.to_path_buf(),
+                        source,
+                    })?;
+                }
+                (source.join(relative), output)
+            }
+            _ => {
+                let output = match (output_dir, input.file_name()) {
+                    (Some(directory), Some(name)) => directory.join(name),
+                    _ => default_output_path(&input)?,
+                };
+                (input.clone(), output)
+            }
+        };
+        info!("[{}/{total}] {}", i + 1, reported.display());
+        let input_size = fs::metadata(&input).map_or(0, |m| m.len());
+
+        // An output directory equal to the input directory would overwrite the pack
+        let overwrites_input = fs::canonicalize(&output)
+            .is_ok_and(|output| fs::canonicalize(&input).is_ok_and(|input| input == output));
+        let result = if overwrites_input {
+            Err(anyhow!("Output would overwrite the input pack"))
+        } else {
+            // Default outputs are recorded in a manifest, making reruns cheap
+            compress_pack(
+                input.clone(),
+                output_dir.map(|_| output.clone()),
+                options.clone(),
+                multi_progress.clone(),
+            )
+        };
+        let (output_size, error) = match result {
+            Ok(_) => (fs::metadata(&output).ok().map(|m| m.len()), None),
+            Err(e) => {
+                warn!("Failed to compress {}: {e:#}", reported.display());
+                (None, Some(format!("{e:#}")))
+            }
+        };
+        entries.push(BatchEntry {
+            input: reported,
+            output,
+            input_size,
+            output_size,
+            error,
+        });
+    }
+    Ok(entries)
+}
+
+/// Per-pack sizes and the overall reduction as a plain text table
+pub fn summary_table(entries: &[BatchEntry]) -> String {
+    let name = |entry: &BatchEntry| {
+        entry
+            .input
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let width = entries
+        .iter()
+        .map(|entry| name(entry).chars().count())
+        .chain(["Pack".len(), "Total".len()])
+        .max()
+        .unwrap_or_default();
+
+    let row = |pack: &str, original: &str, compressed: &str, reduction: &str| {
+        format!("{pack:<width$}  {original:>10}  {compressed:>10}  {reduction:>9}\n")
+    };
+    let mut table = row("Pack", "Original", "Compressed", "Reduction");
+    let (mut total_input, mut total_output, mut failed) = (0, 0, 0);
+    for entry in entries {
+        match entry.output_size {
+            Some(output_size) => {
+                total_input += entry.input_size;
+                total_output += output_size;
+                table.push_str(&row(
+                    &name(entry),
+                    &format_size(entry.input_size),
+                    &format_size(output_size),
+                    &format!("{:.1}%", reduction_percent(entry.input_size, output_size)),
+                ));
+            }
+            None => {
+                failed += 1;
+                table.push_str(&row(
+                    &name(entry),
+                    &format_size(entry.input_size),
+                    "-",
+                    "failed",
+                ));
+            }
+        }
+    }
+    table.push_str(&row(
+        "Total",
+        &format_size(total_input),
+        &format_size(total_output),
+        &format!("{:.1}%", reduction_percent(total_input, total_output)),
+    ));
+    if failed > 0 {
+        table.push_str(&format!("{failed} of {} packs failed\n", entries.len()));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{write_empty_pack, write_pack};
+    use indicatif::ProgressDrawTarget;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.siq", "pack.siq"));
+        assert!(wildcard_match("round?_*.siq", "round1_final.siq"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("a*b*c", "axxbyyc"));
+        assert!(!wildcard_match("*.siq", "pack.zip"));
+        assert!(!wildcard_match("round?.siq", "round10.siq"));
+        assert!(!wildcard_match("a*b", "ba"));
+    }
+
+    #[test]
+    fn test_compress_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let packs = dir.path().join("packs");
+        fs::create_dir(&packs).unwrap();
+        write_empty_pack(&packs.join("a.siq"));
+        fs::write(packs.join("b.siq"), "not a zip").unwrap();
+        write_empty_pack(&packs.join("a_compressed.siq"));
+        fs::write(packs.join("notes.txt"), "").unwrap();
+
+        assert_eq!(
+            find_packs(&packs, false).unwrap(),
+            [packs.join("a.siq"), packs.join("b.siq")]
+        );
+        assert_eq!(
+            find_packs(&packs.join("a*.siq"), false).unwrap(),
+            [packs.join("a.siq"), packs.join("a_compressed.siq")]
+        );
+        assert!(find_packs(&packs.join("missing.siq"), false).is_err());
+
+        let out = dir.path().join("out");
+        let entries = compress_batch(
+            &packs,
+            Some(&out),
+            &CompressOptions::new(),
+            &MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].output, out.join("a.siq"));
+        assert!(entries[0].output_size.is_some() && out.join("a.siq").exists());
+        assert!(entries[1].error.is_some());
+
+        let table = summary_table(&entries);
+        assert!(table.starts_with("Pack "));
+        assert!(table.contains("failed"));
+        assert!(table.ends_with("1 of 2 packs failed\n"));
+    }
+
+    #[test]
+    fn test_compress_collection() {
+        let dir = tempfile::tempdir().unwrap();
+        let pack = dir.path().join("pack.siq");
+        write_empty_pack(&pack);
+        let archive = dir.path().join("collection.zip");
+        write_pack(
+            &archive,
+            &[("Season 1/pack.siq", &fs::read(&pack).unwrap())],
+        );
+
+        assert_eq!(list_packs(&archive, false).unwrap(), ["Season 1/pack.siq"]);
+        let entries = compress_batch(
+            &archive,
+            None,
+            &CompressOptions::new(),
+            &MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].input, archive.join("Season 1/pack.siq"));
+        let output = dir.path().join("collection_compressed/Season 1/pack.siq");
+        assert_eq!(entries[0].output, output);
+        assert!(entries[0].error.is_none() && output.exists());
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{audio, image, video};
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// ffmpeg encoders the pipelines use
+const SICOM_ENCODERS: &[&str] = &["libx265", "libx264", "libmp3lame", "libopus"];
+
+/// Optional cargo features and whether this build has them
+const FEATURES: &[(&str, bool)] = &[
+    ("audio", cfg!(feature = "audio")),
+    ("cli", cfg!(feature = "cli")),
+    ("collections", cfg!(feature = "collections")),
+    ("content-scan", cfg!(feature = "content-scan")),
+    ("mozjpeg", cfg!(feature = "mozjpeg")),
+    ("object-storage", cfg!(feature = "object-storage")),
+    ("server", cfg!(feature = "server")),
+    ("video", cfg!(feature = "video")),
+];
+
+/// Formats one media kind is read from and written to
+#[derive(Debug, Serialize)]
+pub struct FormatSupport {
+    pub kind: &'static str,
+    /// File extensions that are compressed
+    pub inputs: &'static [&'static str],
+    /// Output formats available here (empty when the encoder is missing)
+    pub outputs: Vec<&'static str>,
+}
+
+/// What the ffmpeg found offers
+#[derive(Debug, Serialize)]
+pub struct FfmpegCapabilities {
+    pub path: PathBuf,
+    pub version: Option<String>,
+    /// Encoders sicom uses that this ffmpeg has
+    pub encoders: Vec<String>,
+    /// HEVC hardware encoders (NVENC, Quick Sync, VAAPI, VideoToolbox, AMF, ...)
+    pub hardware_encoders: Vec<String>,
+}
+
+/// What this build and environment can do, for frontends building settings UIs
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    pub version: &'static str,
+    /// Optional cargo features compiled in
+    pub features: Vec<&'static str>,
+    pub formats: Vec<FormatSupport>,
+    pub audio_engines: Vec<&'static str>,
+    /// None when ffmpeg can't be run
+    pub ffmpeg: Option<FfmpegCapabilities>,
+}
+
+/// Encoder names from `ffmpeg -encoders` output
+fn parse_encoders(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Version from the first line of `ffmpeg -version` ("ffmpeg version 6.1.1 Copyright ...")
+fn parse_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .next()?
+        .strip_prefix("ffmpeg version ")?
+        .split_whitespace()
+        .next()
+        .map(str::to_string)
+}
+
+/// Run ffmpeg (from `ffmpeg_path`, or PATH) to list its version and encoders
+fn probe_ffmpeg(ffmpeg_path: Option<&Path>) -> Option<FfmpegCapabilities> {
+    let path = ffmpeg_path.map_or_else(|| PathBuf::from("ffmpeg"), Path::to_path_buf);
+    let run = |args: &[&str]| {
+        Command::new(&path)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let encoders = parse_encoders(&run(&["-hide_banner", "-encoders"])?);
+    let version = run(&["-version"]).and_then(|output| parse_version(&output));
+    Some(FfmpegCapabilities {
+        version,
+        encoders: encoders
+            .iter()
+            .filter(|name| SICOM_ENCODERS.contains(&name.as_str()))
+            .cloned()
+            .collect(),
+        hardware_encoders: encoders
+            .into_iter()
+            .filter(|name| name.starts_with("hevc_"))
+            .collect(),
+        path,
+    })
+}
+
+impl Capabilities {
+    /// Probe the environment, running ffmpeg from `ffmpeg_path` or from PATH with `None`
+    pub fn detect(ffmpeg_path: Option<&Path>) -> Self {
+        Self::from_ffmpeg(probe_ffmpeg(ffmpeg_path))
+    }
+
+    fn from_ffmpeg(ffmpeg: Option<FfmpegCapabilities>) -> Self {
+        // Codecs left out of the build can't be used whatever ffmpeg offers
+        let has_encoder = |name: &str| {
+            cfg!(feature = "ffmpeg")
+                && ffmpeg
+                    .as_ref()
+                    .is_some_and(|ffmpeg| ffmpeg.encoders.iter().any(|encoder| encoder == name))
+        };
+        let mut audio_outputs = Vec::new();
+        let mut audio_engines = Vec::new();
+        if cfg!(feature = "audio") {
+            audio_outputs.push("mp3");
+            if has_encoder("libopus") {
+                audio_outputs.push("opus");
+            }
+            audio_engines.push("native");
+            if has_encoder("libmp3lame") {
+                audio_engines.push("ffmpeg");
+            }
+        }
+        let mut video_outputs = Vec::new();
+        if cfg!(feature = "video") {
+            if has_encoder("libx265") {
+                video_outputs.push("hevc");
+            }
+            if has_encoder("libx264") {
+                video_outputs.push("h264");
+            }
+        }
+
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            features: FEATURES
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(name, _)| *name)
+                .collect(),
+            formats: vec![
+                FormatSupport {
+                    kind: "image",
+                    inputs: image::SUPPORTED_EXTENSIONS,
+                    outputs: vec!["webp", "avif", "jpeg"],
+                },
+                FormatSupport {
+                    kind: "audio",
+                    inputs: audio::SUPPORTED_EXTENSIONS,
+                    outputs: audio_outputs,
+                },
+                FormatSupport {
+                    kind: "video",
+                    inputs: video::SUPPORTED_EXTENSIONS,
+                    outputs: video_outputs,
+                },
+            ],
+            audio_engines,
+            ffmpeg,
+        }
+    }
+}
+
+/// Print capabilities as text or JSON
+pub fn print_capabilities(capabilities: &Capabilities, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(capabilities)?);
+        return Ok(());
+    }
+
+    println!("sicom {}", capabilities.version);
+    let features = if capabilities.features.is_empty() {
+        "none".to_string()
+    } else {
+        capabilities.features.join(", ")
+    };
+    println!("Features: {features}");
+    for format in &capabilities.formats {
+        let outputs = if format.outputs.is_empty() {
+            "unavailable".to_string()
+        } else {
+            format.outputs.join(", ")
+        };
+        println!(
+            "{:<6} {} -> {outputs}",
+            format.kind,
+            format.inputs.join(", ")
+        );
+    }
+    println!("Audio engines: {}", capabilities.audio_engines.join(", "));
+    match &capabilities.ffmpeg {
+        Some(ffmpeg) => {
+            println!(
+                "ffmpeg {} at {}",
+                ffmpeg.version.as_deref().unwrap_or("(unknown version)"),
+                ffmpeg.path.display()
+            );
+            if !ffmpeg.hardware_encoders.is_empty() {
+                println!("Hardware encoders: {}", ffmpeg.hardware_encoders.join(", "));
+            }
+        }
+        None => println!("ffmpeg: not found"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODERS: &str = "Encoders:
+ V..... = Video
+ ------
+ V....D libx265              libx265 H.265 / HEVC (codec hevc)
+ V....D hevc_nvenc           NVIDIA NVENC hevc encoder (codec hevc)
+ A....D libmp3lame           libmp3lame MP3 (MPEG audio layer 3) (codec mp3)
+ A....D aac                  AAC (Advanced Audio Coding)
+";
+
+    #[test]
+    fn test_parse_ffmpeg_output() {
+        assert_eq!(
+            parse_encoders(ENCODERS),
+            ["libx265", "hevc_nvenc", "libmp3lame", "aac"]
+        );
+        assert_eq!(
+            parse_version("ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023\nbuilt with gcc")
+                .as_deref(),
+            Some("6.1.1-3ubuntu5")
+        );
+        assert_eq!(parse_version("garbage"), None);
+    }
+
+    #[test]
+    #[cfg(all(feature = "audio", feature = "video"))]
+    fn test_capabilities() {
+        let none = Capabilities::from_ffmpeg(None);
+        assert_eq!(none.audio_engines, ["native"]);
+        assert!(none.formats[2].outputs.is_empty());
+        assert!(none.formats[1].inputs.contains(&"wav"));
+
+        let ffmpeg = FfmpegCapabilities {
+            path: PathBuf::from("ffmpeg"),
+            version: None,
+            encoders: vec!["libx265".to_string(), "libopus".to_string()],
+            hardware_encoders: Vec::new(),
+        };
+        let with_ffmpeg = Capabilities::from_ffmpeg(Some(ffmpeg));
+        assert_eq!(with_ffmpeg.formats[1].outputs, ["mp3", "opus"]);
+        assert_eq!(with_ffmpeg.formats[2].outputs, ["hevc"]);
+        assert_eq!(with_ffmpeg.audio_engines, ["native"]);
+    }
+}
@@ -0,0 +1,322 @@
+use crate::error::SicomError;
+use anyhow::{Context, Result, anyhow};
+use log::debug;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+/// Archives packs are distributed in as collections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionFormat {
+    /// Read in-process
+    Zip,
+    /// Read with an installed 7-Zip or bsdtar (`collections` feature)
+    SevenZip,
+    /// Read with an installed 7-Zip, unrar or bsdtar (`collections` feature)
+    Rar,
+}
+
+impl CollectionFormat {
+    /// Format of a collection archive from its extension
+    pub fn detect(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "zip" => Some(Self::Zip),
+            "7z" => Some(Self::SevenZip),
+            "rar" => Some(Self::Rar),
+            _ => None,
+        }
+    }
+
+    /// External tools that can read the format, in order of preference
+    fn tools(self) -> &'static [Tool] {
+        match self {
+            Self::Zip => &[],
+            Self::SevenZip => &[Tool::SevenZip("7z"), Tool::SevenZip("7zz"), Tool::Bsdtar],
+            Self::Rar => &[
+                Tool::SevenZip("7z"),
+                Tool::SevenZip("7zz"),
+                Tool::Unrar,
+                Tool::Bsdtar,
+            ],
+        }
+    }
+}
+
+/// Archive tool run to list and extract 7z and RAR collections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    /// 7-Zip under one of its executable names
+    SevenZip(&'static str),
+    Unrar,
+    /// libarchive's tar, which reads both formats
+    Bsdtar,
+}
+
+impl Tool {
+    fn program(self) -> &'static str {
+        match self {
+            Self::SevenZip(program) => program,
+            Self::Unrar => "unrar",
+            Self::Bsdtar => "bsdtar",
+        }
+    }
+
+    /// Whether the tool can be run
+    fn available(self) -> bool {
+        let args: &[&str] = match self {
+            Self::Bsdtar => &["--version"],
+            _ => &[],
+        };
+        Command::new(self.program()).args(args).output().is_ok()
+    }
+
+    fn list_args(self, archive: &Path) -> Vec<OsString> {
+        let flags: &[&str] = match self {
+            Self::SevenZip(_) => &["l", "-ba", "-slt"],
+            Self::Unrar => &["lb"],
+            Self::Bsdtar => &["-tf"],
+        };
+        flags
+            .iter()
+            .map(OsString::from)
+            .chain([archive.as_os_str().to_owned()])
+            .collect()
+    }
+
+    /// Entry names from the listing output
+    fn parse_listing(self, output: &str) -> Vec<String> {
+        match self {
+            // Blocks of "Key = value" lines, one per entry
+            Self::SevenZip(_) => output
+                .split("\n\n")
+                .filter(|block| !block.lines().any(|line| line.trim() == "Folder = +"))
+                .filter_map(|block| {
+                    block
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Path = "))
+                        .map(|path| path.trim_end().to_string())
+                })
+                .collect(),
+            Self::Unrar | Self::Bsdtar => output
+                .lines()
+                .map(str::trim_end)
+                .filter(|line| !line.is_empty() && !line.ends_with('/'))
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    fn extract_args(self, archive: &Path, names: &[String], directory: &Path) -> Vec<OsString> {
+        let mut args: Vec<OsString> = match self {
+            Self::SevenZip(_) => vec![
+                "x".into(),
+                "-y".into(),
+                format!("-o{}", directory.display()).into(),
+                archive.into(),
+                "--".into(),
+            ],
+            Self::Unrar => vec!["x".into(), "-o+".into(), "-y".into(), archive.into()],
+            Self::Bsdtar => vec!["-xf".into(), archive.into(), "-C".into(), directory.into()],
+        };
+        args.extend(names.iter().map(OsString::from));
+        if self == Self::Unrar {
+            // unrar takes the destination last, with a trailing separator
+            let mut destination = directory.as_os_str().to_owned();
+            destination.push(std::path::MAIN_SEPARATOR_STR);
+            args.push(destination);
+        }
+        args
+    }
+
+    /// Run the tool, failing with its stderr when it exits unsuccessfully
+    fn run(self, args: &[OsString]) -> Result<String> {
+        let output = Command::new(self.program())
+            .args(args)
+            .output()
+            .with_context(|| format!("Failed to run {}", self.program()))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} failed: {}",
+                self.program(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Whether an entry is a pack that stays inside the extraction directory
+fn is_pack_entry(name: &str) -> bool {
+    let path = Path::new(name);
+    path.extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("siq"))
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// First tool found for a format that needs one
+fn find_tool(format: CollectionFormat, archive: &Path) -> Result<Tool> {
+    if !cfg!(feature = "collections") {
+        return Err(SicomError::MissingFeature("collections").into());
+    }
+    let tools = format.tools();
+    let tool = tools.iter().copied().find(|tool| tool.available());
+    tool.ok_or_else(|| {
+        let names: Vec<&str> = tools.iter().map(|tool| tool.program()).collect();
+        anyhow!(
+            "Reading {} needs one of: {}",
+            archive.display(),
+            names.join(", ")
+        )
+    })
+}
+
+fn open_zip(archive: &Path) -> Result<zip::ZipArchive<fs::File>> {
+    let file = fs::File::open(archive).map_err(|source| SicomError::Io {
+        path: archive.to_path_buf(),
+        source,
+    })?;
+    zip::ZipArchive::new(file).context("Failed to read collection archive")
+}
+
+/// Names of the .siq packs in a collection archive, sorted
+pub fn list_packs(archive: &Path) -> Result<Vec<String>> {
+    let format = CollectionFormat::detect(archive)
+        .ok_or_else(|| anyhow!("Not a collection archive: {}", archive.display()))?;
+    let mut names: Vec<String> = match format {
+        CollectionFormat::Zip => open_zip(archive)?
+            .file_names()
+            .map(str::to_string)
+            .collect(),
+        _ => {
+            let tool = find_tool(format, archive)?;
+            debug!("Listing {} with {}", archive.display(), tool.program());
+            tool.parse_listing(&tool.run(&tool.list_args(archive))?)
+        }
+    };
+    names.retain(|name| is_pack_entry(name));
+    names.sort();
+    Ok(names)
+}
+
+/// Extract the packs of a collection archive into `directory`, keeping their
+/// paths inside the archive; returns the extracted files in name order
+pub fn extract_packs(archive: &Path, directory: &Path) -> Result<Vec<PathBuf>> {
+    let names = list_packs(archive)?;
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    match CollectionFormat::detect(archive) {
+        Some(CollectionFormat::Zip) => {
+            let mut zip = open_zip(archive)?;
+            for name in &names {
+                let path = directory.join(name);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut entry = zip.by_name(name)?;
+                let mut file = fs::File::create(&path).map_err(|source| SicomError::Io {
+                    path: path.clone(),
+                    source,
+                })?;
+                std::io::copy(&mut entry, &mut file)
+                    .with_context(|| format!("Failed to extract {name}"))?;
+            }
+        }
+        Some(format) => {
+            let tool = find_tool(format, archive)?;
+            tool.run(&tool.extract_args(archive, &names, directory))?;
+        }
+        None => unreachable!("list_packs checked the format"),
+    }
+
+    names
+        .iter()
+        .map(|name| {
+            let path = directory.join(name);
+            if path.is_file() {
+                Ok(path)
+            } else {
+                Err(anyhow!(
+                    "{name} was not extracted from {}",
+                    archive.display()
+                ))
+            }
+        })
+        .collect()
+}
+
+/// Where the packs of a collection go without an output directory: a
+/// `<name>_compressed` directory next to the archive
+pub fn default_output_dir(archive: &Path) -> PathBuf {
+    let stem = archive
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    archive.with_file_name(format!("{stem}_compressed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write_pack;
+
+    #[test]
+    fn test_parse_listings() {
+        let seven_zip = "Path = Round 1/a.siq\nSize = 10\n\nPath = Round 1\nFolder = +\n\nPath = b.siq\nSize = 4\n";
+        assert_eq!(
+            Tool::SevenZip("7z").parse_listing(seven_zip),
+            ["Round 1/a.siq", "b.siq"]
+        );
+        assert_eq!(
+            Tool::Bsdtar.parse_listing("Round 1/\nRound 1/a.siq\nreadme.txt\n"),
+            ["Round 1/a.siq", "readme.txt"]
+        );
+
+        assert!(is_pack_entry("Round 1/a.SIQ"));
+        assert!(!is_pack_entry("readme.txt"));
+        assert!(!is_pack_entry("../escape.siq"));
+        assert!(!is_pack_entry("/abs.siq"));
+
+        let args =
+            Tool::Unrar.extract_args(Path::new("c.rar"), &["a.siq".to_string()], Path::new("out"));
+        assert_eq!(
+            args.last().unwrap(),
+            &OsString::from(format!("out{}", std::path::MAIN_SEPARATOR))
+        );
+    }
+
+    #[test]
+    fn test_zip_collection() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("collection.zip");
+        write_pack(
+            &archive,
+            &[
+                ("Round 2/b.siq", b"Round 2/b.siq"),
+                ("a.siq", b"a.siq"),
+                ("notes.txt", b"notes.txt"),
+            ],
+        );
+
+        assert_eq!(list_packs(&archive).unwrap(), ["Round 2/b.siq", "a.siq"]);
+        let out = dir.path().join("out");
+        let packs = extract_packs(&archive, &out).unwrap();
+        assert_eq!(packs, [out.join("Round 2/b.siq"), out.join("a.siq")]);
+        assert_eq!(fs::read(&packs[1]).unwrap(), b"a.siq");
+
+        assert_eq!(
+            default_output_dir(&archive),
+            dir.path().join("collection_compressed")
+        );
+        assert_eq!(
+            CollectionFormat::detect(Path::new("c.RAR")),
+            Some(CollectionFormat::Rar)
+        );
+        assert_eq!(CollectionFormat::detect(Path::new("c.siq")), None);
+    }
+}
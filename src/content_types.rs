@@ -0,0 +1,190 @@
+use roxmltree::Document;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Entry name of the OPC content type map written by Microsoft-style packagers
+pub const CONTENT_TYPES_ENTRY: &str = "[Content_Types].xml";
+
+const NAMESPACE: &str = "http://schemas.openxmlformats.org/package/2006/content-types";
+
+/// Mime type for an extension (lowercase, without the dot)
+fn mime_type(extension: &str) -> &'static str {
+    match extension {
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
+        "mp3" => "audio/mpeg",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "wav" => "audio/wav",
+        "m4a" => "audio/mp4",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        "mov" => "video/quicktime",
+        "xml" => "application/xml",
+        "txt" => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+fn extension(entry_name: &str) -> Option<String> {
+    Path::new(entry_name)
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(str::to_lowercase)
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Regenerate `[Content_Types].xml` for the entries of the output pack
+///
+/// Existing mappings are kept. Extensions introduced by conversion (e.g.
+/// `.webp`) get a `Default` mapping, and per-part `Override`s follow renamed
+/// entries; an override whose extension changed is dropped so the new
+/// extension's default applies instead of a stale mime type.
+pub fn update_content_types<'a>(
+    xml: &str,
+    entries: impl IntoIterator<Item = &'a str>,
+    renames: &HashMap<String, String>,
+) -> Result<String, roxmltree::Error> {
+    let document = Document::parse(xml)?;
+
+    let mut defaults: Vec<(String, String)> = Vec::new();
+    let mut overrides: Vec<(String, String)> = Vec::new();
+    for node in document.root_element().children() {
+        if node.has_tag_name("Default") {
+            if let (Some(extension), Some(content_type)) =
+                (node.attribute("Extension"), node.attribute("ContentType"))
+            {
+                defaults.push((extension.to_string(), content_type.to_string()));
+            }
+        } else if node.has_tag_name("Override") {
+            if let (Some(part), Some(content_type)) =
+                (node.attribute("PartName"), node.attribute("ContentType"))
+            {
+                overrides.push((part.to_string(), content_type.to_string()));
+            }
+        }
+    }
+
+    let known: HashSet<String> = defaults
+        .iter()
+        .map(|(extension, _)| extension.to_lowercase())
+        .collect();
+    let added: BTreeSet<String> = entries
+        .into_iter()
+        .filter_map(extension)
+        .filter(|extension| !known.contains(extension))
+        .collect();
+    for extension in added {
+        let content_type = mime_type(&extension).to_string();
+        defaults.push((extension, content_type));
+    }
+
+    let mut seen_parts = HashSet::new();
+    let overrides: Vec<(String, String)> = overrides
+        .into_iter()
+        .filter_map(|(part, content_type)| {
+            let entry = part.trim_start_matches('/');
+            match renames.get(entry) {
+                Some(renamed) if extension(renamed) != extension(entry) => None,
+                Some(renamed) => Some((format!("/{renamed}"), content_type)),
+                None => Some((part, content_type)),
+            }
+        })
+        // Deduplicated entries can map several parts onto one
+        .filter(|(part, _)| seen_parts.insert(part.clone()))
+        .collect();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>");
+    let _ = write!(out, "<Types xmlns=\"{NAMESPACE}\">");
+    for (extension, content_type) in &defaults {
+        let _ = write!(
+            out,
+            "<Default Extension=\"{}\" ContentType=\"{}\" />",
+            escape(extension),
+            escape(content_type)
+        );
+    }
+    for (part, content_type) in &overrides {
+        let _ = write!(
+            out,
+            "<Override PartName=\"{}\" ContentType=\"{}\" />",
+            escape(part),
+            escape(content_type)
+        );
+    }
+    out.push_str("</Types>");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_content_types() {
+        let xml = concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+            r#"<Default Extension="xml" ContentType="text/xml" />"#,
+            r#"<Default Extension="jpg" ContentType="image/jpeg" />"#,
+            r#"<Override PartName="/Images/a.jpg" ContentType="image/jpeg" />"#,
+            r#"<Override PartName="/Audio/b.mp3" ContentType="audio/mpeg" />"#,
+            r#"<Override PartName="/Audio/c.mp3" ContentType="audio/mpeg" />"#,
+            r#"</Types>"#
+        );
+        let renames = HashMap::from([
+            ("Images/a.jpg".to_string(), "Images/a.webp".to_string()),
+            ("Audio/b.mp3".to_string(), "Audio/0123.mp3".to_string()),
+            ("Audio/c.mp3".to_string(), "Audio/0123.mp3".to_string()),
+        ]);
+        let entries = ["content.xml", "Images/a.webp", "Audio/0123.mp3"];
+
+        let updated = update_content_types(xml, entries, &renames).unwrap();
+        let document = Document::parse(&updated).unwrap();
+        let defaults: Vec<_> = document
+            .root_element()
+            .children()
+            .filter(|node| node.has_tag_name("Default"))
+            .map(|node| {
+                (
+                    node.attribute("Extension").unwrap(),
+                    node.attribute("ContentType").unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            defaults,
+            [
+                ("xml", "text/xml"),
+                ("jpg", "image/jpeg"),
+                ("mp3", "audio/mpeg"),
+                ("webp", "image/webp")
+            ]
+        );
+        let overrides: Vec<_> = document
+            .root_element()
+            .children()
+            .filter_map(|node| node.attribute("PartName"))
+            .collect();
+        assert_eq!(overrides, ["/Audio/0123.mp3"]);
+
+        assert!(update_content_types("<Types", entries, &renames).is_err());
+    }
+}
@@ -0,0 +1,435 @@
+use crate::error::{self, SicomError};
+use crate::metrics::Metrics;
+use crate::options::CompressOptions;
+use crate::pipeline::PackCompressor;
+use crate::warm::WarmPool;
+use anyhow::{Context, Result, anyhow};
+use indicatif::MultiProgress;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Daemon state kept inside the inbox (hidden, so it's never picked up as a pack)
+const STATE_DIR: &str = ".sicom";
+/// Packs being compressed; anything left here after a crash is retried on startup
+const PROCESSING_DIR: &str = "processing";
+/// Packs that failed every attempt, next to their result file
+const FAILED_DIR: &str = "failed";
+
+/// Settings for watching an inbox directory
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    pub inbox: PathBuf,
+    pub outbox: PathBuf,
+    /// How often the inbox is scanned
+    pub poll_interval: Duration,
+    /// Packs modified more recently than this are assumed to still be uploading
+    pub settle_time: Duration,
+    /// Attempts per pack before it is moved to `failed/`, including crashed runs
+    pub max_attempts: u32,
+}
+
+/// Written to the outbox as `<pack>.json` once a pack is done or gave up
+#[derive(Debug, Serialize)]
+pub struct JobResult {
+    pub input: String,
+    pub status: JobStatus,
+    pub attempts: u32,
+    /// Compressed pack (in the outbox) or the failed input (in `failed/`)
+    pub output: PathBuf,
+    pub input_size: u64,
+    pub output_size: Option<u64>,
+    pub duration_seconds: f64,
+    pub error: Option<JobError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Compressed,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Watches an inbox and compresses every pack dropped there into the outbox
+///
+/// A pack is moved into the state directory while it's compressed, so a crash
+/// never loses it: on startup it is moved back and retried, and the crashed
+/// run counts as an attempt, so a pack that kills the process can't loop
+/// forever. Failed attempts are retried with exponential backoff.
+pub struct Daemon {
+    options: DaemonOptions,
+    compress_options: CompressOptions,
+    multi_progress: MultiProgress,
+    /// Earliest time a failed pack may be retried (in-memory only)
+    retry_after: HashMap<String, Instant>,
+    metrics: Arc<Metrics>,
+    /// ffmpeg lookup and video probes kept between packs
+    warm_pool: WarmPool,
+}
+
+impl Daemon {
+    pub fn new(
+        options: DaemonOptions,
+        compress_options: CompressOptions,
+        multi_progress: MultiProgress,
+    ) -> Result<Self> {
+        if options.max_attempts == 0 {
+            return Err(
+                SicomError::InvalidOptions("max attempts must be at least 1".to_string()).into(),
+            );
+        }
+        compress_options.validate()?;
+
+        let daemon = Self {
+            options,
+            compress_options,
+            multi_progress,
+            retry_after: HashMap::new(),
+            metrics: Arc::new(Metrics::new()),
+            warm_pool: WarmPool::new(),
+        };
+        for dir in [
+            daemon.options.outbox.join(FAILED_DIR),
+            daemon.processing_dir(),
+        ] {
+            fs::create_dir_all(&dir).map_err(|source| SicomError::Io { path: dir, source })?;
+        }
+        daemon.recover_interrupted()?;
+        Ok(daemon)
+    }
+
+    /// Counters updated as packs finish, for the metrics endpoint
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    fn state_dir(&self) -> PathBuf {
+        self.options.inbox.join(STATE_DIR)
+    }
+
+    fn processing_dir(&self) -> PathBuf {
+        self.state_dir().join(PROCESSING_DIR)
+    }
+
+    fn attempts_path(&self, name: &str) -> PathBuf {
+        self.state_dir().join(format!("{name}.attempts"))
+    }
+
+    fn attempts(&self, name: &str) -> u32 {
+        fs::read_to_string(self.attempts_path(name))
+            .ok()
+            .and_then(|count| count.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+    fn record_attempt(&self, name: &str) -> Result<u32> {
+        let attempts = self.attempts(name) + 1;
+        let path = self.attempts_path(name);
+        fs::write(&path, attempts.to_string()).map_err(|source| SicomError::Io { path, source })?;
+        Ok(attempts)
+    }
+
+    /// Return packs left in `processing/` by a crashed run to the inbox
+    fn recover_interrupted(&self) -> Result<()> {
+        let processing = self.processing_dir();
+        let entries = fs::read_dir(&processing).map_err(|source| SicomError::Io {
+            path: processing.clone(),
+            source,
+        })?;
+
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to read {processing:?}"))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".part") {
+                // Half-written output of the interrupted run
+                let _ = fs::remove_file(entry.path());
+                continue;
+            }
+            warn!("Recovering {name} from an interrupted run");
+            fs::rename(entry.path(), self.options.inbox.join(&name))
+                .with_context(|| format!("Failed to move {name} back to the inbox"))?;
+        }
+        Ok(())
+    }
+
+    fn is_pack(&self, path: &Path) -> bool {
+        self.compress_options.generic_zip
+            || path.extension().and_then(|s| s.to_str()) == Some("siq")
+    }
+
+    /// Packs in the inbox that finished uploading and aren't waiting for a retry
+    fn ready_packs(&self) -> Result<Vec<String>> {
+        let inbox = &self.options.inbox;
+        let entries = fs::read_dir(inbox).map_err(|source| SicomError::Io {
+            path: inbox.clone(),
+            source,
+        })?;
+
+        let now = SystemTime::now();
+        let mut ready = Vec::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if name.starts_with('.') || !metadata.is_file() || !self.is_pack(&entry.path()) {
+                continue;
+            }
+
+            let settled = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_none_or(|age| age >= self.options.settle_time);
+            let waiting = self
+                .retry_after
+                .get(&name)
+                .is_some_and(|retry_at| Instant::now() < *retry_at);
+            if settled && !waiting {
+                ready.push(name);
+            }
+        }
+        ready.sort();
+        Ok(ready)
+    }
+
+    /// Process every ready pack once; returns the number of finished packs
+    pub fn poll(&mut self) -> Result<usize> {
+        let mut finished = 0;
+        for name in self.ready_packs()? {
+            if self.process(&name)? {
+                finished += 1;
+            }
+        }
+        Ok(finished)
+    }
+
+    /// Poll the inbox until the process is stopped
+    pub fn run(&mut self) -> Result<()> {
+        info!(
+            "Watching {:?}, writing results to {:?}",
+            self.options.inbox, self.options.outbox
+        );
+        loop {
+            if let Err(e) = self.poll() {
+                error!("Failed to scan inbox: {e:#}");
+            }
+            thread::sleep(self.options.poll_interval);
+        }
+    }
+
+    /// Compress one pack; returns true once it's done (compressed or given up)
+    fn process(&mut self, name: &str) -> Result<bool> {
+        let processing = self.processing_dir().join(name);
+        fs::rename(self.options.inbox.join(name), &processing)
+            .with_context(|| format!("Failed to claim {name}"))?;
+        let input_size = fs::metadata(&processing).map(|m| m.len()).unwrap_or(0);
+        let part = self.processing_dir().join(format!("{name}.part"));
+        let started = Instant::now();
+
+        let previous_attempts = self.attempts(name);
+        let (attempts, result) = if previous_attempts >= self.options.max_attempts {
+            // Every attempt so far took the process down with it
+            let error = anyhow!("Compression crashed in {previous_attempts} runs");
+            (previous_attempts, Err(error))
+        } else {
+            // Counted before compressing so a crash also uses up an attempt
+            let attempts = self.record_attempt(name)?;
+            info!("Compressing {name} (attempt {attempts})");
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                PackCompressor::new(self.compress_options.clone())
+                    .multi_progress(self.multi_progress.clone())
+                    .warm_pool(self.warm_pool.clone())
+                    .compress(processing.clone(), Some(part.clone()))
+            }))
+            .unwrap_or_else(|_| Err(anyhow!("Compression panicked")));
+            (attempts, result)
+        };
+        let duration_seconds = started.elapsed().as_secs_f64();
+
+        let (status, output, output_size, job_error) = match result {
+            Ok(stats) => {
+                self.metrics.record_pack(&stats);
+                let output = self.options.outbox.join(name);
+                let output_size = fs::metadata(&part).map(|m| m.len()).ok();
+                fs::rename(&part, &output)
+                    .with_context(|| format!("Failed to move {name} to the outbox"))?;
+                fs::remove_file(&processing)
+                    .with_context(|| format!("Failed to remove processed {name}"))?;
+                info!("Compressed {name}");
+                (JobStatus::Compressed, output, output_size, None)
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&part);
+                self.metrics.record_failure(error::error_code(&e));
+                if attempts < self.options.max_attempts {
+                    let backoff = self.options.poll_interval * 2u32.saturating_pow(attempts);
+                    warn!("Failed to compress {name}, retrying in {backoff:?}: {e:#}");
+                    fs::rename(&processing, self.options.inbox.join(name))
+                        .with_context(|| format!("Failed to return {name} to the inbox"))?;
+                    self.retry_after
+                        .insert(name.to_string(), Instant::now() + backoff);
+                    return Ok(false);
+                }
+
+                error!("Giving up on {name} after {attempts} attempts: {e:#}");
+                let output = self.options.outbox.join(FAILED_DIR).join(name);
+                fs::rename(&processing, &output)
+                    .with_context(|| format!("Failed to move {name} to {FAILED_DIR}/"))?;
+                let job_error = JobError {
+                    code: error::error_code(&e),
+                    message: format!("{e:#}"),
+                };
+                (JobStatus::Failed, output, None, Some(job_error))
+            }
+        };
+
+        let job = JobResult {
+            input: name.to_string(),
+            status,
+            attempts,
+            output,
+            input_size,
+            output_size,
+            duration_seconds,
+            error: job_error,
+        };
+        self.write_result(name, &job)?;
+        self.retry_after.remove(name);
+        let _ = fs::remove_file(self.attempts_path(name));
+        Ok(true)
+    }
+
+    fn write_result(&self, name: &str, job: &JobResult) -> Result<()> {
+        let path = self.options.outbox.join(format!("{name}.json"));
+        let json = serde_json::to_string_pretty(job)?;
+        fs::write(&path, json).map_err(|source| SicomError::Io { path, source })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write_empty_pack;
+    use indicatif::ProgressDrawTarget;
+
+    fn daemon(dir: &Path, max_attempts: u32) -> Daemon {
+        let options = DaemonOptions {
+            inbox: dir.join("in"),
+            outbox: dir.join("out"),
+            poll_interval: Duration::ZERO,
+            settle_time: Duration::ZERO,
+            max_attempts,
+        };
+        fs::create_dir_all(&options.inbox).unwrap();
+        Daemon::new(
+            options,
+            CompressOptions::new().skip_video(true),
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        )
+        .unwrap()
+    }
+
+    fn read_result(path: &Path) -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_compresses_inbox_packs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut daemon = daemon(dir.path(), 3);
+        write_empty_pack(&dir.path().join("in/pack.siq"));
+        fs::write(dir.path().join("in/notes.txt"), "not a pack").unwrap();
+
+        assert_eq!(daemon.poll().unwrap(), 1);
+        assert!(dir.path().join("out/pack.siq").exists());
+        assert!(!dir.path().join("in/pack.siq").exists());
+        assert!(dir.path().join("in/notes.txt").exists());
+
+        let result = read_result(&dir.path().join("out/pack.siq.json"));
+        assert_eq!(result["status"], "compressed");
+        assert_eq!(result["attempts"], 1);
+        assert!(result["error"].is_null());
+        assert_eq!(daemon.poll().unwrap(), 0);
+        assert!(
+            daemon
+                .metrics()
+                .render()
+                .contains("sicom_packs_processed_total 1\n")
+        );
+    }
+
+    #[test]
+    fn test_failed_pack_retries_then_gives_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut daemon = daemon(dir.path(), 2);
+        fs::write(dir.path().join("in/broken.siq"), "not a zip").unwrap();
+
+        assert_eq!(daemon.poll().unwrap(), 0);
+        assert!(dir.path().join("in/broken.siq").exists());
+
+        assert_eq!(daemon.poll().unwrap(), 1);
+        assert!(dir.path().join("out/failed/broken.siq").exists());
+        let result = read_result(&dir.path().join("out/broken.siq.json"));
+        assert_eq!(result["status"], "failed");
+        assert_eq!(result["attempts"], 2);
+        assert_eq!(result["error"]["code"], "archive_error");
+        assert!(
+            daemon
+                .metrics()
+                .render()
+                .contains("sicom_failures_total{code=\"archive_error\"} 2\n")
+        );
+    }
+
+    #[test]
+    fn test_recovers_interrupted_packs() {
+        let dir = tempfile::tempdir().unwrap();
+        drop(daemon(dir.path(), 2));
+
+        // Simulate a crash mid-compression on the first attempt
+        let processing = dir.path().join("in/.sicom/processing");
+        write_empty_pack(&processing.join("pack.siq"));
+        fs::write(processing.join("pack.siq.part"), "partial").unwrap();
+        fs::write(dir.path().join("in/.sicom/pack.siq.attempts"), "1").unwrap();
+
+        let mut daemon = daemon(dir.path(), 2);
+        assert!(dir.path().join("in/pack.siq").exists());
+        assert!(!processing.join("pack.siq.part").exists());
+
+        assert_eq!(daemon.poll().unwrap(), 1);
+        let result = read_result(&dir.path().join("out/pack.siq.json"));
+        assert_eq!(result["status"], "compressed");
+        assert_eq!(result["attempts"], 2);
+    }
+
+    #[test]
+    fn test_gives_up_on_packs_that_keep_crashing() {
+        let dir = tempfile::tempdir().unwrap();
+        drop(daemon(dir.path(), 2));
+
+        let processing = dir.path().join("in/.sicom/processing");
+        write_empty_pack(&processing.join("pack.siq"));
+        fs::write(dir.path().join("in/.sicom/pack.siq.attempts"), "2").unwrap();
+
+        let mut daemon = daemon(dir.path(), 2);
+        assert_eq!(daemon.poll().unwrap(), 1);
+        assert!(dir.path().join("out/failed/pack.siq").exists());
+        let result = read_result(&dir.path().join("out/pack.siq.json"));
+        assert_eq!(result["status"], "failed");
+        assert_eq!(result["error"]["code"], "internal");
+    }
+}
@@ -0,0 +1,149 @@
+use image::DynamicImage;
+use image::imageops::FilterType;
+use std::collections::HashMap;
+
+/// Largest meaningful Hamming distance between two 64-bit hashes
+pub const MAX_DISTANCE: u32 = 64;
+
+/// Perceptual fingerprint of an image entry
+#[derive(Debug, Clone)]
+pub struct ImageFingerprint {
+    pub name: String,
+    pub hash: u64,
+    /// Width x height - more pixels means a higher-quality variant
+    pub pixels: u64,
+    pub size: u64,
+}
+
+impl ImageFingerprint {
+    pub fn new(name: String, img: &DynamicImage, size: u64) -> Self {
+        Self {
+            name,
+            hash: dhash(img),
+            pixels: u64::from(img.width()) * u64::from(img.height()),
+            size,
+        }
+    }
+}
+
+/// Difference hash: compares adjacent pixels of a 9x8 grayscale thumbnail
+///
+/// Robust to scaling and recompression, so the same picture at different
+/// resolutions or qualities lands within a few bits.
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let thumbnail = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = thumbnail.get_pixel(x, y)[0];
+            let right = thumbnail.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(left > right);
+        }
+    }
+    hash
+}
+
+/// Number of differing bits between two hashes
+pub const fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Group near-identical images, mapping each duplicate to the entry that replaces it
+///
+/// The highest-resolution variant (then the largest file) of each group is kept.
+pub fn find_similar(
+    mut fingerprints: Vec<ImageFingerprint>,
+    threshold: u32,
+) -> HashMap<String, String> {
+    // Best variants first so they become the group representatives
+    fingerprints.sort_by(|a, b| {
+        b.pixels
+            .cmp(&a.pixels)
+            .then(b.size.cmp(&a.size))
+            .then(a.name.cmp(&b.name))
+    });
+
+    let mut keepers: Vec<&ImageFingerprint> = Vec::new();
+    let mut duplicates = HashMap::new();
+    for fingerprint in &fingerprints {
+        match keepers
+            .iter()
+            .find(|keeper| hamming_distance(keeper.hash, fingerprint.hash) <= threshold)
+        {
+            Some(keeper) => {
+                duplicates.insert(fingerprint.name.clone(), keeper.name.clone());
+            }
+            None => keepers.push(fingerprint),
+        }
+    }
+    duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn picture(width: u32, height: u32, seed: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            // Smooth pattern that survives scaling
+            let fx = x * 64 / width;
+            let fy = y * 64 / height;
+            let v = ((fx * seed + fy * 3) % 64 * 4) as u8;
+            Rgb([v, v / 2, 255 - v])
+        }))
+    }
+
+    fn fingerprint(name: &str, img: &DynamicImage, size: u64) -> ImageFingerprint {
+        ImageFingerprint::new(name.to_string(), img, size)
+    }
+
+    #[test]
+    fn test_dhash_survives_scaling() {
+        let large = picture(640, 480, 5);
+        let small = picture(160, 120, 5);
+        let other = picture(640, 480, 11);
+
+        assert!(hamming_distance(dhash(&large), dhash(&small)) <= 4);
+        assert!(hamming_distance(dhash(&large), dhash(&other)) > 10);
+    }
+
+    #[test]
+    fn test_hamming_distance() {
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0b1011, 0b0001), 2);
+        assert_eq!(hamming_distance(0, u64::MAX), MAX_DISTANCE);
+    }
+
+    #[test]
+    fn test_find_similar_keeps_highest_resolution() {
+        let large = picture(640, 480, 5);
+        let small = picture(160, 120, 5);
+        let other = picture(640, 480, 11);
+
+        let duplicates = find_similar(
+            vec![
+                fingerprint("Images/small.jpg", &small, 10_000),
+                fingerprint("Images/large.jpg", &large, 90_000),
+                fingerprint("Images/other.jpg", &other, 80_000),
+            ],
+            6,
+        );
+
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates["Images/small.jpg"], "Images/large.jpg");
+    }
+
+    #[test]
+    fn test_find_similar_prefers_larger_file_at_same_resolution() {
+        let img = picture(320, 240, 7);
+        let duplicates = find_similar(
+            vec![
+                fingerprint("Images/q40.jpg", &img, 20_000),
+                fingerprint("Images/q90.jpg", &img, 60_000),
+            ],
+            0,
+        );
+        assert_eq!(duplicates["Images/q40.jpg"], "Images/q90.jpg");
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt::Write;
+use std::ops::Range;
+
+/// Unchanged lines shown around each change
+const CONTEXT_LINES: usize = 3;
+
+/// Lines `old` of the old text replaced by lines `new` of the new text
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Edit {
+    old: Range<usize>,
+    new: Range<usize>,
+}
+
+/// Changed line ranges between two texts
+///
+/// Reference rewriting replaces text inside lines and never adds or removes
+/// any, so after trimming the common prefix and suffix, equally long middles
+/// are compared line by line. Anything else becomes a single replacement.
+fn line_edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+    if prefix == old_end && prefix == new_end {
+        return Vec::new();
+    }
+    if old_end - prefix != new_end - prefix {
+        return vec![Edit {
+            old: prefix..old_end,
+            new: prefix..new_end,
+        }];
+    }
+
+    let mut edits: Vec<Edit> = Vec::new();
+    for line in prefix..old_end {
+        if old[line] == new[line] {
+            continue;
+        }
+        match edits.last_mut() {
+            Some(edit) if edit.old.end == line => {
+                edit.old.end += 1;
+                edit.new.end += 1;
+            }
+            _ => edits.push(Edit {
+                old: line..line + 1,
+                new: line..line + 1,
+            }),
+        }
+    }
+    edits
+}
+
+/// Hunk header range: 1-based start and length, pointing before the hunk when empty
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+/// Unified diff of two texts, empty when they are the same
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let edits = line_edits(&old_lines, &new_lines);
+    if edits.is_empty() {
+        return String::new();
+    }
+
+    // Edits whose context would touch or overlap share a hunk
+    let mut hunks: Vec<&[Edit]> = Vec::new();
+    let mut first = 0;
+    for i in 1..=edits.len() {
+        if i == edits.len() || edits[i].old.start - edits[i - 1].old.end > 2 * CONTEXT_LINES {
+            hunks.push(&edits[first..i]);
+            first = i;
+        }
+    }
+
+    let mut out = format!("--- {old_name}\n+++ {new_name}\n");
+    for hunk in hunks {
+        let (head, tail) = (&hunk[0], &hunk[hunk.len() - 1]);
+        let leading = head.old.start.min(CONTEXT_LINES);
+        let trailing = (old_lines.len() - tail.old.end).min(CONTEXT_LINES);
+        let old_start = head.old.start - leading;
+        let new_start = head.new.start - leading;
+        let old_len = tail.old.end + trailing - old_start;
+        let new_len = tail.new.end + trailing - new_start;
+        let _ = writeln!(
+            out,
+            "@@ -{} +{} @@",
+            hunk_range(old_start, old_len),
+            hunk_range(new_start, new_len)
+        );
+
+        let mut line = old_start;
+        for edit in hunk {
+            for context in &old_lines[line..edit.old.start] {
+                let _ = writeln!(out, " {context}");
+            }
+            for removed in &old_lines[edit.old.clone()] {
+                let _ = writeln!(out, "-{removed}");
+            }
+            for added in &new_lines[edit.new.clone()] {
+                let _ = writeln!(out, "+{added}");
+            }
+            line = edit.old.end;
+        }
+        for context in &old_lines[line..tail.old.end + trailing] {
+            let _ = writeln!(out, " {context}");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(count: usize) -> Vec<String> {
+        (1..=count).map(|i| format!("line {i}")).collect()
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = lines(20);
+        let mut new = old.clone();
+        new[1] = "line 2 changed".to_string();
+        new[4] = "line 5 changed".to_string();
+        new[17] = "line 18 changed".to_string();
+
+        let diff = unified_diff(&old.join("\n"), &new.join("\n"), "a/x", "b/x");
+        let expected = "\
+--- a/x
++++ b/x
+@@ -1,8 +1,8 @@
+ line 1
+-line 2
++line 2 changed
+ line 3
+ line 4
+-line 5
++line 5 changed
+ line 6
+ line 7
+ line 8
+@@ -15,6 +15,6 @@
+ line 15
+ line 16
+ line 17
+-line 18
++line 18 changed
+ line 19
+ line 20
+";
+        assert_eq!(diff, expected);
+        assert_eq!(unified_diff("same\n", "same\n", "a", "b"), "");
+    }
+
+    #[test]
+    fn test_unified_diff_insertion() {
+        let diff = unified_diff("a\nb\n", "a\nx\ny\nb\n", "old", "new");
+        assert_eq!(diff, "--- old\n+++ new\n@@ -1,2 +1,4 @@\n a\n+x\n+y\n b\n");
+
+        let diff = unified_diff("", "a\n", "old", "new");
+        assert_eq!(diff, "--- old\n+++ new\n@@ -0,0 +1 @@\n+a\n");
+    }
+}
@@ -0,0 +1,192 @@
+use crate::media::MediaKind;
+use crate::stats::CompressionStats;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Failure classes surfaced to users and machine-readable reports
+///
+/// Each variant has a stable string code and process exit status; add new
+/// variants instead of changing existing ones so scripts keep working.
+#[derive(Error, Debug)]
+pub enum SicomError {
+    #[error("Input file does not exist: {0}")]
+    InputNotFound(PathBuf),
+    #[error("Input file is not a valid .siq file: {0}")]
+    InvalidSiqFile(PathBuf),
+    #[error("{0}")]
+    InvalidOptions(String),
+    #[error("Failed to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to read ZIP archive {path}: {source}")]
+    Archive {
+        path: PathBuf,
+        source: zip::result::ZipError,
+    },
+    #[error("Failed to parse content.xml: {0}")]
+    ContentXml(#[from] roxmltree::Error),
+    #[error("ffmpeg not found{}", .0.as_ref().map(|path| format!(" at {}", path.display())).unwrap_or_default())]
+    FfmpegNotFound(Option<PathBuf>),
+    #[error("Failed to compress {} {name}: {source:#}", .kind.label())]
+    MediaEncode {
+        kind: MediaKind,
+        name: String,
+        source: anyhow::Error,
+    },
+    #[error("Failed to transfer {location}: {message}")]
+    Transfer { location: String, message: String },
+    #[error("Compression exceeded the time limit of {0} seconds")]
+    TimedOut(u64),
+    #[error("No references to update for renamed media: {}", .0.join(", "))]
+    DanglingReferences(Vec<String>),
+    #[error("Checksum mismatch for {0}: the file is corrupt or incomplete")]
+    ChecksumMismatch(PathBuf),
+    #[error("Pack verification found {0} problem(s)")]
+    VerificationFailed(usize),
+    #[error("Pack looks already compressed ({}); lossy compression would degrade it further (use --allow-recompress to proceed)", .0.join(", "))]
+    AlreadyCompressed(Vec<String>),
+    #[error("Output is {} even at the lowest image and video quality, over the {} limit", crate::format_size(*.size), crate::format_size(*.limit))]
+    OutputTooLarge { size: u64, limit: u64 },
+    /// Holds the statistics of the files finished before the cancellation
+    #[error("Compression was cancelled")]
+    Cancelled(Box<CompressionStats>),
+    #[error("sicom was built without the `{0}` feature")]
+    MissingFeature(&'static str),
+    #[error("content.xml has {0} structural problem(s)")]
+    LintFailed(usize),
+}
+
+impl SicomError {
+    /// Stable identifier for reports and APIs
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::InputNotFound(_) => "input_not_found",
+            Self::InvalidSiqFile(_) => "invalid_siq_file",
+            Self::InvalidOptions(_) => "invalid_options",
+            Self::Io { .. } => "io_error",
+            Self::Archive { .. } => "archive_error",
+            Self::ContentXml(_) => "xml_parse_error",
+            Self::FfmpegNotFound(_) => "ffmpeg_not_found",
+            Self::MediaEncode { .. } => "media_encode_failed",
+            Self::Transfer { .. } => "transfer_failed",
+            Self::TimedOut(_) => "timed_out",
+            Self::DanglingReferences(_) => "dangling_references",
+            Self::ChecksumMismatch(_) => "checksum_mismatch",
+            Self::VerificationFailed(_) => "verification_failed",
+            Self::AlreadyCompressed(_) => "already_compressed",
+            Self::OutputTooLarge { .. } => "output_too_large",
+            Self::Cancelled(_) => "cancelled",
+            Self::MissingFeature(_) => "missing_feature",
+            Self::LintFailed(_) => "lint_failed",
+        }
+    }
+
+    /// Whether the same input may succeed on a retry (the pack isn't the problem)
+    pub const fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Io { .. } | Self::Transfer { .. } | Self::TimedOut(_)
+        )
+    }
+
+    /// Process exit status (1 is reserved for unclassified errors, 2 for usage errors)
+    pub const fn exit_code(&self) -> i32 {
+        match self {
+            Self::InvalidOptions(_) => 2,
+            Self::InputNotFound(_) => 3,
+            Self::InvalidSiqFile(_) => 4,
+            Self::Io { .. } => 5,
+            Self::Archive { .. } => 6,
+            Self::ContentXml(_) => 7,
+            Self::FfmpegNotFound(_) => 8,
+            Self::MediaEncode { .. } => 9,
+            Self::Transfer { .. } => 10,
+            Self::TimedOut(_) => 11,
+            Self::DanglingReferences(_) => 12,
+            Self::ChecksumMismatch(_) => 13,
+            Self::VerificationFailed(_) => 14,
+            Self::AlreadyCompressed(_) => 15,
+            Self::OutputTooLarge { .. } => 16,
+            Self::Cancelled(_) => 17,
+            Self::MissingFeature(_) => 18,
+            Self::LintFailed(_) => 19,
+        }
+    }
+}
+
+/// Find the classified error anywhere in an error's context chain
+pub fn classify(error: &anyhow::Error) -> Option<&SicomError> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<SicomError>())
+}
+
+/// Stable code for any error; unclassified errors report "internal"
+pub fn error_code(error: &anyhow::Error) -> &'static str {
+    classify(error).map_or("internal", SicomError::code)
+}
+
+/// Whether retrying may help; unclassified errors are assumed to be transient
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    classify(error).is_none_or(SicomError::is_transient)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_codes_survive_context() {
+        let error = Err::<(), _>(SicomError::InputNotFound(PathBuf::from("pack.siq")))
+            .context("Failed to start")
+            .unwrap_err();
+        assert_eq!(error_code(&error), "input_not_found");
+        assert_eq!(classify(&error).map(SicomError::exit_code), Some(3));
+
+        assert_eq!(error_code(&anyhow::anyhow!("boom")), "internal");
+    }
+
+    #[test]
+    fn test_transient_errors() {
+        let error = anyhow::Error::new(SicomError::TimedOut(60)).context("Job failed");
+        assert!(is_transient(&error));
+        assert!(is_transient(&anyhow::anyhow!("boom")));
+        let archive = SicomError::Archive {
+            path: PathBuf::from("pack.siq"),
+            source: zip::result::ZipError::InvalidArchive("bad"),
+        };
+        assert!(!archive.is_transient());
+        assert!(!SicomError::InvalidSiqFile(PathBuf::from("pack.siq")).is_transient());
+    }
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(
+            SicomError::FfmpegNotFound(None).to_string(),
+            "ffmpeg not found"
+        );
+        assert_eq!(
+            SicomError::FfmpegNotFound(Some(PathBuf::from("/opt/ffmpeg"))).to_string(),
+            "ffmpeg not found at /opt/ffmpeg"
+        );
+        let error = SicomError::MediaEncode {
+            kind: MediaKind::Audio,
+            name: "Audio/a.mp3".to_string(),
+            source: anyhow::anyhow!("no frames"),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Failed to compress audio Audio/a.mp3: no frames"
+        );
+        assert_eq!(error.code(), "media_encode_failed");
+    }
+
+    #[test]
+    fn test_xml_errors_convert() {
+        let error: SicomError = roxmltree::Document::parse("<a>").unwrap_err().into();
+        assert_eq!(error.code(), "xml_parse_error");
+    }
+}
@@ -0,0 +1,157 @@
+use crate::error::SicomError;
+use crate::options::CompressOptions;
+use crate::{build_registry, format_size, open_pack, prepare_ffmpeg};
+use anyhow::{Context, Result, anyhow};
+use std::io::Read;
+use std::path::Path;
+
+/// Find a pack entry by exact or URL-decoded name
+pub(crate) fn find_entry_name<'a>(
+    names: impl Iterator<Item = &'a str>,
+    entry: &str,
+) -> Option<String> {
+    let decode = |name: &'a str| {
+        urlencoding::decode(name)
+            .map(|decoded| decoded.into_owned())
+            .unwrap_or_else(|_| name.to_string())
+    };
+
+    let mut decoded_match = None;
+    for name in names {
+        if name == entry {
+            return Some(name.to_string());
+        }
+        if decoded_match.is_none() && decode(name) == entry {
+            decoded_match = Some(name.to_string());
+        }
+    }
+    decoded_match
+}
+
+/// Describe what happens to a planned file once its output size is known
+fn planned_outcome(
+    original_size: u64,
+    estimated_size: Option<u64>,
+    always_compress: bool,
+) -> &'static str {
+    match estimated_size {
+        Some(_) if always_compress => "compressed (always_compress)",
+        Some(size) if size >= original_size => "original kept (compressed would be larger)",
+        Some(_) => "compressed",
+        None => "compressed if the result is smaller than the original",
+    }
+}
+
+/// Print what the pipeline would do with a single pack entry
+pub fn explain_entry(input_pack: &Path, entry: &str, mut options: CompressOptions) -> Result<()> {
+    if !input_pack.exists() {
+        return Err(SicomError::InputNotFound(input_pack.to_path_buf()).into());
+    }
+    options.validate()?;
+
+    let mut archive = open_pack(input_pack)?;
+
+    let file_name = find_entry_name(archive.file_names(), entry)
+        .ok_or_else(|| anyhow!("Entry not found in pack: {entry}"))?;
+    let mut data = Vec::new();
+    archive
+        .by_name(&file_name)
+        .with_context(|| format!("Failed to read entry: {file_name}"))?
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to read entry data: {file_name}"))?;
+    let original_size = data.len() as u64;
+
+    let ffmpeg_available = prepare_ffmpeg(&mut options);
+    let registry = build_registry(&options, ffmpeg_available);
+
+    println!("Entry: {file_name} ({})", format_size(original_size));
+
+    let Some((processor, disabled_reason)) = registry.find(&file_name) else {
+        println!("Type: not a compressible media file");
+        println!("Result: copied unchanged");
+        return Ok(());
+    };
+
+    println!(
+        "Type: {} ({})",
+        processor.kind().label(),
+        processor.analyze(&data, &file_name)
+    );
+
+    if let Some(reason) = disabled_reason {
+        println!("Result: copied unchanged ({reason})");
+        return Ok(());
+    }
+
+    match processor.plan(&data, &file_name) {
+        Ok(plan) => {
+            println!("Encoder: {}", plan.encoder);
+            println!("Output: {}", plan.filename);
+            match plan.estimated_size {
+                Some(size) if size >= original_size => println!(
+                    "Estimated size: {} ({:.1}% increase)",
+                    format_size(size),
+                    (size as f64 / original_size.max(1) as f64 - 1.0) * 100.0
+                ),
+                Some(size) => println!(
+                    "Estimated size: {} ({:.1}% reduction)",
+                    format_size(size),
+                    (1.0 - size as f64 / original_size as f64) * 100.0
+                ),
+                None => println!("Estimated size: unknown (depends on content)"),
+            }
+            println!(
+                "Result: {}",
+                planned_outcome(original_size, plan.estimated_size, options.always_compress)
+            );
+        }
+        Err(e) => println!("Result: copied unchanged ({e})"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_entry_name() {
+        let names = [
+            "content.xml",
+            "Images/%D0%92%D0%94%D0%9D%D0%A5.jpg",
+            "Audio/a.mp3",
+        ];
+
+        assert_eq!(
+            find_entry_name(names.into_iter(), "Audio/a.mp3").as_deref(),
+            Some("Audio/a.mp3")
+        );
+        // Entries are URL-encoded inside packs; accept the decoded name too
+        assert_eq!(
+            find_entry_name(names.into_iter(), "Images/ВДНХ.jpg").as_deref(),
+            Some("Images/%D0%92%D0%94%D0%9D%D0%A5.jpg")
+        );
+        assert_eq!(
+            find_entry_name(names.into_iter(), "Images/missing.jpg"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_planned_outcome() {
+        assert_eq!(planned_outcome(1000, Some(400), false), "compressed");
+        assert_eq!(
+            planned_outcome(1000, Some(1200), false),
+            "original kept (compressed would be larger)"
+        );
+        assert_eq!(
+            planned_outcome(1000, Some(1200), true),
+            "compressed (always_compress)"
+        );
+        assert_eq!(
+            planned_outcome(1000, None, false),
+            "compressed if the result is smaller than the original"
+        );
+    }
+}
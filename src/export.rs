@@ -0,0 +1,235 @@
+use crate::error::SicomError;
+use crate::inspect::EntryIndex;
+use crate::media::MediaKind;
+use crate::open_pack;
+use crate::pack::{self, MediaRef, Package};
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:60em;margin:auto;padding:1em}\
+.question{border-top:1px solid #ccc;padding:.5em 0}\
+.price{font-weight:bold;margin-right:.5em}\
+img,video{max-width:100%;max-height:30em;display:block}\
+.missing{color:#b00}";
+
+/// Escape text for HTML element content and attribute values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Relative URL of an extracted entry (entry names can contain `%` escapes
+/// that are part of the file name on disk)
+fn entry_url(entry: &str) -> String {
+    entry
+        .split('/')
+        .map(|segment| urlencoding::encode(segment).into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Player (or image) for one media reference; `entry` is None when the pack lacks it
+fn render_media(html: &mut String, media: &MediaRef, entry: Option<&str>) {
+    let Some(entry) = entry else {
+        let _ = writeln!(
+            html,
+            "<p class=\"missing\">Missing {}: {}</p>",
+            media.kind.label(),
+            escape(&media.name)
+        );
+        return;
+    };
+    let src = escape(&entry_url(entry));
+    let _ = match media.kind {
+        MediaKind::Image => writeln!(
+            html,
+            "<img src=\"{src}\" alt=\"{}\" loading=\"lazy\">",
+            escape(&media.name)
+        ),
+        MediaKind::Audio => writeln!(
+            html,
+            "<audio controls preload=\"none\" src=\"{src}\"></audio>"
+        ),
+        MediaKind::Video => writeln!(
+            html,
+            "<video controls preload=\"none\" src=\"{src}\"></video>"
+        ),
+    };
+}
+
+/// Static page listing every round, theme and question with its media,
+/// where `resolve` maps a reference to the extracted entry
+fn render(package: &Package, resolve: impl Fn(&MediaRef) -> Option<String>) -> String {
+    let title = escape(&package.name);
+    let mut html = String::new();
+    let _ = writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>"
+    );
+    if let Some(logo) = &package.logo {
+        render_media(&mut html, logo, resolve(logo).as_deref());
+    }
+    for round in &package.rounds {
+        let _ = writeln!(html, "<h2>{}</h2>", escape(&round.name));
+        for theme in &round.themes {
+            let _ = writeln!(html, "<h3>{}</h3>", escape(&theme.name));
+            for question in &theme.questions {
+                html.push_str("<div class=\"question\">\n<p>");
+                if let Some(price) = question.price {
+                    let _ = write!(html, "<span class=\"price\">{price}</span>");
+                }
+                html.push_str(&escape(&question.text));
+                html.push_str("</p>\n");
+                for media in &question.media {
+                    render_media(&mut html, media, resolve(media).as_deref());
+                }
+                if !question.answers.is_empty() {
+                    let _ = writeln!(
+                        html,
+                        "<details><summary>Answer</summary>{}</details>",
+                        escape(&question.answers.join(" / "))
+                    );
+                }
+                html.push_str("</div>\n");
+            }
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Write `index.html` and every media file the questions use to `output_dir`,
+/// for reviewing a (compressed) pack in a browser
+///
+/// Returns the path of the page.
+pub fn export_html(input_pack: &Path, output_dir: &Path) -> Result<PathBuf> {
+    if !input_pack.exists() {
+        return Err(SicomError::InputNotFound(input_pack.to_path_buf()).into());
+    }
+
+    let mut archive = open_pack(input_pack)?;
+    let mut xml = String::new();
+    archive
+        .by_name("content.xml")
+        .map_err(|_| anyhow!("No content.xml found in pack"))?
+        .read_to_string(&mut xml)
+        .with_context(|| "Failed to read content.xml as UTF-8")?;
+    let package = pack::parse_package(&xml)?;
+
+    let entries: Vec<(String, u64)> = archive
+        .file_names()
+        .map(|name| (name.to_string(), 0))
+        .collect();
+    let index = EntryIndex::new(entries.iter().map(|(name, size)| (name.as_str(), *size)));
+    let referenced: HashSet<&str> = package
+        .logo
+        .iter()
+        .chain(
+            package
+                .questions()
+                .flat_map(|(_, _, question)| &question.media),
+        )
+        .filter_map(|media| index.resolve(media))
+        .map(|(entry, _)| entry.as_str())
+        .collect();
+
+    let write_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| SicomError::Io { path, source }
+    };
+    let mut extracted = HashSet::new();
+    for entry in referenced {
+        let mut file = archive
+            .by_name(entry)
+            .with_context(|| format!("Failed to read {entry}"))?;
+        // Entry names come from the archive; never write outside output_dir
+        let Some(relative) = file.enclosed_name() else {
+            warn!("Skipping {entry}: unsafe path");
+            continue;
+        };
+        let path = output_dir.join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(write_error(parent))?;
+        }
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)
+            .with_context(|| format!("Failed to read {entry}"))?;
+        std::fs::write(&path, data).map_err(write_error(&path))?;
+        extracted.insert(entry.to_string());
+    }
+
+    let html = render(&package, |media| {
+        index
+            .resolve(media)
+            .map(|(entry, _)| entry.clone())
+            .filter(|entry| extracted.contains(entry))
+    });
+    std::fs::create_dir_all(output_dir).map_err(write_error(output_dir))?;
+    let page = output_dir.join("index.html");
+    std::fs::write(&page, html).map_err(write_error(&page))?;
+    info!("Exported {} media files to {output_dir:?}", extracted.len());
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::tests::SAMPLE_V5;
+    use crate::test_support::write_pack;
+
+    #[test]
+    fn test_render() {
+        let package = pack::parse_package(SAMPLE_V5).unwrap();
+        let html = render(&package, |media| {
+            (media.kind != MediaKind::Video).then(|| media.entry_name())
+        });
+        assert!(html.contains("<h2>Round 1</h2>"));
+        assert!(html.contains("<span class=\"price\">100</span>Name this place"));
+        // The file on disk keeps the entry's escapes, so the URL escapes them again
+        assert!(html.contains("<img src=\"Images/%25D0%2592%25D0%2594%25D0%259D%25D0%25A5.jpg\""));
+        assert!(html.contains("<audio controls preload=\"none\" src=\"Audio/anthem.mp3\">"));
+        assert!(html.contains("<p class=\"missing\">Missing video: clip.mp4</p>"));
+        assert!(html.contains("<summary>Answer</summary>VDNH"));
+        assert_eq!(escape("<a href='x'>&"), "&lt;a href=&#39;x&#39;&gt;&amp;");
+    }
+
+    #[test]
+    fn test_export_html() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        write_pack(
+            &input,
+            &[
+                ("content.xml", SAMPLE_V5.as_bytes()),
+                ("Audio/anthem.mp3", b"mp3"),
+                ("Images/unused.png", b"png"),
+            ],
+        );
+
+        let output = dir.path().join("out");
+        let page = export_html(&input, &output).unwrap();
+        assert_eq!(page, output.join("index.html"));
+        assert_eq!(
+            std::fs::read(output.join("Audio/anthem.mp3")).unwrap(),
+            b"mp3"
+        );
+        // Only referenced media is copied
+        assert!(!output.join("Images/unused.png").exists());
+        let html = std::fs::read_to_string(page).unwrap();
+        assert!(html.contains("Missing image: ВДНХ.jpg"));
+    }
+}
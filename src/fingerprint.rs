@@ -0,0 +1,248 @@
+use crate::audio::AudioDecoder;
+use anyhow::Result;
+
+/// Length of one fingerprint frame
+const FRAME_SECONDS: f64 = 0.1;
+/// Frames the re-encoded audio may be shifted by (encoder delay and padding)
+const MAX_LAG_FRAMES: usize = 2;
+/// Energy floor keeping the log finite for digital silence
+const ENERGY_FLOOR: f64 = 1e-10;
+/// Log-energy difference under which two flat envelopes count as the same level
+const FLAT_TOLERANCE: f64 = 1.0;
+
+/// Per-frame loudness and brightness envelope of each channel
+///
+/// Coarse on purpose: lossy encoding keeps these envelopes intact, while
+/// catastrophic bugs (swapped or garbled channels, wrong speed, noise) don't.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    /// Per channel and frame: log energy of the signal and of its first difference
+    channels: Vec<Vec<[f64; 2]>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FrameAccumulator {
+    energy: f64,
+    diff_energy: f64,
+    previous: f64,
+    count: usize,
+}
+
+/// Builds a fingerprint from interleaved samples fed in chunks
+pub struct Fingerprinter {
+    frame_len: usize,
+    accumulators: Vec<FrameAccumulator>,
+    channels: Vec<Vec<[f64; 2]>>,
+}
+
+impl Fingerprinter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        let channels = channels.max(1);
+        Self {
+            frame_len: ((f64::from(sample_rate) * FRAME_SECONDS) as usize).max(1),
+            accumulators: vec![FrameAccumulator::default(); channels],
+            channels: vec![Vec::new(); channels],
+        }
+    }
+
+    pub fn push(&mut self, interleaved: &[f32]) {
+        for frame in interleaved.chunks_exact(self.accumulators.len()) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let accumulator = &mut self.accumulators[channel];
+                let sample = f64::from(sample);
+                let diff = sample - accumulator.previous;
+                accumulator.energy += sample * sample;
+                accumulator.diff_energy += diff * diff;
+                accumulator.previous = sample;
+                accumulator.count += 1;
+
+                if accumulator.count == self.frame_len {
+                    let len = self.frame_len as f64;
+                    self.channels[channel].push([
+                        (accumulator.energy / len).max(ENERGY_FLOOR).ln(),
+                        (accumulator.diff_energy / len).max(ENERGY_FLOOR).ln(),
+                    ]);
+                    *accumulator = FrameAccumulator {
+                        previous: sample,
+                        ..FrameAccumulator::default()
+                    };
+                }
+            }
+        }
+    }
+
+    pub fn finish(self) -> Fingerprint {
+        Fingerprint {
+            channels: self.channels,
+        }
+    }
+}
+
+impl Fingerprint {
+    /// Decode an audio file and fingerprint it
+    pub fn from_audio(data: &[u8]) -> Result<Self> {
+        let mut decoder = AudioDecoder::new(data)?;
+        // The decoder yields mono, or the first two channels interleaved
+        let channels = if decoder.channels == 1 { 1 } else { 2 };
+        let mut fingerprinter = Fingerprinter::new(channels, decoder.sample_rate);
+        let mut chunk = Vec::new();
+        while decoder.next_chunk(&mut chunk)? {
+            fingerprinter.push(&chunk);
+        }
+        Ok(fingerprinter.finish())
+    }
+
+    /// Average of all channels, for comparing mono against stereo
+    fn downmix(&self) -> Vec<[f64; 2]> {
+        let frames = self.channels.iter().map(Vec::len).min().unwrap_or(0);
+        let count = self.channels.len() as f64;
+        (0..frames)
+            .map(|frame| {
+                let mut sum = [0.0; 2];
+                for channel in &self.channels {
+                    sum[0] += channel[frame][0];
+                    sum[1] += channel[frame][1];
+                }
+                [sum[0] / count, sum[1] / count]
+            })
+            .collect()
+    }
+
+    /// Similarity from 0 (unrelated) to 1 (same envelopes); the worst channel counts
+    pub fn similarity(&self, other: &Self) -> f64 {
+        if self.channels.len() == other.channels.len() {
+            self.channels
+                .iter()
+                .zip(&other.channels)
+                .map(|(a, b)| envelope_similarity(a, b))
+                .fold(1.0, f64::min)
+        } else {
+            envelope_similarity(&self.downmix(), &other.downmix())
+        }
+    }
+}
+
+/// Best correlation over small shifts, scaled by how much of the longer envelope overlaps
+fn envelope_similarity(a: &[[f64; 2]], b: &[[f64; 2]]) -> f64 {
+    let longer = a.len().max(b.len());
+    if longer == 0 {
+        return 1.0;
+    }
+
+    let best = (0..=MAX_LAG_FRAMES)
+        .flat_map(|lag| [(lag, 0), (0, lag)])
+        .map(|(lag_a, lag_b)| {
+            let a = a.get(lag_a..).unwrap_or_default();
+            let b = b.get(lag_b..).unwrap_or_default();
+            let n = a.len().min(b.len());
+            (0..2)
+                .map(|feature| {
+                    correlation(
+                        a[..n].iter().map(|frame| frame[feature]),
+                        b[..n].iter().map(|frame| frame[feature]),
+                    )
+                })
+                .sum::<f64>()
+                / 2.0
+        })
+        .fold(0.0, f64::max);
+
+    best * a.len().min(b.len()) as f64 / longer as f64
+}
+
+/// Pearson correlation clamped to 0..=1; flat envelopes match when their levels do
+fn correlation(x: impl Iterator<Item = f64> + Clone, y: impl Iterator<Item = f64> + Clone) -> f64 {
+    let n = x.clone().count() as f64;
+    if n == 0.0 {
+        return 0.0;
+    }
+    let mean_x = x.clone().sum::<f64>() / n;
+    let mean_y = y.clone().sum::<f64>() / n;
+
+    let (mut covariance, mut variance_x, mut variance_y) = (0.0, 0.0, 0.0);
+    for (x, y) in x.zip(y) {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance_x += (x - mean_x) * (x - mean_x);
+        variance_y += (y - mean_y) * (y - mean_y);
+    }
+
+    let flat = |variance: f64| variance / n < 1e-6;
+    match (flat(variance_x), flat(variance_y)) {
+        (true, true) => f64::from(u8::from((mean_x - mean_y).abs() < FLAT_TOLERANCE)),
+        (false, false) => (covariance / (variance_x * variance_y).sqrt()).max(0.0),
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 8000;
+
+    /// Tone whose loudness and pitch wander over `seconds`
+    fn channel(seconds: f64, seed: f64) -> Vec<f32> {
+        let samples = (f64::from(RATE) * seconds) as usize;
+        (0..samples)
+            .map(|i| {
+                let t = i as f64 / f64::from(RATE);
+                let envelope = 0.5 + 0.5 * (t * seed).sin();
+                let pitch = 300.0 + 200.0 * (t * seed * 0.7).cos();
+                (envelope * (t * pitch * std::f64::consts::TAU).sin() * 0.8) as f32
+            })
+            .collect()
+    }
+
+    fn fingerprint(channels: &[&[f32]]) -> Fingerprint {
+        let interleaved: Vec<f32> = (0..channels[0].len())
+            .flat_map(|i| channels.iter().map(move |channel| channel[i]))
+            .collect();
+        let mut fingerprinter = Fingerprinter::new(channels.len(), RATE);
+        for chunk in interleaved.chunks(1000 * channels.len()) {
+            fingerprinter.push(chunk);
+        }
+        fingerprinter.finish()
+    }
+
+    #[test]
+    fn test_identical_and_lightly_distorted_audio_match() {
+        let left = channel(10.0, 1.3);
+        let right = channel(10.0, 2.9);
+        let source = fingerprint(&[&left, &right]);
+        assert!((source.similarity(&source) - 1.0).abs() < 1e-9);
+
+        // Quantization and a small delay, like a lossy encode
+        let degrade = |samples: &[f32]| -> Vec<f32> {
+            std::iter::repeat_n(0.0, 100)
+                .chain(samples.iter().map(|s| (s * 64.0).round() / 64.0))
+                .take(samples.len())
+                .collect()
+        };
+        let encoded = fingerprint(&[&degrade(&left), &degrade(&right)]);
+        assert!(source.similarity(&encoded) > 0.9);
+
+        // Mono output of stereo input is compared against the downmix
+        let mono = fingerprint(&[&left]);
+        assert!(source.similarity(&mono) > 0.5);
+    }
+
+    #[test]
+    fn test_broken_audio_is_dissimilar() {
+        let left = channel(10.0, 1.3);
+        let right = channel(10.0, 2.9);
+        let source = fingerprint(&[&left, &right]);
+
+        let swapped = fingerprint(&[&right, &left]);
+        assert!(source.similarity(&swapped) < 0.5);
+
+        let half_speed =
+            |samples: &[f32]| -> Vec<f32> { samples.iter().flat_map(|&s| [s, s]).collect() };
+        let slow = fingerprint(&[&half_speed(&left), &half_speed(&right)]);
+        assert!(source.similarity(&slow) < 0.5);
+
+        let silence = vec![0.0; left.len()];
+        let silent = fingerprint(&[&silence, &silence]);
+        assert!(source.similarity(&silent) < 0.1);
+        assert!((silent.similarity(&silent) - 1.0).abs() < 1e-9);
+    }
+}
@@ -1,23 +1,423 @@
-use anyhow::{Context, Result};
-use std::path::Path;
+use crate::error::SicomError;
+use crate::media::{MediaInfo, MediaKind, MediaOutput, MediaPlan, MediaProcessor};
+use crate::progress::ProgressLogger;
+use anyhow::{Context, Result, anyhow};
+#[cfg(feature = "ffmpeg")]
+use ffmpeg_sidecar::command::FfmpegCommand;
+#[cfg(feature = "ffmpeg")]
+use ffmpeg_sidecar::event::FfmpegEvent;
+use image::imageops::FilterType;
+use image::{AnimationDecoder, ImageDecoder, ImageEncoder};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::Cursor;
+#[cfg(feature = "ffmpeg")]
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Format re-encoded images are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageCodec {
+    #[default]
+    Webp,
+    /// AV1 stills through ravif, about 20% smaller than WebP for photos (no lossless
+    /// mode; animated GIFs stay animated WebP)
+    Avif,
+    /// Re-encode JPEGs as JPEG under their own name, for clients that render WebP
+    /// poorly; other images are left alone
+    Jpeg,
+}
+
+impl ImageCodec {
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+            Self::Jpeg => "jpg",
+        }
+    }
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Webp => "WebP",
+            Self::Avif => "AVIF",
+            Self::Jpeg => "JPEG",
+        }
+    }
+}
+
+/// Settings for image compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ImageOptions {
+    /// Quality 1-100; 95 and above encodes lossless WebP
+    pub quality: u8,
+    /// WebP encoder effort 0-6; higher is slower and a little smaller
+    pub effort: u8,
+    /// Scale images down so neither side exceeds this many pixels (never up)
+    pub max_dimension: Option<u32>,
+    pub codec: ImageCodec,
+    /// Rotate and flip photos as their EXIF orientation says, since the output
+    /// carries no EXIF
+    pub auto_orient: bool,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            quality: 40,
+            effort: DEFAULT_EFFORT,
+            max_dimension: None,
+            codec: ImageCodec::Webp,
+            auto_orient: true,
+        }
+    }
+}
+
+impl ImageOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub const fn quality(mut self, quality: u8) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    pub const fn effort(mut self, effort: u8) -> Self {
+        self.effort = effort;
+        self
+    }
+
+    pub const fn max_dimension(mut self, pixels: Option<u32>) -> Self {
+        self.max_dimension = pixels;
+        self
+    }
+
+    pub const fn codec(mut self, codec: ImageCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    pub const fn auto_orient(mut self, auto_orient: bool) -> Self {
+        self.auto_orient = auto_orient;
+        self
+    }
+}
+
+/// Image pipeline: re-encodes JPEG/PNG/WebP/GIF/BMP/TIFF to WebP or AVIF (animated GIFs to
+/// animated WebP), and HEIC through ffmpeg
+pub struct ImageProcessor {
+    pub options: ImageOptions,
+    /// ffmpeg used to decode HEIC images
+    pub ffmpeg_path: Option<PathBuf>,
+}
+
+impl ImageProcessor {
+    /// The image as the `image` crate can decode it: HEIC converted to PNG, others as they are
+    fn decodable<'a>(&self, data: &'a [u8], filename: &str) -> Result<Cow<'a, [u8]>> {
+        if is_heic(filename) {
+            decode_heic(data, self.ffmpeg_path.as_deref()).map(Cow::Owned)
+        } else {
+            Ok(Cow::Borrowed(data))
+        }
+    }
+}
+
+impl MediaProcessor for ImageProcessor {
+    fn kind(&self) -> MediaKind {
+        MediaKind::Image
+    }
+
+    fn supported(&self, filename: &str) -> bool {
+        match self.options.codec {
+            ImageCodec::Jpeg => is_jpeg_filename(filename),
+            _ => is_supported_image(filename) || (cfg!(feature = "ffmpeg") && is_heic(filename)),
+        }
+    }
+
+    fn analyze(&self, data: &[u8], filename: &str) -> MediaInfo {
+        let mut info = MediaInfo::default();
+        if is_heic(filename) {
+            info.format = Some("Heic".to_string());
+        }
+        let Ok(data) = self.decodable(data, filename) else {
+            return info;
+        };
+        if let Ok(reader) = image::ImageReader::new(Cursor::new(&*data)).with_guessed_format() {
+            if !is_heic(filename) {
+                info.format = reader.format().map(|format| format!("{format:?}"));
+            }
+            info.format = reader.format().map(|format| format!("{format:?}"));
+            if let Ok((width, height)) = reader.into_dimensions() {
+                info.width = Some(width);
+                info.height = Some(height);
+            }
+        }
+        info
+    }
+
+    fn plan(&self, data: &[u8], filename: &str) -> Result<MediaPlan> {
+        // Encoding a single image is cheap - report the exact size
+        let compressed = compress_image(&self.decodable(data, filename)?, &self.options)
+            .with_context(|| format!("Failed to compress image: {filename}"))?;
+        Ok(MediaPlan {
+            filename: output_filename(filename, encoded_codec(&compressed)),
+            encoder: describe_quality(self.options.quality, self.options.codec),
+            estimated_size: Some(compressed.len() as u64),
+        })
+    }
+
+    fn check(&self, data: &[u8], filename: &str) -> Result<()> {
+        image::load_from_memory(&self.decodable(data, filename)?)
+            .context("Failed to decode image")?;
+        Ok(())
+    }
+
+    fn compress(
+        &self,
+        data: &[u8],
+        filename: &str,
+        _progress: &mut ProgressLogger,
+    ) -> Result<MediaOutput> {
+        let compressed = compress_image(&self.decodable(data, filename)?, &self.options)
+            .with_context(|| format!("Failed to compress image: {filename}"))?;
+        let codec = encoded_codec(&compressed);
+        Ok(MediaOutput {
+            filename: output_filename(filename, codec),
+            data: compressed,
+            codec: codec.label(),
+            renditions: Vec::new(),
+        })
+    }
+}
+
+/// libwebp's own default encoder effort (its `method`)
+pub const DEFAULT_EFFORT: u8 = 4;
+
+/// Highest WebP encoder effort
+pub const MAX_EFFORT: u8 = 6;
+
+/// Lowest quality that encodes lossless WebP
+pub const LOSSLESS_QUALITY: u8 = 95;
+
+/// WebP encoder mode derived from quality
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WebpSetting {
+    Lossless,
+    Lossy(f32),
+}
+
+/// Map quality (1-100) to WebP encoder mode (95+ switches to lossless)
+pub fn webp_setting(quality: u8) -> WebpSetting {
+    if quality >= LOSSLESS_QUALITY {
+        WebpSetting::Lossless
+    } else {
+        WebpSetting::Lossy(f32::from(quality))
+    }
+}
+
+/// AV1 speed (1-10, lower is slower) for a WebP-style effort (0-6), so the
+/// default effort gives ravif's slower-than-default speed 6 and the most effort speed 4
+fn avif_speed(effort: u8) -> u8 {
+    10 - effort.min(MAX_EFFORT)
+}
+
+/// Describe what the image quality means in encoder terms
+pub fn describe_quality(quality: u8, codec: ImageCodec) -> String {
+    match codec {
+        ImageCodec::Avif => return format!("AVIF (ravif), quality {}", quality.min(100)),
+        ImageCodec::Jpeg => return format!("JPEG, quality {}", quality.min(100)),
+        ImageCodec::Webp => {}
+    }
+    match webp_setting(quality) {
+        WebpSetting::Lossless => "WebP lossless".to_string(),
+        WebpSetting::Lossy(q) => format!("WebP lossy, quality {q}"),
+    }
+}
+
+/// Image file extensions (lowercase) the pipeline compresses
+pub const SUPPORTED_EXTENSIONS: &[&str] =
+    &["jpg", "jpeg", "png", "webp", "gif", "bmp", "tif", "tiff"];
+
+/// HEIC/HEIF extensions (lowercase), decoded through ffmpeg
+pub const HEIC_EXTENSIONS: &[&str] = &["heic", "heif"];
+
+/// iPhone photos, which the `image` crate can't decode
+pub fn is_heic(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| HEIC_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Convert a HEIC image to PNG with ffmpeg (7.1 or newer joins the tiles of
+/// iPhone photos; older versions only see the first one)
+#[cfg(feature = "ffmpeg")]
+fn decode_heic(data: &[u8], ffmpeg_path: Option<&Path>) -> Result<Vec<u8>> {
+    let mut input_temp = tempfile::NamedTempFile::with_suffix(".heic")
+        .context("Failed to create temporary input file")?;
+    input_temp
+        .write_all(data)
+        .context("Failed to write HEIC data to temporary file")?;
+    input_temp
+        .flush()
+        .context("Failed to flush HEIC data to temporary file")?;
+    let output_temp = tempfile::NamedTempFile::with_suffix(".png")
+        .context("Failed to create temporary output file")?;
+
+    let mut ffmpeg_cmd = ffmpeg_path.map_or_else(FfmpegCommand::new, |path| {
+        FfmpegCommand::new_with_path(path)
+    });
+    ffmpeg_cmd
+        .input(input_temp.path().to_string_lossy())
+        .args(["-frames:v", "1", "-c:v", "png", "-y"])
+        .output(output_temp.path().to_string_lossy());
+    let mut child = ffmpeg_cmd
+        .spawn()
+        .context("Failed to spawn ffmpeg to decode HEIC")?;
+    for event in child.iter().context("Failed to create event iterator")? {
+        match event {
+            FfmpegEvent::Error(error_msg) if error_msg.trim() != "No streams found" => {
+                return Err(anyhow!("Failed to decode HEIC: {}", error_msg.trim()));
+            }
+            FfmpegEvent::Done => break,
+            _ => {}
+        }
+    }
+    std::fs::read(output_temp.path()).context("Failed to read decoded HEIC image")
+}
+
+#[cfg(not(feature = "ffmpeg"))]
+fn decode_heic(_data: &[u8], _ffmpeg_path: Option<&Path>) -> Result<Vec<u8>> {
+    Err(SicomError::MissingFeature("ffmpeg").into())
+}
+
+fn is_jpeg_filename(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg"))
+}
 
 pub fn is_supported_image(filename: &str) -> bool {
     let path = Path::new(filename);
     path.extension()
         .and_then(|s| s.to_str())
-        .is_some_and(|ext| matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg" | "png" | "webp"))
+        .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Encode with MozJPEG: progressive with optimized scans and Huffman tables,
+/// no metadata
+#[cfg(feature = "mozjpeg")]
+fn encode_jpeg(rgb: &image::RgbImage, quality: u8) -> Result<Vec<u8>> {
+    // MozJPEG reports errors by unwinding
+    std::panic::catch_unwind(|| -> std::io::Result<Vec<u8>> {
+        let mut compress = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+        compress.set_size(rgb.width() as usize, rgb.height() as usize);
+        compress.set_quality(f32::from(quality));
+        compress.set_progressive_mode();
+        compress.set_optimize_scans(true);
+        compress.set_optimize_coding(true);
+        let mut started = compress.start_compress(Vec::new())?;
+        started.write_scanlines(rgb.as_raw())?;
+        started.finish()
+    })
+    .map_err(|_| anyhow::anyhow!("MozJPEG encoding panicked"))?
+    .context("JPEG encoding failed")
 }
 
-pub fn compress_image_file(
-    data: &[u8],
-    filename: &str,
-    quality: u8,
-) -> Result<(Vec<u8>, u64, u64)> {
-    let original_size = data.len() as u64;
+/// Encode with the image crate's baseline encoder (builds without MozJPEG)
+#[cfg(not(feature = "mozjpeg"))]
+fn encode_jpeg(rgb: &image::RgbImage, quality: u8) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, quality)
+        .write_image(
+            rgb.as_raw(),
+            rgb.width(),
+            rgb.height(),
+            image::ExtendedColorType::Rgb8,
+        )
+        .context("JPEG encoding failed")?;
+    Ok(buffer)
+}
+
+/// GIF frame delays at or below this are shown at [`SLOW_GIF_DELAY_MS`] by browsers
+const FAST_GIF_DELAY_MS: u32 = 10;
+const SLOW_GIF_DELAY_MS: u32 = 100;
+
+/// Compress a JPEG, PNG, WebP, GIF, BMP or TIFF image to WebP or AVIF
+///
+/// The input format is detected from the data. Animated GIFs become animated
+/// WebP whatever the codec, other images a still one. Images larger than `max_dimension` are
+/// scaled down first, keeping their aspect ratio. Returns the encoded WebP file.
+pub fn compress_image(data: &[u8], options: &ImageOptions) -> Result<Vec<u8>> {
+    let quality = options.quality;
+    if !(1..=100).contains(&quality) {
+        return Err(SicomError::InvalidOptions(
+            "Image quality must be between 1 and 100".to_string(),
+        )
+        .into());
+    }
+    if options.effort > MAX_EFFORT {
+        return Err(SicomError::InvalidOptions(format!(
+            "Image effort must be between 0 and {MAX_EFFORT}"
+        ))
+        .into());
+    }
+    if options.max_dimension == Some(0) {
+        return Err(SicomError::InvalidOptions(
+            "Maximum image dimension must be at least 1".to_string(),
+        )
+        .into());
+    }
+
+    let mut config = webp_config(options)?;
+    let source_format = image::guess_format(data).ok();
+    let gif = source_format == Some(image::ImageFormat::Gif);
+    if gif && options.codec != ImageCodec::Jpeg {
+        let frames = image::codecs::gif::GifDecoder::new(Cursor::new(data))
+            .context("Failed to decode GIF")?
+            .into_frames()
+            .collect_frames()
+            .context("Failed to decode GIF frames")?;
+        if frames.len() > 1 {
+            return encode_animation(&frames, &config, options.max_dimension);
+        }
+    }
 
     // Load image (detect format from data, not extension)
-    let img = image::load_from_memory(data)
-        .with_context(|| format!("Failed to decode image: {filename}"))?;
+    let img = decode_image(data, options.auto_orient).context("Failed to decode image")?;
+    let img = match options.max_dimension {
+        Some(limit) if img.width().max(img.height()) > limit => {
+            img.resize(limit, limit, FilterType::Lanczos3)
+        }
+        _ => img,
+    };
+
+    if options.codec == ImageCodec::Avif {
+        let mut buffer = Vec::new();
+        image::codecs::avif::AvifEncoder::new_with_speed_quality(
+            &mut buffer,
+            avif_speed(options.effort),
+            quality,
+        )
+        .write_image(
+            img.to_rgba8().as_raw(),
+            img.width(),
+            img.height(),
+            image::ExtendedColorType::Rgba8,
+        )
+        .context("AVIF encoding failed")?;
+        return Ok(buffer);
+    }
+    if options.codec == ImageCodec::Jpeg {
+        return encode_jpeg(&img.to_rgb8(), quality);
+    }
 
     // Always convert to WebP format for maximum compression
     let compressed_data = {
@@ -28,37 +428,204 @@ pub fn compress_image_file(
         let height = img.height();
         let rgba_img = img.to_rgba8();
 
-        let webp_encoder = webp::Encoder::new(&rgba_img, webp::PixelLayout::Rgba, width, height);
-        if quality >= 95 {
-            // Use lossless for high quality
-            let encoded_data = webp_encoder.encode_lossless();
-            buffer.extend_from_slice(&encoded_data);
-        } else {
-            // Use lossy compression with quality parameter
-            let encoded_data = webp_encoder.encode(f32::from(quality));
-            buffer.extend_from_slice(&encoded_data);
+        // Lossy WebP smears text, UI and cutouts, which lossless WebP keeps crisp
+        let lossy = matches!(webp_setting(quality), WebpSetting::Lossy(_));
+        if lossy && source_format == Some(image::ImageFormat::Png) {
+            match png_graphics(&rgba_img) {
+                Some(PngGraphics::Palette) => {
+                    debug!("Few colors: encoding PNG as lossless WebP");
+                    config.lossless = 1;
+                    config.alpha_compression = 0;
+                    config.quality = 75.0;
+                }
+                Some(PngGraphics::Alpha) => {
+                    debug!("Transparency: encoding PNG as near-lossless WebP");
+                    config.lossless = 1;
+                    config.alpha_compression = 0;
+                    config.quality = 75.0;
+                    config.near_lossless = ALPHA_NEAR_LOSSLESS;
+                }
+                None => {}
+            }
         }
+
+        let webp_encoder = webp::Encoder::new(&rgba_img, webp::PixelLayout::Rgba, width, height);
+        let encoded_data = webp_encoder
+            .encode_advanced(&config)
+            .map_err(|e| anyhow!("WebP encoding failed: {e:?}"))?;
+        buffer.extend_from_slice(&encoded_data);
         buffer
     };
 
-    let compressed_size = compressed_data.len() as u64;
-    Ok((compressed_data, original_size, compressed_size))
+    Ok(compressed_data)
+}
+
+/// Colors up to which a PNG counts as graphics (UI, text panels, diagrams)
+const PALETTE_COLORS: usize = 256;
+
+/// libwebp near-lossless preprocessing for PNGs with transparency (100 is off,
+/// lower changes pixels more to compress better)
+const ALPHA_NEAR_LOSSLESS: i32 = 60;
+
+/// PNGs that lossy WebP encodes with visible artifacts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PngGraphics {
+    /// Few colors: screenshots, text panels, logos
+    Palette,
+    /// Transparent pixels: cutouts and overlays, whose edges lossy alpha blurs
+    Alpha,
+}
+
+fn png_graphics(rgba: &image::RgbaImage) -> Option<PngGraphics> {
+    let mut colors = HashSet::new();
+    let mut palette = true;
+    let mut alpha = false;
+    for pixel in rgba.pixels() {
+        alpha |= pixel[3] < u8::MAX;
+        if palette {
+            colors.insert(pixel.0);
+            palette = colors.len() <= PALETTE_COLORS;
+        }
+        if alpha && !palette {
+            break;
+        }
+    }
+    if palette {
+        Some(PngGraphics::Palette)
+    } else if alpha {
+        Some(PngGraphics::Alpha)
+    } else {
+        None
+    }
+}
+
+/// Decode an image, turned upright by its EXIF orientation when `auto_orient` is set
+fn decode_image(data: &[u8], auto_orient: bool) -> image::ImageResult<image::DynamicImage> {
+    let mut decoder = image::ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_decoder()?;
+    // A broken EXIF block leaves the image as stored
+    let orientation = if auto_orient {
+        decoder
+            .orientation()
+            .unwrap_or(image::metadata::Orientation::NoTransforms)
+    } else {
+        image::metadata::Orientation::NoTransforms
+    };
+    let mut img = image::DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+/// WebP encoder settings for the quality and effort of `options`
+fn webp_config(options: &ImageOptions) -> Result<webp::WebPConfig> {
+    let mut config =
+        webp::WebPConfig::new().map_err(|()| anyhow!("Failed to set up WebP encoder"))?;
+    config.method = i32::from(options.effort);
+    match webp_setting(options.quality) {
+        // Use lossless for high quality
+        WebpSetting::Lossless => {
+            config.lossless = 1;
+            config.alpha_compression = 0;
+            config.quality = 75.0;
+        }
+        // Use lossy compression with quality parameter
+        WebpSetting::Lossy(webp_quality) => {
+            config.alpha_compression = 1;
+            config.quality = webp_quality;
+        }
+    }
+    Ok(config)
+}
+
+/// Encode decoded GIF frames (full canvases) as a looping animated WebP
+///
+/// The encoder has no end timestamp, so the last frame is shown for the
+/// average delay of the others.
+fn encode_animation(
+    frames: &[image::Frame],
+    config: &webp::WebPConfig,
+    max_dimension: Option<u32>,
+) -> Result<Vec<u8>> {
+    let (width, height) = frames[0].buffer().dimensions();
+    let scaled: Vec<image::RgbaImage> = match max_dimension {
+        Some(limit) if width.max(height) > limit => frames
+            .iter()
+            .map(|frame| {
+                image::DynamicImage::from(frame.buffer().clone())
+                    .resize(limit, limit, FilterType::Lanczos3)
+                    .into_rgba8()
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let (width, height) = scaled
+        .first()
+        .map_or((width, height), image::RgbaImage::dimensions);
+    let mut encoder = webp::AnimEncoder::new(width, height, config);
+    let mut timestamp_ms = 0;
+    for (index, frame) in frames.iter().enumerate() {
+        encoder.add_frame(webp::AnimFrame::from_rgba(
+            scaled.get(index).unwrap_or(frame.buffer()),
+            width,
+            height,
+            i32::try_from(timestamp_ms).unwrap_or(i32::MAX),
+        ));
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let delay_ms = numerator / denominator.max(1);
+        timestamp_ms += if delay_ms <= FAST_GIF_DELAY_MS {
+            SLOW_GIF_DELAY_MS
+        } else {
+            delay_ms
+        };
+    }
+    let encoded = encoder
+        .try_encode()
+        .map_err(|e| anyhow!("Animated WebP encoding failed: {e:?}"))?;
+    Ok(encoded.to_vec())
+}
+
+/// Codec of an encoded image: AVIF files start with an ISO-BMFF `ftyp` box,
+/// JPEGs with a start-of-image marker
+pub fn encoded_codec(data: &[u8]) -> ImageCodec {
+    if data.get(4..8) == Some(b"ftyp") {
+        ImageCodec::Avif
+    } else if data.starts_with(&[0xFF, 0xD8]) {
+        ImageCodec::Jpeg
+    } else {
+        ImageCodec::Webp
+    }
+}
+
+/// Entry name of an image encoded with `codec`: JPEGs keep their name (and
+/// `.jpeg` or upper-case extension), other codecs get their own extension
+pub fn output_filename(filename: &str, codec: ImageCodec) -> String {
+    match codec {
+        ImageCodec::Jpeg => filename.to_string(),
+        codec => with_image_extension(filename, codec),
+    }
 }
 
 /// Convert image filename to WebP extension
 pub fn to_webp_filename(filename: &str) -> String {
+    with_image_extension(filename, ImageCodec::Webp)
+}
+
+/// Convert image filename to the extension of `codec`
+pub fn with_image_extension(filename: &str, codec: ImageCodec) -> String {
+    let extension = codec.extension();
     let path = Path::new(filename);
     path.file_stem().and_then(|s| s.to_str()).map_or_else(
         || filename.to_string(),
         |stem| {
             path.parent().map_or_else(
-                || format!("{stem}.webp"),
+                || format!("{stem}.{extension}"),
                 |parent| {
                     if parent == Path::new("") {
                         // Handle case where there's no directory
-                        format!("{stem}.webp")
+                        format!("{stem}.{extension}")
                     } else {
-                        format!("{}/{}.webp", parent.display(), stem)
+                        format!("{}/{}.{extension}", parent.display(), stem)
                     }
                 },
             )
@@ -69,6 +636,7 @@ pub fn to_webp_filename(filename: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::encode_png;
 
     #[test]
     fn test_is_supported_image() {
@@ -77,12 +645,45 @@ mod tests {
         assert!(is_supported_image("Images/test.png"));
         assert!(is_supported_image("Images/test.webp"));
         assert!(is_supported_image("Images/test.JPG"));
-        assert!(!is_supported_image("Images/test.gif"));
-        assert!(!is_supported_image("Images/test.bmp"));
+        assert!(is_supported_image("Images/test.gif"));
+        assert!(is_supported_image("Images/test.BMP"));
+        assert!(is_supported_image("Images/test.tif"));
+        assert!(is_supported_image("Images/test.tiff"));
+        assert!(!is_supported_image("Images/test.svg"));
         assert!(!is_supported_image("Audio/test.mp3"));
         assert!(!is_supported_image("content.xml"));
     }
 
+    #[test]
+    fn test_heic_input() {
+        assert!(is_heic("Images/IMG_0001.HEIC"));
+        assert!(is_heic("Images/photo.heif"));
+        assert!(!is_heic("Images/photo.jpg"));
+
+        let processor = ImageProcessor {
+            options: ImageOptions::new(),
+            ffmpeg_path: Some(PathBuf::from("/nonexistent/ffmpeg")),
+        };
+        assert_eq!(
+            processor.supported("Images/IMG_0001.heic"),
+            cfg!(feature = "ffmpeg")
+        );
+        // Without a working ffmpeg the photo is skipped, not mistaken for another format
+        let result = processor.compress(
+            b"\0\0\0\x18ftypheic",
+            "Images/IMG_0001.heic",
+            &mut ProgressLogger::hidden(),
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            processor
+                .analyze(b"", "Images/IMG_0001.heic")
+                .format
+                .as_deref(),
+            Some("Heic")
+        );
+    }
+
     #[test]
     fn test_to_webp_filename() {
         // Test basic conversion
@@ -107,4 +708,260 @@ mod tests {
         // Test edge cases
         assert_eq!(to_webp_filename("test"), "test.webp");
     }
+
+    #[test]
+    fn test_image_processor_analyze() {
+        let png = encode_png(&image::RgbImage::new(4, 3));
+
+        let processor = ImageProcessor {
+            options: ImageOptions::default(),
+            ffmpeg_path: None,
+        };
+        let info = processor.analyze(&png, "Images/test.png");
+        assert_eq!(info.format.as_deref(), Some("Png"));
+        assert_eq!((info.width, info.height), (Some(4), Some(3)));
+
+        let info = processor.analyze(b"not an image", "Images/test.png");
+        assert_eq!(info.width, None);
+    }
+
+    #[test]
+    fn test_compress_image() {
+        let png = encode_png(&image::RgbImage::from_fn(32, 32, |x, y| {
+            image::Rgb([(x * 8) as u8, (y * 8) as u8, 0])
+        }));
+
+        let webp = compress_image(&png, &ImageOptions::new().quality(60)).unwrap();
+        assert_eq!(&webp[8..12], b"WEBP");
+
+        let fastest = compress_image(&png, &ImageOptions::new().quality(60).effort(0)).unwrap();
+        assert_eq!(&fastest[8..12], b"WEBP");
+        let lossless = compress_image(&png, &ImageOptions::new().quality(95).effort(6)).unwrap();
+        assert_eq!(&lossless[12..16], b"VP8L");
+
+        assert!(compress_image(&png, &ImageOptions::new().quality(0)).is_err());
+        assert!(compress_image(&png, &ImageOptions::new().effort(7)).is_err());
+        assert!(compress_image(b"not an image", &ImageOptions::new()).is_err());
+    }
+
+    #[test]
+    fn test_compress_avif() {
+        let img = image::RgbaImage::from_fn(32, 24, |x, y| {
+            image::Rgba([(x * 8) as u8, (y * 8) as u8, 0, 255])
+        });
+        let mut png = Vec::new();
+        img.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+
+        let options = ImageOptions::new()
+            .codec(ImageCodec::Avif)
+            .effort(MAX_EFFORT);
+        let avif = compress_image(&png, &options).unwrap();
+        assert_eq!(&avif[4..12], b"ftypavif");
+        assert_eq!(encoded_codec(&avif), ImageCodec::Avif);
+
+        let processor = ImageProcessor {
+            options,
+            ffmpeg_path: None,
+        };
+        let output = processor
+            .compress(&png, "Images/photo.png", &mut ProgressLogger::hidden())
+            .unwrap();
+        assert_eq!(output.filename, "Images/photo.avif");
+        assert_eq!(output.codec, "AVIF");
+
+        // There is no animated AVIF encoder
+        let animated = compress_image(&gif(&[[255, 0, 0], [0, 0, 255]]), &options).unwrap();
+        assert_eq!(encoded_codec(&animated), ImageCodec::Webp);
+    }
+
+    #[test]
+    fn test_compress_jpeg() {
+        let img =
+            image::RgbImage::from_fn(32, 24, |x, y| image::Rgb([(x * 8) as u8, (y * 8) as u8, 0]));
+        let mut jpeg = Vec::new();
+        img.write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+            .unwrap();
+
+        let processor = ImageProcessor {
+            options: ImageOptions::new().codec(ImageCodec::Jpeg).quality(30),
+            ffmpeg_path: None,
+        };
+        let output = processor
+            .compress(&jpeg, "Images/photo.JPEG", &mut ProgressLogger::hidden())
+            .unwrap();
+        assert_eq!(output.filename, "Images/photo.JPEG");
+        assert_eq!(encoded_codec(&output.data), ImageCodec::Jpeg);
+        assert!(output.data.len() < jpeg.len());
+        // Only JPEGs are re-encoded; PNGs and GIFs are left for old clients as they are
+        assert!(processor.supported("Images/photo.jpg"));
+        assert!(!processor.supported("Images/logo.png"));
+    }
+
+    #[test]
+    fn test_jpeg_quality_size() {
+        let img = image::RgbImage::from_fn(256, 192, |x, y| {
+            let noise = (x.wrapping_mul(7919) ^ y.wrapping_mul(104_729)) % 32;
+            image::Rgb([(x + noise) as u8, (y + noise) as u8, ((x * y) % 256) as u8])
+        });
+        let mut source = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut source, 95)
+            .encode_image(&img)
+            .unwrap();
+
+        let options = ImageOptions::new().codec(ImageCodec::Jpeg).quality(60);
+        let output = compress_image(&source, &options).unwrap();
+        assert_eq!(encoded_codec(&output), ImageCodec::Jpeg);
+        assert!(output.len() < source.len() / 2);
+
+        // MozJPEG beats the baseline encoder at the same quality
+        if cfg!(feature = "mozjpeg") {
+            let decoded = image::load_from_memory(&source).unwrap().to_rgb8();
+            let mut baseline = Vec::new();
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut baseline, 60)
+                .encode_image(&decoded)
+                .unwrap();
+            assert!(output.len() < baseline.len());
+        }
+    }
+
+    #[test]
+    fn test_compress_bitmaps() {
+        let img = image::RgbImage::from_fn(32, 32, |x, y| {
+            image::Rgb([(x * 8) as u8, (y * 8) as u8, ((x * y) % 256) as u8])
+        });
+        for format in [image::ImageFormat::Bmp, image::ImageFormat::Tiff] {
+            let mut data = Vec::new();
+            img.write_to(&mut Cursor::new(&mut data), format).unwrap();
+            let webp = compress_image(&data, &ImageOptions::new()).unwrap();
+            assert_eq!(&webp[8..12], b"WEBP", "{format:?}");
+            assert!(webp.len() < data.len(), "{format:?}");
+        }
+    }
+
+    /// A JPEG tagged with EXIF orientation 6 (rotate 90° clockwise to display)
+    fn rotated_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut plain = Vec::new();
+        image::RgbImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut plain), image::ImageFormat::Jpeg)
+            .unwrap();
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        exif.extend_from_slice(b"\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0");
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        jpeg.extend_from_slice(&exif);
+        jpeg.extend_from_slice(&plain[2..]);
+        jpeg
+    }
+
+    #[test]
+    fn test_auto_orient() {
+        let jpeg = rotated_jpeg(32, 16);
+        let dimensions = |options: &ImageOptions| {
+            let webp = compress_image(&jpeg, options).unwrap();
+            image::load_from_memory(&webp)
+                .unwrap()
+                .into_rgb8()
+                .dimensions()
+        };
+        assert_eq!(dimensions(&ImageOptions::new()), (16, 32));
+        assert_eq!(
+            dimensions(&ImageOptions::new().auto_orient(false)),
+            (32, 16)
+        );
+    }
+
+    #[test]
+    fn test_png_graphics() {
+        let photo = image::RgbaImage::from_fn(64, 64, |x, y| {
+            image::Rgba([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8, 255])
+        });
+        assert_eq!(png_graphics(&photo), None);
+        let panel = image::RgbaImage::from_fn(64, 64, |x, _| {
+            image::Rgba(if x % 8 < 2 { [0, 0, 0, 255] } else { [255; 4] })
+        });
+        assert_eq!(png_graphics(&panel), Some(PngGraphics::Palette));
+        let mut cutout = photo.clone();
+        cutout.put_pixel(0, 0, image::Rgba([0, 0, 0, 0]));
+        assert_eq!(png_graphics(&cutout), Some(PngGraphics::Alpha));
+
+        // Text panels come out lossless even at a lossy quality
+        let mut png = Vec::new();
+        image::DynamicImage::from(panel)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let webp = compress_image(&png, &ImageOptions::new().quality(40)).unwrap();
+        assert_eq!(&webp[12..16], b"VP8L");
+    }
+
+    #[test]
+    fn test_max_dimension() {
+        let png = encode_png(&image::RgbImage::new(64, 32));
+        let dimensions = |options: &ImageOptions| {
+            let webp = compress_image(&png, options).unwrap();
+            image::load_from_memory(&webp)
+                .unwrap()
+                .into_rgb8()
+                .dimensions()
+        };
+
+        assert_eq!(
+            dimensions(&ImageOptions::new().max_dimension(Some(16))),
+            (16, 8)
+        );
+        // Smaller images are never scaled up
+        assert_eq!(
+            dimensions(&ImageOptions::new().max_dimension(Some(100))),
+            (64, 32)
+        );
+        assert!(compress_image(&png, &ImageOptions::new().max_dimension(Some(0))).is_err());
+
+        let animated = compress_image(
+            &gif(&[[255, 0, 0], [0, 255, 0]]),
+            &ImageOptions::new().max_dimension(Some(8)),
+        )
+        .unwrap();
+        let decoded = webp::AnimDecoder::new(&animated).decode().unwrap();
+        assert_eq!(decoded.get_frame(0).map(|frame| frame.width()), Some(8));
+    }
+
+    fn gif(frames: &[[u8; 3]]) -> Vec<u8> {
+        use image::codecs::gif::{GifEncoder, Repeat};
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            encoder.set_repeat(Repeat::Infinite).unwrap();
+            for &color in frames {
+                let [r, g, b] = color;
+                let buffer = image::RgbaImage::from_pixel(16, 16, image::Rgba([r, g, b, 255]));
+                let delay = image::Delay::from_numer_denom_ms(50, 1);
+                encoder
+                    .encode_frame(image::Frame::from_parts(buffer, 0, 0, delay))
+                    .unwrap();
+            }
+        }
+        gif
+    }
+
+    #[test]
+    fn test_compress_gif() {
+        let animated = compress_image(
+            &gif(&[[255, 0, 0], [0, 255, 0], [0, 0, 255]]),
+            &ImageOptions::new(),
+        )
+        .unwrap();
+        assert_eq!(&animated[8..12], b"WEBP");
+        let decoded = webp::AnimDecoder::new(&animated).decode().unwrap();
+        assert!(decoded.has_animation());
+        assert_eq!(decoded.len(), 3);
+
+        // A single-frame GIF is just an image
+        let still = compress_image(&gif(&[[255, 0, 0]]), &ImageOptions::new()).unwrap();
+        assert!(
+            !webp::AnimDecoder::new(&still)
+                .decode()
+                .unwrap()
+                .has_animation()
+        );
+    }
 }
@@ -0,0 +1,496 @@
+use crate::error::SicomError;
+use crate::language;
+use crate::media::MediaKind;
+use crate::options::CompressOptions;
+use crate::pack::{self, Package};
+use crate::{build_registry, format_size, open_pack};
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Pack entries indexed by URL-decoded name, with their uncompressed sizes
+pub(crate) struct EntryIndex {
+    entries: HashMap<String, (String, u64)>,
+}
+
+impl EntryIndex {
+    pub(crate) fn new<'a>(entries: impl Iterator<Item = (&'a str, u64)>) -> Self {
+        let entries = entries
+            .map(|(name, size)| {
+                let decoded = urlencoding::decode(name)
+                    .map(|decoded| decoded.into_owned())
+                    .unwrap_or_else(|_| name.to_string());
+                (decoded, (name.to_string(), size))
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Resolve a media reference to its archive entry name and size
+    pub(crate) fn resolve(&self, media: &pack::MediaRef) -> Option<&(String, u64)> {
+        self.entries
+            .get(&format!("{}{}", media.kind.directory(), media.name))
+    }
+
+    /// Entry a reference would resolve to if its name were URL-decoded first
+    pub(crate) fn resolve_decoded(&self, media: &pack::MediaRef) -> Option<&(String, u64)> {
+        let decoded = urlencoding::decode(&media.name).ok()?;
+        (decoded != media.name)
+            .then(|| {
+                self.entries
+                    .get(&format!("{}{decoded}", media.kind.directory()))
+            })
+            .flatten()
+    }
+}
+
+/// Media needed by one theme
+#[derive(Debug, Serialize)]
+pub struct MediaEntry {
+    pub entry: String,
+    pub kind: &'static str,
+    /// Uncompressed size (None when the referenced file is missing from the pack)
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThemeReport {
+    pub name: String,
+    pub questions: usize,
+    pub media: Vec<MediaEntry>,
+    pub total_size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoundReport {
+    pub name: String,
+    pub themes: Vec<ThemeReport>,
+    pub total_size: u64,
+}
+
+/// Round/theme -> media mapping for prefetching
+#[derive(Debug, Serialize)]
+pub struct ThemeMediaReport {
+    pub package: String,
+    pub rounds: Vec<RoundReport>,
+}
+
+/// Totals for one media kind
+#[derive(Debug, Default, Serialize)]
+pub struct MediaTotals {
+    pub files: usize,
+    pub size: u64,
+}
+
+/// Entries listed under "Largest files"
+const LARGEST_FILES: usize = 10;
+
+/// One archive entry and its uncompressed size
+#[derive(Debug, Serialize)]
+pub struct FileSize {
+    pub entry: String,
+    pub size: u64,
+}
+
+/// Host reading speed for question text, in words per second
+const READING_WORDS_PER_SECOND: f64 = 2.5;
+/// Time per question spent buzzing in, answering and scoring
+const ANSWER_SECONDS: f64 = 20.0;
+
+/// Question text statistics
+#[derive(Debug, Default, Serialize)]
+pub struct TextStats {
+    pub words: usize,
+    pub characters: usize,
+    pub average_question_words: f64,
+    pub average_question_characters: f64,
+}
+
+/// Overall pack summary
+#[derive(Debug, Serialize)]
+pub struct PackSummary {
+    pub package: String,
+    pub rounds: usize,
+    pub themes: usize,
+    pub questions: usize,
+    /// Questions per detected language (ISO 639-3 codes)
+    pub languages: BTreeMap<&'static str, usize>,
+    pub text: TextStats,
+    /// Playback time of the audio and video referenced by questions
+    pub media_seconds: f64,
+    /// Rough game length: reading, media playback and answering for every question
+    pub estimated_duration_seconds: u64,
+    pub images: MediaTotals,
+    pub audio: MediaTotals,
+    pub video: MediaTotals,
+    pub total_size: u64,
+    /// Biggest entries first, where compression pays off most
+    pub largest: Vec<FileSize>,
+}
+
+fn build_theme_report(package: &Package, index: &EntryIndex) -> ThemeMediaReport {
+    let rounds = package
+        .rounds
+        .iter()
+        .map(|round| {
+            let themes: Vec<ThemeReport> = round
+                .themes
+                .iter()
+                .map(|theme| {
+                    let mut media: Vec<MediaEntry> = Vec::new();
+                    for media_ref in theme.questions.iter().flat_map(|q| &q.media) {
+                        let (entry, size) = match index.resolve(media_ref) {
+                            Some((entry, size)) => (entry.clone(), Some(*size)),
+                            None => (media_ref.entry_name(), None),
+                        };
+                        // Each file is fetched once per theme
+                        if !media.iter().any(|existing| existing.entry == entry) {
+                            media.push(MediaEntry {
+                                entry,
+                                kind: media_ref.kind.label(),
+                                size,
+                            });
+                        }
+                    }
+                    let total_size = media.iter().filter_map(|m| m.size).sum();
+                    ThemeReport {
+                        name: theme.name.clone(),
+                        questions: theme.questions.len(),
+                        media,
+                        total_size,
+                    }
+                })
+                .collect();
+            let total_size = themes.iter().map(|theme| theme.total_size).sum();
+            RoundReport {
+                name: round.name.clone(),
+                themes,
+                total_size,
+            }
+        })
+        .collect();
+
+    ThemeMediaReport {
+        package: package.name.clone(),
+        rounds,
+    }
+}
+
+fn build_summary(
+    package: &Package,
+    index: &EntryIndex,
+    durations: &HashMap<String, f64>,
+) -> PackSummary {
+    let mut text = TextStats::default();
+    let mut media_seconds = 0.0;
+    let mut questions = 0;
+    for (_, _, question) in package.questions() {
+        questions += 1;
+        text.words += question.text.split_whitespace().count();
+        text.characters += question.text.chars().count();
+        // Media replays for every question that uses it
+        media_seconds += question
+            .media
+            .iter()
+            .filter_map(|media| index.resolve(media))
+            .filter_map(|(entry, _)| durations.get(entry))
+            .sum::<f64>();
+    }
+    if questions > 0 {
+        text.average_question_words = text.words as f64 / questions as f64;
+        text.average_question_characters = text.characters as f64 / questions as f64;
+    }
+    let estimated_duration_seconds = (text.words as f64 / READING_WORDS_PER_SECOND
+        + media_seconds
+        + questions as f64 * ANSWER_SECONDS)
+        .round() as u64;
+
+    let mut summary = PackSummary {
+        package: package.name.clone(),
+        rounds: package.rounds.len(),
+        themes: package.rounds.iter().map(|round| round.themes.len()).sum(),
+        questions,
+        languages: language::language_counts(package),
+        text,
+        media_seconds,
+        estimated_duration_seconds,
+        images: MediaTotals::default(),
+        audio: MediaTotals::default(),
+        video: MediaTotals::default(),
+        total_size: 0,
+        largest: Vec::new(),
+    };
+
+    let mut files: Vec<&(String, u64)> = index.entries.values().collect();
+    files.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    summary.largest = files
+        .into_iter()
+        .take(LARGEST_FILES)
+        .map(|(entry, size)| FileSize {
+            entry: entry.clone(),
+            size: *size,
+        })
+        .collect();
+
+    for (name, size) in index.entries.values() {
+        summary.total_size += size;
+        let totals = if name.starts_with(MediaKind::Image.directory()) {
+            &mut summary.images
+        } else if name.starts_with(MediaKind::Audio.directory()) {
+            &mut summary.audio
+        } else if name.starts_with(MediaKind::Video.directory()) {
+            &mut summary.video
+        } else {
+            continue;
+        };
+        totals.files += 1;
+        totals.size += size;
+    }
+    summary
+}
+
+/// Format seconds as "1 h 05 min" / "12 min"
+fn format_duration(seconds: u64) -> String {
+    let minutes = (seconds + 30) / 60;
+    if minutes >= 60 {
+        format!("{} h {:02} min", minutes / 60, minutes % 60)
+    } else {
+        format!("{minutes} min")
+    }
+}
+
+/// Playback duration of every audio and video entry, from its metadata
+fn media_durations(archive: &mut ZipArchive<BufReader<File>>) -> Result<HashMap<String, f64>> {
+    let registry = build_registry(&CompressOptions::new(), false);
+    let mut durations = HashMap::new();
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .with_context(|| format!("Failed to read file at index {i}"))?;
+        let file_name = file.name().to_string();
+        let Some((processor, _)) = registry.find(&file_name) else {
+            continue;
+        };
+        if processor.kind() == MediaKind::Image {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)
+            .with_context(|| format!("Failed to read entry data: {file_name}"))?;
+        if let Some(seconds) = processor.analyze(&data, &file_name).duration_seconds {
+            durations.insert(file_name, seconds);
+        }
+    }
+    Ok(durations)
+}
+
+fn print_theme_report(report: &ThemeMediaReport) {
+    println!("Package: {}", report.package);
+    for round in &report.rounds {
+        println!();
+        println!("{} ({})", round.name, format_size(round.total_size));
+        for theme in &round.themes {
+            println!(
+                "  {} ({} media, {})",
+                theme.name,
+                theme.media.len(),
+                format_size(theme.total_size)
+            );
+            for media in &theme.media {
+                let size = media
+                    .size
+                    .map_or_else(|| "missing".to_string(), format_size);
+                println!("    {:<50} {size}", media.entry);
+            }
+        }
+    }
+}
+
+fn print_summary(summary: &PackSummary) {
+    println!("Package: {}", summary.package);
+    println!("Rounds: {}", summary.rounds);
+    println!("Themes: {}", summary.themes);
+    println!("Questions: {}", summary.questions);
+    println!(
+        "Question text: {} words, {} characters (average {:.1} words, {:.0} characters)",
+        summary.text.words,
+        summary.text.characters,
+        summary.text.average_question_words,
+        summary.text.average_question_characters
+    );
+    if !summary.languages.is_empty() {
+        // Most common language first
+        let mut languages: Vec<_> = summary.languages.iter().collect();
+        languages.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let languages: Vec<String> = languages
+            .into_iter()
+            .map(|(language, count)| format!("{language} {count}"))
+            .collect();
+        println!("Languages: {}", languages.join(", "));
+    }
+    for (label, totals) in [
+        ("Images", &summary.images),
+        ("Audio", &summary.audio),
+        ("Video", &summary.video),
+    ] {
+        println!(
+            "{label}: {} files, {}",
+            totals.files,
+            format_size(totals.size)
+        );
+    }
+    println!("Total size: {}", format_size(summary.total_size));
+    println!(
+        "Media playback: {}",
+        format_duration(summary.media_seconds.round() as u64)
+    );
+    println!(
+        "Estimated game duration: ~{}",
+        format_duration(summary.estimated_duration_seconds)
+    );
+    if !summary.largest.is_empty() {
+        println!("Largest files:");
+        for file in &summary.largest {
+            println!("  {:<50} {}", file.entry, format_size(file.size));
+        }
+    }
+}
+
+/// Print pack structure and media statistics
+pub fn inspect_pack(input_pack: &Path, by_theme: bool, json: bool) -> Result<()> {
+    if !input_pack.exists() {
+        return Err(SicomError::InputNotFound(input_pack.to_path_buf()).into());
+    }
+
+    let mut archive = open_pack(input_pack)?;
+
+    let mut xml = String::new();
+    archive
+        .by_name("content.xml")
+        .map_err(|_| anyhow!("No content.xml found in pack"))?
+        .read_to_string(&mut xml)
+        .with_context(|| "Failed to read content.xml as UTF-8")?;
+    let package = pack::parse_package(&xml)?;
+
+    let mut entries = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let file = archive
+            .by_index(i)
+            .with_context(|| format!("Failed to read file at index {i}"))?;
+        entries.push((file.name().to_string(), file.size()));
+    }
+    let index = EntryIndex::new(entries.iter().map(|(name, size)| (name.as_str(), *size)));
+
+    if by_theme {
+        let report = build_theme_report(&package, &index);
+        if json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            print_theme_report(&report);
+        }
+    } else {
+        let durations = media_durations(&mut archive)?;
+        let summary = build_summary(&package, &index, &durations);
+        if json {
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        } else {
+            print_summary(&summary);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::tests::SAMPLE_V5;
+
+    const ENTRIES: &[(&str, u64)] = &[
+        ("content.xml", 2_000),
+        ("Images/%D0%92%D0%94%D0%9D%D0%A5.jpg", 50_000),
+        ("Audio/anthem.mp3", 300_000),
+        ("Images/unused.png", 1_000),
+    ];
+
+    #[test]
+    fn test_theme_report() {
+        let package = pack::parse_package(SAMPLE_V5).unwrap();
+        let report = build_theme_report(&package, &EntryIndex::new(ENTRIES.iter().copied()));
+
+        let moscow = &report.rounds[0].themes[0];
+        assert_eq!(moscow.questions, 2);
+        assert_eq!(moscow.media.len(), 2);
+        assert_eq!(moscow.media[0].entry, "Images/%D0%92%D0%94%D0%9D%D0%A5.jpg");
+        assert_eq!(moscow.media[0].kind, "image");
+        assert_eq!(moscow.media[1].size, Some(300_000));
+        assert_eq!(moscow.total_size, 350_000);
+        assert_eq!(report.rounds[0].total_size, 350_000);
+
+        // Referenced but missing from the archive
+        let cinema = &report.rounds[1].themes[0];
+        assert_eq!(cinema.media[0].entry, "Video/clip.mp4");
+        assert_eq!(cinema.media[0].size, None);
+        assert_eq!(cinema.total_size, 0);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["rounds"][0]["themes"][0]["media"][1]["size"], 300_000);
+        assert!(json["rounds"][1]["themes"][0]["media"][0]["size"].is_null());
+    }
+
+    #[test]
+    fn test_summary() {
+        let package = pack::parse_package(SAMPLE_V5).unwrap();
+        let durations = HashMap::from([("Audio/anthem.mp3".to_string(), 30.0)]);
+        let summary = build_summary(
+            &package,
+            &EntryIndex::new(ENTRIES.iter().copied()),
+            &durations,
+        );
+
+        assert_eq!(summary.rounds, 2);
+        assert_eq!(summary.themes, 2);
+        assert_eq!(summary.questions, 3);
+        // Sample questions are too short for a reliable guess
+        assert_eq!(summary.languages.values().sum::<usize>(), 3);
+        assert_eq!(summary.images.files, 2);
+        assert_eq!(summary.images.size, 51_000);
+        assert_eq!(summary.audio.files, 1);
+        assert_eq!(summary.video.files, 0);
+        assert_eq!(summary.total_size, 353_000);
+        let largest: Vec<(&str, u64)> = summary
+            .largest
+            .iter()
+            .map(|file| (file.entry.as_str(), file.size))
+            .collect();
+        assert_eq!(
+            largest,
+            [
+                ("Audio/anthem.mp3", 300_000),
+                ("Images/%D0%92%D0%94%D0%9D%D0%A5.jpg", 50_000),
+                ("content.xml", 2_000),
+                ("Images/unused.png", 1_000),
+            ]
+        );
+
+        // "Name this place" is the only question text
+        assert_eq!(summary.text.words, 3);
+        assert_eq!(summary.text.characters, 15);
+        assert_eq!(summary.text.average_question_words, 1.0);
+        assert_eq!(summary.media_seconds, 30.0);
+        // 3 words read + 30 s of audio + 3 answers
+        assert_eq!(summary.estimated_duration_seconds, 91);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0 min");
+        assert_eq!(format_duration(12 * 60 + 10), "12 min");
+        assert_eq!(format_duration(3600 + 5 * 60), "1 h 05 min");
+    }
+}
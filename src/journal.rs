@@ -0,0 +1,518 @@
+use crate::error::SicomError;
+use crate::manifest::{self, ManifestEntry};
+use crate::options::CompressOptions;
+use crate::pipeline::{self, EntryMetadata};
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use zip::ZipWriter;
+
+/// Bumped when the layout changes; journals of other versions are discarded
+const JOURNAL_VERSION: u32 = 1;
+
+/// Append-only record file inside the journal directory
+const RECORDS_FILE: &str = "journal.jsonl";
+
+/// Entries written between two syncs of the journal
+const CHECKPOINT_ENTRIES: usize = 64;
+/// Entry data written between two syncs of the journal
+const CHECKPOINT_BYTES: u64 = 64 * 1024 * 1024;
+
+/// One line of the record file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum Record {
+    /// First line: the input and settings the journal was written for
+    Header {
+        version: u32,
+        input_size: u64,
+        input_modified: Option<u64>,
+        options: serde_json::Value,
+    },
+    /// An output entry whose data is complete in entry file `file`
+    Entry { name: String, file: u64, size: u64 },
+    /// An input media entry is done; its outputs are recorded above
+    Media {
+        input: String,
+        result: ManifestEntry,
+    },
+}
+
+/// Header line for `input` compressed with `options`
+fn header(input: &Path, options: &CompressOptions) -> Result<Record> {
+    let metadata = fs::metadata(input).map_err(|source| SicomError::Io {
+        path: input.to_path_buf(),
+        source,
+    })?;
+    Ok(Record::Header {
+        version: JOURNAL_VERSION,
+        input_size: metadata.len(),
+        input_modified: metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs()),
+        options: manifest::options_key(options),
+    })
+}
+
+/// Output pack under construction, written through a journal
+///
+/// Every entry goes to a file of its own in `<output>.journal/`. The ZIP is
+/// only assembled from the journal once all entries are in, into a temp file
+/// renamed over the output, so a crash leaves a journal behind but never a
+/// half-written pack.
+///
+/// Runs with `--resume` also record the entries and finished media in an
+/// append-only list, synced to disk at checkpoints (every
+/// [`CHECKPOINT_ENTRIES`] entries or [`CHECKPOINT_BYTES`] of data) after the
+/// entry files they describe. If such a run is interrupted, the next one with
+/// `--resume` takes the media results from the journal instead of compressing
+/// them again, losing at most the work since the last checkpoint.
+pub struct Journal {
+    directory: PathBuf,
+    /// Record file; None when the run can't be resumed
+    records: Option<File>,
+    /// Records waiting for the next checkpoint
+    pending: Vec<u8>,
+    /// Entry files written since the last checkpoint, and their total size
+    unsynced: Vec<File>,
+    unsynced_bytes: u64,
+    /// Output entries in first-written order, with the file of their latest data
+    entries: Vec<(String, u64)>,
+    positions: HashMap<String, usize>,
+    /// Entries written or resumed by this run; leftovers of the interrupted
+    /// run that it doesn't produce again stay out of the pack
+    used: HashSet<String>,
+    /// Media results of the interrupted run being resumed
+    resumed: HashMap<String, ManifestEntry>,
+    next_file: u64,
+}
+
+impl Journal {
+    /// Journal location for an output pack (`pack.siq` -> `pack.siq.journal`)
+    pub fn path_for(output: &Path) -> PathBuf {
+        manifest::with_suffix(output, ".journal")
+    }
+
+    /// Start journaling the output of `input`, picking up an interrupted run's
+    /// journal when `options.resume` is set and it was written for the same
+    /// input and settings
+    pub fn create(output: &Path, input: &Path, options: &CompressOptions) -> Result<Self> {
+        let directory = Self::path_for(output);
+        let header = header(input, options)?;
+        if directory.exists() {
+            if options.resume {
+                if let Some(journal) = Self::open(&directory, &header)? {
+                    info!(
+                        "Resuming an interrupted run: {} media entries already done",
+                        journal.resumed.len()
+                    );
+                    return Ok(journal);
+                }
+                warn!(
+                    "The journal was written without --resume, or for another input or other settings, starting over"
+                );
+            } else {
+                warn!(
+                    "Discarding the journal of an interrupted run at {} (use --resume to continue it)",
+                    directory.display()
+                );
+            }
+            fs::remove_dir_all(&directory).map_err(|source| SicomError::Io {
+                path: directory.clone(),
+                source,
+            })?;
+        } else if options.resume {
+            info!("No interrupted run to resume, compressing everything");
+        }
+
+        fs::create_dir_all(&directory).map_err(|source| SicomError::Io {
+            path: directory.clone(),
+            source,
+        })?;
+        let records = if options.resume {
+            let path = directory.join(RECORDS_FILE);
+            Some(File::create(&path).map_err(|source| SicomError::Io { path, source })?)
+        } else {
+            None
+        };
+        let mut journal = Self {
+            directory,
+            records,
+            pending: Vec::new(),
+            unsynced: Vec::new(),
+            unsynced_bytes: 0,
+            entries: Vec::new(),
+            positions: HashMap::new(),
+            used: HashSet::new(),
+            resumed: HashMap::new(),
+            next_file: 0,
+        };
+        journal.append(&header);
+        journal.checkpoint()?;
+        Ok(journal)
+    }
+
+    /// Read an existing journal; None when it belongs to another header
+    ///
+    /// A torn last line (the crash hit while it was written) is cut off, and
+    /// entries whose data file doesn't hold the recorded size are dropped.
+    fn open(directory: &Path, expected: &Record) -> Result<Option<Self>> {
+        let path = directory.join(RECORDS_FILE);
+        let Ok(file) = File::open(&path) else {
+            return Ok(None);
+        };
+        let mut lines = BufReader::new(file).lines();
+        let header = lines
+            .next()
+            .and_then(|line| serde_json::from_str::<Record>(&line.ok()?).ok());
+        if header.as_ref() != Some(expected) {
+            return Ok(None);
+        }
+
+        let mut journal = Self {
+            directory: directory.to_path_buf(),
+            records: Some(
+                OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .map_err(|source| SicomError::Io {
+                        path: path.clone(),
+                        source,
+                    })?,
+            ),
+            pending: Vec::new(),
+            unsynced: Vec::new(),
+            unsynced_bytes: 0,
+            entries: Vec::new(),
+            positions: HashMap::new(),
+            used: HashSet::new(),
+            resumed: HashMap::new(),
+            next_file: 0,
+        };
+        let mut valid_len = serde_json::to_string(expected)?.len() as u64 + 1;
+        for line in lines {
+            let Ok(line) = line else { break };
+            let Ok(record) = serde_json::from_str::<Record>(&line) else {
+                debug!("Ignoring a torn journal record");
+                break;
+            };
+            valid_len += line.len() as u64 + 1;
+            match record {
+                Record::Entry { name, file, size } => {
+                    journal.next_file = journal.next_file.max(file + 1);
+                    if fs::metadata(journal.entry_path(file)).is_ok_and(|m| m.len() == size) {
+                        journal.insert_entry(name, file);
+                    }
+                }
+                Record::Media { input, result } => {
+                    journal.resumed.insert(input, result);
+                }
+                Record::Header { .. } => break,
+            }
+        }
+        // Appending after a torn line would hide everything written from now on
+        if let Some(records) = &journal.records {
+            records
+                .set_len(valid_len)
+                .context("Failed to truncate the journal")?;
+        }
+        Ok(Some(journal))
+    }
+
+    fn entry_path(&self, file: u64) -> PathBuf {
+        self.directory.join(format!("{file:06}.bin"))
+    }
+
+    fn insert_entry(&mut self, name: String, file: u64) {
+        match self.positions.get(&name) {
+            Some(&position) => self.entries[position].1 = file,
+            None => {
+                self.positions.insert(name.clone(), self.entries.len());
+                self.entries.push((name, file));
+            }
+        }
+    }
+
+    /// Queue a record for the next checkpoint (when the run can be resumed)
+    fn append(&mut self, record: &Record) {
+        if self.records.is_some() {
+            serde_json::to_writer(&mut self.pending, record).expect("records serialize");
+            self.pending.push(b'\n');
+        }
+    }
+
+    /// Sync the entry files written since the last checkpoint, then write and
+    /// sync the records describing them, so records only ever describe data
+    /// already on disk
+    fn checkpoint(&mut self) -> Result<()> {
+        let Some(records) = &mut self.records else {
+            return Ok(());
+        };
+        for entry in self.unsynced.drain(..) {
+            entry.sync_all().context("Failed to sync the journal")?;
+        }
+        self.unsynced_bytes = 0;
+        if !self.pending.is_empty() {
+            records
+                .write_all(&self.pending)
+                .and_then(|()| records.sync_data())
+                .context("Failed to write the journal")?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+
+    /// Add an output entry; writing a name again replaces its data
+    pub fn write_entry(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        let file = self.next_file;
+        self.next_file += 1;
+        let path = self.entry_path(file);
+        let entry = File::create(&path)
+            .and_then(|mut entry| entry.write_all(data).map(|()| entry))
+            .map_err(|source| SicomError::Io { path, source })?;
+        self.append(&Record::Entry {
+            name: name.to_string(),
+            file,
+            size: data.len() as u64,
+        });
+        self.insert_entry(name.to_string(), file);
+        self.used.insert(name.to_string());
+
+        if self.records.is_some() {
+            self.unsynced.push(entry);
+            self.unsynced_bytes += data.len() as u64;
+            if self.unsynced.len() >= CHECKPOINT_ENTRIES || self.unsynced_bytes >= CHECKPOINT_BYTES
+            {
+                self.checkpoint()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Names and sizes of the entries the output pack will hold, in order
+    pub fn entries(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.entries
+            .iter()
+            .filter(|(name, _)| self.used.contains(name))
+            .map(|(name, file)| {
+                let size = fs::metadata(self.entry_path(*file)).map_or(0, |m| m.len());
+                (name.as_str(), size)
+            })
+    }
+
+    /// Latest data written for an entry
+    pub fn read_entry(&self, name: &str) -> Result<Vec<u8>> {
+        let position = self
+            .positions
+            .get(name)
+            .with_context(|| format!("{name} is not in the journal"))?;
+        let file = self.entries[*position].1;
+        fs::read(self.entry_path(file))
+            .with_context(|| format!("Failed to read {name} from the journal"))
+    }
+
+    /// Mark an input media entry as done once its outputs are written
+    pub fn record_media(&mut self, input: &str, result: &ManifestEntry) {
+        self.append(&Record::Media {
+            input: input.to_string(),
+            result: result.clone(),
+        });
+    }
+
+    /// Result of the resumed run for an input media entry with unchanged CRC and size
+    pub fn resumed(&mut self, input: &str, crc32: u32, size: u64) -> Option<ManifestEntry> {
+        let result = self.resumed.get(input)?;
+        if result.crc32 != crc32
+            || result.size != size
+            || !self.positions.contains_key(&result.output)
+        {
+            return None;
+        }
+        self.used.insert(result.output.clone());
+        Some(result.clone())
+    }
+
+    /// Assemble the output pack from the journal and remove the journal,
+    /// deflating compressible entries at `zip_level`; entries made from an
+    /// input entry get its timestamp and permissions from `metadata`
+    pub fn finish(
+        self,
+        output: &Path,
+        zip_level: Option<u8>,
+        metadata: &HashMap<String, EntryMetadata>,
+    ) -> Result<()> {
+        let partial = manifest::with_suffix(output, ".partial");
+        let file = File::create(&partial).map_err(|source| SicomError::Io {
+            path: partial.clone(),
+            source,
+        })?;
+        let mut zip_writer = ZipWriter::new(BufWriter::new(file));
+        for (name, file) in self
+            .entries
+            .iter()
+            .filter(|(name, _)| self.used.contains(name))
+        {
+            let data = fs::read(self.entry_path(*file))
+                .with_context(|| format!("Failed to read {name} from the journal"))?;
+            let options = pipeline::entry_options(name, zip_level);
+            let options = metadata
+                .get(name)
+                .map_or(options, |source| source.apply(options));
+            zip_writer
+                .start_file(name, options)
+                .with_context(|| format!("Failed to start file in output ZIP: {name}"))?;
+            zip_writer
+                .write_all(&data)
+                .with_context(|| format!("Failed to write file: {name}"))?;
+        }
+        let file = zip_writer
+            .finish()
+            .with_context(|| "Failed to finalize output ZIP")?
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .context("Failed to flush output ZIP")?;
+        file.sync_all().context("Failed to flush output ZIP")?;
+        drop(file);
+
+        fs::rename(&partial, output).map_err(|source| SicomError::Io {
+            path: output.to_path_buf(),
+            source,
+        })?;
+        self.discard();
+        Ok(())
+    }
+
+    /// Throw the journal away without producing an output
+    pub fn discard(mut self) {
+        self.records = None;
+        if let Err(e) = fs::remove_dir_all(&self.directory) {
+            warn!("Failed to remove journal {}: {e}", self.directory.display());
+        }
+    }
+}
+
+impl Drop for Journal {
+    /// A run that stops with an error keeps everything it finished for `--resume`
+    fn drop(&mut self) {
+        if let Err(e) = self.checkpoint() {
+            warn!("Failed to save the journal: {e:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::EntryOutcome;
+
+    #[test]
+    fn test_crash_and_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.siq");
+        fs::write(&input, b"input pack").unwrap();
+        let output = dir.path().join("out.siq");
+        let options = CompressOptions::new();
+        let resume = options.clone().resume(true);
+
+        let mut journal = Journal::create(&output, &input, &resume).unwrap();
+        journal.write_entry("Images/a.webp", b"webp").unwrap();
+        let result = ManifestEntry::new(
+            7,
+            100,
+            EntryOutcome::Compressed,
+            "Images/a.webp".to_string(),
+        );
+        journal.record_media("Images/a.png", &result);
+        journal.write_entry("Images/b.webp", b"bbbb").unwrap();
+        journal.checkpoint().unwrap();
+        // The process dies mid-record, losing what came after the checkpoint
+        journal.write_entry("Images/c.webp", b"cccc").unwrap();
+        let records = Journal::path_for(&output).join(RECORDS_FILE);
+        let mut file = OpenOptions::new().append(true).open(&records).unwrap();
+        file.write_all(b"{\"record\":\"media\",\"inp").unwrap();
+        std::mem::forget(journal);
+        assert!(!output.exists());
+
+        // Resuming keeps finished media and the torn line doesn't hide new records
+        let mut journal = Journal::create(&output, &input, &resume).unwrap();
+        assert_eq!(
+            journal.resumed("Images/a.png", 7, 100),
+            Some(result.clone())
+        );
+        assert_eq!(journal.resumed("Images/a.png", 8, 100), None);
+        assert!(!journal.positions.contains_key("Images/c.webp"));
+        // A run stopping with an error saves what it finished
+        journal.write_entry("content.xml", b"<package/>").unwrap();
+        drop(journal);
+        let mut journal = Journal::create(&output, &input, &resume).unwrap();
+        assert_eq!(journal.entries.len(), 3);
+        assert!(journal.resumed("Images/a.png", 7, 100).is_some());
+        journal.write_entry("Images/a.webp", b"webp2").unwrap();
+        journal.write_entry("content.xml", b"<package/>").unwrap();
+
+        // b.webp wasn't produced again, so it's left out
+        journal.finish(&output, None, &HashMap::new()).unwrap();
+        assert!(!Journal::path_for(&output).exists());
+        let mut archive = crate::open_pack(&output).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(names, ["Images/a.webp", "content.xml"]);
+        let mut data = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("Images/a.webp").unwrap(), &mut data)
+            .unwrap();
+        assert_eq!(data, "webp2");
+
+        // Without --resume nothing is recorded, so there is nothing to resume
+        let mut journal = Journal::create(&output, &input, &options).unwrap();
+        journal.write_entry("Images/a.webp", b"webp").unwrap();
+        journal.record_media("Images/a.png", &result);
+        drop(journal);
+        assert!(!records.exists());
+        let journal = Journal::create(&output, &input, &resume).unwrap();
+        assert!(journal.resumed.is_empty());
+        drop(journal);
+
+        // Nor is a journal written for another input
+        let mut journal = Journal::create(&output, &input, &resume).unwrap();
+        journal.record_media("Images/a.png", &result);
+        drop(journal);
+        fs::write(&input, b"another input pack").unwrap();
+        let journal = Journal::create(&output, &input, &resume).unwrap();
+        assert!(journal.resumed.is_empty());
+        journal.discard();
+        assert!(!Journal::path_for(&output).exists());
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.siq");
+        fs::write(&input, b"input pack").unwrap();
+        let output = dir.path().join("out.siq");
+        let records = Journal::path_for(&output).join(RECORDS_FILE);
+        let lines = || fs::read_to_string(&records).unwrap().lines().count();
+
+        let options = CompressOptions::new().resume(true);
+        let mut journal = Journal::create(&output, &input, &options).unwrap();
+        assert_eq!(lines(), 1);
+        for i in 1..CHECKPOINT_ENTRIES {
+            journal.write_entry(&format!("{i}.bin"), b"data").unwrap();
+        }
+        assert_eq!(lines(), 1);
+        journal.write_entry("last.bin", b"data").unwrap();
+        assert_eq!(lines(), 1 + CHECKPOINT_ENTRIES);
+
+        // Large entries reach a checkpoint sooner
+        let large = vec![0; usize::try_from(CHECKPOINT_BYTES).unwrap()];
+        journal.write_entry("large.bin", &large).unwrap();
+        assert_eq!(lines(), 2 + CHECKPOINT_ENTRIES);
+        journal.discard();
+    }
+}
@@ -0,0 +1,100 @@
+use crate::pack::{Package, Question};
+use std::collections::BTreeMap;
+
+/// Minimum whatlang confidence to accept a guess
+///
+/// Lower than whatlang's own "reliable" cut-off: quiz questions are a sentence
+/// or two, which is rarely enough for it between close Cyrillic languages.
+const MIN_CONFIDENCE: f64 = 0.5;
+
+/// Key for questions whose language can't be determined (too short, mixed, no text)
+pub const UNKNOWN: &str = "unknown";
+
+/// Detect the language of a question from its text and answers
+///
+/// Returns the ISO 639-3 code (`rus`, `eng`, ...) when the guess is confident enough.
+pub fn detect_question_language(question: &Question) -> Option<&'static str> {
+    let mut text = question.text.clone();
+    for answer in &question.answers {
+        text.push(' ');
+        text.push_str(answer);
+    }
+
+    whatlang::detect(&text)
+        .filter(|info| info.confidence() >= MIN_CONFIDENCE)
+        .map(|info| info.lang().code())
+}
+
+/// Number of questions per detected language
+pub fn language_counts(package: &Package) -> BTreeMap<&'static str, usize> {
+    let mut counts = BTreeMap::new();
+    for (_, _, question) in package.questions() {
+        let language = detect_question_language(question).unwrap_or(UNKNOWN);
+        *counts.entry(language).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::{Round, Theme};
+
+    fn question(text: &str, answers: &[&str]) -> Question {
+        Question {
+            price: None,
+            text: text.to_string(),
+            answers: answers.iter().map(|a| a.to_string()).collect(),
+            media: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_detect_question_language() {
+        let russian = question(
+            "Этот архитектурный ансамбль был открыт в Москве в тысяча девятьсот тридцать девятом году",
+            &["Выставка достижений народного хозяйства"],
+        );
+        let english = question(
+            "This famous clock tower stands at the north end of the Palace of Westminster in London",
+            &["Big Ben"],
+        );
+        assert_eq!(detect_question_language(&russian), Some("rus"));
+        assert_eq!(detect_question_language(&english), Some("eng"));
+        assert_eq!(detect_question_language(&question("", &[])), None);
+    }
+
+    #[test]
+    fn test_language_counts() {
+        let package = Package {
+            name: "Mixed".to_string(),
+            logo: None,
+            rounds: vec![Round {
+                name: "Round 1".to_string(),
+                themes: vec![Theme {
+                    name: "Cities".to_string(),
+                    questions: vec![
+                        question(
+                            "Какой город называют северной столицей России и городом белых ночей",
+                            &["Санкт-Петербург"],
+                        ),
+                        question(
+                            "Какая река протекает через столицу России и дала ей своё название",
+                            &["Москва"],
+                        ),
+                        question(
+                            "Which city is known as the Big Apple and never sleeps at night",
+                            &["New York"],
+                        ),
+                        question("", &[]),
+                    ],
+                }],
+            }],
+        };
+
+        let counts = language_counts(&package);
+        assert_eq!(counts.get("rus"), Some(&2));
+        assert_eq!(counts.get("eng"), Some(&1));
+        assert_eq!(counts.get(UNKNOWN), Some(&1));
+    }
+}
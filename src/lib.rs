@@ -0,0 +1,207 @@
+//! SIGame pack compression
+//!
+//! The codec functions re-exported at the crate root ([`compress_image`],
+//! [`compress_audio`], [`compress_video`]) and their options structs are the
+//! stable API and follow semver. They work on in-memory files and don't need
+//! the pack pipeline. The remaining modules back the `sicom` command-line tool
+//! and may change between minor versions.
+//!
+//! ```no_run
+//! let jpeg = std::fs::read("photo.jpg")?;
+//! let webp = sicom::compress_image(&jpeg, &sicom::ImageOptions::new().quality(60))?;
+//! std::fs::write("photo.webp", webp)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+#![allow(clippy::collapsible_if)]
+
+#[doc(hidden)]
+pub mod ab;
+#[doc(hidden)]
+pub mod audio;
+#[doc(hidden)]
+pub mod batch;
+pub mod cancel;
+#[doc(hidden)]
+pub mod capabilities;
+#[doc(hidden)]
+pub mod collection;
+#[doc(hidden)]
+pub mod content_types;
+#[doc(hidden)]
+pub mod daemon;
+#[doc(hidden)]
+pub mod dedupe;
+#[doc(hidden)]
+pub mod diff;
+pub mod error;
+#[doc(hidden)]
+pub mod explain;
+#[doc(hidden)]
+pub mod export;
+#[cfg(feature = "audio")]
+#[doc(hidden)]
+pub mod fingerprint;
+#[doc(hidden)]
+pub mod image;
+#[doc(hidden)]
+pub mod inspect;
+#[doc(hidden)]
+pub mod journal;
+#[doc(hidden)]
+pub mod language;
+#[doc(hidden)]
+pub mod lint;
+#[cfg(feature = "audio")]
+#[doc(hidden)]
+pub mod loudness;
+#[doc(hidden)]
+pub mod manifest;
+#[doc(hidden)]
+pub mod media;
+#[doc(hidden)]
+pub mod metrics;
+#[cfg(feature = "content-scan")]
+#[doc(hidden)]
+pub mod moderation;
+#[doc(hidden)]
+pub mod mp3;
+#[doc(hidden)]
+pub mod options;
+#[doc(hidden)]
+pub mod pack;
+#[doc(hidden)]
+pub mod pipeline;
+#[cfg(feature = "server")]
+#[doc(hidden)]
+pub mod policy;
+#[doc(hidden)]
+pub mod portable;
+#[doc(hidden)]
+pub mod preload;
+#[cfg(feature = "server")]
+#[doc(hidden)]
+pub mod profiles;
+#[doc(hidden)]
+pub mod progress;
+#[doc(hidden)]
+pub mod quality;
+#[cfg(feature = "server")]
+#[doc(hidden)]
+pub mod queue;
+#[doc(hidden)]
+pub mod recompress;
+#[cfg(feature = "object-storage")]
+#[doc(hidden)]
+pub mod remote;
+#[doc(hidden)]
+pub mod repair;
+#[cfg(feature = "server")]
+#[doc(hidden)]
+pub mod server;
+#[doc(hidden)]
+pub mod split;
+#[doc(hidden)]
+pub mod standalone;
+#[doc(hidden)]
+pub mod stats;
+#[doc(hidden)]
+pub mod target_size;
+#[doc(hidden)]
+pub mod tempspace;
+#[cfg(test)]
+pub(crate) mod test_support;
+#[doc(hidden)]
+pub mod transliterate;
+#[doc(hidden)]
+pub mod tuning;
+#[doc(hidden)]
+pub mod verify;
+#[doc(hidden)]
+pub mod video;
+#[doc(hidden)]
+pub mod warm;
+#[doc(hidden)]
+pub mod wizard;
+
+pub use audio::{
+    AudioCodec, AudioEngine, AudioMode, AudioOptions, ShortClipPolicy, StereoMode, compress_audio,
+};
+pub use cancel::CancellationToken;
+pub use error::SicomError;
+pub use image::{ImageCodec, ImageOptions, compress_image};
+pub use video::{
+    Rendition, VideoCompat, VideoDenoise, VideoMode, VideoOptions, X265Preset, compress_video,
+};
+
+pub(crate) use pipeline::{build_registry, open_pack, prepare_ffmpeg};
+
+/// Human-readable byte size (e.g. "1.5 MB")
+#[doc(hidden)]
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    #[allow(clippy::cast_precision_loss)]
+    let mut size = bytes as f64;
+    let mut unit_index = 0;
+
+    while size >= 1024.0 && unit_index < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit_index += 1;
+    }
+
+    if unit_index == 0 {
+        format!("{} {}", bytes, UNITS[unit_index])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit_index])
+    }
+}
+
+/// Parse a byte size like "10KB", "1.5 MB" or "512" (units are powers of 1024)
+#[doc(hidden)]
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    const UNITS: &[(&str, u64)] = &[
+        ("TB", 1 << 40),
+        ("GB", 1 << 30),
+        ("MB", 1 << 20),
+        ("KB", 1 << 10),
+        ("B", 1),
+    ];
+    let value = value.trim();
+    let upper = value.to_ascii_uppercase();
+    let (number, multiplier) = UNITS
+        .iter()
+        .find_map(|&(unit, multiplier)| {
+            upper
+                .strip_suffix(unit)
+                .map(|number| (number.trim_end(), multiplier))
+        })
+        .unwrap_or((value, 1));
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {value:?}, expected e.g. 10KB or 1.5MB"))?;
+    if !number.is_finite() || number < 0.0 {
+        return Err(format!("invalid size {value:?}"));
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let bytes = number * multiplier as f64;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Ok(bytes.round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("10KB"), Ok(10 * 1024));
+        assert_eq!(parse_size("10 kb"), Ok(10 * 1024));
+        assert_eq!(parse_size("1.5MB"), Ok(1536 * 1024));
+        assert_eq!(parse_size("2 GB"), Ok(2 << 30));
+        assert_eq!(parse_size("100B"), Ok(100));
+        assert!(parse_size("ten KB").is_err());
+        assert!(parse_size("-1KB").is_err());
+        assert!(parse_size("").is_err());
+    }
+}
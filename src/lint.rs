@@ -0,0 +1,388 @@
+use crate::error::SicomError;
+use crate::open_pack;
+use anyhow::{Context, Result, anyhow};
+use roxmltree::{Document, Node};
+use serde::Serialize;
+use std::io::Read;
+use std::path::Path;
+
+/// Format 4 `atom` types (an atom without one is text)
+const ATOM_TYPES: [&str; 8] = [
+    "text", "say", "image", "voice", "video", "marker", "oral", "html",
+];
+
+/// Format 5 content `item` types (an item without one is text)
+const ITEM_TYPES: [&str; 5] = ["text", "image", "audio", "video", "html"];
+
+/// Where a format 5 content item is shown
+const ITEM_PLACEMENTS: [&str; 3] = ["screen", "replic", "background"];
+
+/// Round types; a round without one is a standard round
+const ROUND_TYPES: [&str; 2] = ["standart", "final"];
+
+/// Structural problem in content.xml
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LintIssue {
+    /// 1-based line of the element the problem is on
+    pub line: u32,
+    pub message: String,
+}
+
+struct Linter<'a, 'input> {
+    document: &'a Document<'input>,
+    /// Format version from the package, 4 or 5
+    format: u32,
+    issues: Vec<LintIssue>,
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.has_tag_name(name))
+}
+
+fn text<'a>(node: Node<'a, '_>) -> &'a str {
+    node.text().unwrap_or_default().trim()
+}
+
+fn text_attribute<'a>(node: Node<'a, '_>, name: &str) -> &'a str {
+    node.attribute(name).unwrap_or_default().trim()
+}
+
+/// Seconds in an `hh:mm:ss` duration (seconds may have a fraction)
+fn parse_duration(value: &str) -> Option<f64> {
+    let parts: Vec<&str> = value.trim().split(':').collect();
+    let [hours, minutes, seconds] = parts.as_slice() else {
+        return None;
+    };
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok().filter(|&m| m < 60)?;
+    let seconds: f64 = seconds
+        .parse()
+        .ok()
+        .filter(|s: &f64| (0.0..60.0).contains(s))?;
+    Some(f64::from(hours * 3600 + minutes * 60) + seconds)
+}
+
+impl Linter<'_, '_> {
+    fn report(&mut self, node: Node<'_, '_>, message: impl Into<String>) {
+        self.issues.push(LintIssue {
+            line: self.document.text_pos_at(node.range().start).row,
+            message: message.into(),
+        });
+    }
+
+    fn package(&mut self, package: Node<'_, '_>) {
+        if text_attribute(package, "name").is_empty() {
+            self.report(package, "Package has no name");
+        }
+        match package.attribute("version").map(str::trim) {
+            Some("4") | None => self.format = 4,
+            Some("5") => self.format = 5,
+            Some(version) => self.report(
+                package,
+                format!("Unknown format version {version:?} (expected 4 or 5)"),
+            ),
+        }
+
+        let rounds: Vec<Node> = children(package, "rounds").collect();
+        match rounds.as_slice() {
+            [] => self.report(package, "Package has no <rounds>"),
+            [rounds] => {
+                let mut empty = true;
+                for round in children(*rounds, "round") {
+                    empty = false;
+                    self.round(round);
+                }
+                if empty {
+                    self.report(*rounds, "Package has no rounds");
+                }
+            }
+            [_, extra, ..] => self.report(*extra, "Package has more than one <rounds>"),
+        }
+    }
+
+    fn round(&mut self, round: Node<'_, '_>) {
+        let name = text_attribute(round, "name");
+        if name.is_empty() {
+            self.report(round, "Round has no name");
+        }
+        if let Some(kind) = round.attribute("type") {
+            if !ROUND_TYPES.contains(&kind) {
+                self.report(round, format!("Unknown round type {kind:?}"));
+            }
+        }
+        let themes: Vec<Node> = children(round, "themes")
+            .flat_map(|themes| children(themes, "theme"))
+            .collect();
+        if themes.is_empty() {
+            self.report(round, format!("Round {name:?} has no themes"));
+        }
+        for theme in themes {
+            self.theme(theme);
+        }
+    }
+
+    fn theme(&mut self, theme: Node<'_, '_>) {
+        let name = text_attribute(theme, "name");
+        if name.is_empty() {
+            self.report(theme, "Theme has no name");
+        }
+        let questions: Vec<Node> = children(theme, "questions")
+            .flat_map(|questions| children(questions, "question"))
+            .collect();
+        if questions.is_empty() {
+            self.report(theme, format!("Theme {name:?} has no questions"));
+        }
+        for question in questions {
+            self.question(question);
+        }
+    }
+
+    fn question(&mut self, question: Node<'_, '_>) {
+        match question.attribute("price").map(str::trim) {
+            None => self.report(question, "Question has no price"),
+            Some(price) if price.parse::<i64>().is_err() => {
+                self.report(question, format!("Price {price:?} is not a whole number"));
+            }
+            Some(_) => {}
+        }
+
+        if self.format == 4 {
+            let atoms: Vec<Node> = children(question, "scenario")
+                .flat_map(|scenario| children(scenario, "atom"))
+                .collect();
+            if atoms.is_empty() {
+                self.report(question, "Question has no scenario atoms");
+            }
+            for atom in atoms {
+                self.atom(atom);
+            }
+        } else {
+            let content = children(question, "params")
+                .flat_map(|params| children(params, "param"))
+                .find(|param| param.attribute("name") == Some("question"));
+            match content {
+                Some(param) => {
+                    for item in param.descendants().filter(|n| n.has_tag_name("item")) {
+                        self.item(item);
+                    }
+                }
+                None => self.report(question, "Question has no \"question\" param"),
+            }
+        }
+
+        let answered = children(question, "right")
+            .flat_map(|right| children(right, "answer"))
+            .any(|answer| !text(answer).is_empty());
+        if !answered {
+            self.report(question, "Question has no right answer");
+        }
+    }
+
+    fn atom(&mut self, atom: Node<'_, '_>) {
+        let kind = atom.attribute("type").unwrap_or("text");
+        if !ATOM_TYPES.contains(&kind) {
+            self.report(atom, format!("Unknown atom type {kind:?}"));
+        } else if ["image", "voice", "video"].contains(&kind) && matches!(text(atom), "" | "@") {
+            self.report(atom, format!("Empty {kind} atom"));
+        }
+        if let Some(time) = atom.attribute("time") {
+            if time.trim().parse::<u32>().is_err() {
+                self.report(
+                    atom,
+                    format!("Atom time {time:?} is not a whole number of seconds"),
+                );
+            }
+        }
+    }
+
+    fn item(&mut self, item: Node<'_, '_>) {
+        let kind = item.attribute("type").unwrap_or("text");
+        if !ITEM_TYPES.contains(&kind) {
+            self.report(item, format!("Unknown item type {kind:?}"));
+        }
+        if let Some(is_ref) = item.attribute("isRef") {
+            let is_ref_true = is_ref.eq_ignore_ascii_case("true");
+            if !is_ref_true && !is_ref.eq_ignore_ascii_case("false") {
+                self.report(item, format!("isRef is {is_ref:?}, expected True or False"));
+            } else if is_ref_true && text(item).is_empty() {
+                self.report(item, format!("Empty {kind} reference"));
+            }
+        }
+        if let Some(duration) = item.attribute("duration") {
+            if parse_duration(duration).is_none() {
+                self.report(item, format!("Duration {duration:?} is not hh:mm:ss"));
+            }
+        }
+        if let Some(placement) = item.attribute("placement") {
+            if !ITEM_PLACEMENTS.contains(&placement) {
+                self.report(item, format!("Unknown placement {placement:?}"));
+            }
+        }
+    }
+}
+
+/// Check content.xml against the SIQ format: the package, round, theme and
+/// question structure, atom and item types, prices and durations
+pub fn lint_content_xml(xml: &str) -> Vec<LintIssue> {
+    let document = match Document::parse(xml) {
+        Ok(document) => document,
+        Err(e) => {
+            return vec![LintIssue {
+                line: e.pos().row,
+                message: format!("Malformed XML: {e}"),
+            }];
+        }
+    };
+    let mut linter = Linter {
+        document: &document,
+        format: 5,
+        issues: Vec::new(),
+    };
+    let root = document.root_element();
+    if root.has_tag_name("package") {
+        linter.package(root);
+    } else {
+        let name = root.tag_name().name().to_string();
+        linter.report(
+            root,
+            format!("Root element is <{name}>, expected <package>"),
+        );
+    }
+    linter.issues
+}
+
+/// Lint the content.xml of a pack (or a content.xml file itself); fails when
+/// anything is wrong
+pub fn lint_pack(input: &Path, json: bool) -> Result<()> {
+    if !input.exists() {
+        return Err(SicomError::InputNotFound(input.to_path_buf()).into());
+    }
+
+    let is_xml = input
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("xml"));
+    let xml = if is_xml {
+        std::fs::read_to_string(input).map_err(|source| SicomError::Io {
+            path: input.to_path_buf(),
+            source,
+        })?
+    } else {
+        let mut xml = String::new();
+        open_pack(input)?
+            .by_name("content.xml")
+            .map_err(|_| anyhow!("No content.xml found in pack"))?
+            .read_to_string(&mut xml)
+            .with_context(|| "Failed to read content.xml as UTF-8")?;
+        xml
+    };
+    let issues = lint_content_xml(&xml);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&issues)?);
+    } else if issues.is_empty() {
+        println!("No problems found");
+    } else {
+        for issue in &issues {
+            println!("content.xml:{}: {}", issue.line, issue.message);
+        }
+    }
+
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(SicomError::LintFailed(issues.len()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::tests::SAMPLE_V5;
+
+    fn messages(xml: &str) -> Vec<(u32, String)> {
+        lint_content_xml(xml)
+            .into_iter()
+            .map(|issue| (issue.line, issue.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("00:00:10"), Some(10.0));
+        assert_eq!(parse_duration("01:02:03.5"), Some(3723.5));
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("00:61:00"), None);
+    }
+
+    #[test]
+    fn test_lint_valid_packages() {
+        assert!(lint_content_xml(SAMPLE_V5).is_empty());
+
+        let v4 = r#"<package name="Old" version="4"><rounds><round name="R"><themes><theme name="T"><questions>
+            <question price="100"><scenario><atom type="voice" time="10">@song.mp3</atom></scenario>
+            <right><answer>Song</answer></right></question>
+        </questions></theme></themes></round></rounds></package>"#;
+        assert!(lint_content_xml(v4).is_empty());
+    }
+
+    #[test]
+    fn test_lint_problems() {
+        let xml = r#"<package name="P" version="5">
+  <rounds>
+    <round name="R" type="bonus">
+      <themes>
+        <theme name="Empty"><questions /></theme>
+        <theme name="T">
+          <questions>
+            <question price="ten">
+              <params>
+                <param name="question" type="content">
+                  <item type="picture" isRef="True">a.png</item>
+                  <item type="audio" isRef="True" duration="10s">b.mp3</item>
+                  <item type="image" isRef="True"></item>
+                </param>
+              </params>
+            </question>
+          </questions>
+        </theme>
+      </themes>
+    </round>
+  </rounds>
+</package>"#;
+        assert_eq!(
+            messages(xml),
+            [
+                (3, "Unknown round type \"bonus\"".to_string()),
+                (5, "Theme \"Empty\" has no questions".to_string()),
+                (8, "Price \"ten\" is not a whole number".to_string()),
+                (11, "Unknown item type \"picture\"".to_string()),
+                (12, "Duration \"10s\" is not hh:mm:ss".to_string()),
+                (13, "Empty image reference".to_string()),
+                (8, "Question has no right answer".to_string()),
+            ]
+        );
+
+        let v4 = "<package name=\"Old\" version=\"4\"><rounds><round name=\"R\"><themes><theme name=\"T\"><questions>\n<question price=\"100\"><scenario><atom type=\"sound\">@a.mp3</atom><atom type=\"image\" time=\"x\">@</atom></scenario><right><answer>A</answer></right></question>\n</questions></theme></themes></round></rounds></package>";
+        assert_eq!(
+            messages(v4),
+            [
+                (2, "Unknown atom type \"sound\"".to_string()),
+                (2, "Empty image atom".to_string()),
+                (
+                    2,
+                    "Atom time \"x\" is not a whole number of seconds".to_string()
+                ),
+            ]
+        );
+
+        assert_eq!(
+            messages("<pack/>"),
+            [(1, "Root element is <pack>, expected <package>".to_string())]
+        );
+        assert_eq!(messages("<package>\n<rounds></package>")[0].0, 2);
+    }
+}
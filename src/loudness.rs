@@ -0,0 +1,249 @@
+use crate::audio::AudioDecoder;
+use anyhow::Result;
+use std::f64::consts::PI;
+
+/// Loudness tracks are normalized to, in LUFS
+pub const TARGET_LUFS: f64 = -16.0;
+/// Highest sample peak normalization may raise a track to, in dBFS
+const MAX_PEAK_DBFS: f64 = -1.0;
+/// Length of one gating block
+const BLOCK_SECONDS: f64 = 0.4;
+/// Blocks overlap by 75%, so one starts every quarter block
+const STEPS_PER_BLOCK: usize = 4;
+/// Blocks quieter than this are silence and never count
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Blocks this far below the ungated loudness don't count either
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Second-order IIR filter section (direct form I)
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// BS.1770 K-weighting (head shelf, then high-pass) designed for `sample_rate`
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = f64::from(sample_rate);
+
+    let k = (PI * 1_681.974_450_955_533 / rate).tan();
+    let q = 0.707_175_236_955_419_6;
+    let vh = 10f64.powf(3.999_843_853_973_347 / 20.0);
+    let vb = vh.powf(0.499_666_774_154_541_6);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Biquad::default()
+    };
+
+    let k = (PI * 38.135_470_876_024_44 / rate).tan();
+    let q = 0.500_327_037_323_877_3;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Biquad::default()
+    };
+
+    [shelf, high_pass]
+}
+
+/// Loudness of mean square `power` in LUFS
+fn power_to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Integrated loudness (EBU R128) and sample peak of a track
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// None for tracks shorter than one block or entirely silent
+    pub integrated_lufs: Option<f64>,
+    /// Largest absolute sample value
+    pub peak: f32,
+}
+
+/// Measures loudness from interleaved samples fed in chunks
+pub struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    step_len: usize,
+    /// Frames and summed weighted energy of the step being filled
+    step_frames: usize,
+    step_energy: f64,
+    /// Energy of the latest steps, oldest first
+    steps: Vec<f64>,
+    /// Mean square of every complete block
+    blocks: Vec<f64>,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        Self {
+            filters: vec![k_weighting(sample_rate); channels.max(1)],
+            step_len: ((f64::from(sample_rate) * BLOCK_SECONDS) as usize / STEPS_PER_BLOCK).max(1),
+            step_frames: 0,
+            step_energy: 0.0,
+            steps: Vec::with_capacity(STEPS_PER_BLOCK),
+            blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, interleaved: &[f32]) {
+        for frame in interleaved.chunks_exact(self.filters.len()) {
+            for (filters, &sample) in self.filters.iter_mut().zip(frame) {
+                self.peak = self.peak.max(sample.abs());
+                let weighted = filters
+                    .iter_mut()
+                    .fold(f64::from(sample), |x, filter| filter.process(x));
+                self.step_energy += weighted * weighted;
+            }
+            self.step_frames += 1;
+
+            if self.step_frames == self.step_len {
+                if self.steps.len() == STEPS_PER_BLOCK {
+                    self.steps.remove(0);
+                }
+                self.steps.push(self.step_energy);
+                if self.steps.len() == STEPS_PER_BLOCK {
+                    let block_len = (self.step_len * STEPS_PER_BLOCK) as f64;
+                    self.blocks.push(self.steps.iter().sum::<f64>() / block_len);
+                }
+                self.step_frames = 0;
+                self.step_energy = 0.0;
+            }
+        }
+    }
+
+    pub fn finish(self) -> Loudness {
+        let gated_mean = |threshold_lufs: f64| {
+            let gated: Vec<f64> = self
+                .blocks
+                .iter()
+                .copied()
+                .filter(|&power| power > 0.0 && power_to_lufs(power) > threshold_lufs)
+                .collect();
+            (!gated.is_empty()).then(|| gated.iter().sum::<f64>() / gated.len() as f64)
+        };
+
+        let integrated_lufs = gated_mean(ABSOLUTE_GATE_LUFS).and_then(|ungated| {
+            let relative_gate = power_to_lufs(ungated) + RELATIVE_GATE_LU;
+            gated_mean(relative_gate.max(ABSOLUTE_GATE_LUFS)).map(power_to_lufs)
+        });
+        Loudness {
+            integrated_lufs,
+            peak: self.peak,
+        }
+    }
+}
+
+impl Loudness {
+    /// Decode an audio file and measure it
+    pub fn from_audio(data: &[u8]) -> Result<Self> {
+        let mut decoder = AudioDecoder::new(data)?;
+        // The decoder yields mono, or the first two channels interleaved
+        let channels = if decoder.channels == 1 { 1 } else { 2 };
+        let mut meter = LoudnessMeter::new(channels, decoder.sample_rate);
+        let mut chunk = Vec::new();
+        while decoder.next_chunk(&mut chunk)? {
+            meter.push(&chunk);
+        }
+        Ok(meter.finish())
+    }
+
+    /// Linear gain bringing the track to `target_lufs`, held back so the peak
+    /// stays under -1 dBFS; 1 when the loudness can't be measured
+    pub fn gain(&self, target_lufs: f64) -> f32 {
+        let Some(lufs) = self.integrated_lufs else {
+            return 1.0;
+        };
+        let mut gain_db = target_lufs - lufs;
+        if self.peak > 0.0 {
+            let peak_dbfs = 20.0 * f64::from(self.peak).log10();
+            gain_db = gain_db.min(MAX_PEAK_DBFS - peak_dbfs);
+        }
+        10f64.powf(gain_db / 20.0) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    /// `seconds` of a 1 kHz sine at `amplitude`, on `channels` identical channels
+    fn sine(seconds: f64, amplitude: f32, channels: usize) -> Vec<f32> {
+        let frames = (f64::from(RATE) * seconds) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let t = i as f64 / f64::from(RATE);
+                let sample = amplitude * (t * 1_000.0 * 2.0 * PI).sin() as f32;
+                std::iter::repeat_n(sample, channels)
+            })
+            .collect()
+    }
+
+    fn measure(samples: &[f32], channels: usize) -> Loudness {
+        let mut meter = LoudnessMeter::new(channels, RATE);
+        for chunk in samples.chunks(1_000 * channels) {
+            meter.push(chunk);
+        }
+        meter.finish()
+    }
+
+    #[test]
+    fn test_reference_tone() {
+        // BS.1770: a 0 dBFS 1 kHz sine on one channel reads -3.01 LUFS
+        let loudness = measure(&sine(5.0, 1.0, 1), 1);
+        let lufs = loudness.integrated_lufs.unwrap();
+        assert!((lufs + 3.01).abs() < 0.05, "{lufs}");
+
+        // Channels add up: the same tone in stereo is 3 dB louder
+        let stereo = measure(&sine(5.0, 0.5, 2), 2).integrated_lufs.unwrap();
+        assert!((stereo - (lufs - 6.02 + 3.01)).abs() < 0.05, "{stereo}");
+    }
+
+    #[test]
+    fn test_gating_and_gain() {
+        // Silence before the tone is gated out instead of dragging the level down
+        let mut samples = vec![0.0; RATE as usize * 5];
+        samples.extend(sine(5.0, 0.1, 1));
+        let loudness = measure(&samples, 1);
+        let lufs = loudness.integrated_lufs.unwrap();
+        assert!((lufs - (-3.01 - 20.0)).abs() < 0.2, "{lufs}");
+
+        // Quiet tone: raised by the full 7 dB to the target
+        let gain = loudness.gain(TARGET_LUFS);
+        assert!((20.0 * f64::from(gain).log10() - 7.01).abs() < 0.2);
+
+        // Loud tone with a near full-scale peak can't go up past -1 dBFS
+        let loud = Loudness {
+            integrated_lufs: Some(-30.0),
+            peak: 0.9,
+        };
+        assert!(loud.gain(TARGET_LUFS) * 0.9 <= 10f32.powf(-1.0 / 20.0) + 1e-6);
+
+        // Too short or silent: left alone
+        assert_eq!(measure(&sine(0.2, 0.5, 1), 1).integrated_lufs, None);
+        assert_eq!(measure(&[0.0; 48_000], 1).gain(TARGET_LUFS), 1.0);
+    }
+}
@@ -1,101 +1,977 @@
 #![allow(clippy::collapsible_if)]
 
-use anyhow::{Context, Result, anyhow};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use indicatif::MultiProgress;
 use indicatif_log_bridge::LogWrapper;
-use log::{debug, error, info, warn};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
+use log::{debug, error};
 use std::path::PathBuf;
-use thiserror::Error;
-use zip::{ZipArchive, ZipWriter};
-
-mod audio;
-mod image;
-mod progress;
-mod stats;
-mod video;
-
-use progress::{ProgressLogger, get_log_color_with_module};
-use stats::CompressionStats;
-
-#[derive(Error, Debug)]
-pub enum SicomError {
-    #[error("Input file does not exist: {0}")]
-    InputNotFound(PathBuf),
-    #[error("Input file is not a valid .siq file: {0}")]
-    InvalidSiqFile(PathBuf),
-    #[error("Failed to process image {name}: {source}")]
-    ImageProcessingError { name: String, source: anyhow::Error },
-}
+use std::time::Duration;
+
+use sicom::error::{self, SicomError};
+use sicom::media::{LongMediaPenalty, RenameScheme};
+#[cfg(feature = "content-scan")]
+use sicom::moderation;
+use sicom::options::{CompressOptions, Preset};
+use sicom::progress::get_log_color_with_module;
+#[cfg(feature = "object-storage")]
+use sicom::remote;
+#[cfg(feature = "server")]
+use sicom::server;
+use sicom::{
+    ab, audio, batch, capabilities, daemon, explain, export, image, inspect, lint, pipeline,
+    portable, quality, repair, split, standalone, target_size, transliterate, tuning, verify,
+    video, wizard,
+};
 
 #[derive(Parser)]
 #[command(name = "sicom")]
 #[command(about = "SIGame pack compression utility")]
 struct Cli {
+    #[arg(
+        long,
+        global = true,
+        help = "Use ffmpeg/ffprobe and a tmp/ directory next to the sicom executable (e.g. on a USB stick)"
+    )]
+    portable: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Compression settings shared by `compress` and `explain`
+#[derive(Args)]
+struct CompressArgs {
+    #[arg(
+        long,
+        value_enum,
+        help = "Quality preset: fast (smaller, quicker video encodes), balanced (the defaults) or max (best quality, slow); quality flags override it"
+    )]
+    preset: Option<Preset>,
+
+    #[arg(long, help = "Image quality (1-100) [default: 40, or from --preset]")]
+    image_quality: Option<u8>,
+
+    #[arg(
+        long,
+        help = "WebP encoder effort (0-6, slower is slightly smaller) [default: picked for this machine]"
+    )]
+    image_effort: Option<u8>,
+
+    #[arg(
+        long,
+        help = "Downscale images so neither side exceeds this many pixels (e.g. 1920)"
+    )]
+    max_image_dimension: Option<u32>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "webp",
+        help = "Format of re-encoded images (avif is ~20% smaller for photos, references are renamed to .avif; jpeg only re-encodes JPEGs, keeping their names)"
+    )]
+    image_format: image::ImageCodec,
+
+    #[arg(
+        long,
+        help = "Keep images as stored instead of rotating photos by their EXIF orientation"
+    )]
+    no_auto_orient: bool,
+
+    #[arg(
+        long,
+        help = "Benchmark this machine again before picking the default WebP effort and video preset"
+    )]
+    retune: bool,
+
+    #[arg(long, help = "Audio quality (1-100) [default: 85, or from --preset]")]
+    audio_quality: Option<u8>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "reencode",
+        help = "Audio processing mode (repack is lossless: drops MP3 padding, ancillary data and duplicate tags)"
+    )]
+    audio_mode: audio::AudioMode,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "native",
+        help = "Audio encoder backend (ffmpeg uses the same ffmpeg as video)"
+    )]
+    audio_engine: audio::AudioEngine,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "mp3",
+        help = "Format of re-encoded audio (opus needs ffmpeg with libopus; references are renamed to .opus)"
+    )]
+    audio_format: audio::AudioCodec,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "LAME stereo mode (derived from audio quality if not set)"
+    )]
+    audio_stereo_mode: Option<audio::StereoMode>,
+
+    #[arg(
+        long,
+        help = "LAME lowpass frequency in Hz (derived from audio quality if not set)"
+    )]
+    audio_lowpass_hz: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "THRESHOLD",
+        help = "Warn when re-encoded audio's fingerprint similarity to the source drops below this (0.0-1.0, e.g. 0.8)"
+    )]
+    audio_verify_similarity: Option<f32>,
+
+    #[arg(
+        long,
+        help = "Re-encode audio at a bitrate above the source's (only adds size)"
+    )]
+    allow_upcoding: bool,
+
+    #[arg(
+        long,
+        help = "Re-encode audio already at 128 kbps or less at an even lower bitrate"
+    )]
+    allow_degrade: bool,
+
+    #[arg(
+        long,
+        value_name = "KBPS",
+        help = "Keep audio averaging this bitrate or less as it is [default: the bitrate from --audio-quality]"
+    )]
+    audio_min_bitrate_kbps: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Normalize the loudness of re-encoded audio to -16 LUFS (EBU R128)"
+    )]
+    normalize_audio: bool,
+
+    #[arg(long, help = "Downmix re-encoded audio to mono")]
+    audio_mono: bool,
+
+    #[arg(
+        long,
+        value_name = "HZ",
+        help = "Resample re-encoded MP3s to this rate (e.g. 32000); lower-rate sources are kept as they are"
+    )]
+    audio_sample_rate: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Drop audio tags (title, artist, album, ...) instead of carrying them over to the output"
+    )]
+    strip_audio_metadata: bool,
+
+    #[arg(long, help = "Video quality (1-100) [default: 50, or from --preset]")]
+    video_quality: Option<u8>,
+
+    #[arg(long, help = "Skip video compression")]
+    skip_video: bool,
+
+    #[arg(
+        long,
+        default_value = "3",
+        help = "Audio clips shorter than this (seconds) are never encoded below 128 kbps"
+    )]
+    short_audio_seconds: f64,
+
+    #[arg(
+        long,
+        default_value = "10",
+        help = "Keep short audio clips untouched when estimated savings are below this (KB)"
+    )]
+    short_audio_min_savings_kb: u64,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "reencode",
+        help = "Video processing mode (remux copies the streams into a faststart MP4 without re-encoding)"
+    )]
+    video_mode: video::VideoMode,
+
+    #[arg(long, value_enum, help = "Denoise video before encoding")]
+    video_denoise: Option<video::VideoDenoise>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Encode video to H.264 (libx264) instead of HEVC, for clients and browsers that can't play HEVC"
+    )]
+    video_compat: Option<video::VideoCompat>,
+
+    #[arg(
+        long,
+        help = "Scan encoded videos for black frames, decode errors and truncation"
+    )]
+    verify_video: bool,
+
+    #[arg(
+        long,
+        help = "Drop the audio stream of videos whose audio track is entirely silent"
+    )]
+    strip_silent_audio: bool,
+
+    #[arg(
+        long,
+        value_name = "RATIO",
+        help = "Pad videos of other shapes (e.g. vertical phone videos) to this aspect ratio, e.g. 16:9"
+    )]
+    video_normalize_aspect: Option<video::AspectRatio>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        help = "Also store scaled-down copies of every video, e.g. 1080p,720p,480p, listed in renditions.json"
+    )]
+    ladder: Vec<video::Rendition>,
+
+    #[arg(
+        long,
+        help = "Downscale taller videos to this height before encoding, e.g. 720 for 4K sources"
+    )]
+    max_video_height: Option<u32>,
+
+    #[arg(long, help = "Downscale wider videos to this width before encoding")]
+    max_video_width: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "MB",
+        help = "Temp disk space shared by concurrent video encodes (each reserves 2x its source)"
+    )]
+    video_temp_space: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Give up on a pack that takes longer than this"
+    )]
+    time_limit: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        help = "Deflate level (0-9) for content.xml and other compressible entries; compressed media is stored"
+    )]
+    zip_level: Option<u8>,
+
+    #[arg(
+        long,
+        value_name = "POINTS",
+        help = "Lower the quality of long audio and video by this many points (e.g. 15)"
+    )]
+    long_media_penalty: Option<u8>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value = "300",
+        help = "Audio and video at least this long gets --long-media-penalty"
+    )]
+    long_media_seconds: u32,
+
+    #[arg(
+        long,
+        help = "Reuse results for media unchanged since the previous run into the same output (tracked in <output>.manifest.json)"
+    )]
+    incremental: bool,
+
+    #[arg(
+        long,
+        help = "Record progress in a journal next to the output (<output>.journal) and continue an interrupted --resume run from it, keeping the media it already finished"
+    )]
+    resume: bool,
+
+    #[arg(
+        long,
+        help = "Compress even if the output's manifest shows it was already made from this input with the same settings"
+    )]
+    force: bool,
+
+    #[arg(
+        long,
+        help = "Compress lossily even if the pack looks already compressed (WebP images, low-bitrate MP3s, HEVC videos, a sicom manifest)"
+    )]
+    allow_recompress: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        default_missing_value = "-",
+        help = "Show a unified diff of the content.xml reference updates (or write it to FILE)"
+    )]
+    show_xml_diff: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Store the unmodified content.xml as content.orig.xml inside the output pack"
+    )]
+    keep_original_xml: bool,
+
+    #[arg(
+        long,
+        help = "Store preload.json in the output pack: each round's media in question order with sizes and durations, for clients that prefetch the next question"
+    )]
+    emit_preload_manifest: bool,
+
+    #[arg(
+        long,
+        help = "Keep unreferenced media under its original name and fail if a renamed file's references can't be updated"
+    )]
+    strict_refs: bool,
+
+    #[arg(
+        long,
+        help = "Leave media in Images/, Audio/ and Video/ that content.xml doesn't reference out of the output"
+    )]
+    prune_unused: bool,
+
+    #[arg(
+        long,
+        help = "Only apply lossless transformations (lossless WebP, MP3 repack, video remux); quality flags are ignored"
+    )]
+    lossless_only: bool,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = sicom::parse_size,
+        help = "Copy media smaller than SIZE (e.g. 10KB) untouched without decoding it"
+    )]
+    min_file_size: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "ENTRY",
+        help = "Only compress this media entry (repeatable, e.g. --include Audio/a.mp3); other media is copied"
+    )]
+    include: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        default_missing_value = "-",
+        help = "List media kept original or skipped, with reasons, as --include filters (or write it to FILE)"
+    )]
+    list_unprocessed: Option<PathBuf>,
+
+    #[arg(long, help = "Skip image compression")]
+    skip_image: bool,
+
+    #[arg(long, help = "Skip audio compression")]
+    skip_audio: bool,
+
+    #[arg(
+        long,
+        help = "Path to ffmpeg binary (optional, auto-detected if not provided)"
+    )]
+    ffmpeg_path: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Always use compressed file even if it's larger than original"
+    )]
+    always_compress: bool,
+
+    #[arg(
+        long,
+        help = "Treat input as an arbitrary ZIP: compress media anywhere in its own format, leave XML untouched"
+    )]
+    generic_zip: bool,
+
+    #[arg(
+        long,
+        value_name = "THRESHOLD",
+        help = "Collapse near-identical images (perceptual hash distance 0-64, e.g. 6)"
+    )]
+    dedupe_similar: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Store byte-identical media files once and point every reference at that copy"
+    )]
+    dedupe_identical: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "extension",
+        help = "Media naming in the output (hashed uses content-hash filenames)"
+    )]
+    rename: RenameScheme,
+
+    #[arg(
+        long,
+        help = "Give media with non-ASCII filenames ASCII transliterations (ВДНХ.png -> VDNKh.webp); the originals are kept in <output>.manifest.json for `sicom restore-names`"
+    )]
+    transliterate_names: bool,
+
+    #[arg(
+        long,
+        value_name = "EXT,...",
+        value_delimiter = ',',
+        default_value = "xml,html,htm",
+        help = "Extensions of other entries (besides content.xml) whose media references are updated"
+    )]
+    text_extensions: Vec<String>,
+
+    #[cfg(feature = "content-scan")]
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        help = "Flag potentially NSFW images using a classifier (image on stdin, score 0-1 on stdout)"
+    )]
+    nsfw_classifier: Option<PathBuf>,
+
+    #[cfg(feature = "content-scan")]
+    #[arg(
+        long,
+        default_value = "0.8",
+        help = "Classifier score at which images are flagged (0.0-1.0)"
+    )]
+    nsfw_threshold: f32,
+}
+
+impl CompressArgs {
+    /// Defaults benchmarked for this machine, measured on the first run
+    fn tuned_defaults(&self) -> Option<tuning::TunedDefaults> {
+        if !self.retune && self.preset.is_some() && self.image_effort.is_some() {
+            return None;
+        }
+        let dir = tuning::config_dir()?;
+        let benchmark = tuning::load_or_measure(&dir, self.retune, self.ffmpeg_path.as_deref());
+        Some(benchmark.tuned_defaults())
+    }
+
+    fn into_options(self) -> CompressOptions {
+        let tuned = self.tuned_defaults();
+        let preset = self.preset.unwrap_or(Preset::Balanced);
+        let (image_quality, audio_quality, video_quality) = preset.qualities();
+        // An explicit preset picks the video preset, the machine tuning otherwise
+        let video_preset = match (self.preset, tuned) {
+            (None, Some(tuned)) => tuned.video_preset,
+            _ => preset.video_preset(),
+        };
+        let image_effort = self
+            .image_effort
+            .or(tuned.map(|tuned| tuned.image_effort))
+            .unwrap_or(image::DEFAULT_EFFORT);
+        let audio_options = audio::AudioOptions::new()
+            .quality(self.audio_quality.unwrap_or(audio_quality))
+            .allow_upcoding(self.allow_upcoding)
+            .allow_degrade(self.allow_degrade)
+            .min_bitrate_kbps(self.audio_min_bitrate_kbps)
+            .normalize_loudness(self.normalize_audio)
+            .mono(self.audio_mono)
+            .sample_rate(self.audio_sample_rate)
+            .strip_metadata(self.strip_audio_metadata)
+            .mode(self.audio_mode)
+            .engine(self.audio_engine)
+            .codec(self.audio_format)
+            .short_clip(
+                audio::ShortClipPolicy::default()
+                    .max_seconds(self.short_audio_seconds)
+                    .min_savings_bytes(self.short_audio_min_savings_kb * 1024),
+            )
+            .stereo_mode(self.audio_stereo_mode)
+            .lowpass_hz(self.audio_lowpass_hz)
+            .verify_similarity(self.audio_verify_similarity);
+        let options = CompressOptions::new()
+            .image_quality(self.image_quality.unwrap_or(image_quality))
+            .image_effort(image_effort)
+            .max_image_dimension(self.max_image_dimension)
+            .image_format(self.image_format)
+            .image_auto_orient(!self.no_auto_orient)
+            .audio(audio_options)
+            .video_quality(self.video_quality.unwrap_or(video_quality))
+            .video_preset(video_preset)
+            .video_mode(self.video_mode)
+            .video_denoise(self.video_denoise)
+            .video_compat(self.video_compat)
+            .verify_video(self.verify_video)
+            .strip_silent_audio(self.strip_silent_audio)
+            .video_normalize_aspect(self.video_normalize_aspect)
+            .video_ladder(self.ladder)
+            .max_video_height(self.max_video_height)
+            .max_video_width(self.max_video_width)
+            .video_temp_space_mb(self.video_temp_space)
+            .long_media(self.long_media_penalty.map(|points| {
+                LongMediaPenalty::default()
+                    .min_seconds(self.long_media_seconds)
+                    .quality_reduction(points)
+            }))
+            .time_limit_seconds(self.time_limit)
+            .zip_level(self.zip_level)
+            .incremental(self.incremental)
+            .force(self.force)
+            .resume(self.resume)
+            .xml_diff(self.show_xml_diff)
+            .keep_original_xml(self.keep_original_xml)
+            .emit_preload_manifest(self.emit_preload_manifest)
+            .strict_refs(self.strict_refs)
+            .prune_unused(self.prune_unused)
+            .lossless_only(self.lossless_only)
+            .allow_recompress(self.allow_recompress)
+            .min_file_size(self.min_file_size)
+            .include(self.include)
+            .unprocessed_list(self.list_unprocessed)
+            .skip_image(self.skip_image)
+            .skip_audio(self.skip_audio)
+            .skip_video(self.skip_video)
+            .ffmpeg_path(self.ffmpeg_path)
+            .always_compress(self.always_compress)
+            .generic_zip(self.generic_zip)
+            .rename(self.rename)
+            .transliterate_names(self.transliterate_names)
+            .text_extensions(self.text_extensions)
+            .dedupe_similar(self.dedupe_similar)
+            .dedupe_identical(self.dedupe_identical);
+        #[cfg(feature = "content-scan")]
+        let options = options.content_scan(self.nsfw_classifier.map(|classifier| {
+            moderation::ContentScanOptions {
+                classifier,
+                threshold: self.nsfw_threshold,
+            }
+        }));
+        options
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     Compress {
-        #[arg(help = "Path to existing SIGame pack (.siq file)")]
+        #[arg(help = "Path to existing SIGame pack (.siq file), s3://bucket/key or URL")]
         input_pack: PathBuf,
 
-        #[arg(help = "Path to output compressed pack (optional)")]
+        #[arg(help = "Path to output compressed pack (optional), s3://bucket/key or URL")]
         output_pack: Option<PathBuf>,
 
-        #[arg(long, default_value = "40", help = "Image quality (1-100)")]
-        image_quality: u8,
+        #[arg(
+            long,
+            value_name = "SIZE",
+            value_parser = sicom::parse_size,
+            help = "Split the output into SIZE parts (pack.siq.001, ...) with checksums; rejoin with `sicom join`"
+        )]
+        split_output: Option<u64>,
+
+        #[arg(
+            long,
+            value_name = "SIZE",
+            value_parser = sicom::parse_size,
+            help = "Lower video and image quality (largest first) until the output fits in SIZE, e.g. 150MB; recompresses the pack for every step"
+        )]
+        max_pack_size: Option<u64>,
+
+        #[command(flatten)]
+        settings: CompressArgs,
+    },
+    /// Compress every pack in a directory (or matching a glob like 'packs/*.siq')
+    Batch {
+        #[arg(
+            help = "Directory of .siq packs, a glob pattern (quote it to keep the shell from expanding it) or a .zip/.7z/.rar collection"
+        )]
+        source: PathBuf,
+
+        #[arg(long, help = "Only list the packs that would be compressed")]
+        list: bool,
+
+        #[arg(
+            long,
+            value_name = "DIR",
+            help = "Write compressed packs here under their original names (default: next to each input with a _compressed suffix)"
+        )]
+        output_dir: Option<PathBuf>,
+
+        #[command(flatten)]
+        settings: CompressArgs,
+    },
+    /// Answer a few questions and compress with settings derived from them
+    Wizard {
+        #[arg(help = "Path to the SIGame pack (.siq file); asked for when omitted")]
+        input_pack: Option<PathBuf>,
+    },
+    /// Rejoin a pack split with --split-output, verifying checksums
+    Join {
+        #[arg(help = "First part of the split pack (e.g. pack_compressed.siq.001)")]
+        first_part: PathBuf,
+
+        #[arg(help = "Path to the joined pack (defaults to the name before splitting)")]
+        output_pack: Option<PathBuf>,
+    },
+    /// Give media renamed by --transliterate-names back its original names
+    RestoreNames {
+        #[arg(help = "Pack compressed with --transliterate-names, next to its manifest")]
+        input_pack: PathBuf,
+
+        #[arg(help = "Path to the restored pack (defaults to <name>_restored.siq)")]
+        output_pack: Option<PathBuf>,
+    },
+    /// Show what compression would do to a single pack entry
+    Explain {
+        #[arg(help = "Path to existing SIGame pack (.siq file)")]
+        input_pack: PathBuf,
+
+        #[arg(help = "Entry inside the pack (e.g. Images/foo.jpg)")]
+        entry: String,
+
+        #[command(flatten)]
+        settings: CompressArgs,
+    },
+    /// Encode audio entries at several qualities as blind samples with an answer key
+    Ab {
+        #[arg(help = "Path to existing SIGame pack (.siq file)")]
+        input_pack: PathBuf,
+
+        #[arg(
+            long,
+            required = true,
+            value_delimiter = ',',
+            help = "Audio entries to sample (e.g. Audio/x.mp3)"
+        )]
+        files: Vec<String>,
+
+        #[arg(
+            long,
+            required = true,
+            value_delimiter = ',',
+            help = "Audio qualities (1-100) to compare, e.g. 40,60,80"
+        )]
+        qualities: Vec<u8>,
+
+        #[arg(long, help = "Directory for the samples (defaults to <name>_ab)")]
+        output_dir: Option<PathBuf>,
+
+        #[command(flatten)]
+        settings: CompressArgs,
+    },
+    /// Watch an inbox directory and compress every pack dropped there
+    Daemon {
+        #[arg(long, help = "Directory to watch for .siq files")]
+        inbox: PathBuf,
+
+        #[arg(
+            long,
+            help = "Directory for compressed packs and <pack>.json result files"
+        )]
+        outbox: PathBuf,
+
+        #[arg(long, default_value = "5", help = "Seconds between inbox scans")]
+        poll_interval: u64,
+
+        #[arg(
+            long,
+            default_value = "10",
+            help = "Seconds a pack must stay unmodified before it counts as fully uploaded"
+        )]
+        settle_time: u64,
+
+        #[arg(
+            long,
+            default_value = "3",
+            help = "Attempts per pack (including crashed runs) before it moves to failed/"
+        )]
+        max_attempts: u32,
+
+        #[cfg(feature = "server")]
+        #[arg(
+            long,
+            value_name = "ADDR",
+            help = "Serve Prometheus metrics on this address (e.g. 0.0.0.0:9090)"
+        )]
+        metrics_addr: Option<String>,
+
+        #[command(flatten)]
+        settings: CompressArgs,
+    },
+    /// Run an HTTP API that queues uploaded packs and compresses them
+    #[cfg(feature = "server")]
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080", help = "Address to listen on")]
+        listen: String,
+
+        #[arg(long, help = "Directory for the job queue, uploads and results")]
+        data_dir: PathBuf,
+
+        #[arg(
+            long,
+            default_value = "3",
+            help = "Attempts per job (including runs interrupted by a restart)"
+        )]
+        max_attempts: u32,
+
+        #[arg(
+            long,
+            default_value = "30",
+            help = "Seconds before the first retry (doubles after each failure)"
+        )]
+        retry_backoff: u64,
+
+        #[arg(
+            long,
+            value_name = "HOURS",
+            default_value = "24",
+            help = "Delete finished jobs and their results after this long"
+        )]
+        job_retention: u64,
 
-        #[arg(long, default_value = "85", help = "Audio quality (1-100)")]
-        audio_quality: u8,
+        #[arg(long, value_name = "N", help = "Requests per client IP per minute")]
+        rate_limit: Option<u32>,
 
-        #[arg(long, default_value = "50", help = "Video quality (1-100)")]
-        video_quality: u8,
+        #[arg(long, value_name = "MB", help = "Largest accepted upload")]
+        max_upload: Option<u64>,
 
-        #[arg(long, help = "Skip video compression")]
-        skip_video: bool,
+        #[arg(
+            long,
+            value_name = "MIN-MAX",
+            default_value = "1-100",
+            value_parser = parse_quality_range,
+            help = "Qualities clients may request with ?quality=, ?image_quality=, ..."
+        )]
+        allowed_quality: std::ops::RangeInclusive<u8>,
 
-        #[arg(long, help = "Skip image compression")]
-        skip_image: bool,
+        #[arg(long, value_name = "SECONDS", help = "Reject uploads that take longer")]
+        upload_timeout: Option<u64>,
 
-        #[arg(long, help = "Skip audio compression")]
-        skip_audio: bool,
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            help = "Fail jobs that compress for longer (checked between media files)"
+        )]
+        job_timeout: Option<u64>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "JSON file of named profiles clients select with ?profile="
+        )]
+        profiles: Option<PathBuf>,
+
+        #[command(flatten)]
+        settings: CompressArgs,
+    },
+    /// Show pack structure and media statistics
+    Inspect {
+        #[arg(help = "Path to existing SIGame pack (.siq file)")]
+        input_pack: PathBuf,
+
+        #[arg(long, help = "List the media used by each round and theme")]
+        by_theme: bool,
+
+        #[arg(long, help = "Print machine-readable JSON")]
+        json: bool,
+    },
+    /// Check that every media file of a pack decodes and every reference resolves
+    Verify {
+        #[arg(help = "Path to the pack to check (.siq file)")]
+        input_pack: PathBuf,
+
+        #[arg(long, help = "Print machine-readable JSON")]
+        json: bool,
+    },
+    /// Check content.xml against the SIQ format and list structural problems by line
+    Lint {
+        #[arg(help = "Path to the pack (.siq file) or a content.xml to check")]
+        input_pack: PathBuf,
+
+        #[arg(long, help = "Print machine-readable JSON")]
+        json: bool,
+    },
+    /// Fix media references that miss their file only by URL encoding or case
+    Repair {
+        #[arg(help = "Path to the pack to repair (.siq file)")]
+        input_pack: PathBuf,
+
+        #[arg(help = "Path to the repaired pack (defaults to <name>_repaired.siq)")]
+        output_pack: Option<PathBuf>,
+
+        #[arg(long, help = "Print machine-readable JSON")]
+        json: bool,
+    },
+    /// Write a static HTML page for reviewing every question and its media in a browser
+    ExportHtml {
+        #[arg(help = "Path to the pack to export (.siq file)")]
+        input_pack: PathBuf,
+
+        #[arg(help = "Directory for index.html and the media it plays")]
+        output_dir: PathBuf,
+    },
+    /// Report the codecs, formats, hardware encoders and features available here
+    Capabilities {
+        #[arg(long, help = "Print machine-readable JSON")]
+        json: bool,
 
         #[arg(
             long,
             help = "Path to ffmpeg binary (optional, auto-detected if not provided)"
         )]
         ffmpeg_path: Option<PathBuf>,
+    },
+    /// Work with standalone media files outside of a pack
+    Media {
+        #[command(subcommand)]
+        command: MediaCommands,
+    },
+    /// Show what a quality value means for each media type
+    ExplainQuality {
+        #[arg(help = "Quality (1-100) applied to images, audio and video")]
+        quality: u8,
+
+        #[arg(long, value_enum, default_value = "webp", help = "Image format")]
+        image_format: image::ImageCodec,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value = "reencode",
+            help = "Audio processing mode"
+        )]
+        audio_mode: audio::AudioMode,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value = "native",
+            help = "Audio encoder backend"
+        )]
+        audio_engine: audio::AudioEngine,
+
+        #[arg(long, value_enum, help = "LAME stereo mode override")]
+        audio_stereo_mode: Option<audio::StereoMode>,
+
+        #[arg(long, help = "LAME lowpass frequency override in Hz")]
+        audio_lowpass_hz: Option<u32>,
 
         #[arg(
             long,
-            help = "Always use compressed file even if it's larger than original"
+            value_enum,
+            default_value = "reencode",
+            help = "Video processing mode"
         )]
-        always_compress: bool,
+        video_mode: video::VideoMode,
+
+        #[arg(long, value_enum, help = "Denoise video before encoding")]
+        video_denoise: Option<video::VideoDenoise>,
+
+        #[arg(long, value_enum, help = "Encode video to H.264 instead of HEVC")]
+        video_compat: Option<video::VideoCompat>,
     },
 }
 
-fn format_size(bytes: u64) -> String {
-    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
-    #[allow(clippy::cast_precision_loss)]
-    let mut size = bytes as f64;
-    let mut unit_index = 0;
+#[derive(Subcommand)]
+enum MediaCommands {
+    /// Compress a single image, audio or video file with the pack pipelines
+    Compress {
+        #[arg(help = "Path to a media file (e.g. photo.jpg, intro.mp3, clip.mp4)")]
+        file: PathBuf,
 
-    while size >= 1024.0 && unit_index < UNITS.len() - 1 {
-        size /= 1024.0;
-        unit_index += 1;
+        #[arg(
+            short,
+            long,
+            help = "Output path (defaults to a file next to the input)"
+        )]
+        output: Option<PathBuf>,
+
+        #[arg(
+            short,
+            long,
+            help = "Quality (1-100) for the file's media type, overrides --image/audio/video-quality"
+        )]
+        quality: Option<u8>,
+
+        #[command(flatten)]
+        settings: CompressArgs,
+    },
+}
+
+/// Parse an inclusive quality range like `20-80`
+#[cfg(feature = "server")]
+fn parse_quality_range(value: &str) -> Result<std::ops::RangeInclusive<u8>, String> {
+    let (min, max) = value
+        .split_once('-')
+        .ok_or_else(|| "expected MIN-MAX, e.g. 20-80".to_string())?;
+    let parse = |q: &str| q.trim().parse::<u8>().map_err(|e| format!("{q:?}: {e}"));
+    let (min, max) = (parse(min)?, parse(max)?);
+    if min == 0 || max > 100 || min > max {
+        return Err("range must be within 1-100 with MIN <= MAX".to_string());
     }
+    Ok(min..=max)
+}
 
-    if unit_index == 0 {
-        format!("{} {}", bytes, UNITS[unit_index])
-    } else {
-        format!("{:.1} {}", size, UNITS[unit_index])
+/// Compress a local pack (to a size limit when given), then split the output when asked to
+fn compress_local(
+    input_pack: PathBuf,
+    output_pack: Option<PathBuf>,
+    split_output: Option<u64>,
+    max_pack_size: Option<u64>,
+    options: CompressOptions,
+    multi_progress: MultiProgress,
+) -> anyhow::Result<()> {
+    let output_pack = match (split_output, output_pack) {
+        // Splitting needs to know where the output ends up
+        (Some(_), None) => Some(pipeline::default_output_path(&input_pack)?),
+        (_, output_pack) => output_pack,
+    };
+    match max_pack_size {
+        Some(max_size) => target_size::compress_to_size(
+            input_pack,
+            output_pack.clone(),
+            options,
+            max_size,
+            multi_progress,
+        )?,
+        None => pipeline::compress_pack(input_pack, output_pack.clone(), options, multi_progress)?,
+    };
+    if let (Some(part_size), Some(output)) = (split_output, output_pack) {
+        split::split_file(&output, part_size)?;
+    }
+    Ok(())
+}
+
+/// Ask the wizard questions on the terminal, then compress with the derived settings
+fn run_wizard(input_pack: Option<PathBuf>, multi_progress: MultiProgress) -> anyhow::Result<()> {
+    let mut input = std::io::stdin().lock();
+    let mut output = std::io::stdout();
+    let input_pack = match input_pack {
+        Some(path) => path,
+        None => wizard::ask_pack(&mut input, &mut output)?,
+    };
+    let plan = wizard::Plan::from_answers(&wizard::ask(&mut input, &mut output)?);
+
+    println!();
+    println!("Equivalent command for next time:");
+    println!("  {}", plan.command_line(&input_pack));
+    println!();
+    if !wizard::confirm(&mut input, &mut output, "Start compression?", true)? {
+        return Ok(());
     }
+    compress_local(
+        input_pack,
+        None,
+        plan.split_output,
+        None,
+        plan.options(),
+        multi_progress,
+    )
+}
+
+/// Log an error and exit with the status of its failure class
+fn fail(e: anyhow::Error) -> ! {
+    error!("{e}");
+    debug!("Error code: {}", error::error_code(&e));
+    std::process::exit(error::classify(&e).map_or(1, SicomError::exit_code));
 }
 
 fn main() {
@@ -153,925 +1029,305 @@ fn main() {
         .expect("Failed to initialize logger");
 
     let cli = Cli::parse();
+    if cli.portable {
+        if let Err(e) = portable::enable() {
+            fail(e);
+        }
+    }
 
     match cli.command {
         Commands::Compress {
             input_pack,
             output_pack,
-            image_quality,
-            audio_quality,
-            video_quality,
-            skip_image,
-            skip_audio,
-            skip_video,
-            ffmpeg_path,
-            always_compress,
+            split_output,
+            max_pack_size,
+            settings,
         } => {
-            match compress_pack(
+            let options = settings.into_options();
+            #[cfg(feature = "object-storage")]
+            if remote::is_remote(&input_pack)
+                || output_pack.as_deref().is_some_and(remote::is_remote)
+            {
+                if split_output.is_some() || max_pack_size.is_some() {
+                    fail(
+                        SicomError::InvalidOptions(
+                            "--split-output and --max-pack-size only work with local output"
+                                .to_string(),
+                        )
+                        .into(),
+                    );
+                }
+                if let Err(e) =
+                    remote::compress_remote_pack(input_pack, output_pack, options, multi_progress)
+                {
+                    fail(e);
+                }
+                return;
+            }
+            if let Err(e) = compress_local(
                 input_pack,
                 output_pack,
-                image_quality,
-                audio_quality,
-                video_quality,
-                skip_image,
-                skip_audio,
-                skip_video,
-                ffmpeg_path,
-                always_compress,
+                split_output,
+                max_pack_size,
+                options,
                 multi_progress,
             ) {
-                Ok(()) => {
-                    // Success - exit normally
+                // Display error in red using our custom logger and exit with error code
+                fail(e);
+            }
+        }
+        Commands::Batch {
+            source,
+            list,
+            output_dir,
+            settings,
+        } => {
+            let options = settings.into_options();
+            if list {
+                match batch::list_packs(&source, options.generic_zip) {
+                    Ok(packs) => packs.iter().for_each(|pack| println!("{pack}")),
+                    Err(e) => fail(e),
                 }
-                Err(e) => {
-                    // Display error in red using our custom logger and exit with error code
-                    error!("{e}");
-                    std::process::exit(1);
+            } else {
+                match batch::compress_batch(
+                    &source,
+                    output_dir.as_deref(),
+                    &options,
+                    &multi_progress,
+                ) {
+                    Ok(entries) => {
+                        print!("{}", batch::summary_table(&entries));
+                        let failed = entries.iter().filter(|entry| entry.error.is_some()).count();
+                        if failed > 0 {
+                            fail(anyhow::anyhow!(
+                                "{failed} of {} packs failed",
+                                entries.len()
+                            ));
+                        }
+                    }
+                    Err(e) => fail(e),
                 }
             }
         }
-    }
-}
-
-#[allow(clippy::too_many_arguments)]
-fn compress_pack(
-    input_pack: PathBuf,
-    output_pack: Option<PathBuf>,
-    image_quality: u8,
-    audio_quality: u8,
-    video_quality: u8,
-    skip_image: bool,
-    skip_audio: bool,
-    skip_video: bool,
-    ffmpeg_path: Option<PathBuf>,
-    always_compress: bool,
-    multi_progress: MultiProgress,
-) -> Result<()> {
-    // Validate input
-    if !input_pack.exists() {
-        return Err(SicomError::InputNotFound(input_pack).into());
-    }
-
-    if input_pack.extension().and_then(|s| s.to_str()) != Some("siq") {
-        return Err(SicomError::InvalidSiqFile(input_pack).into());
-    }
-
-    // Determine output path
-    let output_path = if let Some(path) = output_pack {
-        path
-    } else {
-        let mut path = input_pack.clone();
-        let stem = path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .ok_or_else(|| anyhow!("Invalid file name"))?;
-        path.set_file_name(format!("{stem}_compressed.siq"));
-        path
-    };
-
-    info!("Compressing pack: {input_pack:?}");
-    info!("Output to: {output_path:?}");
-    info!("Image quality: {image_quality}");
-    info!("Audio quality: {audio_quality}");
-    info!("Video quality: {video_quality}");
-    info!("Skip image: {skip_image}");
-    info!("Skip audio: {skip_audio}");
-    info!("Skip video: {skip_video}");
-
-    // Detect or validate ffmpeg path
-    let ffmpeg_available = if let Some(path) = &ffmpeg_path {
-        if path.exists() {
-            info!("Using ffmpeg at: {path:?}");
-            true
-        } else {
-            warn!("Specified ffmpeg path does not exist: {path:?}");
-            false
+        Commands::Wizard { input_pack } => {
+            if let Err(e) = run_wizard(input_pack, multi_progress) {
+                fail(e);
+            }
         }
-    } else {
-        // Auto-detect ffmpeg using 'which' command
-        match std::process::Command::new("which").arg("ffmpeg").output() {
-            Ok(output) if output.status.success() => {
-                let ffmpeg_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                info!("Auto-detected ffmpeg at: {ffmpeg_path}");
-                true
+        Commands::Join {
+            first_part,
+            output_pack,
+        } => {
+            if let Err(e) = split::join_parts(&first_part, output_pack) {
+                fail(e);
             }
-            _ => {
-                if !skip_video {
-                    warn!("ffmpeg not found in PATH. Video compression will be skipped.");
-                    info!("To enable video compression:");
-                    info!(
-                        "  1. Install ffmpeg: brew install ffmpeg (macOS) or apt install ffmpeg (Ubuntu)"
-                    );
-                    info!("  2. Or specify path with --ffmpeg-path");
-                    info!("  3. Or use --skip-video to suppress this warning");
-                }
-                false
+        }
+        Commands::RestoreNames {
+            input_pack,
+            output_pack,
+        } => {
+            if let Err(e) = transliterate::restore_names(&input_pack, output_pack) {
+                fail(e);
             }
         }
-    };
-
-    // Validate quality
-    if !(1..=100).contains(&image_quality) {
-        return Err(anyhow!("Image quality must be between 1 and 100"));
-    }
-    if !(1..=100).contains(&audio_quality) {
-        return Err(anyhow!("Audio quality must be between 1 and 100"));
-    }
-    if !(1..=100).contains(&video_quality) {
-        return Err(anyhow!("Video quality must be between 1 and 100"));
-    }
-
-    // Open input ZIP
-    let input_file = File::open(&input_pack)
-        .with_context(|| format!("Failed to open input file: {input_pack:?}"))?;
-    let mut archive = ZipArchive::new(BufReader::new(input_file))
-        .with_context(|| "Failed to read ZIP archive")?;
-
-    // Create output ZIP
-    let output_file = File::create(&output_path)
-        .with_context(|| format!("Failed to create output file: {output_path:?}"))?;
-    let mut zip_writer = ZipWriter::new(BufWriter::new(output_file));
-
-    // Statistics tracking
-    let mut stats = CompressionStats::new();
-
-    // Track image conversions for content.xml updates
-    let mut image_conversions: HashMap<String, String> = HashMap::new();
-    let mut content_xml_data: Option<String> = None;
-
-    // Initialize progress logger
-    let total_files = archive.len() as u64;
-    let mut logger = ProgressLogger::new(total_files, &multi_progress);
-
-    // Note: indicatif-log-bridge now handles coordination between log messages and progress bars
-
-    // Helper function to get display filename (strip directory and URL decode)
-    fn get_display_filename(file_path: &str) -> String {
-        // Strip directory prefix (Images/, Audio/, Video/)
-        let filename = if let Some(pos) = file_path.find('/') {
-            &file_path[pos + 1..]
-        } else {
-            file_path
-        };
-
-        // URL decode the filename
-        urlencoding::decode(filename)
-            .unwrap_or_else(|_| filename.into())
-            .to_string()
-    }
-
-    // Process each file in the archive
-    for i in 0..archive.len() {
-        let mut file = archive
-            .by_index(i)
-            .with_context(|| format!("Failed to read file at index {i}"))?;
-
-        let file_name = file.name().to_string();
-        let is_image = file_name.starts_with("Images/") && image::is_supported_image(&file_name);
-        let is_audio = file_name.starts_with("Audio/") && audio::is_supported_audio(&file_name);
-        let is_video = file_name.starts_with("Video/") && video::is_supported_video(&file_name);
-        let is_content_xml = file_name == "content.xml";
-
-        debug!("Processing: {file_name}");
-
-        if is_content_xml {
-            // Read content.xml for later processing
-            let mut xml_data = String::new();
-            file.read_to_string(&mut xml_data)
-                .with_context(|| "Failed to read content.xml as UTF-8")?;
-
-            // Track input size
-            stats.add_other_file(xml_data.len() as u64);
-
-            content_xml_data = Some(xml_data);
-
-            // We'll write content.xml after processing all images
-            debug!("  Stored content.xml for path updates");
-        } else if is_image && !skip_image {
-            // Read image data
-            let mut image_data = Vec::new();
-            file.read_to_end(&mut image_data)
-                .with_context(|| format!("Failed to read image data: {file_name}"))?;
-
-            match image::compress_image_file(&image_data, &file_name, image_quality) {
-                Ok((compressed_data, original_size, compressed_size)) => {
-                    // Check if compression actually reduced size
-                    if compressed_size >= original_size && !always_compress {
-                        // Keep original file since compressed version is larger
-                        zip_writer
-                            .start_file(&file_name, zip::write::FileOptions::default())
-                            .with_context(|| {
-                                format!("Failed to start file in output ZIP: {file_name}")
-                            })?;
-                        zip_writer.write_all(&image_data).with_context(|| {
-                            format!("Failed to write original image: {file_name}")
-                        })?;
-
-                        stats.add_kept_original_image(original_size);
-
-                        info!(
-                            "  Keeping original (compressed would be larger): {original_size} bytes vs {compressed_size} bytes"
-                        );
-
-                        // Do NOT track this conversion - content.xml will keep original path
-                    } else {
-                        // Use compressed version (either smaller or always_compress is set)
-                        let webp_filename = image::to_webp_filename(&file_name);
-
-                        // Add compressed image to output ZIP with WebP extension
-                        zip_writer
-                            .start_file(&webp_filename, zip::write::FileOptions::default())
-                            .with_context(|| {
-                                format!("Failed to start file in output ZIP: {webp_filename}")
-                            })?;
-                        zip_writer.write_all(&compressed_data).with_context(|| {
-                            format!("Failed to write compressed image: {webp_filename}")
-                        })?;
-
-                        // Track the conversion for content.xml updates
-                        image_conversions.insert(file_name.clone(), webp_filename.clone());
-
-                        stats.add_processed_image(original_size, compressed_size);
-
-                        let display_filename = get_display_filename(&file_name);
-                        if compressed_size >= original_size {
-                            debug!(
-                                "  Converted \"{}\" to WebP (forced): {} bytes -> {} bytes ({:.1}% increase)",
-                                display_filename,
-                                original_size,
-                                compressed_size,
-                                (compressed_size as f64 / original_size as f64 - 1.0) * 100.0
-                            );
-                        } else {
-                            debug!(
-                                "  Converted \"{}\" to WebP: {} bytes -> {} bytes ({:.1}% reduction)",
-                                display_filename,
-                                original_size,
-                                compressed_size,
-                                (1.0 - compressed_size as f64 / original_size as f64) * 100.0
-                            );
-                        }
-                    }
-                }
-                Err(e) => {
-                    debug!("  Skipping {file_name}: {e}");
-
-                    // Copy original file unchanged (keep original extension)
-                    zip_writer
-                        .start_file(&file_name, zip::write::FileOptions::default())
-                        .with_context(|| {
-                            format!("Failed to start file in output ZIP: {file_name}")
-                        })?;
-                    zip_writer
-                        .write_all(&image_data)
-                        .with_context(|| format!("Failed to write original file: {file_name}"))?;
-
-                    stats.add_skipped_image(image_data.len() as u64);
-
-                    // Do NOT track this conversion - content.xml will keep original path
-                }
+        Commands::Explain {
+            input_pack,
+            entry,
+            settings,
+        } => {
+            if let Err(e) = explain::explain_entry(&input_pack, &entry, settings.into_options()) {
+                fail(e);
             }
-        } else if is_image && skip_image {
-            // Skip image compression - copy original file unchanged
-            let mut image_data = Vec::new();
-            file.read_to_end(&mut image_data)
-                .with_context(|| format!("Failed to read image data: {file_name}"))?;
-
-            // Input size will be tracked by stats methods
-
-            debug!("  Skipping image compression (skip_image flag): {file_name}");
-
-            // Copy original file unchanged (keep original extension)
-            zip_writer
-                .start_file(&file_name, zip::write::FileOptions::default())
-                .with_context(|| format!("Failed to start file in output ZIP: {file_name}"))?;
-            zip_writer
-                .write_all(&image_data)
-                .with_context(|| format!("Failed to write original image: {file_name}"))?;
-
-            stats.add_skipped_image(image_data.len() as u64);
-
-            // Do NOT track this conversion - content.xml will keep original path
-        } else if is_audio && !skip_audio {
-            // Read audio data
-            let mut audio_data = Vec::new();
-            file.read_to_end(&mut audio_data)
-                .with_context(|| format!("Failed to read audio data: {file_name}"))?;
-
-            // Track input size
-
-            // Try to compress audio
-            match audio::compress_audio_file(&audio_data, &file_name, audio_quality) {
-                Ok((compressed_data, original_size, compressed_size)) => {
-                    // Check if compression actually reduced size
-                    if compressed_size >= original_size && !always_compress {
-                        // Keep original file since compressed version is larger
-                        zip_writer
-                            .start_file(&file_name, zip::write::FileOptions::default())
-                            .with_context(|| {
-                                format!("Failed to start file in output ZIP: {file_name}")
-                            })?;
-                        zip_writer.write_all(&audio_data).with_context(|| {
-                            format!("Failed to write original audio: {file_name}")
-                        })?;
-
-                        stats.add_kept_original_audio(original_size);
-
-                        info!(
-                            "  Keeping original (compressed would be larger): {original_size} bytes vs {compressed_size} bytes"
-                        );
-                    } else {
-                        // Use compressed version (either smaller or always_compress is set)
-                        zip_writer
-                            .start_file(&file_name, zip::write::FileOptions::default())
-                            .with_context(|| {
-                                format!("Failed to start file in output ZIP: {file_name}")
-                            })?;
-                        zip_writer.write_all(&compressed_data).with_context(|| {
-                            format!("Failed to write compressed audio: {file_name}")
-                        })?;
-
-                        stats.add_processed_audio(original_size, compressed_size);
-
-                        let display_filename = get_display_filename(&file_name);
-                        if compressed_size >= original_size {
-                            debug!(
-                                "  Compressed \"{}\" to MP3 (forced): {} bytes -> {} bytes ({:.1}% increase)",
-                                display_filename,
-                                original_size,
-                                compressed_size,
-                                (compressed_size as f64 / original_size as f64 - 1.0) * 100.0
-                            );
-                        } else {
-                            debug!(
-                                "  Compressed \"{}\" to MP3: {} bytes -> {} bytes ({:.1}% reduction)",
-                                display_filename,
-                                original_size,
-                                compressed_size,
-                                (1.0 - compressed_size as f64 / original_size as f64) * 100.0
-                            );
-                        }
-                    }
-                }
-                Err(e) => {
-                    debug!("  Skipping {file_name}: {e}");
-
-                    // Copy original file unchanged
-                    zip_writer
-                        .start_file(&file_name, zip::write::FileOptions::default())
-                        .with_context(|| {
-                            format!("Failed to start file in output ZIP: {file_name}")
-                        })?;
-                    zip_writer.write_all(&audio_data).with_context(|| {
-                        format!("Failed to write original audio file: {file_name}")
-                    })?;
-
-                    stats.add_skipped_audio(audio_data.len() as u64);
-                }
+        }
+        Commands::Ab {
+            input_pack,
+            files,
+            qualities,
+            output_dir,
+            settings,
+        } => {
+            if let Err(e) = ab::ab_samples(
+                &input_pack,
+                &files,
+                &qualities,
+                settings.into_options(),
+                output_dir,
+            ) {
+                fail(e);
             }
-        } else if is_audio && skip_audio {
-            // Skip audio compression - copy original file unchanged
-            let mut audio_data = Vec::new();
-            file.read_to_end(&mut audio_data)
-                .with_context(|| format!("Failed to read audio data: {file_name}"))?;
-
-            debug!("  Skipping audio compression (skip_audio flag): {file_name}");
-
-            // Copy original file unchanged
-            zip_writer
-                .start_file(&file_name, zip::write::FileOptions::default())
-                .with_context(|| format!("Failed to start file in output ZIP: {file_name}"))?;
-            zip_writer
-                .write_all(&audio_data)
-                .with_context(|| format!("Failed to write original audio file: {file_name}"))?;
-
-            stats.add_skipped_audio(audio_data.len() as u64);
-        } else if is_video {
-            // Read video data
-            let mut video_data = Vec::new();
-            file.read_to_end(&mut video_data)
-                .with_context(|| format!("Failed to read video data: {file_name}"))?;
-
-            if skip_video || !ffmpeg_available {
-                let reason = if skip_video {
-                    "skip_video flag"
-                } else {
-                    "ffmpeg not available"
-                };
-                debug!("  Skipping video compression ({reason}): {file_name}");
-
-                // Copy original file unchanged
-                zip_writer
-                    .start_file(&file_name, zip::write::FileOptions::default())
-                    .with_context(|| format!("Failed to start file in output ZIP: {file_name}"))?;
-                zip_writer
-                    .write_all(&video_data)
-                    .with_context(|| format!("Failed to write original video file: {file_name}"))?;
-
-                stats.add_skipped_video(video_data.len() as u64);
-            } else {
-                // Try to compress video using ffmpeg-sidecar
-                logger.start_video_progress(&file_name, &multi_progress);
-                let video_result = video::compress_video_file(
-                    &video_data,
-                    &file_name,
-                    video_quality,
-                    ffmpeg_path.as_deref(),
-                    &mut logger,
-                );
-
-                match video_result {
-                    Ok((compressed_data, original_size, compressed_size)) => {
-                        logger.finish_video_progress();
-
-                        // Check if compression actually reduced size
-                        if compressed_size >= original_size && !always_compress {
-                            // Keep original file since compressed version is larger
-                            zip_writer
-                                .start_file(&file_name, zip::write::FileOptions::default())
-                                .with_context(|| {
-                                    format!("Failed to start file in output ZIP: {file_name}")
-                                })?;
-                            zip_writer.write_all(&video_data).with_context(|| {
-                                format!("Failed to write original video: {file_name}")
-                            })?;
-
-                            stats.add_kept_original_video(original_size);
-
-                            info!(
-                                "  Keeping original (compressed would be larger): {} vs {}",
-                                format_size(original_size),
-                                format_size(compressed_size)
-                            );
-                        } else {
-                            // Use compressed version (either smaller or always_compress is set)
-                            zip_writer
-                                .start_file(&file_name, zip::write::FileOptions::default())
-                                .with_context(|| {
-                                    format!("Failed to start file in output ZIP: {file_name}")
-                                })?;
-                            zip_writer.write_all(&compressed_data).with_context(|| {
-                                format!("Failed to write compressed video: {file_name}")
-                            })?;
-
-                            stats.add_processed_video(original_size, compressed_size);
-
-                            let display_filename = get_display_filename(&file_name);
-                            if compressed_size >= original_size {
-                                debug!(
-                                    "  Compressed \"{}\" to HEVC (forced): {} -> {} ({:.1}% increase)",
-                                    display_filename,
-                                    format_size(original_size),
-                                    format_size(compressed_size),
-                                    (compressed_size as f64 / original_size as f64 - 1.0) * 100.0
-                                );
-                            } else {
-                                debug!(
-                                    "  Compressed \"{}\" to HEVC: {} -> {} ({:.1}% reduction)",
-                                    display_filename,
-                                    format_size(original_size),
-                                    format_size(compressed_size),
-                                    (1.0 - compressed_size as f64 / original_size as f64) * 100.0
-                                );
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        logger.finish_video_progress(); // Cleanup on error
-                        warn!("  Video compression failed for {file_name}: {e}");
-
-                        // Copy original file unchanged
-                        zip_writer
-                            .start_file(&file_name, zip::write::FileOptions::default())
-                            .with_context(|| {
-                                format!("Failed to start file in output ZIP: {file_name}")
-                            })?;
-                        zip_writer.write_all(&video_data).with_context(|| {
-                            format!("Failed to write original video file: {file_name}")
-                        })?;
-
-                        stats.add_skipped_video(video_data.len() as u64);
+        }
+        Commands::Daemon {
+            inbox,
+            outbox,
+            poll_interval,
+            settle_time,
+            max_attempts,
+            #[cfg(feature = "server")]
+            metrics_addr,
+            settings,
+        } => {
+            let options = daemon::DaemonOptions {
+                inbox,
+                outbox,
+                poll_interval: Duration::from_secs(poll_interval),
+                settle_time: Duration::from_secs(settle_time),
+                max_attempts,
+            };
+            let result = daemon::Daemon::new(options, settings.into_options(), multi_progress)
+                .and_then(|mut daemon| {
+                    #[cfg(feature = "server")]
+                    if let Some(address) = &metrics_addr {
+                        sicom::metrics::serve(address, daemon.metrics())?;
                     }
-                }
+                    daemon.run()
+                });
+            if let Err(e) = result {
+                fail(e);
             }
-        } else {
-            // Copy other files unchanged
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer)
-                .with_context(|| format!("Failed to read file: {file_name}"))?;
-
-            zip_writer
-                .start_file(&file_name, zip::write::FileOptions::default())
-                .with_context(|| format!("Failed to start file in output ZIP: {file_name}"))?;
-            zip_writer
-                .write_all(&buffer)
-                .with_context(|| format!("Failed to write file: {file_name}"))?;
-
-            stats.add_other_file(buffer.len() as u64);
         }
-
-        // Increment progress after processing each file
-        logger.inc();
-    }
-
-    // Process content.xml with updated image paths
-    if let Some(mut xml_content) = content_xml_data {
-        info!("Updating content.xml with new image paths");
-
-        let mut updated_refs = 0;
-
-        // Update image paths in content.xml
-        for (original_path, webp_path) in &image_conversions {
-            // Extract just the filename from the full path for the XML replacement
-            let original_filename = original_path
-                .strip_prefix("Images/")
-                .unwrap_or(original_path);
-            let webp_filename = webp_path.strip_prefix("Images/").unwrap_or(webp_path);
-
-            // Try different encoding variations of the filename
-            let original_variations = vec![
-                original_filename.to_string(),
-                urlencoding::decode(original_filename)
-                    .unwrap_or_else(|_| original_filename.into())
-                    .to_string(),
-                urlencoding::encode(original_filename).to_string(),
-            ];
-
-            let webp_variations = vec![
-                webp_filename.to_string(),
-                urlencoding::decode(webp_filename)
-                    .unwrap_or_else(|_| webp_filename.into())
-                    .to_string(),
-                urlencoding::encode(webp_filename).to_string(),
-            ];
-
-            let mut file_replacements = 0;
-
-            // Try all combinations of original and webp variations
-            for orig_var in &original_variations {
-                for webp_var in &webp_variations {
-                    // Try different XML patterns that might contain the filename
-                    let patterns = vec![
-                        // Simple filename reference
-                        (orig_var.clone(), webp_var.clone()),
-                        // With isRef="True" wrapper
-                        (
-                            format!("isRef=\"True\">{orig_var}"),
-                            format!("isRef=\"True\">{webp_var}"),
-                        ),
-                        // With type="image" attribute
-                        (
-                            format!("type=\"image\" isRef=\"True\">{orig_var}"),
-                            format!("type=\"image\" isRef=\"True\">{webp_var}"),
-                        ),
-                        // With different quote styles
-                        (
-                            format!("isRef='True'>{orig_var}"),
-                            format!("isRef='True'>{webp_var}"),
-                        ),
-                        // Full path references
-                        (format!("Images/{orig_var}"), format!("Images/{webp_var}")),
-                        // Path references with isRef
-                        (
-                            format!("isRef=\"True\">Images/{orig_var}"),
-                            format!("isRef=\"True\">Images/{webp_var}"),
-                        ),
-                    ];
-
-                    for (old_pattern, new_pattern) in patterns {
-                        if old_pattern != new_pattern {
-                            let count = xml_content.matches(&old_pattern).count();
-                            if count > 0 {
-                                xml_content = xml_content.replace(&old_pattern, &new_pattern);
-                                file_replacements += count;
-                            }
-                        }
-                    }
-                }
+        #[cfg(feature = "server")]
+        Commands::Serve {
+            listen,
+            data_dir,
+            max_attempts,
+            retry_backoff,
+            job_retention,
+            rate_limit,
+            max_upload,
+            allowed_quality,
+            upload_timeout,
+            job_timeout,
+            profiles,
+            settings,
+        } => {
+            let profiles = match profiles.map(|path| sicom::profiles::Profiles::load(&path)) {
+                Some(Ok(profiles)) => profiles,
+                Some(Err(e)) => fail(e),
+                None => sicom::profiles::Profiles::default(),
+            };
+            let options = server::ServeOptions {
+                listen,
+                data_dir,
+                max_attempts,
+                retry_backoff: Duration::from_secs(retry_backoff),
+                job_retention: Duration::from_secs(job_retention * 3600),
+                policy: sicom::policy::ApiPolicy {
+                    requests_per_minute: rate_limit,
+                    max_upload_bytes: max_upload.map(|mb| mb * 1024 * 1024),
+                    allowed_quality,
+                    upload_timeout: upload_timeout.map(Duration::from_secs),
+                    job_timeout: job_timeout.map(Duration::from_secs),
+                },
+                profiles,
+            };
+            let result = server::Server::bind(&options, settings.into_options(), multi_progress)
+                .and_then(server::Server::run);
+            if let Err(e) = result {
+                fail(e);
             }
-
-            updated_refs += file_replacements;
-
-            if file_replacements > 0 {
-                debug!(
-                    "  Updated: {original_filename} -> {webp_filename} ({file_replacements} refs)"
-                );
-            } else {
-                warn!("  Warning: No refs found for {original_filename}");
+        }
+        Commands::Inspect {
+            input_pack,
+            by_theme,
+            json,
+        } => {
+            if let Err(e) = inspect::inspect_pack(&input_pack, by_theme, json) {
+                fail(e);
             }
         }
-
-        // Write updated content.xml to output ZIP
-        zip_writer
-            .start_file("content.xml", zip::write::FileOptions::default())
-            .with_context(|| "Failed to start content.xml in output ZIP")?;
-        zip_writer
-            .write_all(xml_content.as_bytes())
-            .with_context(|| "Failed to write updated content.xml")?;
-
-        // Track updated refs and file size
-        stats.add_updated_refs(updated_refs as u32);
-        // Note: content.xml size was already tracked when we read it
-
-        warn!("Updated {updated_refs} image references in content.xml");
-    } else {
-        warn!("Warning: No content.xml found in pack");
-    }
-
-    zip_writer
-        .finish()
-        .with_context(|| "Failed to finalize output ZIP")?;
-
-    // Finish progress logging and show final summary
-    logger.finish();
-
-    info!("Compression complete!");
-
-    // Images statistics
-    info!("");
-    info!("Images:");
-    info!("  Processed: {}", stats.images_processed());
-    info!(
-        "  Kept original (due to size): {}",
-        stats.images_kept_original()
-    );
-    info!("  Skipped: {}", stats.images_skipped());
-    if stats.image_original_size() > 0 {
-        info!(
-            "  Size reduction: {} -> {} ({:.1}% reduction)",
-            format_size(stats.image_original_size()),
-            format_size(stats.image_compressed_size()),
-            stats.image_compression_ratio()
-        );
-    }
-
-    // Audio statistics
-    info!("");
-    info!("Audio:");
-    info!("  Processed: {}", stats.audio_processed());
-    info!(
-        "  Kept original (due to size): {}",
-        stats.audio_kept_original()
-    );
-    info!("  Skipped: {}", stats.audio_skipped());
-    if stats.audio_original_size() > 0 {
-        if stats.audio_compressed_size() > 0 {
-            info!(
-                "  Size reduction: {} -> {} ({:.1}% reduction)",
-                format_size(stats.audio_original_size()),
-                format_size(stats.audio_compressed_size()),
-                stats.audio_compression_ratio()
-            );
-        } else {
-            info!(
-                "  Total size: {} (no compression applied)",
-                format_size(stats.audio_original_size())
-            );
+        Commands::Verify { input_pack, json } => {
+            if let Err(e) = verify::verify_pack(&input_pack, json) {
+                fail(e);
+            }
         }
-    }
-
-    // Video statistics
-    info!("");
-    info!("Video:");
-    info!("  Processed: {}", stats.video_processed());
-    info!(
-        "  Kept original (due to size): {}",
-        stats.video_kept_original()
-    );
-    info!("  Skipped: {}", stats.video_skipped());
-    if stats.video_original_size() > 0 {
-        if stats.video_compressed_size() > 0 {
-            info!(
-                "  Size reduction: {} -> {} ({:.1}% reduction)",
-                format_size(stats.video_original_size()),
-                format_size(stats.video_compressed_size()),
-                stats.video_compression_ratio()
-            );
-        } else {
-            info!(
-                "  Total size: {} (no compression applied)",
-                format_size(stats.video_original_size())
-            );
+        Commands::Lint { input_pack, json } => {
+            if let Err(e) = lint::lint_pack(&input_pack, json) {
+                fail(e);
+            }
         }
-    }
-
-    // Overall statistics
-    if stats.total_input_size() > 0 {
-        info!("");
-        info!("Overall:");
-        info!(
-            "  Total original size: {}",
-            format_size(stats.total_input_size())
-        );
-        info!(
-            "  Total compressed size: {}",
-            format_size(stats.total_output_size())
-        );
-        info!("  Total reduction: {:.1}%", stats.total_compression_ratio());
-
-        // Show actual filesystem sizes for verification
-        if let Ok(input_metadata) = std::fs::metadata(&input_pack) {
-            let input_file_size = input_metadata.len();
-            info!(
-                "  Input file size: {} (filesystem)",
-                format_size(input_file_size)
-            );
+        Commands::Repair {
+            input_pack,
+            output_pack,
+            json,
+        } => match repair::repair_pack(&input_pack, output_pack) {
+            Ok(report) if json => match serde_json::to_string_pretty(&report) {
+                Ok(json) => println!("{json}"),
+                Err(e) => fail(e.into()),
+            },
+            Ok(report) => repair::print_report(&report),
+            Err(e) => fail(e),
+        },
+        Commands::ExportHtml {
+            input_pack,
+            output_dir,
+        } => match export::export_html(&input_pack, &output_dir) {
+            Ok(page) => println!("Open {} in a browser", page.display()),
+            Err(e) => fail(e),
+        },
+        Commands::Capabilities { json, ffmpeg_path } => {
+            let capabilities = capabilities::Capabilities::detect(ffmpeg_path.as_deref());
+            if let Err(e) = capabilities::print_capabilities(&capabilities, json) {
+                fail(e);
+            }
         }
-        if let Ok(output_metadata) = std::fs::metadata(&output_path) {
-            let output_file_size = output_metadata.len();
-            info!(
-                "  Output file size: {} (filesystem)",
-                format_size(output_file_size)
-            );
+        Commands::Media {
+            command:
+                MediaCommands::Compress {
+                    file,
+                    output,
+                    quality,
+                    settings,
+                },
+        } => {
+            let mut options = settings.into_options();
+            if let Some(quality) = quality {
+                options.image_quality = quality;
+                options.audio.quality = quality;
+                options.video_quality = quality;
+            }
+            if let Err(e) = standalone::compress_media_file(&file, output, options, &multi_progress)
+            {
+                fail(e);
+            }
         }
-    }
-
-    Ok(())
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
-
-    #[test]
-    fn test_output_path_generation() {
-        let input = PathBuf::from("test.siq");
-        let expected = PathBuf::from("test_compressed.siq");
-
-        // This tests the logic in compress_pack function
-        let mut path = input.clone();
-        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap();
-        path.set_file_name(format!("{stem}_compressed.siq"));
-
-        assert_eq!(path, expected);
-    }
-
-    #[test]
-    fn test_invalid_input_validation() {
-        let result = compress_pack(
-            PathBuf::from("nonexistent.siq"),
-            None,
-            85,
-            85,
-            75,
-            false,
-            false,
-            false,
-            None,
-            false,
-            MultiProgress::new(),
-        );
-        assert!(result.is_err());
-
-        // Create a temporary file without .siq extension
-        let mut temp_file = NamedTempFile::new().unwrap();
-        temp_file.write_all(b"test").unwrap();
-        let temp_path = temp_file.path().to_path_buf();
-
-        let result = compress_pack(
-            temp_path,
-            None,
-            85,
-            85,
-            75,
-            false,
-            false,
-            false,
-            None,
-            false,
-            MultiProgress::new(),
-        );
-        assert!(result.is_err());
-    }
-
-    #[test]
-    fn test_quality_validation() {
-        // Quality should be between 1 and 100
-        let temp_siq = create_temp_siq_file();
-
-        let result = compress_pack(
-            temp_siq.clone(),
-            None,
-            0,
-            85,
-            75,
-            false,
-            false,
-            false,
-            None,
-            false,
-            MultiProgress::new(),
-        );
-        assert!(result.is_err());
-
-        let result = compress_pack(
-            temp_siq.clone(),
-            None,
-            101,
-            85,
-            75,
-            false,
-            false,
-            false,
-            None,
-            false,
-            MultiProgress::new(),
-        );
-        assert!(result.is_err());
-
-        let result = compress_pack(
-            temp_siq.clone(),
-            None,
-            85,
-            0,
-            75,
-            false,
-            false,
-            false,
-            None,
-            false,
-            MultiProgress::new(),
-        );
-        assert!(result.is_err());
-
-        let result = compress_pack(
-            temp_siq.clone(),
-            None,
-            85,
-            101,
-            75,
-            false,
-            false,
-            false,
-            None,
-            false,
-            MultiProgress::new(),
-        );
-        assert!(result.is_err());
-
-        let result = compress_pack(
-            temp_siq.clone(),
-            None,
-            85,
-            85,
-            0,
-            false,
-            false,
-            false,
-            None,
-            false,
-            MultiProgress::new(),
-        );
-        assert!(result.is_err());
-
-        let result = compress_pack(
-            temp_siq.clone(),
-            None,
-            85,
-            85,
-            101,
-            false,
-            false,
-            false,
-            None,
-            false,
-            MultiProgress::new(),
-        );
-        assert!(result.is_err());
-
-        // Valid quality should work (though will fail due to invalid ZIP content)
-        let result = compress_pack(
-            temp_siq,
-            None,
-            50,
-            75,
-            60,
-            false,
-            false,
-            false,
-            None,
-            false,
-            MultiProgress::new(),
-        );
-        // This will fail at ZIP reading stage, but quality validation should pass
-        assert!(result.is_err());
-        assert!(
-            !result
-                .unwrap_err()
-                .to_string()
-                .contains("quality must be between")
-        );
-    }
-
-    fn create_temp_siq_file() -> PathBuf {
-        let mut temp_file = NamedTempFile::new().unwrap();
-        temp_file.write_all(b"fake siq content").unwrap();
+        Commands::ExplainQuality {
+            quality,
+            image_format,
+            audio_mode,
+            audio_engine,
+            audio_stereo_mode,
+            audio_lowpass_hz,
+            video_mode,
+            video_denoise,
+            video_compat,
+        } => {
+            if !(1..=100).contains(&quality) {
+                fail(
+                    SicomError::InvalidOptions("Quality must be between 1 and 100".to_string())
+                        .into(),
+                );
+            }
 
-        // Rename to have .siq extension
-        let temp_path = temp_file.path().with_extension("siq");
-        std::fs::copy(temp_file.path(), &temp_path).unwrap();
-        temp_path
+            let options = CompressOptions::new()
+                .image_quality(quality)
+                .image_format(image_format)
+                .audio(
+                    audio::AudioOptions::new()
+                        .quality(quality)
+                        .mode(audio_mode)
+                        .engine(audio_engine)
+                        .stereo_mode(audio_stereo_mode)
+                        .lowpass_hz(audio_lowpass_hz),
+                )
+                .video_quality(quality)
+                .video_mode(video_mode)
+                .video_denoise(video_denoise)
+                .video_compat(video_compat);
+
+            println!("Quality {quality}:");
+            for (kind, description) in quality::explain_quality(&options) {
+                println!("  {:<6} {description}", kind.label());
+            }
+        }
     }
 }
@@ -0,0 +1,340 @@
+use crate::error::SicomError;
+use crate::options::CompressOptions;
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// Bumped when the layout changes; manifests of other versions are ignored
+const MANIFEST_VERSION: u32 = 1;
+
+/// What happened to a media entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryOutcome {
+    Compressed,
+    /// Compression didn't make it smaller
+    KeptOriginal,
+    Skipped,
+}
+
+/// Result recorded for one input media entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// CRC-32 and size of the input entry, as stored in the ZIP directory
+    pub crc32: u32,
+    pub size: u64,
+    pub outcome: EntryOutcome,
+    /// Entry name in the output pack
+    pub output: String,
+    pub output_crc32: u32,
+    pub output_size: u64,
+    /// Name `output` had before `--transliterate-names` made it ASCII
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transliterated_from: Option<String>,
+}
+
+impl ManifestEntry {
+    /// Entry whose output checksum is filled in by [`Manifest::record_outputs`]
+    pub fn new(crc32: u32, size: u64, outcome: EntryOutcome, output: String) -> Self {
+        Self {
+            crc32,
+            size,
+            outcome,
+            output,
+            output_crc32: 0,
+            output_size: 0,
+            transliterated_from: None,
+        }
+    }
+}
+
+/// Sidecar written next to an output pack in incremental mode
+///
+/// Maps every input media entry to its result, so the next run can copy the
+/// results of unchanged entries instead of compressing them again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    version: u32,
+    /// Settings the output was produced with; results are reused only with identical settings
+    options: serde_json::Value,
+    /// BLAKE3 of the input and output packs, for recognising an up-to-date output
+    #[serde(default)]
+    pub input_hash: Option<String>,
+    #[serde(default)]
+    pub output_hash: Option<String>,
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+/// Settings as far as they affect the output bytes
+pub(crate) fn options_key(options: &CompressOptions) -> serde_json::Value {
+    let options = options
+        .clone()
+        .time_limit_seconds(None)
+        .zip_level(None)
+        .incremental(false)
+        .force(false)
+        .resume(false)
+        .allow_recompress(false)
+        .xml_diff(None)
+        .unprocessed_list(None);
+    serde_json::to_value(options).unwrap_or_default()
+}
+
+/// BLAKE3 of a file's contents as hex
+pub fn file_hash(path: &Path) -> Result<String> {
+    let file = File::open(path).map_err(|source| SicomError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut hasher = blake3::Hasher::new();
+    hasher
+        .update_reader(BufReader::new(file))
+        .map_err(|source| SicomError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// `path` with `suffix` appended to the file name
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+impl Manifest {
+    pub fn new(options: &CompressOptions) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            options: options_key(options),
+            input_hash: None,
+            output_hash: None,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Sidecar location for an output pack (`pack.siq` -> `pack.siq.manifest.json`)
+    pub fn path_for(output: &Path) -> PathBuf {
+        with_suffix(output, ".manifest.json")
+    }
+
+    /// Read a manifest; None when it's missing or unusable
+    pub fn load(path: &Path) -> Option<Self> {
+        let json = fs::read_to_string(path).ok()?;
+        match serde_json::from_str::<Self>(&json) {
+            Ok(manifest) if manifest.version == MANIFEST_VERSION => Some(manifest),
+            Ok(manifest) => {
+                debug!("Ignoring manifest version {}", manifest.version);
+                None
+            }
+            Err(e) => {
+                warn!("Ignoring unreadable manifest {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec_pretty(self)?;
+        fs::write(path, json).map_err(|source| SicomError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Ok(())
+    }
+
+    pub fn matches_options(&self, options: &CompressOptions) -> bool {
+        self.options == options_key(options)
+    }
+
+    /// Whether `output` was produced from the input with `input_hash` using these
+    /// settings and hasn't changed since
+    pub fn is_up_to_date(output: &Path, input_hash: &str, options: &CompressOptions) -> bool {
+        let Some(manifest) = Self::load(&Self::path_for(output)) else {
+            return false;
+        };
+        manifest.matches_options(options)
+            && manifest.input_hash.as_deref() == Some(input_hash)
+            && manifest.output_hash.is_some()
+            && file_hash(output).ok() == manifest.output_hash
+    }
+
+    /// Fill in output checksums from the finished pack, dropping entries it lacks
+    pub fn record_outputs(&mut self, output: &Path) -> Result<()> {
+        self.output_hash = Some(file_hash(output)?);
+        let mut archive = crate::open_pack(output)?;
+        self.entries
+            .retain(|_, entry| match archive.by_name(&entry.output) {
+                Ok(file) => {
+                    entry.output_crc32 = file.crc32();
+                    entry.output_size = file.size();
+                    true
+                }
+                Err(_) => false,
+            });
+        Ok(())
+    }
+}
+
+/// Output of the previous incremental run, moved aside while the new one is written
+///
+/// It is put back if the run fails and deleted once the run succeeds.
+pub struct PreviousOutput {
+    manifest: Manifest,
+    archive: Option<ZipArchive<BufReader<File>>>,
+    moved_to: PathBuf,
+    restore_to: PathBuf,
+    succeeded: bool,
+}
+
+impl PreviousOutput {
+    /// Pick up the previous output at `output` if its manifest matches the settings
+    pub fn open(output: &Path, options: &CompressOptions) -> Result<Option<Self>> {
+        let Some(manifest) = Manifest::load(&Manifest::path_for(output)) else {
+            return Ok(None);
+        };
+        if !output.exists() {
+            return Ok(None);
+        }
+        if !manifest.matches_options(options) {
+            info!("Settings changed since the previous run, compressing everything");
+            return Ok(None);
+        }
+
+        let moved_to = with_suffix(output, ".previous");
+        fs::rename(output, &moved_to).map_err(|source| SicomError::Io {
+            path: output.to_path_buf(),
+            source,
+        })?;
+        let mut previous = Self {
+            manifest,
+            archive: None,
+            moved_to,
+            restore_to: output.to_path_buf(),
+            succeeded: false,
+        };
+        match crate::open_pack(&previous.moved_to) {
+            Ok(archive) => previous.archive = Some(archive),
+            Err(e) => {
+                warn!("Previous output is unreadable, compressing everything: {e:#}");
+                return Ok(None);
+            }
+        }
+        info!(
+            "Incremental run: {} media entries recorded by the previous run",
+            previous.manifest.entries.len()
+        );
+        Ok(Some(previous))
+    }
+
+    /// Previous result for an input entry whose CRC and size are unchanged
+    pub fn unchanged(&mut self, name: &str, crc32: u32, size: u64) -> Option<ManifestEntry> {
+        let entry = self.manifest.entries.get(name)?;
+        if entry.crc32 != crc32 || entry.size != size {
+            return None;
+        }
+        // The previous pack must still hold exactly what was recorded
+        let file = self.archive.as_mut()?.by_name(&entry.output).ok()?;
+        (file.crc32() == entry.output_crc32 && file.size() == entry.output_size)
+            .then(|| entry.clone())
+    }
+
+    /// Contents of an entry of the previous output, to carry over without recompressing it
+    pub fn read_entry(&mut self, name: &str) -> Result<Vec<u8>> {
+        let archive = self.archive.as_mut().context("Previous output is closed")?;
+        let mut file = archive
+            .by_name(name)
+            .with_context(|| format!("Failed to read {name} from the previous output"))?;
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)
+            .with_context(|| format!("Failed to read {name} from the previous output"))?;
+        Ok(data)
+    }
+
+    /// The new output is complete; the previous one can go
+    pub fn finish(mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Drop for PreviousOutput {
+    fn drop(&mut self) {
+        // Close the archive first so the file can be renamed or removed everywhere
+        self.archive = None;
+        let result = if self.succeeded {
+            fs::remove_file(&self.moved_to)
+        } else {
+            let _ = fs::remove_file(&self.restore_to);
+            fs::rename(&self.moved_to, &self.restore_to)
+        };
+        if let Err(e) = result {
+            warn!(
+                "Failed to clean up previous output {}: {e}",
+                self.moved_to.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write_pack;
+
+    #[test]
+    fn test_previous_output_reuse_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.siq");
+        write_pack(&output, &[("Images/a.webp", b"webp")]);
+
+        let options = CompressOptions::new().incremental(true);
+        let mut manifest = Manifest::new(&options);
+        manifest.entries.insert(
+            "Images/a.png".to_string(),
+            ManifestEntry::new(
+                7,
+                100,
+                EntryOutcome::Compressed,
+                "Images/a.webp".to_string(),
+            ),
+        );
+        manifest.record_outputs(&output).unwrap();
+        manifest.save(&Manifest::path_for(&output)).unwrap();
+
+        // Different settings: nothing is reused and the output stays in place
+        let other = CompressOptions::new().image_quality(90).incremental(true);
+        assert!(PreviousOutput::open(&output, &other).unwrap().is_none());
+        assert!(output.exists());
+
+        // Time limits don't affect the output
+        let timed = options.clone().time_limit_seconds(Some(60));
+        let mut previous = PreviousOutput::open(&output, &timed).unwrap().unwrap();
+        assert!(!output.exists());
+        assert_eq!(
+            previous.unchanged("Images/a.png", 7, 100).unwrap().output,
+            "Images/a.webp"
+        );
+        assert!(previous.unchanged("Images/a.png", 8, 100).is_none());
+        assert!(previous.unchanged("Images/b.png", 7, 100).is_none());
+
+        // A failed run puts the previous output back
+        File::create(&output).unwrap();
+        drop(previous);
+        assert_eq!(
+            crate::open_pack(&output).unwrap().file_names().next(),
+            Some("Images/a.webp")
+        );
+
+        let previous = PreviousOutput::open(&output, &options).unwrap().unwrap();
+        previous.finish();
+        assert!(!output.exists());
+        assert!(!with_suffix(&output, ".previous").exists());
+    }
+}
@@ -0,0 +1,397 @@
+use crate::progress::ProgressLogger;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// Number of hex digits of the content hash used in hashed filenames
+const HASH_NAME_LEN: usize = 16;
+
+/// Category of media inside a pack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MediaKind {
+    Image,
+    Audio,
+    Video,
+}
+
+impl MediaKind {
+    pub const ALL: [Self; 3] = [Self::Image, Self::Audio, Self::Video];
+
+    /// Pack directory holding this kind of media
+    pub const fn directory(self) -> &'static str {
+        match self {
+            Self::Image => "Images/",
+            Self::Audio => "Audio/",
+            Self::Video => "Video/",
+        }
+    }
+
+    /// Kind whose pack directory holds `entry_name`
+    pub fn of_entry(entry_name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| entry_name.starts_with(kind.directory()))
+    }
+
+    /// Capitalized name used in summaries
+    pub const fn title(self) -> &'static str {
+        match self {
+            Self::Image => "Images",
+            Self::Audio => "Audio",
+            Self::Video => "Video",
+        }
+    }
+
+    /// Lowercase name used in log messages
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Image => "image",
+            Self::Audio => "audio",
+            Self::Video => "video",
+        }
+    }
+}
+
+/// How media entries are named in the output pack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum RenameScheme {
+    /// Keep the original name, only changing the extension on conversion
+    #[default]
+    Extension,
+    /// Content-hash filenames (ASCII-only, stable across runs, cache friendly)
+    Hashed,
+}
+
+/// Lower quality tier for long audio and video
+///
+/// Long background music and clips dominate pack size but matter least, so
+/// they can give up some quality that short media keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct LongMediaPenalty {
+    /// Media at least this long counts as long
+    pub min_seconds: u32,
+    /// Quality points subtracted for long media
+    pub quality_reduction: u8,
+}
+
+impl Default for LongMediaPenalty {
+    fn default() -> Self {
+        Self {
+            min_seconds: 300,
+            quality_reduction: 15,
+        }
+    }
+}
+
+impl LongMediaPenalty {
+    pub const fn min_seconds(mut self, seconds: u32) -> Self {
+        self.min_seconds = seconds;
+        self
+    }
+
+    pub const fn quality_reduction(mut self, points: u8) -> Self {
+        self.quality_reduction = points;
+        self
+    }
+
+    /// Quality to encode with; unknown durations keep the base quality
+    pub fn apply(&self, quality: u8, duration_seconds: Option<f64>) -> u8 {
+        match duration_seconds {
+            Some(duration) if duration >= f64::from(self.min_seconds) => {
+                quality.saturating_sub(self.quality_reduction).max(1)
+            }
+            _ => quality,
+        }
+    }
+}
+
+/// Content-hash name for an entry, keeping its directory and extension
+pub fn hashed_entry_name(entry_name: &str, data: &[u8]) -> String {
+    let hash = blake3::hash(data).to_hex();
+    let directory = entry_name.rfind('/').map_or("", |pos| &entry_name[..=pos]);
+    let extension = Path::new(entry_name)
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(str::to_lowercase);
+
+    match extension {
+        Some(ext) => format!("{directory}{}.{ext}", &hash[..HASH_NAME_LEN]),
+        None => format!("{directory}{}", &hash[..HASH_NAME_LEN]),
+    }
+}
+
+/// Facts about a media file gathered without compressing it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    pub format: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub duration_seconds: Option<f64>,
+    pub bitrate_kbps: Option<u32>,
+}
+
+impl fmt::Display for MediaInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(format) = &self.format {
+            parts.push(format.clone());
+        }
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            parts.push(format!("{width}x{height}"));
+        }
+        if let Some(duration) = self.duration_seconds {
+            parts.push(format!("{duration:.1}s"));
+        }
+        if let Some(bitrate) = self.bitrate_kbps {
+            parts.push(format!("{bitrate} kbps"));
+        }
+
+        if parts.is_empty() {
+            write!(f, "unknown")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+/// Result of compressing a single media file
+#[derive(Debug)]
+pub struct MediaOutput {
+    pub data: Vec<u8>,
+    /// Entry name in the output pack (differs from the input when the extension changes)
+    pub filename: String,
+    /// Output codec name for log messages
+    pub codec: &'static str,
+    /// Lower-resolution copies stored next to the output (video ladder)
+    pub renditions: Vec<RenditionOutput>,
+}
+
+/// Extra encode of a video at a lower resolution
+#[derive(Debug)]
+pub struct RenditionOutput {
+    pub rendition: crate::video::Rendition,
+    pub data: Vec<u8>,
+}
+
+/// What a processor would do with a file, without compressing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaPlan {
+    /// Entry name the file would get in the output pack
+    pub filename: String,
+    /// Encoder and parameters chosen for this file
+    pub encoder: String,
+    /// Expected output size in bytes (None when it can't be predicted cheaply)
+    pub estimated_size: Option<u64>,
+}
+
+/// Common interface of the per-media compression pipelines
+pub trait MediaProcessor {
+    fn kind(&self) -> MediaKind;
+
+    /// Check whether the processor can handle this file
+    fn supported(&self, filename: &str) -> bool;
+
+    /// Gather metadata about the file (best effort)
+    fn analyze(&self, data: &[u8], filename: &str) -> MediaInfo;
+
+    /// Decide how the file would be compressed; errors explain why it would be skipped
+    fn plan(&self, data: &[u8], filename: &str) -> Result<MediaPlan>;
+
+    /// Decode (or probe) the file to confirm players can open it; errors say what is broken
+    fn check(&self, data: &[u8], filename: &str) -> Result<()>;
+
+    /// Compress the file, reporting progress through the logger
+    fn compress(
+        &self,
+        data: &[u8],
+        filename: &str,
+        progress: &mut ProgressLogger,
+    ) -> Result<MediaOutput>;
+}
+
+/// Registered processor with an optional reason why it is disabled for this run
+struct Registration {
+    processor: Box<dyn MediaProcessor>,
+    disabled_reason: Option<&'static str>,
+}
+
+/// Set of media processors driving the pack pipeline
+#[derive(Default)]
+pub struct MediaRegistry {
+    registrations: Vec<Registration>,
+}
+
+impl MediaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a processor; files it supports are copied unchanged when `disabled_reason` is set
+    pub fn register(
+        &mut self,
+        processor: Box<dyn MediaProcessor>,
+        disabled_reason: Option<&'static str>,
+    ) {
+        self.registrations.push(Registration {
+            processor,
+            disabled_reason,
+        });
+    }
+
+    /// Find the processor for a pack entry (media must live in its category directory)
+    pub fn find(&self, filename: &str) -> Option<(&dyn MediaProcessor, Option<&'static str>)> {
+        self.find_matching(|processor| {
+            filename.starts_with(processor.kind().directory()) && processor.supported(filename)
+        })
+    }
+
+    /// Find the processor for a standalone file outside a pack (matched by extension only)
+    pub fn find_standalone(
+        &self,
+        filename: &str,
+    ) -> Option<(&dyn MediaProcessor, Option<&'static str>)> {
+        self.find_matching(|processor| processor.supported(filename))
+    }
+
+    fn find_matching(
+        &self,
+        matches: impl Fn(&dyn MediaProcessor) -> bool,
+    ) -> Option<(&dyn MediaProcessor, Option<&'static str>)> {
+        self.registrations
+            .iter()
+            .find(|registration| matches(registration.processor.as_ref()))
+            .map(|registration| {
+                (
+                    registration.processor.as_ref(),
+                    registration.disabled_reason,
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeProcessor(MediaKind);
+
+    impl MediaProcessor for FakeProcessor {
+        fn kind(&self) -> MediaKind {
+            self.0
+        }
+
+        fn supported(&self, filename: &str) -> bool {
+            filename.ends_with(".fake")
+        }
+
+        fn analyze(&self, _data: &[u8], _filename: &str) -> MediaInfo {
+            MediaInfo::default()
+        }
+
+        fn plan(&self, data: &[u8], filename: &str) -> Result<MediaPlan> {
+            Ok(MediaPlan {
+                filename: filename.to_string(),
+                encoder: "copy".to_string(),
+                estimated_size: Some(data.len() as u64),
+            })
+        }
+
+        fn check(&self, _data: &[u8], _filename: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn compress(
+            &self,
+            data: &[u8],
+            filename: &str,
+            _progress: &mut ProgressLogger,
+        ) -> Result<MediaOutput> {
+            Ok(MediaOutput {
+                data: data.to_vec(),
+                filename: filename.to_string(),
+                codec: "fake",
+                renditions: Vec::new(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_registry_find() {
+        let mut registry = MediaRegistry::new();
+        registry.register(Box::new(FakeProcessor(MediaKind::Image)), None);
+        registry.register(
+            Box::new(FakeProcessor(MediaKind::Video)),
+            Some("skip_video flag"),
+        );
+
+        let (processor, reason) = registry.find("Images/a.fake").unwrap();
+        assert_eq!(processor.kind(), MediaKind::Image);
+        assert_eq!(reason, None);
+
+        let (processor, reason) = registry.find("Video/a.fake").unwrap();
+        assert_eq!(processor.kind(), MediaKind::Video);
+        assert_eq!(reason, Some("skip_video flag"));
+
+        // Wrong directory or unsupported extension
+        assert!(registry.find("Audio/a.fake").is_none());
+        assert!(registry.find("Images/a.jpg").is_none());
+        assert!(registry.find("content.xml").is_none());
+
+        // Standalone files have no pack directory
+        let (processor, _) = registry.find_standalone("/tmp/a.fake").unwrap();
+        assert_eq!(processor.kind(), MediaKind::Image);
+        assert!(registry.find_standalone("/tmp/a.jpg").is_none());
+    }
+
+    #[test]
+    fn test_long_media_penalty() {
+        let penalty = LongMediaPenalty::default().quality_reduction(20);
+        assert_eq!(penalty.apply(60, Some(299.0)), 60);
+        assert_eq!(penalty.apply(60, Some(300.0)), 40);
+        assert_eq!(penalty.apply(60, None), 60);
+        assert_eq!(penalty.apply(10, Some(3600.0)), 1);
+    }
+
+    #[test]
+    fn test_hashed_entry_name() {
+        let name = hashed_entry_name("Images/%D0%92%D0%94%D0%9D%D0%A5.WEBP", b"pixels");
+        assert!(name.starts_with("Images/"));
+        assert!(name.ends_with(".webp"));
+        assert_eq!(name.len(), "Images/".len() + HASH_NAME_LEN + ".webp".len());
+        assert!(name.is_ascii());
+
+        // Stable for identical content, different for different content
+        assert_eq!(name, hashed_entry_name("Images/other.webp", b"pixels"));
+        assert_ne!(
+            name,
+            hashed_entry_name("Images/other.webp", b"other pixels")
+        );
+        assert!(!hashed_entry_name("blob", b"x").contains('.'));
+    }
+
+    #[test]
+    fn test_media_info_display() {
+        let info = MediaInfo {
+            format: Some("MP3".to_string()),
+            duration_seconds: Some(12.345),
+            bitrate_kbps: Some(192),
+            ..Default::default()
+        };
+        assert_eq!(info.to_string(), "MP3, 12.3s, 192 kbps");
+
+        let info = MediaInfo {
+            format: Some("PNG".to_string()),
+            width: Some(640),
+            height: Some(480),
+            ..Default::default()
+        };
+        assert_eq!(info.to_string(), "PNG, 640x480");
+
+        assert_eq!(MediaInfo::default().to_string(), "unknown");
+    }
+}
@@ -0,0 +1,199 @@
+use crate::media::MediaKind;
+use crate::stats::CompressionStats;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+/// Upper bounds (seconds) of the encode duration histogram buckets
+const DURATION_BUCKETS: [f64; 12] = [
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Non-cumulative counts per bucket; the extra slot is +Inf
+    buckets: [u64; DURATION_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    packs_processed: u64,
+    failures: BTreeMap<&'static str, u64>,
+    input_bytes: u64,
+    output_bytes: u64,
+    encode_durations: [Histogram; 3],
+}
+
+/// Service-level counters for long-running modes, rendered for Prometheus
+///
+/// Shared between the worker and the HTTP endpoint, so everything sits
+/// behind one mutex; updates happen once per pack.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        self.counters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Account a successfully compressed pack
+    pub fn record_pack(&self, stats: &CompressionStats) {
+        let mut counters = self.counters();
+        counters.packs_processed += 1;
+        counters.input_bytes = counters
+            .input_bytes
+            .saturating_add(stats.total_input_size());
+        counters.output_bytes = counters
+            .output_bytes
+            .saturating_add(stats.total_output_size());
+        for kind in MediaKind::ALL {
+            for elapsed in stats.encode_times(kind) {
+                counters.encode_durations[kind as usize].observe(elapsed.as_secs_f64());
+            }
+        }
+    }
+
+    /// Account a failed attempt by its error code (see `SicomError::code`)
+    pub fn record_failure(&self, code: &'static str) {
+        *self.counters().failures.entry(code).or_insert(0) += 1;
+    }
+
+    /// Prometheus text exposition format (version 0.0.4)
+    pub fn render(&self) -> String {
+        let counters = self.counters();
+        let mut out = String::new();
+
+        let mut counter = |name: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {value}");
+        };
+        counter(
+            "sicom_packs_processed_total",
+            "Packs compressed successfully.",
+            counters.packs_processed,
+        );
+        counter(
+            "sicom_input_bytes_total",
+            "Uncompressed size of processed packs.",
+            counters.input_bytes,
+        );
+        counter(
+            "sicom_bytes_saved_total",
+            "Bytes saved by compression across processed packs.",
+            counters.input_bytes.saturating_sub(counters.output_bytes),
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP sicom_failures_total Failed pack attempts by error code."
+        );
+        let _ = writeln!(out, "# TYPE sicom_failures_total counter");
+        for (code, count) in &counters.failures {
+            let _ = writeln!(out, "sicom_failures_total{{code=\"{code}\"}} {count}");
+        }
+
+        let name = "sicom_encode_duration_seconds";
+        let _ = writeln!(out, "# HELP {name} Time spent encoding one media file.");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for kind in MediaKind::ALL {
+            let histogram = &counters.encode_durations[kind as usize];
+            let kind = kind.label();
+            let mut cumulative = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{kind=\"{kind}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{kind=\"{kind}\",le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(out, "{name}_sum{{kind=\"{kind}\"}} {}", histogram.sum);
+            let _ = writeln!(out, "{name}_count{{kind=\"{kind}\"}} {}", histogram.count);
+        }
+        out
+    }
+}
+
+/// Serve `GET /metrics` on a background thread
+#[cfg(feature = "server")]
+pub fn serve(address: &str, metrics: std::sync::Arc<Metrics>) -> anyhow::Result<()> {
+    let server = tiny_http::Server::http(address)
+        .map_err(|e| anyhow::anyhow!("Failed to listen on {address}: {e}"))?;
+    log::info!("Serving metrics on http://{address}/metrics");
+
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let content_type = tiny_http::Header::from_bytes(
+                    "Content-Type",
+                    "text/plain; version=0.0.4; charset=utf-8",
+                )
+                .expect("static header is valid");
+                tiny_http::Response::from_string(metrics.render()).with_header(content_type)
+            } else {
+                tiny_http::Response::from_string("Not found").with_status_code(404)
+            };
+            let _ = request.respond(response);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        let mut stats = CompressionStats::new();
+        stats.add_processed(MediaKind::Image, 1000, 400);
+        stats.add_encode_time(MediaKind::Image, Duration::from_millis(200));
+        stats.add_encode_time(MediaKind::Image, Duration::from_secs(7));
+        metrics.record_pack(&stats);
+        metrics.record_failure("archive_error");
+        metrics.record_failure("archive_error");
+
+        let text = metrics.render();
+        assert!(text.contains("sicom_packs_processed_total 1\n"));
+        assert!(text.contains("sicom_input_bytes_total 1000\n"));
+        assert!(text.contains("sicom_bytes_saved_total 600\n"));
+        assert!(text.contains("sicom_failures_total{code=\"archive_error\"} 2\n"));
+        assert!(
+            text.contains("sicom_encode_duration_seconds_bucket{kind=\"image\",le=\"0.25\"} 1\n")
+        );
+        assert!(
+            text.contains("sicom_encode_duration_seconds_bucket{kind=\"image\",le=\"10\"} 2\n")
+        );
+        assert!(
+            text.contains("sicom_encode_duration_seconds_bucket{kind=\"image\",le=\"+Inf\"} 2\n")
+        );
+        assert!(text.contains("sicom_encode_duration_seconds_count{kind=\"video\"} 0\n"));
+    }
+}
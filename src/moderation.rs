@@ -0,0 +1,166 @@
+use anyhow::{Context, Result, anyhow};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Settings for flagging potentially NSFW images
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContentScanOptions {
+    /// Classifier executable (see `CommandClassifier`)
+    pub classifier: PathBuf,
+    /// Score (0.0-1.0) at or above which an image is flagged
+    pub threshold: f32,
+}
+
+/// Pluggable image classifier returning the probability (0.0-1.0) that content is NSFW
+pub trait ContentClassifier {
+    fn classify(&self, data: &[u8], filename: &str) -> Result<f32>;
+}
+
+/// Classifier backed by an external program
+///
+/// The program gets the entry name as its only argument and the image bytes
+/// on stdin, and prints a single score between 0 and 1 to stdout. This keeps
+/// model runtimes (ONNX, Python, remote APIs) out of sicom itself.
+pub struct CommandClassifier {
+    pub command: PathBuf,
+}
+
+impl ContentClassifier for CommandClassifier {
+    fn classify(&self, data: &[u8], filename: &str) -> Result<f32> {
+        let mut child = Command::new(&self.command)
+            .arg(filename)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to start classifier: {:?}", self.command))?;
+
+        // A classifier may exit without reading everything; its output still counts
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(data);
+        }
+
+        let output = child
+            .wait_with_output()
+            .with_context(|| "Failed to wait for classifier")?;
+        if !output.status.success() {
+            return Err(anyhow!("Classifier exited with {}", output.status));
+        }
+        parse_score(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+fn parse_score(output: &str) -> Result<f32> {
+    let score: f32 = output
+        .trim()
+        .parse()
+        .with_context(|| format!("Classifier printed an invalid score: {:?}", output.trim()))?;
+    if !(0.0..=1.0).contains(&score) {
+        return Err(anyhow!("Classifier score out of range: {score}"));
+    }
+    Ok(score)
+}
+
+/// An image the classifier scored at or above the threshold
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContentFlag {
+    pub entry: String,
+    pub score: f32,
+}
+
+/// Runs the classifier over pack images and collects flags without blocking compression
+pub struct ContentScanner {
+    classifier: Box<dyn ContentClassifier>,
+    threshold: f32,
+    flags: Vec<ContentFlag>,
+}
+
+impl ContentScanner {
+    pub fn new(classifier: Box<dyn ContentClassifier>, threshold: f32) -> Self {
+        Self {
+            classifier,
+            threshold,
+            flags: Vec::new(),
+        }
+    }
+
+    pub fn from_options(options: &ContentScanOptions) -> Self {
+        Self::new(
+            Box::new(CommandClassifier {
+                command: options.classifier.clone(),
+            }),
+            options.threshold,
+        )
+    }
+
+    /// Classify one image; failures are logged and never stop processing
+    pub fn scan(&mut self, data: &[u8], filename: &str) {
+        match self.classifier.classify(data, filename) {
+            Ok(score) if score >= self.threshold => {
+                warn!("  Flagged as potentially NSFW ({score:.2}): {filename}");
+                self.flags.push(ContentFlag {
+                    entry: filename.to_string(),
+                    score,
+                });
+            }
+            Ok(_) => {}
+            Err(e) => warn!("  Content scan failed for {filename}: {e}"),
+        }
+    }
+
+    pub fn flags(&self) -> &[ContentFlag] {
+        &self.flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scores images by a marker byte, failing on empty input
+    struct FakeClassifier;
+
+    impl ContentClassifier for FakeClassifier {
+        fn classify(&self, data: &[u8], _filename: &str) -> Result<f32> {
+            data.first()
+                .map(|&b| f32::from(b) / 255.0)
+                .ok_or_else(|| anyhow!("empty image"))
+        }
+    }
+
+    #[test]
+    fn test_scanner_flags_above_threshold() {
+        let mut scanner = ContentScanner::new(Box::new(FakeClassifier), 0.8);
+        scanner.scan(&[255], "Images/a.jpg");
+        scanner.scan(&[10], "Images/b.jpg");
+        scanner.scan(&[], "Images/broken.jpg");
+        scanner.scan(&[230], "Images/c.jpg");
+
+        let flagged: Vec<&str> = scanner.flags().iter().map(|f| f.entry.as_str()).collect();
+        assert_eq!(flagged, ["Images/a.jpg", "Images/c.jpg"]);
+        assert_eq!(scanner.flags()[0].score, 1.0);
+    }
+
+    #[test]
+    fn test_parse_score() {
+        assert_eq!(parse_score("0.25\n").unwrap(), 0.25);
+        assert!(parse_score("nsfw").is_err());
+        assert!(parse_score("1.5").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_classifier() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("classify.sh");
+        std::fs::write(&script, "#!/bin/sh\ncat > /dev/null\necho 0.9\n").unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        let classifier = CommandClassifier { command: script };
+        assert_eq!(classifier.classify(b"image", "Images/a.jpg").unwrap(), 0.9);
+    }
+}
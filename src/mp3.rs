@@ -0,0 +1,794 @@
+use anyhow::{Result, anyhow};
+
+/// MPEG audio version from the frame header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MpegVersion {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+/// Parsed MPEG-1/2/2.5 Layer III frame header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: MpegVersion,
+    pub bitrate_kbps: u32,
+    pub sample_rate: u32,
+    pub padding: bool,
+    pub channels: u8,
+}
+
+impl FrameHeader {
+    /// Number of PCM samples (per channel) encoded in one frame
+    pub const fn samples_per_frame(&self) -> u32 {
+        match self.version {
+            MpegVersion::Mpeg1 => 1152,
+            MpegVersion::Mpeg2 | MpegVersion::Mpeg25 => 576,
+        }
+    }
+
+    /// Total frame length in bytes, including the 4-byte header
+    pub const fn frame_len(&self) -> usize {
+        let coefficient = match self.version {
+            MpegVersion::Mpeg1 => 144,
+            MpegVersion::Mpeg2 | MpegVersion::Mpeg25 => 72,
+        };
+        (coefficient * self.bitrate_kbps * 1000 / self.sample_rate) as usize
+            + if self.padding { 1 } else { 0 }
+    }
+
+    /// Length of the side information following the header (and CRC)
+    pub const fn side_info_len(&self) -> usize {
+        match (self.version, self.channels) {
+            (MpegVersion::Mpeg1, 1) => 17,
+            (MpegVersion::Mpeg1, _) => 32,
+            (_, 1) => 9,
+            _ => 17,
+        }
+    }
+
+    /// Furthest `main_data_begin` can reach back into earlier frames
+    const fn max_main_data_begin(&self) -> usize {
+        match self.version {
+            MpegVersion::Mpeg1 => 511,
+            MpegVersion::Mpeg2 | MpegVersion::Mpeg25 => 255,
+        }
+    }
+}
+
+/// Summary of the Layer III frames found in an MP3 stream
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mp3Summary {
+    pub frames: u32,
+    pub duration_seconds: f64,
+    pub audio_bytes: u64,
+    pub sample_rate: u32,
+}
+
+/// Layer III bitrates in kbps, indexed by the 4-bit bitrate field
+const BITRATES_MPEG1: [u32; 16] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0,
+];
+const BITRATES_MPEG2: [u32; 16] = [
+    0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+];
+
+const fn bitrates(version: MpegVersion) -> &'static [u32; 16] {
+    match version {
+        MpegVersion::Mpeg1 => &BITRATES_MPEG1,
+        MpegVersion::Mpeg2 | MpegVersion::Mpeg25 => &BITRATES_MPEG2,
+    }
+}
+
+/// Parse a Layer III frame header from the first four bytes
+/// Returns None for anything that is not a valid Layer III header
+pub fn parse_frame_header(bytes: &[u8]) -> Option<FrameHeader> {
+    if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+
+    let version = match (bytes[1] >> 3) & 0x03 {
+        0b00 => MpegVersion::Mpeg25,
+        0b10 => MpegVersion::Mpeg2,
+        0b11 => MpegVersion::Mpeg1,
+        _ => return None, // Reserved
+    };
+
+    // Layer III only
+    if (bytes[1] >> 1) & 0x03 != 0b01 {
+        return None;
+    }
+
+    let bitrate_kbps = bitrates(version)[usize::from(bytes[2] >> 4)];
+    if bitrate_kbps == 0 {
+        return None; // Free format or invalid
+    }
+
+    let base_rate = match (bytes[2] >> 2) & 0x03 {
+        0 => 44100,
+        1 => 48000,
+        2 => 32000,
+        _ => return None, // Reserved
+    };
+    let sample_rate = match version {
+        MpegVersion::Mpeg1 => base_rate,
+        MpegVersion::Mpeg2 => base_rate / 2,
+        MpegVersion::Mpeg25 => base_rate / 4,
+    };
+
+    Some(FrameHeader {
+        version,
+        bitrate_kbps,
+        sample_rate,
+        padding: (bytes[2] >> 1) & 0x01 == 1,
+        channels: if bytes[3] >> 6 == 0b11 { 1 } else { 2 },
+    })
+}
+
+/// Length of a leading ID3v2 tag (including header and footer), or 0 when absent
+pub fn id3v2_tag_len(data: &[u8]) -> usize {
+    if data.len() < 10 || &data[..3] != b"ID3" {
+        return 0;
+    }
+
+    // Tag size is a 28-bit syncsafe integer
+    let size = data[6..10]
+        .iter()
+        .fold(0usize, |acc, &b| (acc << 7) | usize::from(b & 0x7F));
+    let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+    (10 + size + footer).min(data.len())
+}
+
+/// Find the next valid frame at or after `pos`
+/// A header only counts when the following frame also starts with a valid header
+/// (or the stream ends), which filters out false sync words inside tags and junk
+pub fn find_frame(data: &[u8], mut pos: usize) -> Option<(usize, FrameHeader)> {
+    while pos + 4 <= data.len() {
+        if let Some(header) = parse_frame_header(&data[pos..]) {
+            let next = pos + header.frame_len();
+            if next == data.len()
+                || (next + 4 <= data.len() && parse_frame_header(&data[next..]).is_some())
+            {
+                return Some((pos, header));
+            }
+        }
+        pos += 1;
+    }
+    None
+}
+
+/// Find the frame following `pos`
+/// Stays in sync while consecutive headers are valid, resyncs with lookahead otherwise.
+/// Returns None at the end of the stream or when the next frame is truncated
+fn next_frame(data: &[u8], pos: usize) -> Option<(usize, FrameHeader)> {
+    data.get(pos..)
+        .and_then(parse_frame_header)
+        .map(|header| (pos, header))
+        .or_else(|| find_frame(data, pos))
+        .filter(|(frame_pos, header)| frame_pos + header.frame_len() <= data.len())
+}
+
+/// Walk all Layer III frames and summarize duration and size
+pub fn scan_frames(data: &[u8]) -> Mp3Summary {
+    let mut summary = Mp3Summary::default();
+    let mut pos = id3v2_tag_len(data);
+
+    while let Some((frame_pos, header)) = next_frame(data, pos) {
+        let frame_len = header.frame_len();
+        summary.frames += 1;
+        summary.audio_bytes += frame_len as u64;
+        summary.duration_seconds +=
+            f64::from(header.samples_per_frame()) / f64::from(header.sample_rate);
+        summary.sample_rate = header.sample_rate;
+        pos = frame_pos + frame_len;
+    }
+
+    summary
+}
+
+/// Read an ID3v2 frame size (syncsafe in v2.4, plain big-endian in v2.3)
+fn id3v2_frame_size(bytes: &[u8], major_version: u8) -> usize {
+    if major_version == 4 {
+        bytes
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 7) | usize::from(b & 0x7F))
+    } else {
+        bytes
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 8) | usize::from(b))
+    }
+}
+
+/// Rebuild an ID3v2 tag without padding and without byte-identical duplicate frames
+/// Tags that use unsynchronisation, an extended header or an unknown version are kept as-is
+fn compact_id3v2(tag: &[u8]) -> Vec<u8> {
+    let major_version = tag.get(3).copied().unwrap_or(0);
+    let flags = tag.get(5).copied().unwrap_or(0);
+    if tag.len() < 10 || !matches!(major_version, 3 | 4) || flags & 0xC0 != 0 {
+        return tag.to_vec();
+    }
+
+    let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+    let end = tag.len() - footer;
+    let mut frames: Vec<&[u8]> = Vec::new();
+    let mut pos = 10;
+
+    while pos + 10 <= end && tag[pos] != 0 {
+        let frame_end = pos + 10 + id3v2_frame_size(&tag[pos + 4..pos + 8], major_version);
+        if frame_end > end {
+            return tag.to_vec(); // Malformed frame - don't risk losing metadata
+        }
+        let frame = &tag[pos..frame_end];
+        if !frames.contains(&frame) {
+            frames.push(frame);
+        }
+        pos = frame_end;
+    }
+
+    let body_len: usize = frames.iter().map(|frame| frame.len()).sum();
+    let mut compacted = Vec::with_capacity(10 + body_len);
+    compacted.extend_from_slice(&tag[..5]);
+    compacted.push(flags & !0x10); // Footer is dropped along with the padding
+    for shift in [21, 14, 7, 0] {
+        #[allow(clippy::cast_possible_truncation)]
+        compacted.push(((body_len >> shift) & 0x7F) as u8);
+    }
+    for frame in frames {
+        compacted.extend_from_slice(frame);
+    }
+    compacted
+}
+
+/// ID3v2.4 tag holding UTF-8 text frames (frame ID, text), empty without frames
+pub fn id3v2_text_tag(frames: &[(&str, String)]) -> Vec<u8> {
+    let syncsafe = |size: usize| {
+        [21, 14, 7, 0].map(|shift| {
+            #[allow(clippy::cast_possible_truncation)]
+            let byte = ((size >> shift) & 0x7F) as u8;
+            byte
+        })
+    };
+
+    let mut body = Vec::new();
+    for (id, text) in frames {
+        body.extend_from_slice(id.as_bytes());
+        body.extend_from_slice(&syncsafe(text.len() + 1));
+        body.extend_from_slice(&[0, 0]);
+        body.push(3); // UTF-8
+        body.extend_from_slice(text.as_bytes());
+    }
+    if body.is_empty() {
+        return body;
+    }
+
+    let mut tag = b"ID3\x04\x00\x00".to_vec();
+    tag.extend_from_slice(&syncsafe(body.len()));
+    tag.extend_from_slice(&body);
+    tag
+}
+
+/// The audio frames of an MP3 without its leading ID3v2 and trailing ID3v1 tags
+pub fn strip_tags(data: &[u8]) -> &[u8] {
+    let audio = &data[id3v2_tag_len(data)..];
+    match audio.len().checked_sub(128) {
+        Some(id3v1) if audio[id3v1..].starts_with(b"TAG") => &audio[..id3v1],
+        _ => audio,
+    }
+}
+
+/// Where a frame's main data (scale factors and Huffman bits) lives: `main_data_begin`
+/// bytes before the end of its side information, `part2_3_length` bits long in total.
+/// Anything in the frame areas outside a frame's main data is ancillary data
+fn main_data_layout(header: &FrameHeader, side_info: &[u8]) -> (usize, usize) {
+    let read = |start: usize, bits: usize| {
+        (start..start + bits).fold(0, |acc, bit| {
+            (acc << 1) | usize::from((side_info[bit / 8] >> (7 - bit % 8)) & 1)
+        })
+    };
+    let mono = header.channels == 1;
+    // Bits before the first part2_3_length, and bits per granule and channel
+    let (begin_bits, first, stride, granules) = match header.version {
+        MpegVersion::Mpeg1 => (9, if mono { 18 } else { 20 }, 59, 2),
+        MpegVersion::Mpeg2 | MpegVersion::Mpeg25 => (8, if mono { 9 } else { 10 }, 63, 1),
+    };
+    let bits: usize = (0..granules * usize::from(header.channels))
+        .map(|block| read(first + block * stride, 12))
+        .sum();
+    (read(0, begin_bits), bits.div_ceil(8))
+}
+
+/// Overwrite `main_data_begin` at the start of the side information
+fn set_main_data_begin(header: &FrameHeader, side_info: &mut [u8], begin: usize) {
+    #[allow(clippy::cast_possible_truncation)]
+    match header.version {
+        MpegVersion::Mpeg1 => {
+            side_info[0] = (begin >> 1) as u8;
+            side_info[1] = (side_info[1] & 0x7F) | (((begin & 1) as u8) << 7);
+        }
+        MpegVersion::Mpeg2 | MpegVersion::Mpeg25 => side_info[0] = begin as u8,
+    }
+}
+
+/// CRC-16 used by the LAME tag (polynomial 0x8005, reflected)
+fn crc16(data: &[u8], crc: u16) -> u16 {
+    data.iter().fold(crc, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte), |crc, _| {
+            if crc & 1 == 0 {
+                crc >> 1
+            } else {
+                (crc >> 1) ^ 0xA001
+            }
+        })
+    })
+}
+
+/// Point a Xing/Info tag frame at the repacked frames: the stream size, the seek
+/// table and, when a LAME tag with a valid checksum follows, its music length and CRCs.
+/// `offsets` are where the frames start in `audio`, the frames following the tag frame
+fn update_vbr_tag(tag_frame: &mut [u8], tag_offset: usize, offsets: &[usize], audio: &[u8]) {
+    let total = tag_frame.len() + audio.len();
+    let read_u32 = |bytes: &[u8], at: usize| {
+        bytes
+            .get(at..at + 4)
+            .map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let lame = tag_offset + 120;
+    let lame_crc_valid = tag_frame.len() >= lame + 36
+        && crc16(&tag_frame[..lame + 34], 0)
+            == u16::from_be_bytes([tag_frame[lame + 34], tag_frame[lame + 35]]);
+
+    // Bitrates now change from frame to frame, which "Info" would deny
+    tag_frame[tag_offset..tag_offset + 4].copy_from_slice(b"Xing");
+    let flags = read_u32(tag_frame, tag_offset + 4);
+    let mut field = tag_offset + 8;
+    if flags & 1 != 0 {
+        field += 4;
+    }
+    if flags & 2 != 0 && field + 4 <= tag_frame.len() {
+        #[allow(clippy::cast_possible_truncation)]
+        tag_frame[field..field + 4].copy_from_slice(&(total as u32).to_be_bytes());
+        field += 4;
+    }
+    if flags & 4 != 0 && field + 100 <= tag_frame.len() && !offsets.is_empty() {
+        for (percent, entry) in tag_frame[field..field + 100].iter_mut().enumerate() {
+            let offset = offsets[percent * offsets.len() / 100];
+            #[allow(clippy::cast_possible_truncation)]
+            let position = (offset * 256 / audio.len()).min(255) as u8;
+            *entry = position;
+        }
+    }
+
+    if lame_crc_valid {
+        #[allow(clippy::cast_possible_truncation)]
+        tag_frame[lame + 28..lame + 32].copy_from_slice(&(total as u32).to_be_bytes());
+        tag_frame[lame + 32..lame + 34].copy_from_slice(&crc16(audio, 0).to_be_bytes());
+        let tag_crc = crc16(&tag_frame[..lame + 34], 0);
+        tag_frame[lame + 34..lame + 36].copy_from_slice(&tag_crc.to_be_bytes());
+    }
+}
+
+/// Rebuild Layer III frames around their main data alone
+///
+/// The main data of every frame is copied bit-exact, while padding bytes and
+/// ancillary data are dropped: frames are laid out again through the bit
+/// reservoir, each at the smallest bitrate that still leaves later frames the
+/// room they need. A leading Xing/Info frame is kept and updated for the new
+/// layout. Returns None for streams this can't handle (CRC protection, a VBRI
+/// header, changing formats or main data outside the stream); those are copied
+/// frame by frame instead
+fn repack_frames(frames: &[&[u8]]) -> Option<Vec<u8>> {
+    let first = parse_frame_header(frames.first()?)?;
+    let side_len = first.side_info_len();
+    let max_begin = first.max_main_data_begin();
+
+    let mut headers = Vec::with_capacity(frames.len());
+    for frame in frames {
+        let header = parse_frame_header(frame)?;
+        let same_format = header.version == first.version
+            && header.sample_rate == first.sample_rate
+            && header.channels == first.channels;
+        // The CRC covers the header, which gets a new bitrate
+        if !same_format || frame[1] & 1 == 0 || frame.len() < 4 + side_len {
+            return None;
+        }
+        headers.push(header);
+    }
+    if frames[0].get(36..40) == Some(b"VBRI") {
+        return None;
+    }
+    let tag_offset = 4 + side_len;
+    let tag_frame = matches!(
+        frames[0].get(tag_offset..tag_offset + 4),
+        Some(b"Xing" | b"Info")
+    );
+    let audio_frames = &frames[usize::from(tag_frame)..];
+    let headers = &headers[usize::from(tag_frame)..];
+
+    // Concatenate the original frame areas to pull out each frame's main data
+    let mut reservoir = Vec::new();
+    let mut main_data = Vec::with_capacity(audio_frames.len());
+    for (frame, header) in audio_frames.iter().zip(headers) {
+        let (begin, len) = main_data_layout(header, &frame[4..tag_offset]);
+        let start = reservoir.len().checked_sub(begin)?;
+        reservoir.extend_from_slice(&frame[tag_offset..]);
+        if start + len > reservoir.len() {
+            return None;
+        }
+        main_data.push(start..start + len);
+    }
+
+    // Frame sizes to choose from, smallest first: (length, bitrate index, padding)
+    let mut sizes: Vec<(usize, u8, bool)> = (1..15u8)
+        .flat_map(|index| [(index, false), (index, true)])
+        .map(|(index, padding)| {
+            let header = FrameHeader {
+                bitrate_kbps: bitrates(first.version)[usize::from(index)],
+                padding,
+                ..first
+            };
+            (header.frame_len(), index, padding)
+        })
+        .collect();
+    sizes.sort_unstable();
+    let capacity = |len: usize| len - tag_offset;
+    let max_capacity = capacity(sizes.last()?.0);
+
+    // Reservoir bytes each frame needs from the frames before it
+    let mut needed = vec![0; main_data.len() + 1];
+    for (i, range) in main_data.iter().enumerate().rev() {
+        needed[i] = (range.len() + needed[i + 1]).saturating_sub(max_capacity);
+        if needed[i] > max_begin {
+            return None;
+        }
+    }
+    if needed[0] > 0 {
+        return None;
+    }
+
+    // Pick the smallest frame that keeps enough free reservoir for the next one
+    let mut free = 0;
+    let mut layout = Vec::with_capacity(main_data.len());
+    for (i, range) in main_data.iter().enumerate() {
+        let &(len, index, padding) = sizes
+            .iter()
+            .find(|(len, ..)| free + capacity(*len) >= range.len() + needed[i + 1])?;
+        // Empty main data needs nothing from the reservoir
+        layout.push((len, index, padding, if range.is_empty() { 0 } else { free }));
+        free = (free + capacity(len) - range.len()).min(max_begin);
+    }
+
+    let mut stream = vec![0; layout.iter().map(|&(len, ..)| capacity(len)).sum()];
+    let mut area_start = 0;
+    for ((len, _, _, begin), range) in layout.iter().zip(&main_data) {
+        let start = area_start - begin;
+        stream[start..start + range.len()].copy_from_slice(&reservoir[range.clone()]);
+        area_start += capacity(*len);
+    }
+
+    let mut packed = Vec::with_capacity(frames[0].len() * usize::from(tag_frame) + stream.len());
+    let mut offsets = Vec::with_capacity(layout.len());
+    let mut area_start = 0;
+    for (frame, &(len, index, padding, begin)) in audio_frames.iter().zip(&layout) {
+        offsets.push(packed.len());
+        let mut header = [frame[0], frame[1], frame[2] & 0x0D, frame[3]];
+        header[2] |= (index << 4) | (u8::from(padding) << 1);
+        packed.extend_from_slice(&header);
+        let side_info = packed.len();
+        packed.extend_from_slice(&frame[4..tag_offset]);
+        set_main_data_begin(&first, &mut packed[side_info..], begin);
+        packed.extend_from_slice(&stream[area_start..area_start + capacity(len)]);
+        area_start += capacity(len);
+    }
+
+    if tag_frame {
+        let mut tag = frames[0].to_vec();
+        update_vbr_tag(&mut tag, tag_offset, &offsets, &packed);
+        tag.append(&mut packed);
+        packed = tag;
+    }
+    Some(packed)
+}
+
+/// Losslessly repackage an MP3 stream
+/// Keeps the audio data of every frame bit-exact while dropping padding bytes, ancillary
+/// data, ID3v2 padding, duplicate tag frames, extra stacked tags and junk bytes between
+/// frames. A trailing ID3v1 tag is preserved
+pub fn repack(data: &[u8]) -> Result<Vec<u8>> {
+    let tag_len = id3v2_tag_len(data);
+    let id3v1_len = if data.len() >= tag_len + 128 && data[data.len() - 128..].starts_with(b"TAG") {
+        128
+    } else {
+        0
+    };
+    let audio = &data[..data.len() - id3v1_len];
+
+    let mut frames = Vec::new();
+    let mut pos = tag_len;
+    let mut audio_bytes = 0;
+    while let Some((frame_pos, header)) = next_frame(audio, pos) {
+        let frame_end = frame_pos + header.frame_len();
+        frames.push(&audio[frame_pos..frame_end]);
+        audio_bytes += frame_end - frame_pos;
+        pos = frame_end;
+    }
+
+    // Refuse to drop large unrecognized regions (e.g. Layer II audio named .mp3)
+    let payload_len = audio.len() - tag_len;
+    if audio_bytes == 0 || audio_bytes * 10 < payload_len * 9 {
+        return Err(anyhow!(
+            "Only {audio_bytes} of {payload_len} bytes are recognizable MP3 frames, not repacking"
+        ));
+    }
+
+    let mut repacked = compact_id3v2(&data[..tag_len]);
+    match repack_frames(&frames) {
+        Some(packed) => repacked.extend_from_slice(&packed),
+        None => frames
+            .iter()
+            .for_each(|frame| repacked.extend_from_slice(frame)),
+    }
+    repacked.extend_from_slice(&data[data.len() - id3v1_len..]);
+    Ok(repacked)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Build a synthetic MPEG-1 Layer III stream of silent frames
+    pub fn synthetic_mp3(frames: usize, bitrate_index: u8) -> Vec<u8> {
+        // 0xFFFB = MPEG-1 Layer III without CRC, 44.1 kHz, no padding, joint stereo
+        let header = [0xFF, 0xFB, bitrate_index << 4, 0x44];
+        let frame_len = parse_frame_header(&header).unwrap().frame_len();
+        let mut data = Vec::with_capacity(frames * frame_len);
+        for _ in 0..frames {
+            data.extend_from_slice(&header);
+            data.resize(data.len() + frame_len - 4, 0);
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_frame_header() {
+        // 128 kbps, 44.1 kHz, MPEG-1, stereo
+        let header = parse_frame_header(&[0xFF, 0xFB, 0x90, 0x44]).unwrap();
+        assert_eq!(header.version, MpegVersion::Mpeg1);
+        assert_eq!(header.bitrate_kbps, 128);
+        assert_eq!(header.sample_rate, 44100);
+        assert_eq!(header.channels, 2);
+        assert!(!header.padding);
+        assert_eq!(header.frame_len(), 417);
+        assert_eq!(header.samples_per_frame(), 1152);
+
+        // Padding adds one byte
+        let padded = parse_frame_header(&[0xFF, 0xFB, 0x92, 0x44]).unwrap();
+        assert_eq!(padded.frame_len(), 418);
+
+        // MPEG-2, 64 kbps, 22.05 kHz, mono
+        let mpeg2 = parse_frame_header(&[0xFF, 0xF3, 0x80, 0xC4]).unwrap();
+        assert_eq!(mpeg2.version, MpegVersion::Mpeg2);
+        assert_eq!(mpeg2.bitrate_kbps, 64);
+        assert_eq!(mpeg2.sample_rate, 22050);
+        assert_eq!(mpeg2.channels, 1);
+        assert_eq!(mpeg2.samples_per_frame(), 576);
+
+        // Invalid headers
+        assert!(parse_frame_header(&[0x00, 0xFB, 0x90, 0x44]).is_none()); // No sync
+        assert!(parse_frame_header(&[0xFF, 0xFD, 0x90, 0x44]).is_none()); // Layer II
+        assert!(parse_frame_header(&[0xFF, 0xFB, 0xF0, 0x44]).is_none()); // Bad bitrate
+        assert!(parse_frame_header(&[0xFF, 0xFB, 0x9C, 0x44]).is_none()); // Bad sample rate
+        assert!(parse_frame_header(&[0xFF, 0xFB]).is_none()); // Too short
+    }
+
+    #[test]
+    fn test_id3v2_tag_len() {
+        let mut tagged = b"ID3\x04\x00\x00\x00\x00\x01\x00".to_vec();
+        tagged.resize(10 + 128, 0);
+        assert_eq!(id3v2_tag_len(&tagged), 138);
+        assert_eq!(id3v2_tag_len(&[0xFF, 0xFB, 0x90, 0x44]), 0);
+        assert_eq!(id3v2_tag_len(b"ID3"), 0);
+    }
+
+    #[test]
+    fn test_scan_frames() {
+        let data = synthetic_mp3(100, 9);
+        let summary = scan_frames(&data);
+        assert_eq!(summary.frames, 100);
+        assert_eq!(summary.sample_rate, 44100);
+        assert!((summary.duration_seconds - 100.0 * 1152.0 / 44100.0).abs() < 1e-9);
+        assert_eq!(summary.audio_bytes, 100 * 417);
+
+        // Leading tag and junk between frames are skipped
+        let mut tagged = b"ID3\x04\x00\x00\x00\x00\x00\x05".to_vec();
+        tagged.extend_from_slice(&[0; 5]);
+        tagged.extend_from_slice(&synthetic_mp3(10, 9));
+        tagged.extend_from_slice(b"junk");
+        tagged.extend_from_slice(&synthetic_mp3(10, 9));
+        assert_eq!(scan_frames(&tagged).frames, 20);
+
+        assert_eq!(scan_frames(&[]), Mp3Summary::default());
+
+        // Truncated final frame is not counted
+        let mut truncated = synthetic_mp3(5, 9);
+        truncated.truncate(truncated.len() - 100);
+        assert_eq!(scan_frames(&truncated).frames, 4);
+    }
+
+    /// Build an ID3v2.3 tag from frames and trailing padding
+    fn id3v23_tag(frames: &[(&[u8; 4], &[u8])], padding: usize) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (id, content) in frames {
+            body.extend_from_slice(*id);
+            body.extend_from_slice(&u32::try_from(content.len()).unwrap().to_be_bytes());
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(content);
+        }
+        body.resize(body.len() + padding, 0);
+
+        let mut tag = b"ID3\x03\x00\x00".to_vec();
+        let size = body.len();
+        for shift in [21, 14, 7, 0] {
+            tag.push(u8::try_from((size >> shift) & 0x7F).unwrap());
+        }
+        tag.extend_from_slice(&body);
+        tag
+    }
+
+    #[test]
+    fn test_id3v2_text_tag() {
+        let tag = id3v2_text_tag(&[("TIT2", "Гимн".to_string())]);
+        assert_eq!(id3v2_tag_len(&tag), tag.len());
+        assert_eq!(&tag[10..14], b"TIT2");
+        assert_eq!(&tag[20..], "\x03Гимн".as_bytes());
+        assert!(id3v2_text_tag(&[]).is_empty());
+
+        let mut tagged = tag.clone();
+        let audio = synthetic_mp3(3, 9);
+        tagged.extend_from_slice(&audio);
+        tagged.extend_from_slice(b"TAG");
+        tagged.resize(tagged.len() + 125, 0);
+        assert_eq!(strip_tags(&tagged), audio.as_slice());
+        assert_eq!(strip_tags(&audio), audio.as_slice());
+    }
+
+    #[test]
+    fn test_compact_id3v2() {
+        let title: (&[u8; 4], &[u8]) = (b"TIT2", b"\x00Buzzer");
+        let artist: (&[u8; 4], &[u8]) = (b"TPE1", b"\x00Host");
+
+        // Padding and duplicate frames are removed
+        let tag = id3v23_tag(&[title, artist, title], 2048);
+        let compacted = compact_id3v2(&tag);
+        assert_eq!(compacted, id3v23_tag(&[title, artist], 0));
+        assert_eq!(id3v2_tag_len(&compacted), compacted.len());
+
+        // Unsynchronised tags are kept verbatim
+        let mut unsync = tag.clone();
+        unsync[5] = 0x80;
+        assert_eq!(compact_id3v2(&unsync), unsync);
+
+        // No tag stays empty
+        assert!(compact_id3v2(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_repack() {
+        let title: (&[u8; 4], &[u8]) = (b"TIT2", b"\x00Buzzer");
+        let frames = synthetic_mp3(50, 9);
+
+        let mut id3v1 = b"TAG".to_vec();
+        id3v1.resize(128, b' ');
+
+        let mut source = id3v23_tag(&[title, title], 4096);
+        source.extend_from_slice(&frames[..417 * 25]);
+        source.extend_from_slice(b"junk");
+        source.extend_from_slice(&frames[417 * 25..]);
+        source.extend_from_slice(&id3v1);
+
+        // Frames without main data shrink to the lowest bitrate
+        let repacked = repack(&source).unwrap();
+        let mut expected = id3v23_tag(&[title], 0);
+        expected.extend_from_slice(&synthetic_mp3(50, 1));
+        expected.extend_from_slice(&id3v1);
+        assert_eq!(repacked, expected);
+        assert_eq!(scan_frames(&repacked).frames, 50);
+
+        // Non-MP3 payloads are rejected instead of being emptied
+        assert!(repack(&[0u8; 4096]).is_err());
+    }
+
+    /// MPEG-1 Layer III stereo frame at 128 kbps whose side information points
+    /// `begin` bytes back and holds `main_len` bytes of main data, filled with `fill`
+    fn layer3_frame(padding: bool, begin: usize, main_len: usize, fill: u8) -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xFB, 0x90 | (u8::from(padding) << 1), 0x44];
+        let header = parse_frame_header(&frame).unwrap();
+        frame.resize(header.frame_len(), fill);
+        frame[4..36].fill(0);
+        set_main_data_begin(&header, &mut frame[4..36], begin);
+        // All main data is in the first granule of the first channel
+        for bit in 0..12 {
+            let value = ((main_len * 8) >> (11 - bit)) & 1;
+            let position = 32 + 20 + bit;
+            #[allow(clippy::cast_possible_truncation)]
+            let mask = (value as u8) << (7 - position % 8);
+            frame[position / 8] |= mask;
+        }
+        frame
+    }
+
+    /// The main data of every frame in `stream`, following `main_data_begin`
+    fn main_data(stream: &[u8]) -> Vec<Vec<u8>> {
+        let mut reservoir = Vec::new();
+        let mut frames = Vec::new();
+        let mut pos = 0;
+        while let Some((frame_pos, header)) = next_frame(stream, pos) {
+            let frame = &stream[frame_pos..frame_pos + header.frame_len()];
+            let (begin, len) = main_data_layout(&header, &frame[4..36]);
+            let start = reservoir.len() - begin;
+            reservoir.extend_from_slice(&frame[36..]);
+            frames.push(reservoir[start..start + len].to_vec());
+            pos = frame_pos + frame.len();
+        }
+        frames
+    }
+
+    #[test]
+    fn test_repack_bit_reservoir() {
+        // Frame areas are 381 bytes (382 with padding). Main data is filled with
+        // the frame number, ancillary data with 0xAA
+        let layout = [
+            (false, 0, 100),
+            (true, 0, 300),
+            (false, 50, 400),
+            (true, 31, 200),
+        ];
+        let mut frames: Vec<Vec<u8>> = Vec::new();
+        for (i, (padding, begin, main_len)) in layout.into_iter().enumerate() {
+            let marker = u8::try_from(i + 1).unwrap();
+            if let Some(previous) = frames.last_mut() {
+                let len = previous.len();
+                previous[len - begin..].fill(marker);
+            }
+            let mut frame = layer3_frame(padding, begin, main_len, 0xAA);
+            frame[36..36 + main_len - begin].fill(marker);
+            frames.push(frame);
+        }
+        let source = frames.concat();
+        let expected: Vec<Vec<u8>> = layout
+            .iter()
+            .enumerate()
+            .map(|(i, &(_, _, main_len))| vec![u8::try_from(i + 1).unwrap(); main_len])
+            .collect();
+        assert_eq!(main_data(&source), expected);
+
+        let repacked = repack(&source).unwrap();
+        assert!(repacked.len() < source.len());
+        assert_eq!(main_data(&repacked), expected);
+        assert!(!repacked.contains(&0xAA));
+        assert_eq!(scan_frames(&repacked).frames, 4);
+
+        // A Xing/LAME tag frame is kept and describes the repacked frames
+        let mut tag = layer3_frame(false, 0, 0, 0);
+        tag[36..40].copy_from_slice(b"Info");
+        tag[40..44].copy_from_slice(&7u32.to_be_bytes()); // Frames, bytes and seek table
+        tag[156..165].copy_from_slice(b"LAME3.100");
+        let crc = crc16(&tag[..190], 0);
+        tag[190..192].copy_from_slice(&crc.to_be_bytes());
+        let tagged = repack(&[tag.clone(), source].concat()).unwrap();
+        assert_eq!(&tagged[..4], &tag[..4]);
+        assert_eq!(&tagged[36..40], b"Xing");
+        let total = u32::from_be_bytes(tagged[48..52].try_into().unwrap());
+        assert_eq!(total as usize, tagged.len());
+        assert_eq!(tagged[52], 0);
+        assert!(tagged[52..152].is_sorted());
+        assert_eq!(
+            crc16(&tagged[..190], 0),
+            u16::from_be_bytes([tagged[190], tagged[191]])
+        );
+        assert_eq!(main_data(&tagged[tag.len()..]), expected);
+
+        // Main data reaching back before the stream can't be relaid
+        let orphan = layer3_frame(false, 20, 10, 0);
+        assert_eq!(repack(&orphan).unwrap(), orphan);
+    }
+}
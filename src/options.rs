@@ -0,0 +1,695 @@
+use crate::audio::AudioOptions;
+use crate::error::SicomError;
+use crate::image::ImageCodec;
+use crate::media::{LongMediaPenalty, RenameScheme};
+use crate::video::{AspectRatio, Rendition, VideoCompat, VideoDenoise, VideoMode, X265Preset};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Named starting points for the quality settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Preset {
+    /// Smaller output and quicker video encodes, with visible artifacts on close look
+    Fast,
+    /// The defaults
+    Balanced,
+    /// Near-transparent quality at larger sizes and slow video encodes
+    Max,
+}
+
+impl Preset {
+    /// Image, audio and video quality
+    pub const fn qualities(self) -> (u8, u8, u8) {
+        match self {
+            Self::Fast => (30, 70, 40),
+            Self::Balanced => (40, 85, 50),
+            Self::Max => (75, 95, 70),
+        }
+    }
+
+    pub const fn video_preset(self) -> X265Preset {
+        match self {
+            Self::Fast => X265Preset::Faster,
+            Self::Balanced => X265Preset::Medium,
+            Self::Max => X265Preset::Slow,
+        }
+    }
+}
+
+/// Settings for compressing a pack
+///
+/// Defaults match the CLI defaults. Fields missing from a config file
+/// fall back to those defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct CompressOptions {
+    pub image_quality: u8,
+    /// WebP encoder effort 0-6 (see [`crate::image::ImageOptions::effort`])
+    pub image_effort: u8,
+    /// Downscale images so neither side exceeds this many pixels
+    pub max_image_dimension: Option<u32>,
+    /// Format images are re-encoded to
+    pub image_format: ImageCodec,
+    /// Turn photos upright by their EXIF orientation before encoding
+    pub image_auto_orient: bool,
+    pub audio: AudioOptions,
+    pub video_quality: u8,
+    /// Re-encode videos, or only remux them into faststart MP4
+    pub video_mode: VideoMode,
+    pub video_denoise: Option<VideoDenoise>,
+    pub video_preset: X265Preset,
+    /// Encode video to a widely supported codec instead of HEVC
+    pub video_compat: Option<VideoCompat>,
+    pub verify_video: bool,
+    /// Drop the audio stream of videos whose audio is entirely silent
+    pub strip_silent_audio: bool,
+    /// Pad videos of other shapes to this aspect ratio (e.g. vertical phone videos to 16:9)
+    pub video_normalize_aspect: Option<AspectRatio>,
+    /// Scaled-down copies of every video for adaptive streaming, listed in `renditions.json`
+    pub video_ladder: Vec<Rendition>,
+    /// Downscale taller videos to this height (e.g. 4K sources to 720)
+    pub max_video_height: Option<u32>,
+    /// Downscale wider videos to this width
+    pub max_video_width: Option<u32>,
+    /// Temp disk space shared by in-flight video encodes, in MB (unlimited when None)
+    pub video_temp_space_mb: Option<u64>,
+    /// Lower quality for long audio and video (overrides `audio.long_media` when set)
+    pub long_media: Option<LongMediaPenalty>,
+    pub skip_image: bool,
+    pub skip_audio: bool,
+    pub skip_video: bool,
+    /// ffmpeg binary (auto-detected from PATH when None)
+    pub ffmpeg_path: Option<PathBuf>,
+    /// Use compressed files even when they are larger than the original
+    pub always_compress: bool,
+    /// Treat the input as an arbitrary ZIP instead of a SIGame pack
+    pub generic_zip: bool,
+    /// Naming scheme for media entries in the output
+    pub rename: RenameScheme,
+    /// Give media with non-ASCII filenames ASCII transliterations (originals go in the manifest)
+    pub transliterate_names: bool,
+    /// Extensions of entries besides content.xml whose media references are rewritten
+    pub text_extensions: Vec<String>,
+    /// Perceptual hash distance under which images count as duplicates
+    pub dedupe_similar: Option<u32>,
+    /// Store byte-identical media once and point every reference at that copy
+    pub dedupe_identical: bool,
+    /// Give up on the pack after this many seconds; checked between entries, so
+    /// an encode already running is not cut off
+    pub time_limit_seconds: Option<u64>,
+    /// Deflate level (0-9) for content.xml and other compressible entries; already
+    /// compressed media is always stored
+    pub zip_level: Option<u8>,
+    /// Reuse results for media unchanged since the previous run (tracked in a sidecar manifest)
+    pub incremental: bool,
+    /// Compress even when the manifest shows the output is up to date
+    pub force: bool,
+    /// Record progress in a journal next to the output, continuing an
+    /// interrupted run that did the same
+    pub resume: bool,
+    /// Write a unified diff of the content.xml reference updates here (`-` prints it)
+    pub xml_diff: Option<PathBuf>,
+    /// Store the unmodified content.xml in the output as content.orig.xml
+    pub keep_original_xml: bool,
+    /// Store `preload.json` in the output: the media of every round in question order,
+    /// with sizes and durations, for clients that prefetch the next question
+    pub emit_preload_manifest: bool,
+    /// Never ship renamed media without references: keep unreferenced media as is and fail
+    /// when a renamed entry's references can't be updated
+    pub strict_refs: bool,
+    /// Leave media in Images/, Audio/ and Video/ that no text entry references out of the output
+    pub prune_unused: bool,
+    /// Only lossless transformations: lossless WebP, MP3 repack and video remux
+    /// (quality settings are ignored)
+    pub lossless_only: bool,
+    /// Compress lossily even when the pack looks already compressed
+    pub allow_recompress: bool,
+    /// Media smaller than this many bytes is copied without decoding
+    pub min_file_size: Option<u64>,
+    /// Only compress these media entries (raw entry names), copying the rest; empty means all
+    pub include: Vec<String>,
+    /// Write the media kept original or skipped, with reasons, here (`-` prints it)
+    pub unprocessed_list: Option<PathBuf>,
+    /// Flag potentially NSFW images in the report (never blocks compression)
+    #[cfg(feature = "content-scan")]
+    pub content_scan: Option<crate::moderation::ContentScanOptions>,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            image_quality: 40,
+            image_effort: crate::image::DEFAULT_EFFORT,
+            max_image_dimension: None,
+            image_format: ImageCodec::Webp,
+            image_auto_orient: true,
+            audio: AudioOptions::default(),
+            video_quality: 50,
+            video_mode: VideoMode::Reencode,
+            video_denoise: None,
+            video_preset: X265Preset::Medium,
+            video_compat: None,
+            verify_video: false,
+            strip_silent_audio: false,
+            video_normalize_aspect: None,
+            video_ladder: Vec::new(),
+            max_video_height: None,
+            max_video_width: None,
+            video_temp_space_mb: None,
+            long_media: None,
+            skip_image: false,
+            skip_audio: false,
+            skip_video: false,
+            ffmpeg_path: None,
+            always_compress: false,
+            generic_zip: false,
+            rename: RenameScheme::default(),
+            transliterate_names: false,
+            text_extensions: ["xml", "html", "htm"].map(String::from).to_vec(),
+            dedupe_similar: None,
+            dedupe_identical: false,
+            time_limit_seconds: None,
+            zip_level: None,
+            incremental: false,
+            force: false,
+            resume: false,
+            xml_diff: None,
+            keep_original_xml: false,
+            emit_preload_manifest: false,
+            strict_refs: false,
+            prune_unused: false,
+            lossless_only: false,
+            allow_recompress: false,
+            min_file_size: None,
+            include: Vec::new(),
+            unprocessed_list: None,
+            #[cfg(feature = "content-scan")]
+            content_scan: None,
+        }
+    }
+}
+
+impl CompressOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub const fn image_quality(mut self, quality: u8) -> Self {
+        self.image_quality = quality;
+        self
+    }
+
+    pub const fn image_effort(mut self, effort: u8) -> Self {
+        self.image_effort = effort;
+        self
+    }
+
+    pub const fn max_image_dimension(mut self, pixels: Option<u32>) -> Self {
+        self.max_image_dimension = pixels;
+        self
+    }
+
+    pub const fn image_format(mut self, format: ImageCodec) -> Self {
+        self.image_format = format;
+        self
+    }
+
+    pub const fn image_auto_orient(mut self, auto_orient: bool) -> Self {
+        self.image_auto_orient = auto_orient;
+        self
+    }
+
+    pub const fn audio(mut self, audio: AudioOptions) -> Self {
+        self.audio = audio;
+        self
+    }
+
+    pub const fn video_quality(mut self, quality: u8) -> Self {
+        self.video_quality = quality;
+        self
+    }
+
+    pub const fn video_mode(mut self, mode: VideoMode) -> Self {
+        self.video_mode = mode;
+        self
+    }
+
+    pub const fn video_denoise(mut self, denoise: Option<VideoDenoise>) -> Self {
+        self.video_denoise = denoise;
+        self
+    }
+
+    pub const fn video_preset(mut self, preset: X265Preset) -> Self {
+        self.video_preset = preset;
+        self
+    }
+
+    pub const fn video_compat(mut self, compat: Option<VideoCompat>) -> Self {
+        self.video_compat = compat;
+        self
+    }
+
+    pub const fn max_video_height(mut self, height: Option<u32>) -> Self {
+        self.max_video_height = height;
+        self
+    }
+
+    pub const fn max_video_width(mut self, width: Option<u32>) -> Self {
+        self.max_video_width = width;
+        self
+    }
+
+    pub fn video_ladder(mut self, ladder: Vec<Rendition>) -> Self {
+        self.video_ladder = ladder;
+        self
+    }
+
+    pub const fn verify_video(mut self, verify: bool) -> Self {
+        self.verify_video = verify;
+        self
+    }
+
+    pub const fn strip_silent_audio(mut self, strip: bool) -> Self {
+        self.strip_silent_audio = strip;
+        self
+    }
+
+    pub const fn video_normalize_aspect(mut self, aspect: Option<AspectRatio>) -> Self {
+        self.video_normalize_aspect = aspect;
+        self
+    }
+
+    pub const fn video_temp_space_mb(mut self, megabytes: Option<u64>) -> Self {
+        self.video_temp_space_mb = megabytes;
+        self
+    }
+
+    pub const fn long_media(mut self, penalty: Option<LongMediaPenalty>) -> Self {
+        self.long_media = penalty;
+        self
+    }
+
+    pub const fn skip_image(mut self, skip: bool) -> Self {
+        self.skip_image = skip;
+        self
+    }
+
+    pub const fn skip_audio(mut self, skip: bool) -> Self {
+        self.skip_audio = skip;
+        self
+    }
+
+    pub const fn skip_video(mut self, skip: bool) -> Self {
+        self.skip_video = skip;
+        self
+    }
+
+    pub fn ffmpeg_path(mut self, path: Option<PathBuf>) -> Self {
+        self.ffmpeg_path = path;
+        self
+    }
+
+    pub const fn always_compress(mut self, always: bool) -> Self {
+        self.always_compress = always;
+        self
+    }
+
+    pub const fn generic_zip(mut self, generic: bool) -> Self {
+        self.generic_zip = generic;
+        self
+    }
+
+    pub const fn rename(mut self, rename: RenameScheme) -> Self {
+        self.rename = rename;
+        self
+    }
+
+    pub const fn transliterate_names(mut self, transliterate: bool) -> Self {
+        self.transliterate_names = transliterate;
+        self
+    }
+
+    pub fn text_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.text_extensions = extensions;
+        self
+    }
+
+    pub const fn dedupe_similar(mut self, threshold: Option<u32>) -> Self {
+        self.dedupe_similar = threshold;
+        self
+    }
+
+    pub const fn dedupe_identical(mut self, dedupe: bool) -> Self {
+        self.dedupe_identical = dedupe;
+        self
+    }
+
+    pub const fn time_limit_seconds(mut self, seconds: Option<u64>) -> Self {
+        self.time_limit_seconds = seconds;
+        self
+    }
+
+    pub const fn zip_level(mut self, level: Option<u8>) -> Self {
+        self.zip_level = level;
+        self
+    }
+
+    pub const fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    pub const fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    pub const fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    pub fn xml_diff(mut self, path: Option<PathBuf>) -> Self {
+        self.xml_diff = path;
+        self
+    }
+
+    pub const fn keep_original_xml(mut self, keep: bool) -> Self {
+        self.keep_original_xml = keep;
+        self
+    }
+
+    pub const fn emit_preload_manifest(mut self, emit: bool) -> Self {
+        self.emit_preload_manifest = emit;
+        self
+    }
+
+    pub const fn strict_refs(mut self, strict: bool) -> Self {
+        self.strict_refs = strict;
+        self
+    }
+
+    pub const fn prune_unused(mut self, prune: bool) -> Self {
+        self.prune_unused = prune;
+        self
+    }
+
+    pub const fn lossless_only(mut self, lossless: bool) -> Self {
+        self.lossless_only = lossless;
+        self
+    }
+
+    pub const fn allow_recompress(mut self, allow: bool) -> Self {
+        self.allow_recompress = allow;
+        self
+    }
+
+    pub const fn min_file_size(mut self, bytes: Option<u64>) -> Self {
+        self.min_file_size = bytes;
+        self
+    }
+
+    pub fn include(mut self, entries: Vec<String>) -> Self {
+        self.include = entries;
+        self
+    }
+
+    pub fn unprocessed_list(mut self, path: Option<PathBuf>) -> Self {
+        self.unprocessed_list = path;
+        self
+    }
+
+    #[cfg(feature = "content-scan")]
+    pub fn content_scan(mut self, scan: Option<crate::moderation::ContentScanOptions>) -> Self {
+        self.content_scan = scan;
+        self
+    }
+
+    /// Whether an entry (other than content.xml) may reference media by name
+    ///
+    /// Backups of the original content.xml keep the original references.
+    pub fn is_text_entry(&self, entry_name: &str) -> bool {
+        entry_name != crate::content_types::CONTENT_TYPES_ENTRY
+            && entry_name != crate::pipeline::ORIGINAL_CONTENT_XML
+            && std::path::Path::new(entry_name)
+                .extension()
+                .and_then(|s| s.to_str())
+                .is_some_and(|extension| {
+                    self.text_extensions
+                        .iter()
+                        .any(|text| text.trim_start_matches('.').eq_ignore_ascii_case(extension))
+                })
+    }
+
+    /// Check that all quality values are in range and options are compatible
+    pub fn validate(&self) -> Result<()> {
+        self.check()
+            .map_err(|message| SicomError::InvalidOptions(message).into())
+    }
+
+    fn check(&self) -> std::result::Result<(), String> {
+        if !(1..=100).contains(&self.image_quality) {
+            return Err("Image quality must be between 1 and 100".to_string());
+        }
+        if self.image_effort > crate::image::MAX_EFFORT {
+            return Err(format!(
+                "Image effort must be between 0 and {}",
+                crate::image::MAX_EFFORT
+            ));
+        }
+        if self.max_image_dimension == Some(0) {
+            return Err("Maximum image dimension must be at least 1".to_string());
+        }
+        if !(1..=100).contains(&self.audio.quality) {
+            return Err("Audio quality must be between 1 and 100".to_string());
+        }
+        if self
+            .zip_level
+            .is_some_and(|level| level > crate::pipeline::MAX_ZIP_LEVEL)
+        {
+            return Err(format!(
+                "ZIP level must be between 0 and {}",
+                crate::pipeline::MAX_ZIP_LEVEL
+            ));
+        }
+        if !(1..=100).contains(&self.video_quality) {
+            return Err("Video quality must be between 1 and 100".to_string());
+        }
+        if [self.max_video_height, self.max_video_width]
+            .iter()
+            .any(|limit| limit.is_some_and(|pixels| pixels < 2))
+        {
+            return Err("Maximum video height and width must be at least 2".to_string());
+        }
+        if let Some(message) = crate::audio::check_sample_rate(self.audio.sample_rate) {
+            return Err(message);
+        }
+        if let Some(threshold) = self.audio.verify_similarity {
+            if !(0.0..=1.0).contains(&threshold) {
+                return Err("Audio similarity threshold must be between 0.0 and 1.0".to_string());
+            }
+        }
+        if self.generic_zip && self.rename == RenameScheme::Hashed {
+            return Err("Hashed renaming needs content.xml to rewrite references and can't be used with --generic-zip".to_string());
+        }
+        if self.generic_zip && self.emit_preload_manifest {
+            return Err("The preload manifest is built from content.xml and can't be used with --generic-zip".to_string());
+        }
+        if self.generic_zip && self.dedupe_identical {
+            return Err("Media deduplication needs content.xml to rewrite references and can't be used with --generic-zip".to_string());
+        }
+        if self.generic_zip && self.prune_unused {
+            return Err(
+                "Finding unused media needs content.xml and can't be used with --generic-zip"
+                    .to_string(),
+            );
+        }
+        if self.generic_zip && self.transliterate_names {
+            return Err("Transliterating names needs content.xml to rewrite references and can't be used with --generic-zip".to_string());
+        }
+        if let Some(threshold) = self.dedupe_similar {
+            if threshold > crate::dedupe::MAX_DISTANCE {
+                return Err(format!(
+                    "Similarity threshold must be between 0 and {}",
+                    crate::dedupe::MAX_DISTANCE
+                ));
+            }
+            if self.generic_zip {
+                return Err("Image deduplication needs content.xml to rewrite references and can't be used with --generic-zip".to_string());
+            }
+        }
+        if self.video_mode == VideoMode::Remux {
+            let reencoding = [
+                (self.video_denoise.is_some(), "--video-denoise"),
+                (
+                    self.video_normalize_aspect.is_some(),
+                    "--video-normalize-aspect",
+                ),
+                (!self.video_ladder.is_empty(), "--ladder"),
+                (self.video_compat.is_some(), "--video-compat"),
+                (self.max_video_height.is_some(), "--max-video-height"),
+                (self.max_video_width.is_some(), "--max-video-width"),
+            ];
+            if let Some((_, flag)) = reencoding.iter().find(|(enabled, _)| *enabled) {
+                return Err(format!(
+                    "{flag} needs a video re-encode and can't be used with --video-mode remux"
+                ));
+            }
+        }
+        if self.lossless_only {
+            let lossy = [
+                (self.dedupe_similar.is_some(), "--dedupe-similar"),
+                (self.video_denoise.is_some(), "--video-denoise"),
+                (
+                    self.video_normalize_aspect.is_some(),
+                    "--video-normalize-aspect",
+                ),
+                (self.strip_silent_audio, "--strip-silent-audio"),
+                (!self.video_ladder.is_empty(), "--ladder"),
+                (self.video_compat.is_some(), "--video-compat"),
+                (self.max_video_height.is_some(), "--max-video-height"),
+                (self.max_video_width.is_some(), "--max-video-width"),
+                (self.max_image_dimension.is_some(), "--max-image-dimension"),
+                (self.image_format != ImageCodec::Webp, "--image-format"),
+            ];
+            if let Some((_, flag)) = lossy.iter().find(|(enabled, _)| *enabled) {
+                return Err(format!(
+                    "{flag} changes media content and can't be used with --lossless-only"
+                ));
+            }
+        }
+        #[cfg(feature = "content-scan")]
+        if let Some(scan) = &self.content_scan {
+            if !(0.0..=1.0).contains(&scan.threshold) {
+                return Err("NSFW threshold must be between 0.0 and 1.0".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioMode;
+
+    #[test]
+    fn test_builder() {
+        let options = CompressOptions::new()
+            .image_quality(70)
+            .audio(AudioOptions {
+                quality: 60,
+                ..Default::default()
+            })
+            .skip_video(true)
+            .video_denoise(Some(VideoDenoise::Light));
+
+        assert_eq!(options.image_quality, 70);
+        assert_eq!(options.audio.quality, 60);
+        assert!(options.skip_video);
+        assert_eq!(options.video_denoise, Some(VideoDenoise::Light));
+        // Untouched fields keep their defaults
+        assert_eq!(options.video_quality, 50);
+        assert!(!options.skip_image);
+
+        assert!(options.clone().zip_level(Some(9)).validate().is_ok());
+        assert!(options.zip_level(Some(10)).validate().is_err());
+    }
+
+    #[test]
+    fn test_balanced_preset_is_default() {
+        let options = CompressOptions::new();
+        assert_eq!(
+            Preset::Balanced.qualities(),
+            (
+                options.image_quality,
+                options.audio.quality,
+                options.video_quality
+            )
+        );
+        assert_eq!(Preset::Balanced.video_preset(), options.video_preset);
+        assert!(Preset::Max.qualities().2 > Preset::Fast.qualities().2);
+    }
+
+    #[test]
+    fn test_text_entries() {
+        let options = CompressOptions::new();
+        assert!(options.is_text_entry("authors.xml"));
+        assert!(options.is_text_entry("Texts/intro.HTML"));
+        assert!(!options.is_text_entry("[Content_Types].xml"));
+        assert!(!options.is_text_entry("content.orig.xml"));
+        assert!(!options.is_text_entry("notes.txt"));
+
+        let options = options.text_extensions(vec![".txt".to_string()]);
+        assert!(options.is_text_entry("notes.txt"));
+        assert!(!options.is_text_entry("authors.xml"));
+    }
+
+    #[test]
+    fn test_lossless_only_conflicts() {
+        let options = CompressOptions::new().lossless_only(true);
+        assert!(options.validate().is_ok());
+        assert!(options.clone().dedupe_similar(Some(4)).validate().is_err());
+        assert!(
+            options
+                .clone()
+                .max_video_height(Some(720))
+                .validate()
+                .is_err()
+        );
+        assert!(
+            options
+                .clone()
+                .video_ladder(vec![Rendition::P720])
+                .validate()
+                .is_err()
+        );
+        let error = options
+            .video_denoise(Some(VideoDenoise::Light))
+            .validate()
+            .unwrap_err();
+        assert!(error.to_string().contains("--video-denoise"));
+    }
+
+    #[test]
+    fn test_video_remux_conflicts() {
+        let options = CompressOptions::new().video_mode(VideoMode::Remux);
+        assert!(options.validate().is_ok());
+        // Dropping a silent audio stream needs no re-encode
+        assert!(options.clone().strip_silent_audio(true).validate().is_ok());
+        let error = options
+            .video_compat(Some(VideoCompat::H264))
+            .validate()
+            .unwrap_err();
+        assert!(error.to_string().contains("--video-compat"));
+    }
+
+    #[test]
+    fn test_partial_config() {
+        let json = r#"{"image_quality": 60, "audio": {"mode": "repack"}, "video_denoise": "strong", "video_ladder": ["720p", "360p"]}"#;
+        let options: CompressOptions = serde_json::from_str(json).unwrap();
+
+        assert_eq!(options.image_quality, 60);
+        assert_eq!(options.audio.mode, AudioMode::Repack);
+        assert_eq!(options.audio.quality, 85);
+        assert_eq!(options.video_denoise, Some(VideoDenoise::Strong));
+        assert_eq!(options.video_ladder, [Rendition::P720, Rendition::P360]);
+        assert_eq!(options.video_quality, 50);
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let options = CompressOptions::new()
+            .audio(AudioOptions {
+                quality: 30,
+                ..Default::default()
+            })
+            .ffmpeg_path(Some(PathBuf::from("/usr/bin/ffmpeg")));
+        let json = serde_json::to_string(&options).unwrap();
+        let parsed: CompressOptions = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, options);
+    }
+}
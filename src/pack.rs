@@ -0,0 +1,343 @@
+use crate::error::SicomError;
+use crate::media::MediaKind;
+use anyhow::Result;
+use roxmltree::{Document, Node};
+
+/// Parsed SIGame package structure (content.xml)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Package {
+    pub name: String,
+    /// Cover image from the `logo="@file"` attribute (None for none or an external link)
+    pub logo: Option<MediaRef>,
+    pub rounds: Vec<Round>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Round {
+    pub name: String,
+    pub themes: Vec<Theme>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Theme {
+    pub name: String,
+    pub questions: Vec<Question>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Question {
+    /// Points from the `price` attribute (None when missing or not a number)
+    pub price: Option<i64>,
+    /// Text shown to players (media captions excluded)
+    pub text: String,
+    /// Accepted answers
+    pub answers: Vec<String>,
+    pub media: Vec<MediaRef>,
+}
+
+/// Reference from a question to a media file stored in the pack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaRef {
+    pub kind: MediaKind,
+    /// File name as written in the XML (without directory)
+    pub name: String,
+}
+
+impl MediaRef {
+    /// Expected pack entry name (entries are URL-encoded inside the archive)
+    pub fn entry_name(&self) -> String {
+        format!(
+            "{}{}",
+            self.kind.directory(),
+            urlencoding::encode(&self.name)
+        )
+    }
+}
+
+impl Package {
+    /// Iterate over all questions with their round and theme
+    pub fn questions(&self) -> impl Iterator<Item = (&Round, &Theme, &Question)> {
+        self.rounds.iter().flat_map(|round| {
+            round.themes.iter().flat_map(move |theme| {
+                theme
+                    .questions
+                    .iter()
+                    .map(move |question| (round, theme, question))
+            })
+        })
+    }
+}
+
+/// Map a content type attribute to the media it references
+fn media_kind(content_type: &str) -> Option<MediaKind> {
+    match content_type {
+        "image" => Some(MediaKind::Image),
+        "audio" | "voice" => Some(MediaKind::Audio),
+        "video" => Some(MediaKind::Video),
+        _ => None,
+    }
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.has_tag_name(name))
+}
+
+fn attribute(node: Node<'_, '_>, name: &str) -> String {
+    node.attribute(name).unwrap_or_default().to_string()
+}
+
+/// Collect media references from a question (v5 `item`s and v4 `atom`s)
+fn parse_question_media(question: Node<'_, '_>) -> Vec<MediaRef> {
+    let mut media = Vec::new();
+    for node in question.descendants() {
+        let text = node.text().unwrap_or_default().trim();
+        let Some(kind) = node.attribute("type").and_then(media_kind) else {
+            continue;
+        };
+
+        let name = if node.has_tag_name("item") {
+            // Format 5: <item type="image" isRef="True">file.jpg</item>
+            if !node
+                .attribute("isRef")
+                .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+            {
+                continue;
+            }
+            text
+        } else if node.has_tag_name("atom") {
+            // Format 4: <atom type="image">@file.jpg</atom>, without "@" it's an external link
+            match text.strip_prefix('@') {
+                Some(name) => name,
+                None => continue,
+            }
+        } else {
+            continue;
+        };
+
+        if !name.is_empty() {
+            media.push(MediaRef {
+                kind,
+                name: name.to_string(),
+            });
+        }
+    }
+    media
+}
+
+/// Collect the question's own text (v5 text `item`s, v4 text `atom`s)
+fn parse_question_text(question: Node<'_, '_>) -> String {
+    let mut parts = Vec::new();
+    for node in question.descendants() {
+        let is_text = node
+            .attribute("type")
+            .is_none_or(|content_type| content_type == "text");
+        if !is_text {
+            continue;
+        }
+
+        let in_question = if node.has_tag_name("item") {
+            // Format 5: only the "question" param, not the answer reveal
+            node.ancestors().any(|ancestor| {
+                ancestor.has_tag_name("param") && ancestor.attribute("name") == Some("question")
+            })
+        } else {
+            node.has_tag_name("atom")
+        };
+
+        let text = node.text().unwrap_or_default().trim();
+        if in_question && !text.is_empty() {
+            parts.push(text);
+        }
+    }
+    parts.join(" ")
+}
+
+fn parse_answers(question: Node<'_, '_>) -> Vec<String> {
+    children(question, "right")
+        .flat_map(|right| children(right, "answer"))
+        .filter_map(|answer| answer.text())
+        .map(str::trim)
+        .filter(|answer| !answer.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parse content.xml into the package model
+pub fn parse_package(xml: &str) -> Result<Package> {
+    let document = Document::parse(xml).map_err(SicomError::ContentXml)?;
+    let root = document.root_element();
+
+    let rounds = children(root, "rounds")
+        .flat_map(|rounds| children(rounds, "round"))
+        .map(|round| Round {
+            name: attribute(round, "name"),
+            themes: children(round, "themes")
+                .flat_map(|themes| children(themes, "theme"))
+                .map(|theme| Theme {
+                    name: attribute(theme, "name"),
+                    questions: children(theme, "questions")
+                        .flat_map(|questions| children(questions, "question"))
+                        .map(|question| Question {
+                            price: question
+                                .attribute("price")
+                                .and_then(|price| price.trim().parse().ok()),
+                            text: parse_question_text(question),
+                            answers: parse_answers(question),
+                            media: parse_question_media(question),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        })
+        .collect();
+
+    let logo = root
+        .attribute("logo")
+        .and_then(|logo| logo.trim().strip_prefix('@'))
+        .filter(|name| !name.is_empty())
+        .map(|name| MediaRef {
+            kind: MediaKind::Image,
+            name: name.to_string(),
+        });
+
+    Ok(Package {
+        name: attribute(root, "name"),
+        logo,
+        rounds,
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Small format 5 package with two rounds
+    pub const SAMPLE_V5: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<package name="Sample" version="5" xmlns="https://github.com/VladimirKhil/SI/blob/master/assets/siq_5.xsd">
+  <rounds>
+    <round name="Round 1">
+      <themes>
+        <theme name="Moscow">
+          <questions>
+            <question price="100">
+              <params>
+                <param name="question" type="content">
+                  <item>Name this place</item>
+                  <item type="image" isRef="True">ВДНХ.jpg</item>
+                </param>
+              </params>
+              <right><answer>VDNH</answer></right>
+            </question>
+            <question price="200">
+              <params>
+                <param name="question" type="content">
+                  <item type="audio" isRef="True">anthem.mp3</item>
+                  <item type="image">https://example.com/remote.jpg</item>
+                </param>
+              </params>
+              <right><answer>Anthem</answer></right>
+            </question>
+          </questions>
+        </theme>
+      </themes>
+    </round>
+    <round name="Final">
+      <themes>
+        <theme name="Cinema">
+          <questions>
+            <question price="0">
+              <params>
+                <param name="question" type="content">
+                  <item type="video" isRef="True">clip.mp4</item>
+                </param>
+              </params>
+              <right><answer>Film</answer></right>
+            </question>
+          </questions>
+        </theme>
+      </themes>
+    </round>
+  </rounds>
+</package>"#;
+
+    #[test]
+    fn test_parse_v5_package() {
+        let package = parse_package(SAMPLE_V5).unwrap();
+        assert_eq!(package.name, "Sample");
+        let question = &package.rounds[0].themes[0].questions[0];
+        assert_eq!(question.text, "Name this place");
+        assert_eq!(question.answers, ["VDNH"]);
+        assert_eq!(question.price, Some(100));
+        assert_eq!(package.rounds.len(), 2);
+        assert_eq!(package.rounds[0].themes[0].name, "Moscow");
+        assert_eq!(package.questions().count(), 3);
+
+        let media = &package.rounds[0].themes[0].questions[0].media;
+        assert_eq!(
+            media,
+            &vec![MediaRef {
+                kind: MediaKind::Image,
+                name: "ВДНХ.jpg".to_string()
+            }]
+        );
+        // External links are not pack media
+        assert_eq!(package.rounds[0].themes[0].questions[1].media.len(), 1);
+        assert_eq!(
+            package.rounds[1].themes[0].questions[0].media[0].kind,
+            MediaKind::Video
+        );
+    }
+
+    #[test]
+    fn test_parse_v4_atoms() {
+        let xml = r#"<package name="Old" version="4">
+  <rounds><round name="R"><themes><theme name="T"><questions>
+    <question price="100">
+      <scenario>
+        <atom>Listen</atom>
+        <atom type="voice">@song.mp3</atom>
+        <atom type="image">http://example.com/x.jpg</atom>
+      </scenario>
+      <right><answer>Song</answer></right>
+    </question>
+  </questions></theme></themes></round></rounds>
+</package>"#;
+        let package = parse_package(xml).unwrap();
+        let media = &package.rounds[0].themes[0].questions[0].media;
+        assert_eq!(media.len(), 1);
+        assert_eq!(media[0].kind, MediaKind::Audio);
+        assert_eq!(media[0].name, "song.mp3");
+        assert_eq!(package.rounds[0].themes[0].questions[0].text, "Listen");
+    }
+
+    #[test]
+    fn test_parse_logo() {
+        let package =
+            parse_package(r#"<package name="P" logo="@ВДНХ.jpg"><rounds /></package>"#).unwrap();
+        let logo = package.logo.unwrap();
+        assert_eq!(logo.kind, MediaKind::Image);
+        assert_eq!(logo.entry_name(), "Images/%D0%92%D0%94%D0%9D%D0%A5.jpg");
+
+        let external = r#"<package name="P" logo="https://example.com/x.jpg"><rounds /></package>"#;
+        assert_eq!(parse_package(external).unwrap().logo, None);
+        assert_eq!(parse_package(SAMPLE_V5).unwrap().logo, None);
+    }
+
+    #[test]
+    fn test_media_ref_entry_name() {
+        let media = MediaRef {
+            kind: MediaKind::Image,
+            name: "ВДНХ.jpg".to_string(),
+        };
+        assert_eq!(media.entry_name(), "Images/%D0%92%D0%94%D0%9D%D0%A5.jpg");
+    }
+
+    #[test]
+    fn test_parse_invalid_xml() {
+        assert!(parse_package("<package><rounds>").is_err());
+    }
+}
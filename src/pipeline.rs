@@ -0,0 +1,3236 @@
+use crate::cancel::CancellationToken;
+use crate::content_types::{self, CONTENT_TYPES_ENTRY};
+use crate::error::SicomError;
+use crate::inspect::EntryIndex;
+use crate::journal::Journal;
+use crate::manifest::{self, EntryOutcome, Manifest, ManifestEntry, PreviousOutput};
+use crate::media::{self, MediaKind, MediaRegistry, RenameScheme, RenditionOutput};
+#[cfg(feature = "content-scan")]
+use crate::moderation;
+use crate::options::CompressOptions;
+use crate::pack;
+use crate::preload::{self, PRELOAD_ENTRY};
+use crate::progress::{ProgressCallback, ProgressLogger};
+use crate::recompress::recompression_signs;
+use crate::stats::{CompressionStats, SkipReason, StatsAggregator, StatsEvent};
+use crate::transliterate;
+use crate::verify::{self, Problem};
+use crate::warm::WarmPool;
+use crate::{audio, dedupe, diff, format_size, image, tempspace, video};
+use anyhow::{Context, Result, anyhow};
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use zip::read::ZipFile;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive};
+
+/// Entry holding the unmodified content.xml with `keep_original_xml`
+pub const ORIGINAL_CONTENT_XML: &str = "content.orig.xml";
+
+/// Entry listing the ladder renditions of every video (`video_ladder`)
+pub const RENDITIONS_ENTRY: &str = "renditions.json";
+
+/// One rendition of a video in `renditions.json`
+#[derive(Debug, Serialize)]
+struct RenditionEntry {
+    rendition: video::Rendition,
+    height: u32,
+    entry: String,
+    size: u64,
+}
+
+/// Why media left out by `--include` was copied; not worth listing as unprocessed
+const NOT_INCLUDED: &str = "not in --include";
+
+/// Detect or validate ffmpeg; switches the ffmpeg audio engine to native when it's missing
+pub fn prepare_ffmpeg(options: &mut CompressOptions) -> bool {
+    prepare_ffmpeg_with(options, None)
+}
+
+/// `prepare_ffmpeg` that looks ffmpeg up once per warm pool
+fn prepare_ffmpeg_with(options: &mut CompressOptions, warm_pool: Option<&WarmPool>) -> bool {
+    let ffmpeg_available = match warm_pool {
+        Some(pool) => {
+            pool.ffmpeg_available(options.ffmpeg_path.as_deref(), || detect_ffmpeg(options))
+        }
+        None => detect_ffmpeg(options),
+    };
+
+    // The ffmpeg audio engine needs ffmpeg too - fall back to the native encoder
+    if options.audio.engine == audio::AudioEngine::Ffmpeg
+        && !ffmpeg_available
+        && !options.skip_audio
+    {
+        warn!("ffmpeg not available. Falling back to the native audio engine.");
+        options.audio.engine = audio::AudioEngine::Native;
+    }
+    // Opus is only encoded through ffmpeg
+    if options.audio.codec == audio::AudioCodec::Opus && !ffmpeg_available && !options.skip_audio {
+        warn!("ffmpeg not available. Falling back to MP3 audio.");
+        options.audio.codec = audio::AudioCodec::Mp3;
+    }
+
+    ffmpeg_available
+}
+
+/// Whether ffmpeg exists at the configured path or on PATH
+fn detect_ffmpeg(options: &CompressOptions) -> bool {
+    if !cfg!(feature = "ffmpeg") {
+        // Nothing in this build could run it
+        return false;
+    }
+    if let Some(path) = &options.ffmpeg_path {
+        if path.exists() {
+            info!("Using ffmpeg at: {path:?}");
+            true
+        } else {
+            warn!("Specified ffmpeg path does not exist: {path:?}");
+            false
+        }
+    } else {
+        // Auto-detect ffmpeg using 'which' command
+        match std::process::Command::new("which").arg("ffmpeg").output() {
+            Ok(output) if output.status.success() => {
+                let ffmpeg_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                info!("Auto-detected ffmpeg at: {ffmpeg_path}");
+                true
+            }
+            _ => {
+                if !options.skip_video {
+                    warn!("ffmpeg not found in PATH. Video compression will be skipped.");
+                    info!("To enable video compression:");
+                    info!(
+                        "  1. Install ffmpeg: brew install ffmpeg (macOS) or apt install ffmpeg (Ubuntu)"
+                    );
+                    info!("  2. Or specify path with --ffmpeg-path");
+                    info!("  3. Or use --skip-video to suppress this warning");
+                }
+                false
+            }
+        }
+    }
+}
+
+/// Register media pipelines; disabled ones copy their files unchanged
+pub fn build_registry(options: &CompressOptions, ffmpeg_available: bool) -> MediaRegistry {
+    build_registry_with(options, ffmpeg_available, None)
+}
+
+/// `build_registry` whose video pipeline reuses the probes of a warm pool
+fn build_registry_with(
+    options: &CompressOptions,
+    ffmpeg_available: bool,
+    warm_pool: Option<&WarmPool>,
+) -> MediaRegistry {
+    let video_disabled_reason = if options.skip_video {
+        Some("skip_video flag")
+    } else if !cfg!(feature = "video") {
+        Some("built without video support")
+    } else if !ffmpeg_available {
+        Some("ffmpeg not available")
+    } else {
+        None
+    };
+
+    // Lossless-only swaps every pipeline for its lossless variant
+    let (image_quality, audio_mode) = if options.lossless_only {
+        (image::LOSSLESS_QUALITY, audio::AudioMode::Repack)
+    } else {
+        (options.image_quality, options.audio.mode)
+    };
+
+    let mut registry = MediaRegistry::new();
+    registry.register(
+        Box::new(image::ImageProcessor {
+            options: image::ImageOptions::new()
+                .quality(image_quality)
+                .effort(options.image_effort)
+                .max_dimension(options.max_image_dimension)
+                .codec(options.image_format)
+                .auto_orient(options.image_auto_orient),
+            ffmpeg_path: options.ffmpeg_path.clone(),
+        }),
+        options.skip_image.then_some("skip_image flag"),
+    );
+    registry.register(
+        Box::new(audio::AudioProcessor {
+            options: options
+                .audio
+                .mode(audio_mode)
+                .long_media(options.long_media.or(options.audio.long_media)),
+            ffmpeg_path: options.ffmpeg_path.clone(),
+        }),
+        if options.skip_audio {
+            Some("skip_audio flag")
+        } else if !cfg!(feature = "audio") {
+            Some("built without audio support")
+        } else {
+            None
+        },
+    );
+    registry.register(
+        Box::new(video::VideoProcessor {
+            options: video::VideoOptions::new()
+                .quality(options.video_quality)
+                .denoise(options.video_denoise)
+                .verify_output(options.verify_video)
+                .strip_silent_audio(options.strip_silent_audio)
+                .normalize_aspect(options.video_normalize_aspect)
+                .remux(options.lossless_only || options.video_mode == video::VideoMode::Remux)
+                .preset(options.video_preset)
+                .compat(options.video_compat)
+                .max_height(options.max_video_height)
+                .max_width(options.max_video_width)
+                .long_media(options.long_media),
+            temp_space: options.video_temp_space_mb.map(|megabytes| {
+                tempspace::TempSpacePool::new(megabytes.saturating_mul(1024 * 1024))
+            }),
+            ffmpeg_path: options.ffmpeg_path.clone(),
+            ladder: options.video_ladder.clone(),
+            warm_pool: warm_pool.cloned(),
+        }),
+        video_disabled_reason,
+    );
+    registry
+}
+
+/// Fingerprint every pack image and group near-identical ones
+fn find_similar_images(
+    archive: &mut ZipArchive<BufReader<File>>,
+    threshold: u32,
+    exclude: &HashMap<String, String>,
+) -> Result<HashMap<String, String>> {
+    let mut fingerprints = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .with_context(|| format!("Failed to read file at index {i}"))?;
+        let file_name = file.name().to_string();
+        if !(file_name.starts_with("Images/") && image::is_supported_image(&file_name))
+            || exclude.contains_key(&file_name)
+        {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)
+            .with_context(|| format!("Failed to read image data: {file_name}"))?;
+        match ::image::load_from_memory(&data) {
+            Ok(img) => fingerprints.push(dedupe::ImageFingerprint::new(
+                file_name,
+                &img,
+                data.len() as u64,
+            )),
+            Err(e) => debug!("  Not fingerprinting {file_name}: {e}"),
+        }
+    }
+
+    let similar = dedupe::find_similar(fingerprints, threshold);
+    info!("Found {} near-duplicate images", similar.len());
+    Ok(similar)
+}
+
+/// Hash every media entry and map byte-identical copies onto the first of them
+fn find_identical_media(
+    archive: &mut ZipArchive<BufReader<File>>,
+) -> Result<HashMap<String, String>> {
+    let mut first_by_hash: HashMap<blake3::Hash, String> = HashMap::new();
+    let mut identical = HashMap::new();
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .with_context(|| format!("Failed to read file at index {i}"))?;
+        let file_name = file.name().to_string();
+        if MediaKind::of_entry(&file_name).is_none() {
+            continue;
+        }
+
+        let mut hasher = blake3::Hasher::new();
+        std::io::copy(&mut file, &mut hasher)
+            .with_context(|| format!("Failed to read media data: {file_name}"))?;
+        match first_by_hash.entry(hasher.finalize()) {
+            Entry::Occupied(first) => {
+                identical.insert(file_name, first.get().clone());
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(file_name);
+            }
+        }
+    }
+
+    info!("Found {} identical media files", identical.len());
+    Ok(identical)
+}
+
+/// Highest `--zip-level` (deflate's best compression)
+pub const MAX_ZIP_LEVEL: u8 = 9;
+
+/// Extensions of media whose data is already compressed; deflating it again
+/// costs time and saves nothing. AVI and WMV are left out, as they often carry
+/// uncompressed PCM audio or raw video that deflate still shrinks
+const STORED_EXTENSIONS: &[&str] = &[
+    "webp", "jpg", "jpeg", "png", "gif", "avif", "heic", "heif", "mp3", "ogg", "opus", "m4a",
+    "aac", "flac", "mp4", "mov", "mkv", "webm",
+];
+
+/// How an output entry is written: already compressed media is stored,
+/// everything else deflated at `level` (the deflate default when None)
+pub(crate) fn entry_options(entry_name: &str, level: Option<u8>) -> FileOptions {
+    let compressed = Path::new(entry_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| STORED_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+    if compressed {
+        FileOptions::default().compression_method(CompressionMethod::Stored)
+    } else {
+        FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(level.map(i32::from))
+    }
+}
+
+/// Timestamp and permissions of a source entry, carried over to the output entry
+/// made from it (pack editors track changes by entry timestamps)
+#[derive(Debug, Clone, Copy)]
+pub struct EntryMetadata {
+    pub modified: zip::DateTime,
+    pub unix_mode: Option<u32>,
+}
+
+impl EntryMetadata {
+    pub fn of(file: &ZipFile<'_>) -> Self {
+        Self {
+            modified: file.last_modified(),
+            unix_mode: file.unix_mode(),
+        }
+    }
+
+    pub fn apply(self, options: FileOptions) -> FileOptions {
+        let options = options.last_modified_time(self.modified);
+        match self.unix_mode {
+            Some(mode) => options.unix_permissions(mode),
+            None => options,
+        }
+    }
+}
+
+/// Metadata of every input entry under the name of the output entry it became;
+/// dropped duplicates leave the metadata of the copy that was kept
+fn output_metadata(
+    archive: &mut ZipArchive<BufReader<File>>,
+    renames: &HashMap<String, String>,
+    duplicates: &HashMap<String, String>,
+) -> Result<HashMap<String, EntryMetadata>> {
+    let mut metadata = HashMap::new();
+    for i in 0..archive.len() {
+        let file = archive
+            .by_index_raw(i)
+            .with_context(|| format!("Failed to read file at index {i}"))?;
+        if duplicates.contains_key(file.name()) {
+            continue;
+        }
+        let output = renames.get(file.name()).map_or(file.name(), String::as_str);
+        metadata
+            .entry(output.to_string())
+            .or_insert_with(|| EntryMetadata::of(&file));
+    }
+    Ok(metadata)
+}
+
+/// Open a pack (or any ZIP) for reading
+pub fn open_pack(path: &Path) -> Result<ZipArchive<BufReader<File>>> {
+    let file = File::open(path).map_err(|source| SicomError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let archive = ZipArchive::new(BufReader::new(file)).map_err(|source| SicomError::Archive {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(archive)
+}
+
+/// `preload.json` for the final content.xml, with the sizes and durations of
+/// the journaled output entries
+fn preload_listing(xml: &str, journal: &Journal, registry: &MediaRegistry) -> Result<Vec<u8>> {
+    let package = pack::parse_package(xml)?;
+    let index = EntryIndex::new(journal.entries());
+    let manifest = preload::preload_manifest(&package, |media| {
+        let (entry, size) = index.resolve(media)?;
+        let duration_seconds = (media.kind != MediaKind::Image)
+            .then(|| {
+                let (processor, _) = registry.find(entry)?;
+                let data = journal.read_entry(entry).ok()?;
+                processor.analyze(&data, entry).duration_seconds
+            })
+            .flatten();
+        Some((entry.clone(), *size, duration_seconds))
+    });
+    Ok(serde_json::to_vec_pretty(&manifest)?)
+}
+
+/// References in content.xml to entries the pack doesn't have, each listed once
+fn missing_references<'a>(xml: &str, entry_names: impl Iterator<Item = &'a str>) -> Vec<Problem> {
+    let package = match pack::parse_package(xml) {
+        Ok(package) => package,
+        Err(e) => {
+            debug!("Not checking references, content.xml didn't parse: {e:#}");
+            return Vec::new();
+        }
+    };
+    let index = EntryIndex::new(entry_names.map(|name| (name, 0)));
+    let mut missing: Vec<Problem> = Vec::new();
+    for media in verify::media_references(&package) {
+        if index.resolve(media).is_none() {
+            let problem = verify::missing_reference(&index, media);
+            if !missing.contains(&problem) {
+                missing.push(problem);
+            }
+        }
+    }
+    missing
+}
+
+/// Quote an entry name for a POSIX shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Media left uncompressed grouped by reason, each group a ready-to-paste `--include` list
+fn unprocessed_report(unprocessed: &[(String, String)]) -> String {
+    let mut groups: Vec<(&str, Vec<&str>)> = Vec::new();
+    for (file_name, reason) in unprocessed {
+        match groups.iter_mut().find(|(group, _)| group == reason) {
+            Some((_, files)) => files.push(file_name),
+            None => groups.push((reason, vec![file_name])),
+        }
+    }
+
+    let mut report = String::new();
+    for (reason, files) in groups {
+        let includes: Vec<String> = files
+            .iter()
+            .map(|file| format!("--include {}", shell_quote(file)))
+            .collect();
+        // ffmpeg errors carry multi-line stderr
+        let reason = reason.replace('\n', " ");
+        report.push_str(&format!(
+            "# {reason} ({})\n{}\n",
+            files.len(),
+            includes.join(" ")
+        ));
+    }
+    report
+}
+
+/// Directory prefix (with the slash) and file name of an entry path
+fn split_entry_path(path: &str) -> (&str, &str) {
+    let directory = path.find('/').map_or("", |pos| &path[..=pos]);
+    (directory, &path[directory.len()..])
+}
+
+/// A file name as stored, URL-decoded and URL-encoded
+fn name_variations(name: &str) -> Vec<String> {
+    vec![
+        name.to_string(),
+        urlencoding::decode(name)
+            .unwrap_or_else(|_| name.into())
+            .to_string(),
+        urlencoding::encode(name).to_string(),
+    ]
+}
+
+/// Whether any text mentions an entry in a form `rewrite_references` updates
+fn has_references(texts: &[String], entry_path: &str) -> bool {
+    let renames = ReferenceRenames::new(&[(entry_path, entry_path)]);
+    texts.iter().any(|text| {
+        reference_values(text)
+            .into_iter()
+            .any(|range| renames.replace(&text[range]).is_some())
+    })
+}
+
+/// Entry a dropped duplicate ends up pointing at (an identical copy's keeper
+/// can itself be a near-duplicate image)
+fn final_keeper<'a>(duplicates: &'a HashMap<String, String>, entry: &'a str) -> &'a str {
+    let mut keeper = entry;
+    while let Some(next) = duplicates.get(keeper) {
+        keeper = next;
+    }
+    keeper
+}
+
+/// Whether any text mentions an entry or a duplicate dropped in its favour,
+/// whose references are redirected to it
+fn has_references_with_duplicates(
+    texts: &[String],
+    entry_path: &str,
+    duplicates: &HashMap<String, String>,
+) -> bool {
+    has_references(texts, entry_path)
+        || duplicates.keys().any(|duplicate| {
+            final_keeper(duplicates, duplicate) == entry_path && has_references(texts, duplicate)
+        })
+}
+
+/// Read content.xml and the other text entries up front
+fn read_reference_texts(
+    archive: &mut ZipArchive<BufReader<File>>,
+    options: &CompressOptions,
+) -> Result<Vec<String>> {
+    let mut texts = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .with_context(|| format!("Failed to read file at index {i}"))?;
+        let file_name = file.name().to_string();
+        if file_name == "content.xml" || options.is_text_entry(&file_name) {
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer)
+                .with_context(|| format!("Failed to read file: {file_name}"))?;
+            texts.push(String::from_utf8_lossy(&buffer).into_owned());
+        }
+    }
+    Ok(texts)
+}
+
+/// Byte ranges of the values a text entry can refer to media by: the text
+/// between tags and every quoted value inside a tag
+fn reference_values(text: &str) -> Vec<Range<usize>> {
+    let mut values = Vec::new();
+    let mut pos = 0;
+    let mut in_tag = false;
+    while pos < text.len() {
+        if in_tag {
+            match text.as_bytes()[pos] {
+                b'>' => {
+                    in_tag = false;
+                    pos += 1;
+                }
+                quote @ (b'"' | b'\'') => {
+                    let start = pos + 1;
+                    let end = text[start..]
+                        .find(char::from(quote))
+                        .map_or(text.len(), |len| start + len);
+                    values.push(start..end);
+                    pos = end + 1;
+                }
+                _ => pos += 1,
+            }
+        } else {
+            let end = text[pos..].find('<').map_or(text.len(), |len| pos + len);
+            values.push(pos..end);
+            pos = end + 1;
+            in_tag = true;
+        }
+    }
+    values
+}
+
+/// Renamed media entries, looked up by whole reference values
+struct ReferenceRenames {
+    /// Each form of an old file name with the index of its rename and the same
+    /// form of the new name
+    names: HashMap<String, (usize, String)>,
+    /// The same for `directory/file` paths
+    paths: HashMap<String, (usize, String)>,
+}
+
+impl ReferenceRenames {
+    fn new(renames: &[(&str, &str)]) -> Self {
+        let mut names = HashMap::new();
+        let mut paths = HashMap::new();
+        for (index, (original_path, new_path)) in renames.iter().enumerate() {
+            let (directory, original_filename) = split_entry_path(original_path);
+            let new_filename = new_path.strip_prefix(directory).unwrap_or(new_path);
+            for (old, new) in name_variations(original_filename)
+                .into_iter()
+                .zip(name_variations(new_filename))
+            {
+                paths
+                    .entry(format!("{directory}{old}"))
+                    .or_insert_with(|| (index, format!("{directory}{new}")));
+                names.entry(old).or_insert((index, new));
+            }
+        }
+        Self { names, paths }
+    }
+
+    /// Replacement for a whole value, with the index of the rename it applies:
+    /// a file name (also as an `@file` atom or logo) or a path ending in
+    /// `directory/file`, possibly below other directories (`../Images/pic.png`)
+    fn replace(&self, value: &str) -> Option<(usize, String)> {
+        let (prefix, name) = value
+            .strip_prefix('@')
+            .map_or(("", value), |name| ("@", name));
+        if let Some((index, new)) = self.names.get(name) {
+            return Some((*index, format!("{prefix}{new}")));
+        }
+        let starts = std::iter::once(0).chain(name.match_indices('/').map(|(pos, _)| pos + 1));
+        starts.into_iter().find_map(|start| {
+            self.paths
+                .get(&name[start..])
+                .map(|(index, new)| (*index, format!("{prefix}{}{new}", &name[..start])))
+        })
+    }
+}
+
+/// Replace references to renamed media entries in a text entry, returning how
+/// many references each rename updated
+///
+/// Only whole values are replaced: element text (`isRef` items, `@file` atoms)
+/// and attribute values (the package `logo="@file"`, HTML `src`), naming the
+/// file or its `directory/file` path in raw, URL-decoded or URL-encoded form.
+/// A name that merely ends another one (`1.jpg` in `11.jpg`) is left alone.
+/// All renames apply in one pass; when two claim the same value the earlier
+/// one wins, so callers pass them in a fixed order
+pub(crate) fn rewrite_references(text: &mut String, renames: &[(&str, &str)]) -> Vec<usize> {
+    let lookup = ReferenceRenames::new(renames);
+    let mut counts = vec![0; renames.len()];
+    let mut rewritten = String::with_capacity(text.len());
+    let mut copied = 0;
+    for range in reference_values(text) {
+        if let Some((index, new)) = lookup.replace(&text[range.clone()]) {
+            rewritten.push_str(&text[copied..range.start]);
+            rewritten.push_str(&new);
+            copied = range.end;
+            counts[index] += 1;
+        }
+    }
+    if copied > 0 {
+        rewritten.push_str(&text[copied..]);
+        *text = rewritten;
+    }
+    counts
+}
+
+/// Output next to the input with a `_compressed` suffix (e.g. pack_compressed.siq)
+pub fn default_output_path(input_pack: &Path) -> Result<PathBuf> {
+    let stem = input_pack
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow!("Invalid file name"))?;
+    let extension = input_pack
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("siq");
+    Ok(input_pack.with_file_name(format!("{stem}_compressed.{extension}")))
+}
+
+/// Compress every media file of a pack (or generic ZIP) into a new archive
+pub fn compress_pack(
+    input_pack: PathBuf,
+    output_pack: Option<PathBuf>,
+    options: CompressOptions,
+    multi_progress: MultiProgress,
+) -> Result<CompressionStats> {
+    compress_pack_with_progress(input_pack, output_pack, options, multi_progress, None)
+}
+
+/// `compress_pack` that also reports per-file progress to a callback
+pub fn compress_pack_with_progress(
+    input_pack: PathBuf,
+    output_pack: Option<PathBuf>,
+    options: CompressOptions,
+    multi_progress: MultiProgress,
+    on_progress: Option<ProgressCallback>,
+) -> Result<CompressionStats> {
+    let mut compressor = PackCompressor::new(options).multi_progress(multi_progress);
+    compressor.on_progress = on_progress;
+    compressor.compress(input_pack, output_pack)
+}
+
+/// Pack compression for embedding applications (GUI, bots, servers)
+///
+/// Draws no progress bars unless given a [`MultiProgress`]. A cancelled run
+/// removes its partial output and fails with [`SicomError::Cancelled`], which
+/// carries the statistics of the files finished so far.
+///
+/// ```no_run
+/// use sicom::CancellationToken;
+/// use sicom::options::CompressOptions;
+/// use sicom::pipeline::PackCompressor;
+///
+/// let token = CancellationToken::new();
+/// let handle = token.clone(); // call handle.cancel() from a UI thread
+/// let stats = PackCompressor::new(CompressOptions::default())
+///     .cancellation(token)
+///     .compress("pack.siq".into(), None)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct PackCompressor {
+    options: CompressOptions,
+    multi_progress: MultiProgress,
+    on_progress: Option<ProgressCallback>,
+    cancellation: Option<CancellationToken>,
+    warm_pool: Option<WarmPool>,
+}
+
+impl PackCompressor {
+    pub fn new(options: CompressOptions) -> Self {
+        Self {
+            options,
+            multi_progress: MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            on_progress: None,
+            cancellation: None,
+            warm_pool: None,
+        }
+    }
+
+    /// Draw progress bars into `multi_progress`
+    pub fn multi_progress(mut self, multi_progress: MultiProgress) -> Self {
+        self.multi_progress = multi_progress;
+        self
+    }
+
+    /// Receive (files done, total files) after each file
+    pub fn on_progress(mut self, callback: ProgressCallback) -> Self {
+        self.on_progress = Some(callback);
+        self
+    }
+
+    /// Stop the run when `token` is cancelled
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Reuse the ffmpeg lookup and video probes of earlier runs sharing `pool`
+    pub fn warm_pool(mut self, pool: WarmPool) -> Self {
+        self.warm_pool = Some(pool);
+        self
+    }
+
+    /// Compress `input_pack` to `output_pack` (or next to the input with `None`)
+    pub fn compress(
+        self,
+        input_pack: PathBuf,
+        output_pack: Option<PathBuf>,
+    ) -> Result<CompressionStats> {
+        run_compression(input_pack, output_pack, self)
+    }
+}
+
+/// Stop a cancelled run, removing its partial output
+fn cancel_run(
+    logger: &mut ProgressLogger,
+    journal: Journal,
+    output_path: &Path,
+    stats: StatsAggregator,
+) -> anyhow::Error {
+    logger.finish();
+    journal.discard();
+    info!("Compression cancelled, removed the partial output for {output_path:?}");
+    SicomError::Cancelled(Box::new(stats.finish())).into()
+}
+
+fn run_compression(
+    input_pack: PathBuf,
+    output_pack: Option<PathBuf>,
+    compressor: PackCompressor,
+) -> Result<CompressionStats> {
+    let PackCompressor {
+        mut options,
+        multi_progress,
+        on_progress,
+        cancellation,
+        warm_pool,
+    } = compressor;
+
+    // Validate input
+    if !input_pack.exists() {
+        return Err(SicomError::InputNotFound(input_pack).into());
+    }
+
+    // Generic ZIPs can use any extension (.zip, .pack, ...)
+    if !options.generic_zip && input_pack.extension().and_then(|s| s.to_str()) != Some("siq") {
+        return Err(SicomError::InvalidSiqFile(input_pack).into());
+    }
+
+    // The default sibling output always gets a manifest, so an accidental rerun is cheap
+    // Transliterated names can only be reversed with the manifest
+    let record_manifest =
+        options.incremental || options.transliterate_names || output_pack.is_none();
+
+    // Determine output path
+    let output_path = match output_pack {
+        Some(path) => path,
+        None => default_output_path(&input_pack)?,
+    };
+
+    info!("Compressing pack: {input_pack:?}");
+    info!("Output to: {output_path:?}");
+    info!("Image quality: {}", options.image_quality);
+    info!("Audio quality: {}", options.audio.quality);
+    info!("Audio mode: {:?}", options.audio.mode);
+    info!("Audio engine: {:?}", options.audio.engine);
+    info!("Video quality: {}", options.video_quality);
+    info!("Skip image: {}", options.skip_image);
+    info!("Skip audio: {}", options.skip_audio);
+    info!("Skip video: {}", options.skip_video);
+    if options.video_mode == video::VideoMode::Remux {
+        info!("Video mode: remux into faststart MP4, no re-encode");
+    }
+    if let Some(denoise) = options.video_denoise {
+        info!("Video denoise: {denoise:?}");
+    }
+    if options.lossless_only {
+        info!("Lossless only: lossless WebP, MP3 repack and video remux");
+    }
+    if options.generic_zip {
+        info!(
+            "Generic ZIP mode: media matched anywhere by extension, entries keep their names and formats"
+        );
+    }
+
+    let ffmpeg_available = prepare_ffmpeg_with(&mut options, warm_pool.as_ref());
+
+    options.validate()?;
+
+    let input_hash = if record_manifest {
+        Some(manifest::file_hash(&input_pack)?)
+    } else {
+        None
+    };
+    if let Some(input_hash) = &input_hash {
+        if !options.force && Manifest::is_up_to_date(&output_path, input_hash, &options) {
+            info!(
+                "{} is already compressed from this input with these settings (use --force to compress again)",
+                output_path.display()
+            );
+            return Ok(CompressionStats::new());
+        }
+    }
+
+    let deadline = options
+        .time_limit_seconds
+        .map(|seconds| (seconds, Instant::now() + Duration::from_secs(seconds)));
+
+    // Open input ZIP
+    let mut archive = open_pack(&input_pack)?;
+
+    // Another lossy pass over already compressed media only loses quality
+    if !options.lossless_only {
+        let signs = recompression_signs(&mut archive, &input_pack, &options)?;
+        if !signs.is_empty() {
+            if !options.allow_recompress {
+                return Err(SicomError::AlreadyCompressed(signs).into());
+            }
+            warn!(
+                "Pack looks already compressed ({}), recompressing will degrade it further",
+                signs.join(", ")
+            );
+        }
+    }
+
+    // Results of the previous incremental run, reused for unchanged media
+    // (except with a ladder: reused entries come without their renditions)
+    let manifest_path = Manifest::path_for(&output_path);
+    let mut previous = if options.incremental && !options.force && options.video_ladder.is_empty() {
+        PreviousOutput::open(&output_path, &options)?
+    } else {
+        None
+    };
+    let mut manifest = Manifest::new(&options);
+    manifest.input_hash = input_hash;
+    let mut reused_entries = 0;
+
+    // Entries are journaled and only assembled into the output ZIP at the end
+    // (resuming skips media finished by an interrupted run, except with a ladder)
+    let mut journal = Journal::create(&output_path, &input_pack, &options)?;
+    let resume = options.resume && options.video_ladder.is_empty();
+    let mut resumed_entries = 0;
+
+    // Track renamed media (e.g. images converted to WebP) for content.xml updates
+    let mut media_conversions: HashMap<String, String> = HashMap::new();
+    let mut content_xml_data: Option<String> = None;
+    let mut content_types_data: Option<String> = None;
+    // Other text entries (name, contents) whose media references get rewritten too
+    let mut text_entries: Vec<(String, String)> = Vec::new();
+    let mut written_entries: HashSet<String> = HashSet::new();
+    // Ladder renditions by the entry name of their video
+    let mut renditions_by_video: BTreeMap<String, Vec<RenditionEntry>> = BTreeMap::new();
+    // Media copied unchanged (entry name, reason) for --list-unprocessed
+    let mut unprocessed: Vec<(String, String)> = Vec::new();
+
+    let registry = build_registry_with(&options, ffmpeg_available, warm_pool.as_ref());
+
+    // Transliterated names must not clash with entries that already have them
+    let mut taken_stems: HashSet<String> = archive
+        .file_names()
+        .map(|name| transliterate::entry_stem(name).to_string())
+        .collect();
+
+    // Identical media collapses onto its first copy and near-identical images onto
+    // their best variant (duplicate -> kept entry)
+    let mut duplicates = if options.dedupe_identical && !options.generic_zip {
+        find_identical_media(&mut archive)?
+    } else {
+        HashMap::new()
+    };
+    if let Some(threshold) = options.dedupe_similar {
+        let similar_images = find_similar_images(&mut archive, threshold, &duplicates)?;
+        duplicates.extend(similar_images);
+    }
+
+    // Strict mode and pruning need to know up front which media nothing refers to
+    let reference_texts = if (options.strict_refs || options.prune_unused) && !options.generic_zip {
+        Some(read_reference_texts(&mut archive, &options)?)
+    } else {
+        None
+    };
+
+    #[cfg(feature = "content-scan")]
+    let mut content_scanner = options
+        .content_scan
+        .as_ref()
+        .map(moderation::ContentScanner::from_options);
+
+    // Initialize progress logger
+    let total_files = archive.len() as u64;
+    let mut logger = ProgressLogger::new(total_files, &multi_progress);
+    if let Some(callback) = on_progress {
+        logger.set_callback(callback);
+    }
+    if let Some(token) = cancellation {
+        logger.set_cancellation(token);
+    }
+
+    // Every count goes through the aggregator, which also keeps the live savings current
+    let stats = StatsAggregator::spawn(logger.savings_sink());
+
+    // Note: indicatif-log-bridge now handles coordination between log messages and progress bars
+
+    // Helper function to get display filename (strip directory and URL decode)
+    fn get_display_filename(file_path: &str) -> String {
+        // Strip directory prefix (Images/, Audio/, Video/)
+        let filename = if let Some(pos) = file_path.find('/') {
+            &file_path[pos + 1..]
+        } else {
+            file_path
+        };
+
+        // URL decode the filename
+        urlencoding::decode(filename)
+            .unwrap_or_else(|_| filename.into())
+            .to_string()
+    }
+
+    // Process each file in the archive
+    for i in 0..archive.len() {
+        if let Some((seconds, deadline)) = deadline {
+            if Instant::now() >= deadline {
+                journal.discard();
+                return Err(SicomError::TimedOut(seconds).into());
+            }
+        }
+        if logger.is_cancelled() {
+            return Err(cancel_run(&mut logger, journal, &output_path, stats));
+        }
+
+        let mut file = archive
+            .by_index(i)
+            .with_context(|| format!("Failed to read file at index {i}"))?;
+
+        let file_name = file.name().to_string();
+        let is_content_xml = !options.generic_zip && file_name == "content.xml";
+        let is_content_types = file_name == CONTENT_TYPES_ENTRY;
+        let media = if options.generic_zip {
+            registry.find_standalone(&file_name)
+        } else {
+            registry.find(&file_name)
+        };
+
+        debug!("Processing: {file_name}");
+
+        if let Some(keeper) = duplicates.get(&file_name) {
+            // Dropped in favour of the kept copy; references are redirected below
+            debug!("  Duplicate of {keeper}, dropping it");
+            stats.send(StatsEvent::FileSkipped {
+                kind: MediaKind::of_entry(&file_name).unwrap_or(MediaKind::Image),
+                size: file.size(),
+                reason: SkipReason::Deduplicated,
+            });
+            logger.inc();
+            continue;
+        }
+
+        if options.prune_unused {
+            let unused_kind = MediaKind::of_entry(&file_name).filter(|_| {
+                reference_texts.as_ref().is_some_and(|texts| {
+                    !has_references_with_duplicates(texts, &file_name, &duplicates)
+                })
+            });
+            if let Some(kind) = unused_kind {
+                info!(
+                    "Pruning unused {}: {file_name} ({})",
+                    kind.label(),
+                    format_size(file.size())
+                );
+                stats.send(StatsEvent::FileSkipped {
+                    kind,
+                    size: file.size(),
+                    reason: SkipReason::Pruned,
+                });
+                logger.inc();
+                continue;
+            }
+        }
+
+        // Media finished by the interrupted run is already in the journal, and
+        // unchanged media is copied from the previous output without decoding it
+        let reused = match media {
+            Some((processor, _)) if !is_content_xml => {
+                let resumed = resume
+                    .then(|| journal.resumed(&file_name, file.crc32(), file.size()))
+                    .flatten();
+                if let Some(entry) = resumed {
+                    debug!("  Finished by the interrupted run: {}", entry.output);
+                    written_entries.insert(entry.output.clone());
+                    resumed_entries += 1;
+                    Some((processor.kind(), entry))
+                } else if let Some((previous, entry)) = previous.as_mut().and_then(|previous| {
+                    let entry = previous.unchanged(&file_name, file.crc32(), file.size())?;
+                    Some((previous, entry))
+                }) {
+                    debug!("  Unchanged since the previous run: {}", entry.output);
+                    if written_entries.insert(entry.output.clone()) {
+                        let data = previous.read_entry(&entry.output)?;
+                        journal.write_entry(&entry.output, &data)?;
+                    }
+                    journal.record_media(&file_name, &entry);
+                    reused_entries += 1;
+                    Some((processor.kind(), entry))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+
+        if let Some((kind, entry)) = reused {
+            stats.send(match entry.outcome {
+                EntryOutcome::Compressed => StatsEvent::FileProcessed {
+                    kind,
+                    original_size: entry.size,
+                    compressed_size: entry.output_size,
+                    lossless_source: kind == MediaKind::Audio
+                        && audio::is_lossless_audio(&file_name),
+                },
+                EntryOutcome::KeptOriginal => StatsEvent::FileSkipped {
+                    kind,
+                    size: entry.size,
+                    reason: SkipReason::KeptOriginal,
+                },
+                EntryOutcome::Skipped => StatsEvent::FileSkipped {
+                    kind,
+                    size: entry.size,
+                    reason: SkipReason::Skipped,
+                },
+            });
+            if entry.output != file_name {
+                media_conversions.insert(file_name.clone(), entry.output.clone());
+            }
+            if entry.transliterated_from.is_some() {
+                taken_stems.insert(transliterate::entry_stem(&entry.output).to_string());
+            }
+            manifest.entries.insert(file_name, entry);
+        } else if is_content_xml {
+            // Read content.xml for later processing
+            let mut xml_data = String::new();
+            file.read_to_string(&mut xml_data)
+                .with_context(|| "Failed to read content.xml as UTF-8")?;
+
+            // Track input size
+            stats.send(StatsEvent::OtherFile {
+                size: xml_data.len() as u64,
+            });
+
+            content_xml_data = Some(xml_data);
+
+            // We'll write content.xml after processing all media
+            debug!("  Stored content.xml for path updates");
+        } else if is_content_types {
+            // Mime types are regenerated once the final entry names are known
+            let mut xml_data = String::new();
+            file.read_to_string(&mut xml_data)
+                .with_context(|| format!("Failed to read {CONTENT_TYPES_ENTRY} as UTF-8"))?;
+            stats.send(StatsEvent::OtherFile {
+                size: xml_data.len() as u64,
+            });
+            content_types_data = Some(xml_data);
+        } else if let Some((processor, disabled_reason)) = media {
+            let kind = processor.kind();
+            let disabled_reason = disabled_reason
+                .or_else(|| {
+                    options
+                        .min_file_size
+                        .filter(|&min| file.size() < min)
+                        .map(|_| "below --min-file-size")
+                })
+                .or_else(|| {
+                    (!options.include.is_empty() && !options.include.contains(&file_name))
+                        .then_some(NOT_INCLUDED)
+                });
+
+            let mut media_data = Vec::new();
+            file.read_to_end(&mut media_data)
+                .with_context(|| format!("Failed to read {} data: {file_name}", kind.label()))?;
+            let original_size = media_data.len() as u64;
+            let mut outcome = EntryOutcome::Skipped;
+
+            // Renaming media nothing refers to would leave an unreachable entry;
+            // generic ZIPs are never rewritten, so their references can't follow
+            let unreferenced = options.generic_zip
+                || reference_texts.as_ref().is_some_and(|texts| {
+                    !has_references_with_duplicates(texts, &file_name, &duplicates)
+                });
+            let keep_name_reason = if options.generic_zip {
+                "generic ZIP entries keep their names"
+            } else {
+                "no references to update"
+            };
+
+            // Moderation looks at every image, even ones that end up uncompressed
+            #[cfg(feature = "content-scan")]
+            if let Some(scanner) = content_scanner.as_mut() {
+                if kind == MediaKind::Image {
+                    scanner.scan(&media_data, &file_name);
+                }
+            }
+
+            // Compressed output to use, None keeps the original bytes
+            let mut renditions = Vec::new();
+            let compressed = if let Some(reason) = disabled_reason {
+                debug!(
+                    "  Skipping {} compression ({reason}): {file_name}",
+                    kind.label()
+                );
+                stats.send(StatsEvent::FileSkipped {
+                    kind,
+                    size: original_size,
+                    reason: SkipReason::Skipped,
+                });
+                if reason != NOT_INCLUDED {
+                    unprocessed.push((file_name.clone(), format!("Skipped: {reason}")));
+                }
+                None
+            } else {
+                if log::log_enabled!(log::Level::Debug) {
+                    debug!("  Source: {}", processor.analyze(&media_data, &file_name));
+                }
+
+                let started = Instant::now();
+                let result = processor.compress(&media_data, &file_name, &mut logger);
+                // The file isn't done, so it stays out of the partial statistics
+                if logger.is_cancelled() {
+                    return Err(cancel_run(&mut logger, journal, &output_path, stats));
+                }
+                stats.send(StatsEvent::StageTiming {
+                    kind,
+                    elapsed: started.elapsed(),
+                });
+                match result {
+                    Ok(mut output) => {
+                        let compressed_size = output.data.len() as u64;
+                        // Kept even when the main output isn't
+                        renditions = std::mem::take(&mut output.renditions);
+
+                        // Check if renaming is safe and compression actually reduced size
+                        if unreferenced && output.filename != file_name {
+                            stats.send(StatsEvent::FileSkipped {
+                                kind,
+                                size: original_size,
+                                reason: SkipReason::KeptOriginal,
+                            });
+                            outcome = EntryOutcome::KeptOriginal;
+                            warn!("  Keeping original {file_name}: {keep_name_reason}");
+                            unprocessed.push((
+                                file_name.clone(),
+                                format!("Kept original: {keep_name_reason}"),
+                            ));
+                            None
+                        } else if compressed_size >= original_size && !options.always_compress {
+                            // Keep original file since compressed version is larger
+                            stats.send(StatsEvent::FileSkipped {
+                                kind,
+                                size: original_size,
+                                reason: SkipReason::KeptOriginal,
+                            });
+                            outcome = EntryOutcome::KeptOriginal;
+                            unprocessed.push((
+                                file_name.clone(),
+                                "Kept original: compressed would be larger".to_string(),
+                            ));
+
+                            info!(
+                                "  Keeping original (compressed would be larger): {} vs {}",
+                                format_size(original_size),
+                                format_size(compressed_size)
+                            );
+                            None
+                        } else {
+                            // Use compressed version (either smaller or always_compress is set)
+                            stats.send(StatsEvent::FileProcessed {
+                                kind,
+                                original_size,
+                                compressed_size,
+                                lossless_source: kind == MediaKind::Audio
+                                    && audio::is_lossless_audio(&file_name),
+                            });
+                            outcome = EntryOutcome::Compressed;
+
+                            let display_filename = get_display_filename(&file_name);
+                            if compressed_size >= original_size {
+                                debug!(
+                                    "  Compressed \"{}\" to {} (forced): {} -> {} ({:.1}% increase)",
+                                    display_filename,
+                                    output.codec,
+                                    format_size(original_size),
+                                    format_size(compressed_size),
+                                    (compressed_size as f64 / original_size as f64 - 1.0) * 100.0
+                                );
+                            } else {
+                                debug!(
+                                    "  Compressed \"{}\" to {}: {} -> {} ({:.1}% reduction)",
+                                    display_filename,
+                                    output.codec,
+                                    format_size(original_size),
+                                    format_size(compressed_size),
+                                    (1.0 - compressed_size as f64 / original_size as f64) * 100.0
+                                );
+                            }
+                            Some(output)
+                        }
+                    }
+                    Err(e) => {
+                        // Video failures are rare and expensive - surface them
+                        if kind == MediaKind::Video {
+                            warn!("  Video compression failed for {file_name}: {e}");
+                        } else {
+                            debug!("  Skipping {file_name}: {e}");
+                        }
+                        stats.send(StatsEvent::FileSkipped {
+                            kind,
+                            size: original_size,
+                            reason: SkipReason::Skipped,
+                        });
+                        unprocessed.push((file_name.clone(), format!("Skipped: {e}")));
+                        None
+                    }
+                }
+            };
+
+            // Originals keep their name (and extension) unless renaming is requested
+            let (entry_name, entry_data) = match &compressed {
+                Some(output) => (output.filename.clone(), output.data.as_slice()),
+                None => (file_name.clone(), media_data.as_slice()),
+            };
+            let entry_name = match options.rename {
+                RenameScheme::Hashed if !unreferenced => {
+                    media::hashed_entry_name(&entry_name, entry_data)
+                }
+                _ => entry_name,
+            };
+            let ascii_name = (options.transliterate_names && !unreferenced)
+                .then(|| {
+                    transliterate::ascii_entry_name(&entry_name, |stem| taken_stems.contains(stem))
+                })
+                .flatten();
+            let (entry_name, transliterated_from) = match ascii_name {
+                Some(ascii_name) => {
+                    debug!("  Transliterated: {entry_name} -> {ascii_name}");
+                    taken_stems.insert(transliterate::entry_stem(&ascii_name).to_string());
+                    (ascii_name, Some(entry_name))
+                }
+                None => (entry_name, None),
+            };
+
+            // Hashed names collide only for identical content - store it once
+            if written_entries.insert(entry_name.clone()) {
+                journal.write_entry(&entry_name, entry_data)?;
+            } else {
+                debug!("  Identical to an existing entry: {entry_name}");
+            }
+            let mut result =
+                ManifestEntry::new(file.crc32(), original_size, outcome, entry_name.clone());
+            result.transliterated_from = transliterated_from;
+
+            // Renditions are named after the final entry
+            for RenditionOutput { rendition, data } in renditions {
+                let name = rendition.entry_name(&entry_name);
+                if written_entries.insert(name.clone()) {
+                    journal.write_entry(&name, &data)?;
+                    stats.send(StatsEvent::ExtraFile {
+                        size: data.len() as u64,
+                    });
+                }
+                renditions_by_video
+                    .entry(entry_name.clone())
+                    .or_default()
+                    .push(RenditionEntry {
+                        rendition,
+                        height: rendition.height(),
+                        entry: name,
+                        size: data.len() as u64,
+                    });
+            }
+
+            // Done once all its outputs are in the journal
+            journal.record_media(&file_name, &result);
+            if record_manifest {
+                manifest.entries.insert(file_name.clone(), result);
+            }
+
+            // Track renames for content.xml updates
+            if entry_name != file_name {
+                media_conversions.insert(file_name.clone(), entry_name);
+            }
+        } else {
+            // Copy other files unchanged
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer)
+                .with_context(|| format!("Failed to read file: {file_name}"))?;
+
+            if !options.generic_zip && options.is_text_entry(&file_name) {
+                match String::from_utf8(buffer) {
+                    Ok(text) => {
+                        // Written after media renames are known
+                        debug!("  Stored {file_name} for path updates");
+                        stats.send(StatsEvent::OtherFile {
+                            size: text.len() as u64,
+                        });
+                        text_entries.push((file_name, text));
+                        logger.inc();
+                        continue;
+                    }
+                    Err(e) => {
+                        debug!("  Not rewriting references in non-UTF-8 {file_name}");
+                        buffer = e.into_bytes();
+                    }
+                }
+            }
+
+            journal.write_entry(&file_name, &buffer)?;
+            written_entries.insert(file_name);
+            stats.send(StatsEvent::OtherFile {
+                size: buffer.len() as u64,
+            });
+        }
+
+        // Increment progress after processing each file
+        logger.inc();
+    }
+
+    if !renditions_by_video.is_empty() {
+        let listing = serde_json::to_vec_pretty(&renditions_by_video)?;
+        journal.write_entry(RENDITIONS_ENTRY, &listing)?;
+        stats.send(StatsEvent::ExtraFile {
+            size: listing.len() as u64,
+        });
+        info!(
+            "Wrote {} renditions of {} videos, listed in {RENDITIONS_ENTRY}",
+            renditions_by_video.values().map(Vec::len).sum::<usize>(),
+            renditions_by_video.len()
+        );
+    }
+
+    // Point duplicates at the final name of the media that replaced them
+    for duplicate in duplicates.keys() {
+        let keeper = final_keeper(&duplicates, duplicate);
+        let target = media_conversions
+            .get(keeper)
+            .cloned()
+            .unwrap_or_else(|| keeper.to_string());
+        media_conversions.insert(duplicate.clone(), target);
+    }
+
+    // A broken pack compresses fine and only fails once the game reaches the reference
+    let missing = content_xml_data
+        .as_deref()
+        .map(|xml| missing_references(xml, archive.file_names()))
+        .unwrap_or_default();
+
+    // content.xml first, then auxiliary text entries referencing media
+    if let Some(xml_content) = content_xml_data {
+        // A backup carried over from an earlier run is the real original
+        if options.keep_original_xml && !written_entries.contains(ORIGINAL_CONTENT_XML) {
+            journal.write_entry(ORIGINAL_CONTENT_XML, xml_content.as_bytes())?;
+            written_entries.insert(ORIGINAL_CONTENT_XML.to_string());
+            stats.send(StatsEvent::ExtraFile {
+                size: xml_content.len() as u64,
+            });
+            debug!("  Stored original content.xml as {ORIGINAL_CONTENT_XML}");
+        }
+        text_entries.insert(0, ("content.xml".to_string(), xml_content));
+    } else if !options.generic_zip {
+        warn!("Warning: No content.xml found in pack");
+    }
+
+    // Kept to diff against once the references are updated
+    let original_content_xml = options.xml_diff.as_ref().and_then(|_| {
+        text_entries
+            .iter()
+            .find(|(name, _)| name == "content.xml")
+            .map(|(_, text)| text.clone())
+    });
+    let mut xml_diff_text = None;
+    let mut final_content_xml = None;
+
+    // Process text entries with updated media paths
+    if !text_entries.is_empty() {
+        info!(
+            "Updating media references in {} text entries",
+            text_entries.len()
+        );
+
+        let mut updated_refs = 0;
+        let mut dangling = Vec::new();
+        let mut renames: Vec<(&str, &str)> = media_conversions
+            .iter()
+            .map(|(original, new)| (original.as_str(), new.as_str()))
+            .collect();
+        renames.sort_unstable();
+        let mut counts = vec![0; renames.len()];
+        for (_, text) in &mut text_entries {
+            for (count, replaced) in counts.iter_mut().zip(rewrite_references(text, &renames)) {
+                *count += replaced;
+            }
+        }
+        for (&(original_path, new_path), &file_replacements) in renames.iter().zip(&counts) {
+            updated_refs += file_replacements;
+
+            if file_replacements > 0 {
+                debug!("  Updated: {original_path} -> {new_path} ({file_replacements} refs)");
+            } else {
+                warn!("  Warning: No refs found for {original_path}");
+                // Dropped duplicates leave nothing behind to point at
+                if !duplicates.contains_key(original_path) {
+                    dangling.push(original_path.to_string());
+                }
+            }
+        }
+
+        if options.strict_refs && !dangling.is_empty() {
+            journal.discard();
+            dangling.sort();
+            return Err(SicomError::DanglingReferences(dangling).into());
+        }
+
+        let updated_content_xml = text_entries
+            .iter()
+            .find(|(name, _)| name == "content.xml")
+            .map(|(_, text)| text);
+        if let (Some(target), Some(original), Some(updated)) = (
+            &options.xml_diff,
+            &original_content_xml,
+            updated_content_xml,
+        ) {
+            let xml_diff = diff::unified_diff(original, updated, "a/content.xml", "b/content.xml");
+            if target.as_os_str() == "-" {
+                // Printed after the progress bars are gone
+                xml_diff_text = Some(xml_diff);
+            } else {
+                std::fs::write(target, &xml_diff).map_err(|source| SicomError::Io {
+                    path: target.clone(),
+                    source,
+                })?;
+                info!("Wrote content.xml diff to {}", target.display());
+            }
+        }
+
+        // Write updated entries to output ZIP
+        for (name, text) in text_entries {
+            journal.write_entry(&name, text.as_bytes())?;
+            if options.emit_preload_manifest && name == "content.xml" {
+                final_content_xml = Some(text);
+            }
+            written_entries.insert(name);
+        }
+
+        // Track updated refs (sizes were tracked when the entries were read)
+        stats.send(StatsEvent::RefUpdated {
+            count: updated_refs as u64,
+        });
+        warn!("Updated {updated_refs} media references");
+    }
+
+    if let Some(xml_content) = final_content_xml {
+        let listing = preload_listing(&xml_content, &journal, &registry)?;
+        journal.write_entry(PRELOAD_ENTRY, &listing)?;
+        written_entries.insert(PRELOAD_ENTRY.to_string());
+        stats.send(StatsEvent::ExtraFile {
+            size: listing.len() as u64,
+        });
+        info!("Listed the media of every round in {PRELOAD_ENTRY}");
+    }
+
+    if let Some(xml_content) = content_types_data {
+        let xml_content = content_types::update_content_types(
+            &xml_content,
+            written_entries.iter().map(String::as_str),
+            &media_conversions,
+        )
+        .unwrap_or_else(|e| {
+            warn!("Copying unparseable {CONTENT_TYPES_ENTRY} unchanged: {e}");
+            xml_content
+        });
+        journal.write_entry(CONTENT_TYPES_ENTRY, xml_content.as_bytes())?;
+    }
+
+    let metadata = output_metadata(&mut archive, &media_conversions, &duplicates)?;
+    journal.finish(&output_path, options.zip_level, &metadata)?;
+    if resumed_entries > 0 {
+        info!("Resumed {resumed_entries} media entries finished by the interrupted run");
+    }
+
+    if record_manifest {
+        manifest.record_outputs(&output_path)?;
+        manifest.save(&manifest_path)?;
+    }
+    if options.incremental {
+        if let Some(previous) = previous {
+            previous.finish();
+        }
+        info!("Reused {reused_entries} unchanged media entries from the previous run");
+    }
+
+    let mut unprocessed_text = None;
+    if let Some(target) = &options.unprocessed_list {
+        let report = unprocessed_report(&unprocessed);
+        if target.as_os_str() == "-" {
+            unprocessed_text = Some(report);
+        } else {
+            std::fs::write(target, &report).map_err(|source| SicomError::Io {
+                path: target.clone(),
+                source,
+            })?;
+            info!(
+                "Wrote {} uncompressed media entries to {}",
+                unprocessed.len(),
+                target.display()
+            );
+        }
+    }
+
+    // Finish progress logging and show final summary
+    let mut stats = stats.finish();
+    stats.set_missing_references(missing);
+    logger.finish();
+
+    if let Some(xml_diff) = xml_diff_text {
+        if xml_diff.is_empty() {
+            info!("content.xml unchanged");
+        } else {
+            print!("{xml_diff}");
+        }
+    }
+
+    info!("Compression complete!");
+
+    // Per-kind statistics
+    for kind in MediaKind::ALL {
+        let media = stats.media(kind);
+        info!("");
+        info!("{}:", kind.title());
+        info!("  Processed: {}", media.processed);
+        info!("  Kept original (due to size): {}", media.kept_original);
+        info!("  Skipped: {}", media.skipped);
+        if media.deduplicated > 0 {
+            info!("  Deduplicated: {}", media.deduplicated);
+        }
+        if media.pruned > 0 {
+            info!("  Pruned (unused): {}", media.pruned);
+        }
+        if media.original_size > 0 {
+            if media.compressed_size > 0 {
+                info!(
+                    "  Size reduction: {} -> {} ({:.1}% reduction)",
+                    format_size(media.original_size),
+                    format_size(media.compressed_size),
+                    media.compression_ratio()
+                );
+            } else {
+                info!(
+                    "  Total size: {} (no compression applied)",
+                    format_size(media.original_size)
+                );
+            }
+        }
+        let lossless = stats.lossless_audio();
+        if kind == MediaKind::Audio && lossless.processed > 0 {
+            info!(
+                "  From lossless sources ({} WAV/FLAC): {} -> {} ({} saved)",
+                lossless.processed,
+                format_size(lossless.original_size),
+                format_size(lossless.compressed_size),
+                format_size(
+                    lossless
+                        .original_size
+                        .saturating_sub(lossless.compressed_size)
+                )
+            );
+        }
+    }
+
+    if !stats.missing_references().is_empty() {
+        info!("");
+        warn!(
+            "Missing media ({} references to files the pack doesn't have):",
+            stats.missing_references().len()
+        );
+        for problem in stats.missing_references() {
+            warn!("  {}: {}", problem.entry, problem.message);
+        }
+    }
+
+    // Moderation report
+    #[cfg(feature = "content-scan")]
+    if let Some(scanner) = &content_scanner {
+        info!("");
+        info!("Content scan:");
+        info!("  Flagged as potentially NSFW: {}", scanner.flags().len());
+        for flag in scanner.flags() {
+            warn!("  {} ({:.2})", flag.entry, flag.score);
+        }
+    }
+
+    // Overall statistics
+    if stats.total_input_size() > 0 {
+        info!("");
+        info!("Overall:");
+        info!(
+            "  Total original size: {}",
+            format_size(stats.total_input_size())
+        );
+        info!(
+            "  Total compressed size: {}",
+            format_size(stats.total_output_size())
+        );
+        info!("  Total reduction: {:.1}%", stats.total_compression_ratio());
+
+        // Show actual filesystem sizes for verification
+        if let Ok(input_metadata) = std::fs::metadata(&input_pack) {
+            let input_file_size = input_metadata.len();
+            info!(
+                "  Input file size: {} (filesystem)",
+                format_size(input_file_size)
+            );
+        }
+        if let Ok(output_metadata) = std::fs::metadata(&output_path) {
+            let output_file_size = output_metadata.len();
+            info!(
+                "  Output file size: {} (filesystem)",
+                format_size(output_file_size)
+            );
+        }
+    }
+
+    if let Some(report) = unprocessed_text {
+        if report.is_empty() {
+            info!("No media left uncompressed");
+        } else {
+            info!("");
+            info!("Uncompressed media (re-run with these --include filters to retry):");
+            print!("{report}");
+        }
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{encode_png, gradient, png, write_pack};
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+    use zip::ZipWriter;
+
+    #[test]
+    fn test_output_path_generation() {
+        assert_eq!(
+            default_output_path(Path::new("test.siq")).unwrap(),
+            PathBuf::from("test_compressed.siq")
+        );
+        assert_eq!(
+            default_output_path(Path::new("dir/archive.zip")).unwrap(),
+            PathBuf::from("dir/archive_compressed.zip")
+        );
+    }
+
+    #[test]
+    fn test_invalid_input_validation() {
+        let result = compress_pack(
+            PathBuf::from("nonexistent.siq"),
+            None,
+            CompressOptions::new()
+                .image_quality(85)
+                .audio(audio::AudioOptions {
+                    quality: 85,
+                    ..Default::default()
+                })
+                .video_quality(75),
+            MultiProgress::new(),
+        );
+        assert!(result.is_err());
+
+        // Create a temporary file without .siq extension
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(b"test").unwrap();
+        let temp_path = temp_file.path().to_path_buf();
+
+        let result = compress_pack(
+            temp_path,
+            None,
+            CompressOptions::new()
+                .image_quality(85)
+                .audio(audio::AudioOptions {
+                    quality: 85,
+                    ..Default::default()
+                })
+                .video_quality(75),
+            MultiProgress::new(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_quality_validation() {
+        // Quality should be between 1 and 100
+        let temp_siq = create_temp_siq_file();
+
+        let result = compress_pack(
+            temp_siq.clone(),
+            None,
+            CompressOptions::new()
+                .image_quality(0)
+                .audio(audio::AudioOptions {
+                    quality: 85,
+                    ..Default::default()
+                })
+                .video_quality(75),
+            MultiProgress::new(),
+        );
+        assert!(result.is_err());
+
+        let result = compress_pack(
+            temp_siq.clone(),
+            None,
+            CompressOptions::new()
+                .image_quality(101)
+                .audio(audio::AudioOptions {
+                    quality: 85,
+                    ..Default::default()
+                })
+                .video_quality(75),
+            MultiProgress::new(),
+        );
+        assert!(result.is_err());
+
+        let result = compress_pack(
+            temp_siq.clone(),
+            None,
+            CompressOptions::new()
+                .image_quality(85)
+                .audio(audio::AudioOptions {
+                    quality: 0,
+                    ..Default::default()
+                })
+                .video_quality(75),
+            MultiProgress::new(),
+        );
+        assert!(result.is_err());
+
+        let result = compress_pack(
+            temp_siq.clone(),
+            None,
+            CompressOptions::new()
+                .image_quality(85)
+                .audio(audio::AudioOptions {
+                    quality: 101,
+                    ..Default::default()
+                })
+                .video_quality(75),
+            MultiProgress::new(),
+        );
+        assert!(result.is_err());
+
+        let result = compress_pack(
+            temp_siq.clone(),
+            None,
+            CompressOptions::new()
+                .image_quality(85)
+                .audio(audio::AudioOptions {
+                    quality: 85,
+                    ..Default::default()
+                })
+                .video_quality(0),
+            MultiProgress::new(),
+        );
+        assert!(result.is_err());
+
+        let result = compress_pack(
+            temp_siq.clone(),
+            None,
+            CompressOptions::new()
+                .image_quality(85)
+                .audio(audio::AudioOptions {
+                    quality: 85,
+                    ..Default::default()
+                })
+                .video_quality(101),
+            MultiProgress::new(),
+        );
+        assert!(result.is_err());
+
+        // Valid quality should work (though will fail due to invalid ZIP content)
+        let result = compress_pack(
+            temp_siq,
+            None,
+            CompressOptions::new()
+                .image_quality(50)
+                .audio(audio::AudioOptions {
+                    quality: 75,
+                    ..Default::default()
+                })
+                .video_quality(60),
+            MultiProgress::new(),
+        );
+        // This will fail at ZIP reading stage, but quality validation should pass
+        assert!(result.is_err());
+        assert!(
+            !result
+                .unwrap_err()
+                .to_string()
+                .contains("quality must be between")
+        );
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("quiz.siq");
+        let output = dir.path().join("quiz_small.siq");
+        write_pack(&input, &[("content.xml", b"<package/>")]);
+
+        let error = compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .skip_video(true)
+                .time_limit_seconds(Some(0)),
+            MultiProgress::new(),
+        )
+        .unwrap_err();
+        assert_eq!(crate::error::error_code(&error), "timed_out");
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("quiz.siq");
+        let output = dir.path().join("quiz_small.siq");
+        write_pack(
+            &input,
+            &[
+                ("notes.txt", b"12345".as_slice()),
+                ("content.xml", b"<package/>".as_slice()),
+            ],
+        );
+
+        // Cancel once the first entry is done
+        let token = CancellationToken::new();
+        let handle = token.clone();
+        let error = PackCompressor::new(CompressOptions::new().skip_video(true))
+            .on_progress(Box::new(move |_, _| handle.cancel()))
+            .cancellation(token)
+            .compress(input, Some(output.clone()))
+            .unwrap_err();
+        let Some(SicomError::Cancelled(stats)) = crate::error::classify(&error) else {
+            panic!("expected a cancellation, got {error:#}");
+        };
+        assert_eq!(stats.total_input_size(), 5);
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_generic_zip_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("quiz.zip");
+        let output = dir.path().join("quiz_small.zip");
+
+        let png = png(64, 64);
+
+        let xml = "<quiz><img>assets/deep/pic.png</img><sound>sfx/beep.mp3</sound></quiz>";
+        write_pack(
+            &input,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("assets/deep/pic.png", png.as_slice()),
+                ("sfx/beep.mp3", b"not really audio".as_slice()),
+            ],
+        );
+
+        // Without --generic-zip a .zip input is rejected
+        assert!(
+            compress_pack(
+                input.clone(),
+                Some(output.clone()),
+                CompressOptions::new(),
+                MultiProgress::new(),
+            )
+            .is_err()
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new().generic_zip(true).skip_video(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        // Nothing is rewritten, so every name the XML mentions must still be there
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let mut content = String::new();
+        archive
+            .by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, xml);
+        for range in reference_values(&content) {
+            let value = &content[range];
+            if value.contains('/') {
+                assert!(archive.by_name(value).is_ok(), "{value} is gone");
+            }
+        }
+        assert!(archive.by_name("assets/deep/pic.webp").is_err());
+    }
+
+    #[test]
+    fn test_incremental_reuses_unchanged_media() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+
+        let png = |seed: u32| encode_png(&gradient(64, 64, seed));
+        let write_input = |b: &[u8]| {
+            let xml = concat!(
+                r#"<item type="image" isRef="True">a.png</item>"#,
+                r#"<item type="image" isRef="True">b.png</item>"#
+            );
+            write_pack(
+                &input,
+                &[
+                    ("content.xml", xml.as_bytes()),
+                    ("Images/a.png", png(4).as_slice()),
+                    ("Images/b.png", b),
+                ],
+            );
+        };
+        let run = |options: CompressOptions| {
+            compress_pack(
+                input.clone(),
+                Some(output.clone()),
+                options.skip_video(true).always_compress(true),
+                MultiProgress::new(),
+            )
+            .unwrap()
+        };
+        let options = CompressOptions::new().incremental(true);
+
+        write_input(&png(5));
+        let stats = run(options.clone());
+        assert_eq!(stats.encode_times(MediaKind::Image).len(), 2);
+        assert!(Manifest::path_for(&output).exists());
+
+        // Only the changed image is compressed again
+        write_input(&png(7));
+        let stats = run(options.clone());
+        assert_eq!(stats.encode_times(MediaKind::Image).len(), 1);
+        assert_eq!(stats.media(MediaKind::Image).processed, 2);
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        assert!(archive.by_name("Images/a.webp").is_ok());
+        assert!(archive.by_name("Images/b.webp").is_ok());
+        let mut content = String::new();
+        archive
+            .by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.contains("a.webp") && content.contains("b.webp"));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+
+        // Different settings invalidate everything
+        let stats = run(options.image_quality(80));
+        assert_eq!(stats.encode_times(MediaKind::Image).len(), 2);
+    }
+
+    #[test]
+    fn test_resume_after_interruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+        let xml = concat!(
+            r#"<item type="image" isRef="True">a.png</item>"#,
+            r#"<item type="image" isRef="True">b.png</item>"#
+        );
+        write_pack(
+            &input,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Images/a.png", &encode_png(&gradient(64, 64, 4))),
+                ("Images/b.png", &encode_png(&gradient(64, 64, 5))),
+            ],
+        );
+        let options = CompressOptions::new()
+            .skip_video(true)
+            .always_compress(true)
+            .resume(true);
+
+        // The process dies once the first image is done
+        let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            PackCompressor::new(options.clone())
+                .on_progress(Box::new(|done, _| assert!(done < 2, "crash")))
+                .compress(input.clone(), Some(output.clone()))
+        }));
+        assert!(crashed.is_err());
+        assert!(!output.exists());
+        assert!(Journal::path_for(&output).exists());
+
+        // Only the second image is left to compress
+        let stats =
+            compress_pack(input, Some(output.clone()), options, MultiProgress::new()).unwrap();
+        assert_eq!(stats.encode_times(MediaKind::Image).len(), 1);
+        assert_eq!(stats.media(MediaKind::Image).processed, 2);
+        assert!(!Journal::path_for(&output).exists());
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        assert_eq!(archive.len(), 3);
+        let mut content = String::new();
+        archive
+            .by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.contains("a.webp") && content.contains("b.webp"));
+    }
+
+    #[test]
+    fn test_up_to_date_sibling_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let write_input = |seed: u8| {
+            let png = encode_png(&::image::RgbImage::from_pixel(
+                16,
+                16,
+                ::image::Rgb([seed, 0, 0]),
+            ));
+            write_pack(
+                &input,
+                &[
+                    (
+                        "content.xml",
+                        br#"<item type="image" isRef="True">a.png</item>"#.as_slice(),
+                    ),
+                    ("Images/a.png", png.as_slice()),
+                ],
+            );
+        };
+        let run = |options: CompressOptions| {
+            compress_pack(
+                input.clone(),
+                None,
+                options.skip_video(true).always_compress(true),
+                MultiProgress::new(),
+            )
+            .unwrap()
+            .media(MediaKind::Image)
+            .processed
+        };
+
+        write_input(1);
+        assert_eq!(run(CompressOptions::new()), 1);
+        let output = default_output_path(&input).unwrap();
+        assert!(Manifest::path_for(&output).exists());
+
+        // Same input and settings: nothing to do unless forced
+        assert_eq!(run(CompressOptions::new()), 0);
+        assert_eq!(run(CompressOptions::new().force(true)), 1);
+
+        // A changed input, changed settings or a modified output are compressed again
+        write_input(2);
+        assert_eq!(run(CompressOptions::new()), 1);
+        assert_eq!(run(CompressOptions::new().image_quality(80)), 1);
+        std::fs::write(&output, "edited").unwrap();
+        assert_eq!(run(CompressOptions::new().image_quality(80)), 1);
+    }
+
+    #[test]
+    fn test_content_types_updated() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+
+        let png = png(64, 64);
+
+        let content_types = concat!(
+            r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+            r#"<Default Extension="xml" ContentType="text/xml" />"#,
+            r#"<Default Extension="png" ContentType="image/png" />"#,
+            r#"<Override PartName="/Images/pic.png" ContentType="image/png" />"#,
+            r#"</Types>"#
+        );
+        write_pack(
+            &input,
+            &[
+                (CONTENT_TYPES_ENTRY, content_types.as_bytes()),
+                (
+                    "content.xml",
+                    br#"<item type="image" isRef="True">pic.png</item>"#.as_slice(),
+                ),
+                ("Images/pic.png", png.as_slice()),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .skip_video(true)
+                .always_compress(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        assert!(archive.by_name("Images/pic.webp").is_ok());
+        let mut content = String::new();
+        archive
+            .by_name(CONTENT_TYPES_ENTRY)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.contains(r#"<Default Extension="webp" ContentType="image/webp" />"#));
+        assert!(!content.contains("/Images/pic.png"));
+    }
+
+    #[test]
+    fn test_strict_refs_keeps_unreferenced_media() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+
+        let png = png(64, 64);
+
+        write_pack(
+            &input,
+            &[
+                ("Images/used.png", png.as_slice()),
+                ("Images/orphan.png", png.as_slice()),
+                // content.xml after the media: references are known up front anyway
+                (
+                    "content.xml",
+                    br#"<item type="image" isRef="True">used.png</item>"#.as_slice(),
+                ),
+            ],
+        );
+
+        let stats = compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .skip_video(true)
+                .always_compress(true)
+                .strict_refs(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+        assert_eq!(stats.media(MediaKind::Image).processed, 1);
+        assert_eq!(stats.media(MediaKind::Image).kept_original, 1);
+
+        let archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            ["Images/orphan.png", "Images/used.webp", "content.xml"]
+        );
+    }
+
+    #[test]
+    fn test_missing_references() {
+        let xml = r#"<package name="P"><rounds><round name="R"><themes><theme name="T"><questions>
+            <question price="100"><params><param name="question" type="content">
+                <item type="image" isRef="True">present.png</item>
+                <item type="image" isRef="True">%D0%B3.png</item>
+                <item type="audio" isRef="True">gone.mp3</item>
+            </param></params></question>
+            <question price="200"><params><param name="question" type="content">
+                <item type="audio" isRef="True">gone.mp3</item>
+            </param></params></question>
+        </questions></theme></themes></round></rounds></package>"#;
+        let entries = ["content.xml", "Images/present.png", "Images/%D0%B3.png"];
+
+        let missing = missing_references(xml, entries.into_iter());
+        let names: Vec<&str> = missing.iter().map(|p| p.entry.as_str()).collect();
+        assert_eq!(names, ["Images/%25D0%25B3.png", "Audio/gone.mp3"]);
+        assert!(missing[0].message.contains("matches Images/%D0%B3.png"));
+        assert!(missing_references("<package", entries.into_iter()).is_empty());
+    }
+
+    #[test]
+    fn test_dedupe_identical_keeps_referenced_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+
+        // Only the second copy is referenced; the first one is kept in its place
+        write_pack(
+            &input,
+            &[
+                ("Audio/template.mp3", b"jingle".as_slice()),
+                ("Audio/jingle.mp3", b"jingle".as_slice()),
+                ("Audio/orphan.mp3", b"orphan".as_slice()),
+                (
+                    "content.xml",
+                    br#"<item type="audio" isRef="True">jingle.mp3</item>"#.as_slice(),
+                ),
+            ],
+        );
+
+        let stats = compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .skip_audio(true)
+                .skip_video(true)
+                .dedupe_identical(true)
+                .prune_unused(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+        assert_eq!(stats.media(MediaKind::Audio).deduplicated, 1);
+        assert_eq!(stats.media(MediaKind::Audio).pruned, 1);
+        assert!(stats.missing_references().is_empty());
+
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort_unstable();
+        assert_eq!(names, ["Audio/template.mp3", "content.xml"]);
+        let mut content = String::new();
+        archive
+            .by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.contains(">template.mp3<"));
+    }
+
+    #[test]
+    fn test_dedupe_identical_overlapping_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_deduped.siq");
+
+        // 1.mp3 is a copy of 0.mp3 and ends the name of the unrelated 11.mp3
+        write_pack(
+            &input,
+            &[
+                ("Audio/0.mp3", b"jingle".as_slice()),
+                ("Audio/1.mp3", b"jingle".as_slice()),
+                ("Audio/11.mp3", b"other".as_slice()),
+                (
+                    "content.xml",
+                    concat!(
+                        r#"<item type="audio" isRef="True">0.mp3</item>"#,
+                        r#"<item type="audio" isRef="True">1.mp3</item>"#,
+                        r#"<item type="audio" isRef="True">11.mp3</item>"#
+                    )
+                    .as_bytes(),
+                ),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .skip_audio(true)
+                .skip_video(true)
+                .dedupe_identical(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let content = resolved_content_xml(&output);
+        assert_eq!(content.matches(">0.mp3<").count(), 2);
+        assert!(content.contains(">11.mp3<"));
+    }
+
+    #[test]
+    fn test_stored_media_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+
+        write_pack(
+            &input,
+            &[
+                ("Audio/song.mp3", b"mp3".as_slice()),
+                ("Audio/take.WAV", b"wav".as_slice()),
+                ("Video/clip.avi", b"avi".as_slice()),
+                (
+                    "content.xml",
+                    b"<item type=\"audio\" isRef=\"True\">song.mp3</item>".as_slice(),
+                ),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .skip_audio(true)
+                .skip_video(true)
+                .zip_level(Some(9)),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let method = |archive: &mut ZipArchive<File>, name: &str| {
+            archive.by_name(name).unwrap().compression()
+        };
+        assert_eq!(
+            method(&mut archive, "Audio/song.mp3"),
+            CompressionMethod::Stored
+        );
+        assert_eq!(
+            method(&mut archive, "Audio/take.WAV"),
+            CompressionMethod::Deflated
+        );
+        assert_eq!(
+            method(&mut archive, "Video/clip.avi"),
+            CompressionMethod::Deflated
+        );
+        assert_eq!(
+            method(&mut archive, "content.xml"),
+            CompressionMethod::Deflated
+        );
+    }
+
+    #[test]
+    fn test_entry_metadata_carried_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+
+        let png = png(32, 32);
+
+        let modified = zip::DateTime::from_date_and_time(2021, 5, 17, 10, 30, 0).unwrap();
+        let source = zip::write::FileOptions::default()
+            .last_modified_time(modified)
+            .unix_permissions(0o640);
+        let mut writer = ZipWriter::new(File::create(&input).unwrap());
+        for (name, data) in [
+            ("Images/photo.png", png.as_slice()),
+            (
+                "content.xml",
+                br#"<item type="image" isRef="True">photo.png</item>"#.as_slice(),
+            ),
+        ] {
+            writer.start_file(name, source).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap();
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .skip_video(true)
+                .always_compress(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        for name in ["Images/photo.webp", "content.xml"] {
+            let entry = archive.by_name(name).unwrap();
+            let written = entry.last_modified();
+            assert_eq!(
+                (written.datepart(), written.timepart()),
+                (modified.datepart(), modified.timepart()),
+                "{name}"
+            );
+            assert_eq!(entry.unix_mode().map(|mode| mode & 0o777), Some(0o640));
+        }
+    }
+
+    #[test]
+    fn test_prune_unused_media() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+
+        write_pack(
+            &input,
+            &[
+                ("Images/used.png", b"png".as_slice()),
+                ("Images/orphan.png", b"png".as_slice()),
+                ("Audio/%D0%B3%D0%B8%D0%BC%D0%BD.mp3", b"mp3".as_slice()),
+                ("Audio/unused.mp3", b"mp3".as_slice()),
+                ("Texts/notes.txt", b"not media".as_slice()),
+                (
+                    "content.xml",
+                    "<item type=\"image\" isRef=\"True\">used.png</item>\
+                     <item type=\"audio\" isRef=\"True\">гимн.mp3</item>"
+                        .as_bytes(),
+                ),
+            ],
+        );
+
+        let stats = compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .skip_image(true)
+                .skip_audio(true)
+                .skip_video(true)
+                .prune_unused(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+        assert_eq!(stats.media(MediaKind::Image).pruned, 1);
+        assert_eq!(stats.media(MediaKind::Audio).pruned, 1);
+
+        let archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            [
+                "Audio/%D0%B3%D0%B8%D0%BC%D0%BD.mp3",
+                "Images/used.png",
+                "Texts/notes.txt",
+                "content.xml"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_references_in_other_text_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+
+        let png = png(64, 64);
+
+        write_pack(
+            &input,
+            &[
+                (
+                    "content.xml",
+                    br#"<item type="image" isRef="True">pic.png</item>"#.as_slice(),
+                ),
+                ("authors.xml", br#"<author photo="Images/pic.png" />"#),
+                ("Texts/intro.html", br#"<img src="../Images/pic.png">"#),
+                ("notes.txt", b"pic.png"),
+                ("Images/pic.png", png.as_slice()),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .skip_video(true)
+                .always_compress(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+        assert!(read("content.xml").contains("pic.webp"));
+        assert_eq!(read("authors.xml"), r#"<author photo="Images/pic.webp" />"#);
+        assert_eq!(
+            read("Texts/intro.html"),
+            r#"<img src="../Images/pic.webp">"#
+        );
+        // Not in the text extension list
+        assert_eq!(read("notes.txt"), "pic.png");
+    }
+
+    #[test]
+    fn test_rewrite_audio_and_video_references() {
+        let mut xml = concat!(
+            r#"<item type="audio" isRef="True">гимн.wav</item>"#,
+            r#"<atom type="voice">@гимн.wav</atom>"#,
+            r#"<item type="video" isRef="True">clip.mkv</item>"#,
+            r#"<atom type="video">@clip.mkv</atom>"#
+        )
+        .to_string();
+        let counts = rewrite_references(
+            &mut xml,
+            &[
+                (
+                    "Audio/%D0%B3%D0%B8%D0%BC%D0%BD.wav",
+                    "Audio/%D0%B3%D0%B8%D0%BC%D0%BD.mp3",
+                ),
+                ("Video/clip.mkv", "Video/clip.mp4"),
+            ],
+        );
+        assert_eq!(counts, [2, 2]);
+        assert_eq!(
+            xml,
+            concat!(
+                r#"<item type="audio" isRef="True">гимн.mp3</item>"#,
+                r#"<atom type="voice">@гимн.mp3</atom>"#,
+                r#"<item type="video" isRef="True">clip.mp4</item>"#,
+                r#"<atom type="video">@clip.mp4</atom>"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_rewrite_logo_reference() {
+        let mut xml = concat!(
+            r#"<package name="P" logo="@ВДНХ.png" version="5">"#,
+            r#"<item type="image" isRef="True">ВДНХ.png</item></package>"#
+        )
+        .to_string();
+        let replaced = rewrite_references(
+            &mut xml,
+            &[(
+                "Images/%D0%92%D0%94%D0%9D%D0%A5.png",
+                "Images/%D0%92%D0%94%D0%9D%D0%A5.webp",
+            )],
+        );
+        assert_eq!(replaced, [2]);
+        assert!(xml.contains(r#"logo="@ВДНХ.webp""#));
+        assert!(!xml.contains(".png"));
+        assert_eq!(
+            crate::pack::parse_package(&xml).unwrap().logo.unwrap().name,
+            "ВДНХ.webp"
+        );
+
+        // Single quotes, hashed name
+        let mut xml = "<package logo='@cover.jpg'></package>".to_string();
+        rewrite_references(
+            &mut xml,
+            &[("Images/cover.jpg", "Images/3f2a9c0d1e4b5a67.webp")],
+        );
+        assert_eq!(xml, "<package logo='@3f2a9c0d1e4b5a67.webp'></package>");
+
+        // External logos are left alone
+        let mut xml = r#"<package logo="https://example.com/cover.jpg"></package>"#.to_string();
+        let before = xml.clone();
+        rewrite_references(&mut xml, &[("Images/other.jpg", "Images/other.webp")]);
+        assert_eq!(xml, before);
+    }
+
+    #[test]
+    fn test_xml_diff_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+        let diff_path = dir.path().join("content.diff");
+
+        let png = png(64, 64);
+
+        let xml = "<package>\n<item>Text</item>\n<item type=\"image\" isRef=\"True\">pic.png</item>\n</package>\n";
+        write_pack(
+            &input,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Images/pic.png", png.as_slice()),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output),
+            CompressOptions::new()
+                .skip_video(true)
+                .always_compress(true)
+                .xml_diff(Some(diff_path.clone())),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&diff_path).unwrap(),
+            concat!(
+                "--- a/content.xml\n",
+                "+++ b/content.xml\n",
+                "@@ -1,4 +1,4 @@\n",
+                " <package>\n",
+                " <item>Text</item>\n",
+                "-<item type=\"image\" isRef=\"True\">pic.png</item>\n",
+                "+<item type=\"image\" isRef=\"True\">pic.webp</item>\n",
+                " </package>\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_keep_original_xml() {
+        let dir = tempfile::tempdir().unwrap();
+        let run = |entries: &[(&str, &str)]| {
+            let input = dir.path().join("pack.siq");
+            let output = dir.path().join("pack_small.siq");
+            let entries: Vec<(&str, &[u8])> = entries
+                .iter()
+                .map(|(name, data)| (*name, data.as_bytes()))
+                .collect();
+            write_pack(&input, &entries);
+
+            compress_pack(
+                input,
+                Some(output.clone()),
+                // A backup in the input marks it as sicom output
+                CompressOptions::new()
+                    .keep_original_xml(true)
+                    .allow_recompress(true),
+                MultiProgress::new(),
+            )
+            .unwrap();
+
+            let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+            let mut backup = String::new();
+            archive
+                .by_name(ORIGINAL_CONTENT_XML)
+                .unwrap()
+                .read_to_string(&mut backup)
+                .unwrap();
+            backup
+        };
+
+        assert_eq!(run(&[("content.xml", "<package />")]), "<package />");
+        // The backup of an earlier run stays the original
+        assert_eq!(
+            run(&[
+                ("content.xml", "<package>b</package>"),
+                (ORIGINAL_CONTENT_XML, "<package>a</package>"),
+            ]),
+            "<package>a</package>"
+        );
+    }
+
+    #[test]
+    fn test_lossless_only_registry() {
+        let img = ::image::RgbImage::from_fn(32, 32, |x, y| {
+            ::image::Rgb([(x * 8) as u8, (y * 8) as u8, ((x * y) % 256) as u8])
+        });
+        let png = encode_png(&img);
+
+        let options = CompressOptions::new().image_quality(10).lossless_only(true);
+        let registry = build_registry(&options, false);
+        let (processor, _) = registry.find("Images/a.png").unwrap();
+        let output = processor
+            .compress(&png, "Images/a.png", &mut ProgressLogger::hidden())
+            .unwrap();
+        let decoded = ::image::load_from_memory(&output.data).unwrap().to_rgb8();
+        assert_eq!(decoded, img);
+    }
+
+    #[test]
+    fn test_min_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+
+        let (icon, picture) = (png(4, 4), png(64, 64));
+        assert!(icon.len() < 1024 && picture.len() > 1024);
+
+        let xml = concat!(
+            r#"<item type="image" isRef="True">icon.png</item>"#,
+            r#"<item type="image" isRef="True">picture.png</item>"#
+        );
+        write_pack(
+            &input,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Images/icon.png", icon.as_slice()),
+                ("Images/picture.png", picture.as_slice()),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .min_file_size(Some(1024))
+                .always_compress(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let mut copied = Vec::new();
+        archive
+            .by_name("Images/icon.png")
+            .unwrap()
+            .read_to_end(&mut copied)
+            .unwrap();
+        assert_eq!(copied, icon);
+        assert!(archive.by_name("Images/picture.webp").is_ok());
+    }
+
+    #[test]
+    fn test_unprocessed_report() {
+        let unprocessed = [
+            ("Audio/a.mp3", "Kept original: compressed would be larger"),
+            ("Video/b.mp4", "Skipped: ffmpeg not available"),
+            (
+                "Audio/it's.mp3",
+                "Kept original: compressed would be larger",
+            ),
+        ]
+        .map(|(file, reason)| (file.to_string(), reason.to_string()));
+        assert_eq!(
+            unprocessed_report(&unprocessed),
+            "\
+# Kept original: compressed would be larger (2)
+--include 'Audio/a.mp3' --include 'Audio/it'\\''s.mp3'
+# Skipped: ffmpeg not available (1)
+--include 'Video/b.mp4'
+"
+        );
+        assert_eq!(unprocessed_report(&[]), "");
+    }
+
+    #[test]
+    fn test_include_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+        let list = dir.path().join("unprocessed.txt");
+
+        let png = png(64, 64);
+
+        write_pack(
+            &input,
+            &[
+                ("content.xml", b"<package />".as_slice()),
+                ("Images/a.png", png.as_slice()),
+                ("Images/b.png", png.as_slice()),
+                ("Images/broken.png", b"not an image".as_slice()),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .include(vec![
+                    "Images/b.png".to_string(),
+                    "Images/broken.png".to_string(),
+                ])
+                .unprocessed_list(Some(list.clone()))
+                .always_compress(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            [
+                "Images/a.png",
+                "Images/b.webp",
+                "Images/broken.png",
+                "content.xml"
+            ]
+        );
+        // Entries left out by --include aren't reported
+        let report = std::fs::read_to_string(&list).unwrap();
+        assert!(report.starts_with("# Skipped: "));
+        assert!(report.ends_with("(1)\n--include 'Images/broken.png'\n"));
+    }
+
+    #[test]
+    fn test_hashed_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_hashed.siq");
+
+        let png = png(64, 64);
+
+        let xml = concat!(
+            r#"<item type="image" isRef="True">ВДНХ.png</item>"#,
+            r#"<item type="audio" isRef="True">гимн.mp3</item>"#
+        );
+        write_pack(
+            &input,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Images/%D0%92%D0%94%D0%9D%D0%A5.png", png.as_slice()),
+                (
+                    "Audio/%D0%B3%D0%B8%D0%BC%D0%BD.mp3",
+                    b"not really audio".as_slice(),
+                ),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .rename(RenameScheme::Hashed)
+                .skip_audio(true)
+                .skip_video(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let names: Vec<String> = archive.file_names().map(str::to_string).collect();
+        let image_name = names.iter().find(|n| n.starts_with("Images/")).unwrap();
+        let audio_name = names.iter().find(|n| n.starts_with("Audio/")).unwrap();
+        assert!(image_name.is_ascii() && image_name.ends_with(".webp"));
+        // Skipped media is renamed too, keeping its extension
+        assert_eq!(
+            audio_name,
+            &media::hashed_entry_name("Audio/x.mp3", b"not really audio")
+        );
+
+        let mut content = String::new();
+        archive
+            .by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.contains(image_name.strip_prefix("Images/").unwrap()));
+        assert!(content.contains(audio_name.strip_prefix("Audio/").unwrap()));
+        assert!(!content.contains("ВДНХ") && !content.contains("гимн"));
+    }
+
+    /// content.xml of an output pack, after checking that every reference in
+    /// it names an entry of the pack
+    fn resolved_content_xml(output: &Path) -> String {
+        let mut archive = ZipArchive::new(File::open(output).unwrap()).unwrap();
+        let mut content = String::new();
+        archive
+            .by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        let missing = missing_references(&content, archive.file_names());
+        assert!(missing.is_empty(), "{missing:?} in {content}");
+        content
+    }
+
+    #[test]
+    fn test_hashed_rename_overlapping_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_hashed.siq");
+
+        let png = |shade: u32| encode_png(&gradient(64, 64, shade));
+
+        // 1.jpg ends 11.jpg, and is renamed first
+        let xml = concat!(
+            r#"<package name="P" logo="@11.jpg"><rounds><round name="R"><themes><theme name="T">"#,
+            r#"<questions><question price="100"><params><param name="question" type="content">"#,
+            r#"<item type="image" isRef="True">1.jpg</item>"#,
+            r#"<item type="image" isRef="True">11.jpg</item>"#,
+            r#"</param></params></question></questions></theme></themes></round></rounds></package>"#
+        );
+        write_pack(
+            &input,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Images/1.jpg", &png(4)),
+                ("Images/11.jpg", &png(3)),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .rename(RenameScheme::Hashed)
+                .skip_audio(true)
+                .skip_video(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let content = resolved_content_xml(&output);
+        assert!(!content.contains(".jpg"));
+        let package = pack::parse_package(&content).unwrap();
+        let references: HashSet<String> = verify::media_references(&package)
+            .map(|media| media.name.clone())
+            .collect();
+        assert_eq!(references.len(), 2);
+    }
+
+    #[test]
+    fn test_transliterate_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_ascii.siq");
+
+        let png = png(64, 64);
+
+        let xml = concat!(
+            r#"<item type="image" isRef="True">ВДНХ.png</item>"#,
+            r#"<item type="audio" isRef="True">гимн.mp3</item>"#,
+            r#"<item type="audio" isRef="True">gimn.mp3</item>"#
+        );
+        write_pack(
+            &input,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Images/%D0%92%D0%94%D0%9D%D0%A5.png", png.as_slice()),
+                (
+                    "Audio/%D0%B3%D0%B8%D0%BC%D0%BD.mp3",
+                    b"not really audio".as_slice(),
+                ),
+                ("Audio/gimn.mp3", b"other audio".as_slice()),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .transliterate_names(true)
+                .skip_audio(true)
+                .skip_video(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let read = |path: &Path| {
+            let mut archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+            let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+            names.sort();
+            let mut content = String::new();
+            archive
+                .by_name("content.xml")
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            (names, content)
+        };
+        // The existing gimn.mp3 keeps its name, so the transliteration gets a suffix
+        let (names, content) = read(&output);
+        assert_eq!(
+            names,
+            [
+                "Audio/gimn-2.mp3",
+                "Audio/gimn.mp3",
+                "Images/VDNKh.webp",
+                "content.xml"
+            ]
+        );
+        assert!(content.contains(">VDNKh.webp<") && content.contains(">gimn-2.mp3<"));
+        assert!(names.iter().all(|name| name.is_ascii()));
+
+        // The manifest takes the names back, keeping the conversion
+        let restored = transliterate::restore_names(&output, None).unwrap();
+        assert_eq!(restored, dir.path().join("pack_ascii_restored.siq"));
+        let (names, content) = read(&restored);
+        assert_eq!(
+            names,
+            [
+                "Audio/%D0%B3%D0%B8%D0%BC%D0%BD.mp3",
+                "Audio/gimn.mp3",
+                "Images/%D0%92%D0%94%D0%9D%D0%A5.webp",
+                "content.xml"
+            ]
+        );
+        assert!(content.contains(">ВДНХ.webp<") && content.contains(">гимн.mp3<"));
+        assert!(content.contains(">gimn.mp3<"));
+    }
+
+    #[test]
+    fn test_transliterate_suffix_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_ascii.siq");
+
+        // а.mp3 ends ба.mp3, and a.mp3 ends ba.mp3 on the way back
+        let xml = concat!(
+            r#"<item type="audio" isRef="True">а.mp3</item>"#,
+            r#"<item type="audio" isRef="True">ба.mp3</item>"#
+        );
+        write_pack(
+            &input,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Audio/%D0%B0.mp3", b"first".as_slice()),
+                ("Audio/%D0%B1%D0%B0.mp3", b"second".as_slice()),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .transliterate_names(true)
+                .skip_audio(true)
+                .skip_video(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+        let content = resolved_content_xml(&output);
+        assert!(content.contains(">a.mp3<") && content.contains(">ba.mp3<"));
+
+        let restored = transliterate::restore_names(&output, None).unwrap();
+        let content = resolved_content_xml(&restored);
+        assert_eq!(content, xml);
+    }
+
+    #[test]
+    fn test_preload_manifest_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_small.siq");
+
+        let png = png(64, 64);
+
+        let xml = r#"<package name="P"><rounds><round name="R"><themes><theme name="T"><questions>
+            <question price="100"><params><param name="question" type="content">
+              <item type="image" isRef="True">pic.png</item>
+            </param></params></question>
+            <question price="200"><params><param name="question" type="content">
+              <item type="audio" isRef="True">tune.mp3</item>
+            </param></params></question>
+        </questions></theme></themes></round></rounds></package>"#;
+        write_pack(
+            &input,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Images/pic.png", png.as_slice()),
+                ("Audio/tune.mp3", b"not really audio".as_slice()),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .emit_preload_manifest(true)
+                .skip_audio(true)
+                .skip_video(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let webp_size = archive.by_name("Images/pic.webp").unwrap().size();
+        let mut listing = String::new();
+        archive
+            .by_name(PRELOAD_ENTRY)
+            .unwrap()
+            .read_to_string(&mut listing)
+            .unwrap();
+        let listing: serde_json::Value = serde_json::from_str(&listing).unwrap();
+        let media = &listing["rounds"][0]["media"];
+        assert_eq!(media[0]["entry"], "Images/pic.webp");
+        assert_eq!(media[0]["size"], webp_size);
+        assert_eq!(media[1]["entry"], "Audio/tune.mp3");
+        assert_eq!(media[1]["question"], 1);
+    }
+
+    #[test]
+    fn test_dedupe_similar_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_deduped.siq");
+
+        let encode = |width: u32, height: u32, seed: u32| {
+            encode_png(&::image::RgbImage::from_fn(width, height, |x, y| {
+                let v = ((x * 64 / width * seed + y * 64 / height * 3) % 64 * 4) as u8;
+                ::image::Rgb([v, v / 2, 255 - v])
+            }))
+        };
+
+        let xml = concat!(
+            r#"<item type="image" isRef="True">large.png</item>"#,
+            r#"<item type="image" isRef="True">thumb.png</item>"#,
+            r#"<item type="image" isRef="True">other.png</item>"#
+        );
+        write_pack(
+            &input,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Images/thumb.png", &encode(160, 120, 5)),
+                ("Images/large.png", &encode(640, 480, 5)),
+                ("Images/other.png", &encode(640, 480, 11)),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .dedupe_similar(Some(6))
+                .skip_video(true)
+                .always_compress(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        assert!(archive.by_name("Images/thumb.png").is_err());
+        assert!(archive.by_name("Images/thumb.webp").is_err());
+        assert!(archive.by_name("Images/large.webp").is_ok());
+        assert!(archive.by_name("Images/other.webp").is_ok());
+
+        let mut content = String::new();
+        archive
+            .by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content.matches("large.webp").count(), 2);
+        assert!(content.contains("other.webp"));
+        assert!(!content.contains("thumb"));
+    }
+
+    #[test]
+    fn test_dedupe_similar_overlapping_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_deduped.siq");
+
+        let encode = |width: u32, height: u32, seed: u32| {
+            encode_png(&::image::RgbImage::from_fn(width, height, |x, y| {
+                let v = ((x * 64 / width * seed + y * 64 / height * 3) % 64 * 4) as u8;
+                ::image::Rgb([v, v / 2, 255 - v])
+            }))
+        };
+
+        // The thumbnail 1.png collapses onto big.png; 11.png is a different image
+        let xml = concat!(
+            r#"<item type="image" isRef="True">big.png</item>"#,
+            r#"<item type="image" isRef="True">1.png</item>"#,
+            r#"<item type="image" isRef="True">11.png</item>"#
+        );
+        write_pack(
+            &input,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Images/1.png", &encode(160, 120, 5)),
+                ("Images/big.png", &encode(640, 480, 5)),
+                ("Images/11.png", &encode(640, 480, 11)),
+            ],
+        );
+
+        compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .dedupe_similar(Some(6))
+                .skip_video(true)
+                .always_compress(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+
+        let content = resolved_content_xml(&output);
+        assert_eq!(content.matches(">big.webp<").count(), 2);
+        assert!(content.contains(">11.webp<"));
+    }
+
+    #[test]
+    fn test_dedupe_identical_media() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let output = dir.path().join("pack_deduped.siq");
+
+        let png = png(32, 32);
+
+        let xml = concat!(
+            r#"<item type="image" isRef="True">logo.png</item>"#,
+            r#"<item type="image" isRef="True">logo2.png</item>"#,
+            r#"<item type="audio" isRef="True">jingle.mp3</item>"#,
+            r#"<item type="audio" isRef="True">jingle copy.mp3</item>"#,
+            r#"<item type="audio" isRef="True">other.mp3</item>"#
+        );
+        write_pack(
+            &input,
+            &[
+                ("Images/logo.png", png.as_slice()),
+                ("Images/logo2.png", png.as_slice()),
+                ("Audio/jingle.mp3", b"jingle".as_slice()),
+                ("Audio/jingle%20copy.mp3", b"jingle".as_slice()),
+                ("Audio/other.mp3", b"other".as_slice()),
+                ("content.xml", xml.as_bytes()),
+            ],
+        );
+
+        let stats = compress_pack(
+            input,
+            Some(output.clone()),
+            CompressOptions::new()
+                .dedupe_identical(true)
+                .skip_audio(true)
+                .skip_video(true)
+                .always_compress(true),
+            MultiProgress::new(),
+        )
+        .unwrap();
+        assert_eq!(stats.media(MediaKind::Image).deduplicated, 1);
+        assert_eq!(stats.media(MediaKind::Audio).deduplicated, 1);
+
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            [
+                "Audio/jingle.mp3",
+                "Audio/other.mp3",
+                "Images/logo.webp",
+                "content.xml"
+            ]
+        );
+
+        let mut content = String::new();
+        archive
+            .by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content.matches(">logo.webp<").count(), 2);
+        assert_eq!(content.matches(">jingle.mp3<").count(), 2);
+        assert!(content.contains(">other.mp3<"));
+    }
+
+    fn create_temp_siq_file() -> PathBuf {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(b"fake siq content").unwrap();
+
+        // Rename to have .siq extension
+        let temp_path = temp_file.path().with_extension("siq");
+        std::fs::copy(temp_file.path(), &temp_path).unwrap();
+        temp_path
+    }
+}
@@ -0,0 +1,236 @@
+use crate::options::CompressOptions;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Length of the rate limiting window
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits applied to the public HTTP API
+///
+/// Everything defaults to unlimited, matching a private deployment; set
+/// limits before exposing the API publicly.
+#[derive(Debug, Clone)]
+pub struct ApiPolicy {
+    /// Requests per client IP per minute (`/metrics` is exempt)
+    pub requests_per_minute: Option<u32>,
+    /// Largest accepted upload in bytes
+    pub max_upload_bytes: Option<u64>,
+    /// Qualities clients may request per job
+    pub allowed_quality: RangeInclusive<u8>,
+    /// Time to receive an upload before it is rejected
+    pub upload_timeout: Option<Duration>,
+    /// Time a job may spend compressing before it fails; checked between
+    /// entries, so a single long encode can overrun it
+    pub job_timeout: Option<Duration>,
+}
+
+impl Default for ApiPolicy {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: None,
+            max_upload_bytes: None,
+            allowed_quality: 1..=100,
+            upload_timeout: None,
+            job_timeout: None,
+        }
+    }
+}
+
+/// A request rejected by the policy, rendered as a structured error response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    pub status: u16,
+    pub code: &'static str,
+    pub message: String,
+    /// Seconds until the client may retry (`Retry-After`)
+    pub retry_after: Option<u64>,
+}
+
+impl PolicyViolation {
+    fn new(status: u16, code: &'static str, message: String) -> Self {
+        Self {
+            status,
+            code,
+            message,
+            retry_after: None,
+        }
+    }
+}
+
+impl ApiPolicy {
+    /// Reject uploads whose declared size is over the limit
+    pub fn check_upload_size(&self, bytes: u64) -> Result<(), PolicyViolation> {
+        match self.max_upload_bytes {
+            Some(limit) if bytes > limit => Err(PolicyViolation::new(
+                413,
+                "upload_too_large",
+                format!("Upload is {bytes} bytes, the limit is {limit} bytes"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Apply per-job quality parameters, rejecting values outside the allowed range
+    ///
+    /// `quality` sets all three media types; the specific parameters override it.
+    pub fn apply_query(
+        &self,
+        query: &str,
+        mut options: CompressOptions,
+    ) -> Result<CompressOptions, PolicyViolation> {
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let setters: &[fn(&mut CompressOptions, u8)] = match name {
+                "quality" => &[
+                    |o, q| o.image_quality = q,
+                    |o, q| o.audio.quality = q,
+                    |o, q| o.video_quality = q,
+                ],
+                "image_quality" => &[|o, q| o.image_quality = q],
+                "audio_quality" => &[|o, q| o.audio.quality = q],
+                "video_quality" => &[|o, q| o.video_quality = q],
+                _ => {
+                    return Err(PolicyViolation::new(
+                        400,
+                        "invalid_parameter",
+                        format!("Unknown parameter: {name}"),
+                    ));
+                }
+            };
+
+            let quality = value
+                .parse::<u8>()
+                .ok()
+                .filter(|quality| self.allowed_quality.contains(quality))
+                .ok_or_else(|| {
+                    PolicyViolation::new(
+                        400,
+                        "quality_not_allowed",
+                        format!(
+                            "{name} must be between {} and {}",
+                            self.allowed_quality.start(),
+                            self.allowed_quality.end()
+                        ),
+                    )
+                })?;
+            for set in setters {
+                set(&mut options, quality);
+            }
+        }
+
+        if let Some(timeout) = self.job_timeout {
+            options.time_limit_seconds = Some(timeout.as_secs());
+        }
+        Ok(options)
+    }
+
+    pub fn upload_timed_out(&self) -> PolicyViolation {
+        PolicyViolation::new(
+            408,
+            "upload_timeout",
+            format!(
+                "Upload took longer than {} seconds",
+                self.upload_timeout.unwrap_or_default().as_secs()
+            ),
+        )
+    }
+}
+
+/// Fixed-window request counter per client IP
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a request from `client`, rejecting it once the window's budget is spent
+    pub fn check(&self, client: IpAddr, limit: u32, now: Instant) -> Result<(), PolicyViolation> {
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        // Forget idle clients so the map can't grow without bound
+        windows.retain(|_, (started, _)| now.duration_since(*started) < RATE_WINDOW);
+
+        let (started, count) = windows.entry(client).or_insert((now, 0));
+        if *count >= limit {
+            let remaining = RATE_WINDOW.saturating_sub(now.duration_since(*started));
+            return Err(PolicyViolation {
+                retry_after: Some(remaining.as_secs().max(1)),
+                ..PolicyViolation::new(
+                    429,
+                    "rate_limited",
+                    format!("Rate limit of {limit} requests per minute exceeded"),
+                )
+            });
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new();
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(limiter.check(client, 2, start).is_ok());
+        assert!(limiter.check(client, 2, start).is_ok());
+        let violation = limiter
+            .check(client, 2, start + Duration::from_secs(15))
+            .unwrap_err();
+        assert_eq!(violation.status, 429);
+        assert_eq!(violation.retry_after, Some(45));
+        assert!(limiter.check(other, 2, start).is_ok());
+
+        // A new window starts after a minute
+        assert!(limiter.check(client, 2, start + RATE_WINDOW).is_ok());
+    }
+
+    #[test]
+    fn test_upload_size() {
+        let policy = ApiPolicy {
+            max_upload_bytes: Some(1000),
+            ..ApiPolicy::default()
+        };
+        assert!(policy.check_upload_size(1000).is_ok());
+        assert_eq!(policy.check_upload_size(1001).unwrap_err().status, 413);
+        assert!(ApiPolicy::default().check_upload_size(u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn test_apply_query() {
+        let policy = ApiPolicy {
+            allowed_quality: 20..=80,
+            job_timeout: Some(Duration::from_secs(600)),
+            ..ApiPolicy::default()
+        };
+
+        let options = policy
+            .apply_query("quality=50&video_quality=30", CompressOptions::new())
+            .unwrap();
+        assert_eq!(options.image_quality, 50);
+        assert_eq!(options.audio.quality, 50);
+        assert_eq!(options.video_quality, 30);
+        assert_eq!(options.time_limit_seconds, Some(600));
+
+        let violation = policy
+            .apply_query("image_quality=95", CompressOptions::new())
+            .unwrap_err();
+        assert_eq!(violation.code, "quality_not_allowed");
+        let violation = policy
+            .apply_query("skip_video=true", CompressOptions::new())
+            .unwrap_err();
+        assert_eq!(violation.code, "invalid_parameter");
+    }
+}
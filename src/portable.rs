@@ -0,0 +1,72 @@
+use anyhow::{Context, Result, anyhow};
+use log::info;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Temporary files (video encodes, staging downloads) in portable mode
+const TEMP_DIR: &str = "tmp";
+/// Extra directory searched for ffmpeg/ffprobe besides the executable's own
+const BIN_DIR: &str = "bin";
+
+/// Directory holding the sicom executable
+pub fn base_dir() -> Result<PathBuf> {
+    let exe = std::env::current_exe().context("Failed to locate the sicom executable")?;
+    exe.parent()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| anyhow!("Executable has no parent directory: {}", exe.display()))
+}
+
+/// `PATH` with the portable tool directories in front of `current`
+fn search_path(base: &Path, current: Option<OsString>) -> Result<OsString> {
+    let mut paths = vec![base.to_path_buf(), base.join(BIN_DIR)];
+    if let Some(current) = current {
+        paths.extend(std::env::split_paths(&current));
+    }
+    std::env::join_paths(paths).context("Failed to build PATH")
+}
+
+/// Run from the executable's directory: ffmpeg and ffprobe placed next to it
+/// (or in its `bin/`) win over installed ones, and temp files go to its `tmp/`
+///
+/// Must be called at startup, before any other thread is running.
+pub fn enable() -> Result<PathBuf> {
+    let base = base_dir()?;
+    let path = search_path(&base, std::env::var_os("PATH"))?;
+    // SAFETY: called from main before any threads are spawned
+    unsafe { std::env::set_var("PATH", path) };
+
+    let temp = base.join(TEMP_DIR);
+    std::fs::create_dir_all(&temp)
+        .with_context(|| format!("Failed to create {}", temp.display()))?;
+    // Only fails when already overridden, which leaves it pointing here anyway
+    let _ = tempfile::env::override_temp_dir(&temp);
+
+    info!("Portable mode: tools and temp files in {}", base.display());
+    Ok(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_path() {
+        let base = Path::new("/media/usb/sicom");
+        let current = std::env::join_paths(["/usr/bin", "/bin"]).unwrap();
+        let path = search_path(base, Some(current)).unwrap();
+        let paths: Vec<PathBuf> = std::env::split_paths(&path).collect();
+        assert_eq!(
+            paths,
+            [
+                base.to_path_buf(),
+                base.join("bin"),
+                PathBuf::from("/usr/bin"),
+                PathBuf::from("/bin")
+            ]
+        );
+        assert_eq!(
+            std::env::split_paths(&search_path(base, None).unwrap()).count(),
+            2
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use crate::pack::{MediaRef, Package};
+use serde::Serialize;
+
+/// Entry the preload manifest is stored under in the output pack
+pub const PRELOAD_ENTRY: &str = "preload.json";
+
+/// Bumped when the layout changes
+const PRELOAD_VERSION: u32 = 1;
+
+/// Media in the order questions use it, for clients prefetching the next question
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PreloadManifest {
+    pub version: u32,
+    pub rounds: Vec<PreloadRound>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PreloadRound {
+    pub name: String,
+    pub media: Vec<PreloadMedia>,
+}
+
+/// One media file of a question; a file used by several questions is listed for each
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PreloadMedia {
+    /// Positions of the question in the round (theme, then question within the theme)
+    pub theme: usize,
+    pub question: usize,
+    pub entry: String,
+    pub kind: &'static str,
+    pub size: u64,
+    /// Playback length of audio and video, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_seconds: Option<f64>,
+}
+
+/// Build the manifest, where `resolve` maps a reference to its entry, size and
+/// duration; references to files missing from the pack are left out
+pub fn preload_manifest(
+    package: &Package,
+    resolve: impl Fn(&MediaRef) -> Option<(String, u64, Option<f64>)>,
+) -> PreloadManifest {
+    let rounds = package
+        .rounds
+        .iter()
+        .map(|round| {
+            let mut media = Vec::new();
+            for (theme_index, theme) in round.themes.iter().enumerate() {
+                for (question_index, question) in theme.questions.iter().enumerate() {
+                    for media_ref in &question.media {
+                        let Some((entry, size, duration_seconds)) = resolve(media_ref) else {
+                            continue;
+                        };
+                        media.push(PreloadMedia {
+                            theme: theme_index,
+                            question: question_index,
+                            entry,
+                            kind: media_ref.kind.label(),
+                            size,
+                            duration_seconds,
+                        });
+                    }
+                }
+            }
+            PreloadRound {
+                name: round.name.clone(),
+                media,
+            }
+        })
+        .collect();
+    PreloadManifest {
+        version: PRELOAD_VERSION,
+        rounds,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::MediaKind;
+    use crate::pack::{self, tests::SAMPLE_V5};
+
+    #[test]
+    fn test_preload_manifest() {
+        let package = pack::parse_package(SAMPLE_V5).unwrap();
+        let manifest = preload_manifest(&package, |media| {
+            let duration = (media.kind != MediaKind::Image).then_some(12.5);
+            (media.name != "clip.mp4").then(|| (media.entry_name(), 1000, duration))
+        });
+
+        assert_eq!(manifest.rounds.len(), 2);
+        let first = &manifest.rounds[0];
+        assert_eq!(first.name, "Round 1");
+        let entries: Vec<(usize, &str, Option<f64>)> = first
+            .media
+            .iter()
+            .map(|media| (media.question, media.entry.as_str(), media.duration_seconds))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (0, "Images/%D0%92%D0%94%D0%9D%D0%A5.jpg", None),
+                (1, "Audio/anthem.mp3", Some(12.5))
+            ]
+        );
+        // The missing video leaves its round empty
+        assert!(manifest.rounds[1].media.is_empty());
+
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(json["rounds"][0]["media"][1]["kind"], "audio");
+        assert!(
+            json["rounds"][0]["media"][0]
+                .get("duration_seconds")
+                .is_none()
+        );
+    }
+}
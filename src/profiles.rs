@@ -0,0 +1,164 @@
+use crate::error::SicomError;
+use crate::options::CompressOptions;
+use crate::policy::PolicyViolation;
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Named compression profiles clients of the HTTP API can pick from
+///
+/// Loaded from a JSON file:
+///
+/// ```json
+/// {
+///   "default_profile": "standard",
+///   "allow_parameters": false,
+///   "profiles": {
+///     "standard": {"image_quality": 40},
+///     "telegram": {"image_quality": 30, "video_quality": 35, "audio": {"quality": 50}}
+///   }
+/// }
+/// ```
+///
+/// Each profile is a full set of compression options; fields it leaves out
+/// use the CLI defaults, not the server's command-line settings.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profiles {
+    #[serde(default)]
+    profiles: BTreeMap<String, CompressOptions>,
+    /// Profile for requests that don't name one (server settings when None)
+    #[serde(default)]
+    default_profile: Option<String>,
+    /// Whether clients may still adjust qualities with raw query parameters
+    #[serde(default = "default_allow_parameters")]
+    pub allow_parameters: bool,
+}
+
+const fn default_allow_parameters() -> bool {
+    true
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Self {
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            allow_parameters: true,
+        }
+    }
+}
+
+impl Profiles {
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path).map_err(|source| SicomError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let profiles: Self = serde_json::from_str(&json).map_err(|e| {
+            SicomError::InvalidOptions(format!("Invalid profiles file {}: {e}", path.display()))
+        })?;
+        profiles.validate()?;
+        Ok(profiles)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (name, options) in &self.profiles {
+            options.validate().map_err(|e| {
+                SicomError::InvalidOptions(format!("Invalid profile {name:?}: {e}"))
+            })?;
+        }
+        if let Some(name) = &self.default_profile {
+            if !self.profiles.contains_key(name) {
+                return Err(SicomError::InvalidOptions(format!(
+                    "Default profile {name:?} is not defined"
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Profile names, for listing to clients
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    pub fn default_profile(&self) -> Option<&str> {
+        self.default_profile.as_deref()
+    }
+
+    /// Options for a request: the named profile, else the default profile, else `fallback`
+    pub fn resolve(
+        &self,
+        name: Option<&str>,
+        fallback: &CompressOptions,
+    ) -> Result<CompressOptions, PolicyViolation> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(fallback.clone());
+        };
+        self.profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| PolicyViolation {
+                status: 400,
+                code: "unknown_profile",
+                message: format!("Unknown profile: {name}"),
+                retry_after: None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"{
+        "default_profile": "standard",
+        "allow_parameters": false,
+        "profiles": {
+            "standard": {"image_quality": 40},
+            "telegram": {"image_quality": 30, "audio": {"quality": 50}}
+        }
+    }"#;
+
+    fn load(json: &str) -> Result<Profiles> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        std::fs::write(&path, json).unwrap();
+        Profiles::load(&path)
+    }
+
+    #[test]
+    fn test_resolve() {
+        let profiles = load(CONFIG).unwrap();
+        let fallback = CompressOptions::new().image_quality(90);
+        assert!(!profiles.allow_parameters);
+        assert_eq!(
+            profiles.names().collect::<Vec<_>>(),
+            ["standard", "telegram"]
+        );
+
+        let telegram = profiles.resolve(Some("telegram"), &fallback).unwrap();
+        assert_eq!(telegram.image_quality, 30);
+        assert_eq!(telegram.audio.quality, 50);
+        assert_eq!(telegram.video_quality, 50);
+
+        let standard = profiles.resolve(None, &fallback).unwrap();
+        assert_eq!(standard.image_quality, 40);
+
+        let violation = profiles.resolve(Some("archive"), &fallback).unwrap_err();
+        assert_eq!(violation.code, "unknown_profile");
+
+        let options = Profiles::default().resolve(None, &fallback).unwrap();
+        assert_eq!(options.image_quality, 90);
+    }
+
+    #[test]
+    fn test_invalid_configs() {
+        assert!(load(r#"{"profiles": {"bad": {"image_quality": 0}}}"#).is_err());
+        assert!(load(r#"{"default_profile": "missing"}"#).is_err());
+        assert!(load(r#"{"profile": {}}"#).is_err());
+    }
+}
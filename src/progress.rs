@@ -1,8 +1,17 @@
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use crate::cancel::CancellationToken;
+use crate::format_size;
+use crate::stats::{CompressionStats, reduction_percent};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// Receives (files done, total files) after each file
+pub type ProgressCallback = Box<dyn FnMut(u64, u64) + Send>;
 
 pub struct ProgressLogger {
+    multi_progress: MultiProgress,
     progress_bar: ProgressBar,
     video_progress_bar: Option<ProgressBar>, // Video encoding progress
+    callback: Option<ProgressCallback>,
+    cancellation: Option<CancellationToken>,
 }
 
 impl ProgressLogger {
@@ -11,23 +20,68 @@ impl ProgressLogger {
         let progress_bar = multi_progress.add(ProgressBar::new(total_files));
         progress_bar.set_style(
             ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} files (ETA: {eta})")
+                .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} files (ETA: {eta}) {msg}")
                 .unwrap()
                 .progress_chars("#>-"),
         );
 
         Self {
+            multi_progress: multi_progress.clone(),
             progress_bar,
             video_progress_bar: None,
+            callback: None,
+            cancellation: None,
         }
     }
 
+    /// Logger that draws nothing, for library calls without a terminal UI
+    pub fn hidden() -> Self {
+        Self::new(
+            1,
+            &MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        )
+    }
+
+    /// Report progress to a callback in addition to the progress bar
+    pub fn set_callback(&mut self, callback: ProgressCallback) {
+        self.callback = Some(callback);
+    }
+
+    /// Let encoders see when the caller cancels the run
+    pub fn set_cancellation(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
     pub fn inc(&mut self) {
         self.progress_bar.inc(1);
+        if let Some(callback) = self.callback.as_mut() {
+            callback(
+                self.progress_bar.position(),
+                self.progress_bar.length().unwrap_or_default(),
+            );
+        }
     }
 
-    pub fn start_video_progress(&mut self, filename: &str, multi_progress: &MultiProgress) {
-        let video_bar = multi_progress.add(ProgressBar::new(100));
+    /// Update for a [`crate::stats::StatsAggregator`] showing the running size
+    /// difference of the files done so far
+    pub fn savings_sink(&self) -> impl FnMut(&CompressionStats) + Send + 'static {
+        let progress_bar = self.progress_bar.clone();
+        move |stats| {
+            progress_bar.set_message(savings_message(
+                stats.total_input_size(),
+                stats.total_output_size(),
+            ));
+        }
+    }
+
+    pub fn start_video_progress(&mut self, filename: &str) {
+        let video_bar = self.multi_progress.add(ProgressBar::new(100));
         video_bar.set_style(
             ProgressStyle::default_bar()
                 .template("{spinner:.blue} Encoding {msg}: [{wide_bar:.yellow/blue}] {percent}%")
@@ -57,6 +111,23 @@ impl ProgressLogger {
     }
 }
 
+/// Header text for the running savings, e.g. "saved so far: 412.0 MB (34.0%)"
+fn savings_message(input_size: u64, output_size: u64) -> String {
+    let percent = reduction_percent(input_size, output_size);
+    if output_size > input_size {
+        format!(
+            "grown so far: {} ({:.1}%)",
+            format_size(output_size - input_size),
+            -percent
+        )
+    } else {
+        format!(
+            "saved so far: {} ({percent:.1}%)",
+            format_size(input_size - output_size)
+        )
+    }
+}
+
 /// Get ANSI color code for log level
 pub const fn get_log_color(level: log::Level) -> &'static str {
     match level {
@@ -80,3 +151,18 @@ pub fn get_log_color_with_module(level: log::Level, module_path: Option<&str>) -
     // Use default color for all other cases
     get_log_color(level)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_savings_message() {
+        assert_eq!(savings_message(0, 0), "saved so far: 0 B (0.0%)");
+        assert_eq!(
+            savings_message(3 << 20, 2 << 20),
+            "saved so far: 1.0 MB (33.3%)"
+        );
+        assert_eq!(savings_message(1000, 1500), "grown so far: 500 B (50.0%)");
+    }
+}
@@ -0,0 +1,167 @@
+use crate::media::MediaKind;
+use crate::options::CompressOptions;
+use crate::{audio, image, video};
+
+/// Describe what the configured qualities mean for each media type
+pub fn explain_quality(options: &CompressOptions) -> Vec<(MediaKind, String)> {
+    if options.lossless_only {
+        return vec![
+            (
+                MediaKind::Image,
+                image::describe_quality(image::LOSSLESS_QUALITY, image::ImageCodec::Webp),
+            ),
+            (
+                MediaKind::Audio,
+                audio::describe_quality(&options.audio.mode(audio::AudioMode::Repack)),
+            ),
+            (MediaKind::Video, video::REMUX_DESCRIPTION.to_string()),
+        ];
+    }
+    vec![
+        (
+            MediaKind::Image,
+            image::describe_quality(options.image_quality, options.image_format),
+        ),
+        (MediaKind::Audio, audio::describe_quality(&options.audio)),
+        (
+            MediaKind::Video,
+            match options.video_mode {
+                video::VideoMode::Remux => video::REMUX_DESCRIPTION.to_string(),
+                video::VideoMode::Reencode => video::describe_quality(
+                    options.video_quality,
+                    options.video_denoise,
+                    options.video_preset,
+                    options.video_compat,
+                ),
+            },
+        ),
+    ]
+}
+
+#[cfg(all(test, feature = "audio"))]
+mod tests {
+    use super::*;
+    use crate::audio::{AudioOptions, StereoMode};
+    use crate::image::WebpSetting;
+
+    /// Golden quality mapping: every bucket edge for every codec
+    /// (quality, WebP, MP3 kbps, stereo mode, lowpass Hz, x265 CRF)
+    #[rustfmt::skip]
+    const GOLDEN: &[(u8, WebpSetting, u16, StereoMode, u32, u8)] = &[
+        (1,   WebpSetting::Lossy(1.0),   64,  StereoMode::Joint,  13_000, 51),
+        (15,  WebpSetting::Lossy(15.0),  64,  StereoMode::Joint,  13_000, 47),
+        (16,  WebpSetting::Lossy(16.0),  80,  StereoMode::Joint,  14_000, 46),
+        (25,  WebpSetting::Lossy(25.0),  80,  StereoMode::Joint,  14_000, 43),
+        (26,  WebpSetting::Lossy(26.0),  96,  StereoMode::Joint,  15_000, 43),
+        (35,  WebpSetting::Lossy(35.0),  96,  StereoMode::Joint,  15_000, 40),
+        (36,  WebpSetting::Lossy(36.0),  128, StereoMode::Joint,  17_000, 40),
+        (45,  WebpSetting::Lossy(45.0),  128, StereoMode::Joint,  17_000, 37),
+        (46,  WebpSetting::Lossy(46.0),  160, StereoMode::Joint,  17_500, 36),
+        (50,  WebpSetting::Lossy(50.0),  160, StereoMode::Joint,  17_500, 35),
+        (55,  WebpSetting::Lossy(55.0),  160, StereoMode::Joint,  17_500, 33),
+        (56,  WebpSetting::Lossy(56.0),  192, StereoMode::Joint,  19_000, 33),
+        (65,  WebpSetting::Lossy(65.0),  192, StereoMode::Joint,  19_000, 30),
+        (66,  WebpSetting::Lossy(66.0),  224, StereoMode::Joint,  19_500, 30),
+        (75,  WebpSetting::Lossy(75.0),  224, StereoMode::Joint,  19_500, 27),
+        (76,  WebpSetting::Lossy(76.0),  256, StereoMode::Joint,  20_000, 26),
+        (94,  WebpSetting::Lossy(94.0),  256, StereoMode::Joint,  20_000, 20),
+        (95,  WebpSetting::Lossless,     256, StereoMode::Joint,  20_000, 20),
+        (96,  WebpSetting::Lossless,     320, StereoMode::Stereo, 20_500, 20),
+        (100, WebpSetting::Lossless,     320, StereoMode::Stereo, 20_500, 18),
+    ];
+
+    #[test]
+    fn test_golden_quality_mapping() {
+        let audio_options = AudioOptions::default();
+        for &(quality, webp, kbps, stereo_mode, lowpass_hz, crf) in GOLDEN {
+            assert_eq!(image::webp_setting(quality), webp, "WebP at {quality}");
+
+            let settings = audio_options.lame_settings(audio::quality_to_mp3_bitrate(quality));
+            assert_eq!(settings.bitrate_kbps, kbps, "MP3 bitrate at {quality}");
+            assert_eq!(
+                settings.stereo_mode, stereo_mode,
+                "stereo mode at {quality}"
+            );
+            assert_eq!(settings.lowpass_hz, lowpass_hz, "lowpass at {quality}");
+
+            assert_eq!(video::quality_to_crf(quality), crf, "CRF at {quality}");
+        }
+    }
+
+    #[test]
+    fn test_mappings_are_monotonic() {
+        let audio_options = AudioOptions::default();
+        let mut previous_kbps = 0;
+        let mut previous_crf = u8::MAX;
+        for quality in 1..=100 {
+            let settings = audio_options.lame_settings(audio::quality_to_mp3_bitrate(quality));
+            assert!(settings.bitrate_kbps >= previous_kbps);
+            previous_kbps = settings.bitrate_kbps;
+
+            let crf = video::quality_to_crf(quality);
+            assert!(crf <= previous_crf);
+            previous_crf = crf;
+        }
+    }
+
+    #[test]
+    fn test_overrides_win_over_mapping() {
+        let audio_options = AudioOptions {
+            stereo_mode: Some(StereoMode::Stereo),
+            lowpass_hz: Some(16_000),
+            ..Default::default()
+        };
+        let settings = audio_options.lame_settings(audio::quality_to_mp3_bitrate(20));
+        assert_eq!(settings.bitrate_kbps, 80);
+        assert_eq!(settings.stereo_mode, StereoMode::Stereo);
+        assert_eq!(settings.lowpass_hz, 16_000);
+    }
+
+    #[test]
+    fn test_explain_quality() {
+        let options = CompressOptions::new()
+            .image_quality(75)
+            .audio(AudioOptions {
+                quality: 75,
+                ..Default::default()
+            })
+            .video_quality(75);
+        let explanation = explain_quality(&options);
+
+        assert_eq!(
+            explanation[0],
+            (MediaKind::Image, "WebP lossy, quality 75".to_string())
+        );
+        assert_eq!(
+            explanation[1],
+            (
+                MediaKind::Audio,
+                "MP3 224 kbps CBR, joint stereo, lowpass 19500 Hz, Native engine (clips under 3s: at least 128 kbps)"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            explanation[2],
+            (
+                MediaKind::Video,
+                "HEVC (libx265) CRF 27, preset medium".to_string()
+            )
+        );
+
+        let lossless: Vec<String> = explain_quality(&options.clone().lossless_only(true))
+            .into_iter()
+            .map(|(_, description)| description)
+            .collect();
+        assert_eq!(
+            lossless,
+            [
+                "WebP lossless",
+                "lossless MP3 repack (quality not used)",
+                "stream copy into a faststart MP4 (remux)"
+            ]
+        );
+
+        let remux = explain_quality(&options.video_mode(video::VideoMode::Remux));
+        assert_eq!(remux[2].1, video::REMUX_DESCRIPTION);
+    }
+}
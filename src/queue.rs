@@ -0,0 +1,417 @@
+use crate::options::CompressOptions;
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Lifecycle of a queued compression job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Waiting for a worker (new, or retrying after a failure)
+    Queued,
+    Running,
+    Succeeded,
+    /// Failed every attempt
+    Failed,
+}
+
+impl JobState {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// Last failure of a job, by stable error code (see `SicomError::code`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobFailure {
+    pub code: String,
+    pub message: String,
+}
+
+/// A compression job as stored in the queue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub state: JobState,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Settings the job was submitted with
+    pub options: CompressOptions,
+    pub attempts: u32,
+    /// Unix time before which a queued job is not picked up (retry backoff)
+    pub not_before: u64,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub input_size: u64,
+    pub output_size: Option<u64>,
+    pub error: Option<JobFailure>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Persistent job queue backed by sled
+///
+/// Jobs survive restarts: anything still `running` when the queue is opened
+/// was interrupted by a crash and goes back to `queued`. Transient failures
+/// are retried with exponential backoff until `max_attempts` is reached.
+pub struct JobQueue {
+    db: sled::Db,
+    max_attempts: u32,
+    retry_backoff: Duration,
+    /// Queued job ids with the time they're due, so polling doesn't read
+    /// every stored job (rebuilt from the database on open)
+    pending: Mutex<BTreeMap<String, u64>>,
+}
+
+impl JobQueue {
+    pub fn open(path: &Path, max_attempts: u32, retry_backoff: Duration) -> Result<Self> {
+        // Every write is flushed explicitly, so no background flusher thread is
+        // needed; without one the database is released as soon as it's dropped
+        let db = sled::Config::new()
+            .path(path)
+            .flush_every_ms(None)
+            .open()
+            .with_context(|| format!("Failed to open job queue {path:?}"))?;
+        let queue = Self {
+            db,
+            max_attempts: max_attempts.max(1),
+            retry_backoff,
+            pending: Mutex::new(BTreeMap::new()),
+        };
+
+        for job in queue.jobs()? {
+            if job.state != JobState::Running {
+                queue.index(&job);
+                continue;
+            }
+            if job.attempts >= queue.max_attempts {
+                // The job keeps taking the process down with it
+                let message = format!("Interrupted in all {} attempts", job.attempts);
+                queue.put(&Job {
+                    state: JobState::Failed,
+                    error: Some(JobFailure {
+                        code: "internal".to_string(),
+                        message,
+                    }),
+                    ..job
+                })?;
+            } else {
+                log::warn!("Requeueing job {} interrupted by a restart", job.id);
+                queue.put(&Job {
+                    state: JobState::Queued,
+                    ..job
+                })?;
+            }
+        }
+        Ok(queue)
+    }
+
+    fn put(&self, job: &Job) -> Result<()> {
+        self.db
+            .insert(job.id.as_bytes(), serde_json::to_vec(job)?)?;
+        self.db.flush()?;
+        self.index(job);
+        Ok(())
+    }
+
+    fn index(&self, job: &Job) {
+        let mut pending = self.pending.lock().unwrap();
+        if job.state == JobState::Queued {
+            pending.insert(job.id.clone(), job.not_before);
+        } else {
+            pending.remove(&job.id);
+        }
+    }
+
+    fn jobs(&self) -> Result<Vec<Job>> {
+        self.db
+            .iter()
+            .values()
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    /// Allocate an id for a new job (unique across restarts)
+    pub fn next_id(&self) -> Result<String> {
+        Ok(format!("{:016x}", self.db.generate_id()?))
+    }
+
+    /// Queue a job whose input is already in place
+    pub fn submit(
+        &self,
+        id: String,
+        input: PathBuf,
+        output: PathBuf,
+        options: CompressOptions,
+    ) -> Result<Job> {
+        let now = unix_now();
+        let input_size = std::fs::metadata(&input).map(|m| m.len()).unwrap_or(0);
+        let job = Job {
+            id,
+            state: JobState::Queued,
+            input,
+            output,
+            options,
+            attempts: 0,
+            not_before: now,
+            created_at: now,
+            updated_at: now,
+            input_size,
+            output_size: None,
+            error: None,
+        };
+        self.put(&job)?;
+        Ok(job)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<Job>> {
+        self.db
+            .get(id.as_bytes())?
+            .map(|value| Ok(serde_json::from_slice(&value)?))
+            .transpose()
+    }
+
+    /// Claim the oldest queued job that is due, marking it running
+    pub fn claim_next(&self) -> Result<Option<Job>> {
+        let now = unix_now();
+        let id = {
+            let mut pending = self.pending.lock().unwrap();
+            // Ids are allocated in increasing order, so iteration is oldest first
+            let Some(id) = pending
+                .iter()
+                .find(|&(_, &not_before)| not_before <= now)
+                .map(|(id, _)| id.clone())
+            else {
+                return Ok(None);
+            };
+            pending.remove(&id);
+            id
+        };
+
+        self.update(&id, |job| Job {
+            state: JobState::Running,
+            attempts: job.attempts + 1,
+            ..job
+        })
+        .map(Some)
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(Job) -> Job) -> Result<Job> {
+        let job = self.get(id)?.ok_or_else(|| anyhow!("Unknown job: {id}"))?;
+        let job = change(Job {
+            updated_at: unix_now(),
+            ..job
+        });
+        self.put(&job)?;
+        Ok(job)
+    }
+
+    pub fn complete(&self, id: &str, output_size: u64) -> Result<Job> {
+        self.update(id, |job| Job {
+            state: JobState::Succeeded,
+            output_size: Some(output_size),
+            error: None,
+            ..job
+        })
+    }
+
+    /// Record a failed attempt; a `transient` failure is retried later unless
+    /// the job is out of attempts
+    pub fn fail(&self, id: &str, failure: JobFailure, transient: bool) -> Result<Job> {
+        let (max_attempts, base) = (self.max_attempts, self.retry_backoff);
+        self.update(id, |job| {
+            if !transient || job.attempts >= max_attempts {
+                return Job {
+                    state: JobState::Failed,
+                    error: Some(failure),
+                    ..job
+                };
+            }
+            let backoff = base.saturating_mul(2u32.saturating_pow(job.attempts - 1));
+            Job {
+                state: JobState::Queued,
+                not_before: job.updated_at.saturating_add(backoff.as_secs()),
+                error: Some(failure),
+                ..job
+            }
+        })
+    }
+
+    /// Remove jobs that finished at least `retention` ago, returning them so
+    /// their files can be deleted
+    pub fn prune(&self, retention: Duration) -> Result<Vec<Job>> {
+        let now = unix_now();
+        let finished: Vec<Job> = self
+            .jobs()?
+            .into_iter()
+            .filter(|job| {
+                matches!(job.state, JobState::Succeeded | JobState::Failed)
+                    && job.updated_at.saturating_add(retention.as_secs()) <= now
+            })
+            .collect();
+        for job in &finished {
+            self.db.remove(job.id.as_bytes())?;
+        }
+        if !finished.is_empty() {
+            self.db.flush()?;
+        }
+        Ok(finished)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(code: &str) -> JobFailure {
+        JobFailure {
+            code: code.to_string(),
+            message: "boom".to_string(),
+        }
+    }
+
+    fn submit(queue: &JobQueue) -> Job {
+        let id = queue.next_id().unwrap();
+        queue
+            .submit(
+                id,
+                PathBuf::from("in.siq"),
+                PathBuf::from("out.siq"),
+                CompressOptions::new(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_jobs_run_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = JobQueue::open(dir.path(), 3, Duration::ZERO).unwrap();
+        let first = submit(&queue);
+        let second = submit(&queue);
+
+        let claimed = queue.claim_next().unwrap().unwrap();
+        assert_eq!(claimed.id, first.id);
+        assert_eq!(claimed.state, JobState::Running);
+        assert_eq!(claimed.attempts, 1);
+
+        queue.complete(&first.id, 100).unwrap();
+        let done = queue.get(&first.id).unwrap().unwrap();
+        assert_eq!(done.state, JobState::Succeeded);
+        assert_eq!(done.output_size, Some(100));
+
+        assert_eq!(queue.claim_next().unwrap().unwrap().id, second.id);
+        assert_eq!(queue.claim_next().unwrap(), None);
+        assert_eq!(queue.get("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_failed_jobs_retry_then_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = JobQueue::open(dir.path(), 2, Duration::ZERO).unwrap();
+        let job = submit(&queue);
+
+        queue.claim_next().unwrap().unwrap();
+        let retrying = queue.fail(&job.id, failure("io_error"), true).unwrap();
+        assert_eq!(retrying.state, JobState::Queued);
+
+        let claimed = queue.claim_next().unwrap().unwrap();
+        assert_eq!(claimed.attempts, 2);
+        let failed = queue.fail(&job.id, failure("io_error"), true).unwrap();
+        assert_eq!(failed.state, JobState::Failed);
+        assert_eq!(failed.error.unwrap().code, "io_error");
+        assert_eq!(queue.claim_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_permanent_failures_are_not_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = JobQueue::open(dir.path(), 3, Duration::ZERO).unwrap();
+        let job = submit(&queue);
+
+        queue.claim_next().unwrap().unwrap();
+        let failed = queue
+            .fail(&job.id, failure("archive_error"), false)
+            .unwrap();
+        assert_eq!((failed.state, failed.attempts), (JobState::Failed, 1));
+        assert_eq!(queue.claim_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_prune_finished_jobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = JobQueue::open(dir.path(), 3, Duration::ZERO).unwrap();
+        let done = submit(&queue);
+        let waiting = submit(&queue);
+        queue.claim_next().unwrap().unwrap();
+        queue.complete(&done.id, 100).unwrap();
+
+        assert!(queue.prune(Duration::from_secs(3600)).unwrap().is_empty());
+        let pruned = queue.prune(Duration::ZERO).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].id, done.id);
+        assert_eq!(queue.get(&done.id).unwrap(), None);
+        assert_eq!(queue.claim_next().unwrap().unwrap().id, waiting.id);
+    }
+
+    #[test]
+    fn test_backoff_delays_retry() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = JobQueue::open(dir.path(), 3, Duration::from_secs(60)).unwrap();
+        let job = submit(&queue);
+
+        queue.claim_next().unwrap().unwrap();
+        let retrying = queue.fail(&job.id, failure("io_error"), true).unwrap();
+        assert_eq!(retrying.not_before, retrying.updated_at + 60);
+        assert_eq!(queue.claim_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_running_jobs_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let (id, waiting) = {
+            let queue = JobQueue::open(dir.path(), 3, Duration::ZERO).unwrap();
+            let job = submit(&queue);
+            let waiting = submit(&queue);
+            queue.claim_next().unwrap().unwrap();
+            (job.id, waiting.id)
+        };
+
+        let queue = JobQueue::open(dir.path(), 3, Duration::ZERO).unwrap();
+        assert_eq!(queue.get(&id).unwrap().unwrap().state, JobState::Queued);
+        let claimed = queue.claim_next().unwrap().unwrap();
+        assert_eq!(claimed.id, id);
+        assert_eq!(claimed.attempts, 2);
+        assert_eq!(queue.claim_next().unwrap().unwrap().id, waiting);
+        assert_ne!(queue.next_id().unwrap(), id);
+    }
+
+    #[test]
+    fn test_jobs_interrupted_too_often_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = {
+            let queue = JobQueue::open(dir.path(), 1, Duration::ZERO).unwrap();
+            let job = submit(&queue);
+            queue.claim_next().unwrap().unwrap();
+            job.id
+        };
+
+        let queue = JobQueue::open(dir.path(), 1, Duration::ZERO).unwrap();
+        let job = queue.get(&id).unwrap().unwrap();
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.error.unwrap().code, "internal");
+        assert_eq!(queue.claim_next().unwrap(), None);
+    }
+}
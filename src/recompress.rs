@@ -0,0 +1,152 @@
+use crate::manifest::Manifest;
+use crate::options::CompressOptions;
+use crate::pipeline::ORIGINAL_CONTENT_XML;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// MP3s at or below this average bitrate look like earlier lossy re-encodes
+const LOW_MP3_BITRATE_KBPS: f64 = 128.0;
+
+/// MP4 sample entry types of HEVC video, as written by sicom's encodes
+const HEVC_FOURCCS: [&[u8]; 2] = [b"hvc1", b"hev1"];
+
+fn extension(name: &str) -> Option<String> {
+    Path::new(name)
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+/// "n of m <what>" when most of the `total` files of a kind look compressed
+fn majority(found: usize, total: usize, what: &str) -> Option<String> {
+    (found > 0 && found * 2 > total).then(|| format!("{found} of {total} {what}"))
+}
+
+/// Signs that a pack already went through sicom or a similar tool: a sicom
+/// manifest or content.xml backup, or media that is mostly WebP images,
+/// low-bitrate MP3s or HEVC videos
+///
+/// Media kinds skipped by `options` aren't looked at, nor HEVC videos when
+/// `video_compat` asks to convert them.
+pub fn recompression_signs(
+    archive: &mut ZipArchive<BufReader<File>>,
+    input_pack: &Path,
+    options: &CompressOptions,
+) -> Result<Vec<String>> {
+    let mut signs = Vec::new();
+    if Manifest::path_for(input_pack).exists() {
+        signs.push("a sicom manifest next to it".to_string());
+    }
+    if archive.by_name(ORIGINAL_CONTENT_XML).is_ok() {
+        signs.push(format!("{ORIGINAL_CONTENT_XML} kept by sicom"));
+    }
+
+    let (mut images, mut webp) = (0, 0);
+    let (mut mp3s, mut low_bitrate) = (0, 0);
+    let (mut videos, mut hevc) = (0, 0);
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .with_context(|| format!("Failed to read file at index {i}"))?;
+        let Some(extension) = extension(file.name()) else {
+            continue;
+        };
+        match extension.as_str() {
+            "webp" | "avif" | "png" | "jpg" | "jpeg" | "gif" | "bmp" | "tif" | "tiff" | "heic"
+            | "heif"
+                if !options.skip_image =>
+            {
+                images += 1;
+                webp += usize::from(matches!(extension.as_str(), "webp" | "avif"));
+            }
+            "mp3" if !options.skip_audio => {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)
+                    .with_context(|| format!("Failed to read {}", file.name()))?;
+                let summary = crate::mp3::scan_frames(&data);
+                mp3s += 1;
+                if summary.frames > 0 && summary.duration_seconds > 0.0 {
+                    let kbps = summary.audio_bytes as f64 * 8.0 / summary.duration_seconds / 1000.0;
+                    low_bitrate += usize::from(kbps <= LOW_MP3_BITRATE_KBPS);
+                }
+            }
+            "mp4" | "mov" | "m4v" if !options.skip_video => {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)
+                    .with_context(|| format!("Failed to read {}", file.name()))?;
+                videos += 1;
+                hevc += usize::from(data.windows(4).any(|window| HEVC_FOURCCS.contains(&window)));
+            }
+            _ => {}
+        }
+    }
+
+    signs.extend(majority(webp, images, "images are WebP or AVIF"));
+    signs.extend(majority(
+        low_bitrate,
+        mp3s,
+        &format!("MP3s are at {LOW_MP3_BITRATE_KBPS} kbps or less"),
+    ));
+    if options.video_compat.is_none() {
+        signs.extend(majority(hevc, videos, "videos are HEVC"));
+    }
+    Ok(signs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write_pack;
+
+    #[test]
+    fn test_recompression_signs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.siq");
+        let options = CompressOptions::new();
+
+        write_pack(
+            &path,
+            &[
+                ("content.xml", b"<package/>"),
+                ("Images/a.png", b"png"),
+                ("Images/b.webp", b"webp"),
+                ("Video/c.mp4", b"....ftypisom....stsd....hvc1"),
+            ],
+        );
+        let signs =
+            recompression_signs(&mut crate::open_pack(&path).unwrap(), &path, &options).unwrap();
+        // Half the images being WebP isn't enough
+        assert_eq!(signs, ["1 of 1 videos are HEVC"]);
+        let compat = options
+            .clone()
+            .video_compat(Some(crate::video::VideoCompat::H264));
+        let signs =
+            recompression_signs(&mut crate::open_pack(&path).unwrap(), &path, &compat).unwrap();
+        assert!(signs.is_empty());
+
+        write_pack(
+            &path,
+            &[
+                ("content.xml", b"<package/>"),
+                (ORIGINAL_CONTENT_XML, b"<package/>"),
+                ("Images/b.webp", b"webp"),
+            ],
+        );
+        std::fs::write(Manifest::path_for(&path), "{}").unwrap();
+        let signs =
+            recompression_signs(&mut crate::open_pack(&path).unwrap(), &path, &options).unwrap();
+        assert_eq!(signs.len(), 3);
+        assert!(signs[2].starts_with("1 of 1 images"));
+
+        let signs = recompression_signs(
+            &mut crate::open_pack(&path).unwrap(),
+            &path,
+            &options.skip_image(true),
+        )
+        .unwrap();
+        assert_eq!(signs.len(), 2);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::content_types::{self, CONTENT_TYPES_ENTRY};
+use crate::error::SicomError;
+use crate::inspect::EntryIndex;
+use crate::media::MediaKind;
+use crate::pack::{self, MediaRef};
+use crate::pipeline::{EntryMetadata, entry_options, open_pack};
+use crate::verify::{self, Problem};
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use zip::ZipWriter;
+
+/// What `repair` changed, and what it couldn't fix
+#[derive(Debug, Default, Serialize)]
+pub struct RepairReport {
+    /// Media entries renamed to the URL-encoded form SIGame looks them up by (old, new)
+    pub renamed_entries: Vec<(String, String)>,
+    /// content.xml references pointed at the entry they were meant for (old, new name)
+    pub fixed_references: Vec<(String, String)>,
+    /// References that still match no entry
+    pub unresolved: Vec<Problem>,
+}
+
+/// Name SIGame expects a media entry under: the directory, then the URL-encoded file name
+fn canonical_entry_name(entry_name: &str, kind: MediaKind) -> String {
+    let filename = &entry_name[kind.directory().len()..];
+    let decoded = urlencoding::decode(filename).unwrap_or_else(|_| filename.into());
+    format!("{}{}", kind.directory(), urlencoding::encode(&decoded))
+}
+
+/// File name a broken reference was most likely meant for, among the decoded
+/// `names` in its directory: its own name URL-decoded, or the only name that
+/// matches it case-insensitively
+fn intended_name(media: &MediaRef, names: &[String]) -> Option<String> {
+    let decoded = urlencoding::decode(&media.name)
+        .map_or_else(|_| media.name.clone(), |decoded| decoded.into_owned());
+    if decoded != media.name && names.contains(&decoded) {
+        return Some(decoded);
+    }
+    let lowercase = decoded.to_lowercase();
+    let mut matches = names.iter().filter(|name| name.to_lowercase() == lowercase);
+    match (matches.next(), matches.next()) {
+        (Some(name), None) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+/// Point every reference to `old` at `new`: `item` texts, `@` atoms and the
+/// package logo, leaving the name alone anywhere else in the text
+fn replace_reference(xml: &mut String, old: &str, new: &str) {
+    let escape = |name: &str| name.replace('&', "&amp;").replace('<', "&lt;");
+    let (old, new) = (escape(old), escape(new));
+    for (before, after) in [(">", "<"), (">@", "<"), ("\"@", "\""), ("'@", "'")] {
+        *xml = xml.replace(
+            &format!("{before}{old}{after}"),
+            &format!("{before}{new}{after}"),
+        );
+    }
+}
+
+/// Write a copy of a pack whose media entries carry URL-encoded names and whose
+/// content.xml references match the entries they were meant for (same name once
+/// URL-decoded or in another case); the output defaults to `<name>_repaired.siq`
+pub fn repair_pack(pack: &Path, output: Option<PathBuf>) -> Result<RepairReport> {
+    if !pack.exists() {
+        return Err(SicomError::InputNotFound(pack.to_path_buf()).into());
+    }
+    let output = output.unwrap_or_else(|| {
+        let stem = pack.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = pack.extension().unwrap_or_default().to_string_lossy();
+        pack.with_file_name(format!("{stem}_repaired.{extension}"))
+    });
+
+    let mut archive = open_pack(pack)?;
+    let mut report = RepairReport::default();
+
+    // Raw names become encoded ones, unless the encoded name is already taken
+    let names: HashSet<String> = archive.file_names().map(str::to_string).collect();
+    let mut renames: HashMap<String, String> = HashMap::new();
+    for name in archive.file_names() {
+        let Some(kind) = MediaKind::of_entry(name) else {
+            continue;
+        };
+        let canonical = canonical_entry_name(name, kind);
+        if canonical != name && !names.contains(&canonical) {
+            renames.insert(name.to_string(), canonical);
+        }
+    }
+    let output_names: Vec<String> = archive
+        .file_names()
+        .map(|name| renames.get(name).map_or(name, String::as_str).to_string())
+        .collect();
+
+    let mut xml = String::new();
+    archive
+        .by_name("content.xml")
+        .map_err(|_| anyhow!("No content.xml found in pack"))?
+        .read_to_string(&mut xml)
+        .with_context(|| "Failed to read content.xml as UTF-8")?;
+    let package = pack::parse_package(&xml)?;
+
+    let index = EntryIndex::new(output_names.iter().map(|name| (name.as_str(), 0)));
+    let mut fixed = HashSet::new();
+    for media in verify::media_references(&package) {
+        if index.resolve(media).is_some() || fixed.contains(&(media.kind, &media.name)) {
+            continue;
+        }
+        let directory = media.kind.directory();
+        let names: Vec<String> = output_names
+            .iter()
+            .filter_map(|name| name.strip_prefix(directory))
+            .map(|name| urlencoding::decode(name).map_or_else(|_| name.to_string(), Into::into))
+            .collect();
+        match intended_name(media, &names) {
+            Some(name) => {
+                replace_reference(&mut xml, &media.name, &name);
+                fixed.insert((media.kind, &media.name));
+                report.fixed_references.push((media.name.clone(), name));
+            }
+            None => {
+                let problem = verify::missing_reference(&index, media);
+                if !report.unresolved.contains(&problem) {
+                    report.unresolved.push(problem);
+                }
+            }
+        }
+    }
+
+    let file = File::create(&output).map_err(|source| SicomError::Io {
+        path: output.clone(),
+        source,
+    })?;
+    let mut writer = ZipWriter::new(BufWriter::new(file));
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let name = entry.name().to_string();
+        let source = EntryMetadata::of(&entry);
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read file: {name}"))?;
+
+        if name == "content.xml" {
+            data = xml.clone().into_bytes();
+        } else if name == CONTENT_TYPES_ENTRY {
+            if let Ok(text) = std::str::from_utf8(&data) {
+                match content_types::update_content_types(
+                    text,
+                    output_names.iter().map(String::as_str),
+                    &renames,
+                ) {
+                    Ok(updated) => data = updated.into_bytes(),
+                    Err(e) => warn!("Copying unparseable {CONTENT_TYPES_ENTRY} unchanged: {e}"),
+                }
+            }
+        }
+        let name = match renames.get(&name) {
+            Some(canonical) => {
+                report.renamed_entries.push((name, canonical.clone()));
+                canonical.clone()
+            }
+            None => name,
+        };
+
+        writer
+            .start_file(&name, source.apply(entry_options(&name, None)))
+            .with_context(|| format!("Failed to start file in output ZIP: {name}"))?;
+        writer
+            .write_all(&data)
+            .with_context(|| format!("Failed to write file: {name}"))?;
+    }
+    writer
+        .finish()
+        .with_context(|| "Failed to finalize output ZIP")?;
+
+    info!(
+        "Renamed {} entries and fixed {} references into {}",
+        report.renamed_entries.len(),
+        report.fixed_references.len(),
+        output.display()
+    );
+    Ok(report)
+}
+
+/// Print what was changed and what is still broken
+pub fn print_report(report: &RepairReport) {
+    for (old, new) in &report.renamed_entries {
+        println!("Renamed {old} -> {new}");
+    }
+    for (old, new) in &report.fixed_references {
+        println!("Fixed reference {old} -> {new}");
+    }
+    if report.unresolved.is_empty() {
+        println!("Every reference resolves");
+    } else {
+        println!("Unresolved references ({}):", report.unresolved.len());
+        for problem in &report.unresolved {
+            println!("  {}: {}", problem.entry, problem.message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write_pack;
+
+    #[test]
+    fn test_intended_name() {
+        let names = ["гимн.mp3".to_string(), "Logo.PNG".to_string()];
+        let media = |name: &str| MediaRef {
+            kind: MediaKind::Audio,
+            name: name.to_string(),
+        };
+        assert_eq!(
+            intended_name(&media("%D0%B3%D0%B8%D0%BC%D0%BD.mp3"), &names).as_deref(),
+            Some("гимн.mp3")
+        );
+        assert_eq!(
+            intended_name(&media("logo.png"), &names).as_deref(),
+            Some("Logo.PNG")
+        );
+        assert_eq!(
+            intended_name(&media("%D0%93%D0%B8%D0%BC%D0%BD.MP3"), &names).as_deref(),
+            Some("гимн.mp3")
+        );
+        assert_eq!(intended_name(&media("missing.mp3"), &names), None);
+
+        // Two entries differing only in case leave the choice open
+        let ambiguous = ["a.png".to_string(), "A.png".to_string()];
+        assert_eq!(intended_name(&media("A.PNG"), &ambiguous), None);
+    }
+
+    #[test]
+    fn test_repair_pack() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.siq");
+        let xml = r#"<package name="P" logo="@cover.JPG"><rounds><round name="R"><themes><theme name="T"><questions><question price="100"><params><param name="question" type="content"><item type="image" isRef="True">a b.png</item><item type="audio" isRef="True">%D0%B3.mp3</item><item type="audio" isRef="True">gone.mp3</item></param></params></question></questions></theme></themes></round></rounds></package>"#;
+
+        write_pack(
+            &path,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Images/a b.png", b"png"),
+                ("Images/cover.jpg", b"jpg"),
+                ("Audio/%D0%B3.mp3", b"mp3"),
+            ],
+        );
+
+        let report = repair_pack(&path, None).unwrap();
+        assert_eq!(
+            report.renamed_entries,
+            [("Images/a b.png".to_string(), "Images/a%20b.png".to_string())]
+        );
+        assert_eq!(
+            report.fixed_references,
+            [
+                ("cover.JPG".to_string(), "cover.jpg".to_string()),
+                ("%D0%B3.mp3".to_string(), "г.mp3".to_string())
+            ]
+        );
+        let unresolved: Vec<&str> = report.unresolved.iter().map(|p| p.entry.as_str()).collect();
+        assert_eq!(unresolved, ["Audio/gone.mp3"]);
+
+        let repaired = dir.path().join("pack_repaired.siq");
+        let mut archive = open_pack(&repaired).unwrap();
+        let mut content = String::new();
+        archive
+            .by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.contains(r#"logo="@cover.jpg""#));
+        assert!(content.contains(">г.mp3<"));
+        assert!(content.contains(">a b.png<"));
+        assert!(archive.by_name("Images/a%20b.png").is_ok());
+    }
+}
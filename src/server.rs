@@ -0,0 +1,943 @@
+use crate::capabilities::Capabilities;
+use crate::error::{self, SicomError};
+use crate::metrics::Metrics;
+use crate::options::CompressOptions;
+use crate::pipeline::PackCompressor;
+use crate::policy::{ApiPolicy, PolicyViolation, RateLimiter};
+use crate::profiles::Profiles;
+use crate::queue::{Job, JobFailure, JobQueue, JobState};
+use crate::warm::WarmPool;
+use anyhow::{Result, anyhow};
+use indicatif::MultiProgress;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::{HTTPVersion, Header, Method, Response, StatusCode};
+
+/// How long the worker sleeps when no job is due
+const IDLE_POLL: Duration = Duration::from_secs(1);
+/// How often the worker deletes jobs past their retention
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Uploads are read in chunks of this size, checking limits in between
+const UPLOAD_CHUNK: usize = 64 * 1024;
+/// Requests handled at once; each runs on its own thread so a stalled upload
+/// only holds up its own client
+const MAX_ACTIVE_REQUESTS: usize = 64;
+/// Longest a connection may go without sending anything (shorter with an
+/// upload timeout), so stalled clients give their request slot back
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+/// Longest request line plus headers accepted
+const MAX_HEAD: u64 = 16 * 1024;
+
+/// Settings for the HTTP job API
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// Address to listen on, e.g. `0.0.0.0:8080`
+    pub listen: String,
+    /// Holds the job queue, uploaded packs and results
+    pub data_dir: PathBuf,
+    /// Attempts per job, including runs interrupted by a restart
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubles with every failed attempt
+    pub retry_backoff: Duration,
+    /// How long finished jobs and their results are kept
+    pub job_retention: Duration,
+    /// Rate, size, quality and time limits for clients
+    pub policy: ApiPolicy,
+    /// Named option sets clients select with `?profile=`
+    pub profiles: Profiles,
+}
+
+/// Job as reported by the API (server-side paths stay private)
+#[derive(Debug, Serialize)]
+struct JobStatus<'a> {
+    id: &'a str,
+    state: JobState,
+    attempts: u32,
+    created_at: u64,
+    updated_at: u64,
+    input_size: u64,
+    output_size: Option<u64>,
+    error: Option<&'a JobFailure>,
+}
+
+impl<'a> From<&'a Job> for JobStatus<'a> {
+    fn from(job: &'a Job) -> Self {
+        Self {
+            id: &job.id,
+            state: job.state,
+            attempts: job.attempts,
+            created_at: job.created_at,
+            updated_at: job.updated_at,
+            input_size: job.input_size,
+            output_size: job.output_size,
+            error: job.error.as_ref(),
+        }
+    }
+}
+
+type ApiResponse = Response<Box<dyn Read + Send>>;
+
+fn respond(
+    status: u16,
+    content_type: &str,
+    body: Box<dyn Read + Send>,
+    length: usize,
+) -> ApiResponse {
+    let header = Header::from_bytes("Content-Type", content_type).expect("static header is valid");
+    Response::new(StatusCode(status), vec![header], body, Some(length), None)
+}
+
+fn json(status: u16, value: &impl Serialize) -> ApiResponse {
+    let body = serde_json::to_vec(value).unwrap_or_default();
+    let length = body.len();
+    respond(
+        status,
+        "application/json",
+        Box::new(io::Cursor::new(body)),
+        length,
+    )
+}
+
+/// Structured error body: `{"error": {"code": ..., "message": ...}}`
+fn api_error(status: u16, code: &str, message: impl Into<String>) -> ApiResponse {
+    json(
+        status,
+        &serde_json::json!({ "error": { "code": code, "message": message.into() } }),
+    )
+}
+
+fn violation_response(violation: PolicyViolation) -> ApiResponse {
+    let response = api_error(violation.status, violation.code, violation.message);
+    match violation.retry_after {
+        Some(seconds) => response.with_header(
+            Header::from_bytes("Retry-After", seconds.to_string())
+                .expect("numeric header is valid"),
+        ),
+        None => response,
+    }
+}
+
+/// Request read off an accepted connection
+///
+/// The API parses requests itself instead of going through tiny_http, which
+/// hides its sockets: every connection needs a read timeout so a client that
+/// stops sending can't hold its request slot forever. One request is served
+/// per connection.
+struct Request<'a> {
+    method: Method,
+    url: String,
+    remote_addr: SocketAddr,
+    body_length: Option<usize>,
+    /// The client waits for `100 Continue` before sending the body
+    expect_continue: bool,
+    connection: &'a mut BufReader<TcpStream>,
+}
+
+impl Request<'_> {
+    /// Read the request line and headers
+    fn read<'a>(
+        connection: &'a mut BufReader<TcpStream>,
+        remote_addr: SocketAddr,
+    ) -> Result<Request<'a>, PolicyViolation> {
+        let bad_request = |message: &str| PolicyViolation {
+            status: 400,
+            code: "bad_request",
+            message: message.to_string(),
+            retry_after: None,
+        };
+        let mut head = (&mut *connection).take(MAX_HEAD);
+        let mut read_line = || {
+            let mut line = String::new();
+            match head.read_line(&mut line) {
+                Ok(_) if line.ends_with('\n') => Ok(line.trim_end().to_string()),
+                Ok(_) => Err(bad_request("Request head is incomplete or too long")),
+                Err(e) if is_timeout(&e) => Err(PolicyViolation {
+                    status: 408,
+                    code: "request_timeout",
+                    message: "No request received in time".to_string(),
+                    retry_after: None,
+                }),
+                Err(e) => Err(bad_request(&e.to_string())),
+            }
+        };
+
+        let request_line = read_line()?;
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(url), Some(version)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(bad_request("Malformed request line"));
+        };
+        let method = method
+            .parse()
+            .map_err(|()| bad_request("Unknown request method"))?;
+        if !version.starts_with("HTTP/1.") {
+            return Err(bad_request("Only HTTP/1.x is supported"));
+        }
+
+        let mut body_length = None;
+        let mut expect_continue = false;
+        loop {
+            let line = read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                return Err(bad_request("Malformed header"));
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("Content-Length") {
+                body_length = Some(
+                    value
+                        .parse()
+                        .map_err(|_| bad_request("Invalid Content-Length"))?,
+                );
+            } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+                return Err(PolicyViolation {
+                    status: 411,
+                    code: "length_required",
+                    message: "Uploads need a Content-Length".to_string(),
+                    retry_after: None,
+                });
+            } else if name.eq_ignore_ascii_case("Expect") {
+                expect_continue = value.eq_ignore_ascii_case("100-continue");
+            }
+        }
+
+        Ok(Request {
+            method,
+            url: url.to_string(),
+            remote_addr,
+            body_length,
+            expect_continue,
+            connection,
+        })
+    }
+
+    /// The request body, limited to its `Content-Length`
+    fn body(&mut self) -> impl Read + '_ {
+        if std::mem::take(&mut self.expect_continue) {
+            // A failed write shows up again when reading the body
+            let _ = self
+                .connection
+                .get_mut()
+                .write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+        }
+        let length = self.body_length.unwrap_or_default() as u64;
+        (&mut *self.connection).take(length)
+    }
+}
+
+/// Write `response` and close the connection
+fn send(stream: &TcpStream, response: ApiResponse) {
+    let close = Header::from_bytes("Connection", "close").expect("static header is valid");
+    let mut writer = BufWriter::new(stream);
+    let sent = response
+        .with_header(close)
+        .raw_print(&mut writer, HTTPVersion(1, 1), &[], false, None)
+        .and_then(|()| writer.flush());
+    if let Err(e) = sent {
+        warn!("Failed to send response: {e}");
+    }
+    let _ = stream.shutdown(Shutdown::Write);
+}
+
+/// A socket read ran into its timeout
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Stream an upload to `file`, enforcing the size limit and upload timeout
+fn receive_upload(
+    reader: &mut dyn Read,
+    expected: u64,
+    file: &mut File,
+    policy: &ApiPolicy,
+) -> Result<(), PolicyViolation> {
+    let started = Instant::now();
+    let mut received: u64 = 0;
+    let mut buffer = vec![0; UPLOAD_CHUNK];
+    loop {
+        let read = reader.read(&mut buffer).map_err(|e| {
+            if is_timeout(&e) {
+                // The socket read timeout ran out without a byte arriving
+                PolicyViolation {
+                    status: 408,
+                    code: "upload_timeout",
+                    message: format!(
+                        "Upload stalled for {} seconds",
+                        read_timeout(policy).as_secs_f64()
+                    ),
+                    retry_after: None,
+                }
+            } else {
+                PolicyViolation {
+                    status: 400,
+                    code: "upload_failed",
+                    message: e.to_string(),
+                    retry_after: None,
+                }
+            }
+        })?;
+        if read == 0 && received < expected {
+            return Err(PolicyViolation {
+                status: 400,
+                code: "upload_failed",
+                message: format!("Upload ended after {received} of {expected} bytes"),
+                retry_after: None,
+            });
+        }
+        if read == 0 {
+            return Ok(());
+        }
+        received += read as u64;
+        policy.check_upload_size(received)?;
+        if policy
+            .upload_timeout
+            .is_some_and(|timeout| started.elapsed() > timeout)
+        {
+            return Err(policy.upload_timed_out());
+        }
+        file.write_all(&buffer[..read])
+            .map_err(|e| PolicyViolation {
+                status: 500,
+                code: "io_error",
+                message: e.to_string(),
+                retry_after: None,
+            })?;
+    }
+}
+
+/// Socket read timeout: the upload timeout when it is shorter than [`READ_TIMEOUT`]
+fn read_timeout(policy: &ApiPolicy) -> Duration {
+    policy
+        .upload_timeout
+        .filter(|timeout| !timeout.is_zero())
+        .map_or(READ_TIMEOUT, |timeout| timeout.min(READ_TIMEOUT))
+}
+
+/// HTTP API that queues uploaded packs and compresses them in the background
+///
+/// `POST /jobs` takes the pack as the request body and returns the job;
+/// `GET /jobs/{id}` reports its state and `GET /jobs/{id}/result` downloads
+/// the compressed pack once it succeeded; `GET /capabilities` lists the
+/// formats and encoders available. Jobs live in a persistent queue, so
+/// they survive restarts and failed attempts are retried with backoff.
+pub struct Server {
+    listener: TcpListener,
+    queue: Arc<JobQueue>,
+    data_dir: PathBuf,
+    job_retention: Duration,
+    compress_options: CompressOptions,
+    policy: ApiPolicy,
+    profiles: Profiles,
+    rate_limiter: RateLimiter,
+    metrics: Arc<Metrics>,
+    multi_progress: MultiProgress,
+    active_requests: AtomicUsize,
+}
+
+impl Server {
+    pub fn bind(
+        options: &ServeOptions,
+        compress_options: CompressOptions,
+        multi_progress: MultiProgress,
+    ) -> Result<Self> {
+        compress_options.validate()?;
+        for dir in ["uploads", "results"].map(|name| options.data_dir.join(name)) {
+            fs::create_dir_all(&dir).map_err(|source| SicomError::Io { path: dir, source })?;
+        }
+        let queue = JobQueue::open(
+            &options.data_dir.join("queue"),
+            options.max_attempts,
+            options.retry_backoff,
+        )?;
+        let listener = TcpListener::bind(&options.listen)
+            .map_err(|e| anyhow!("Failed to listen on {}: {e}", options.listen))?;
+
+        Ok(Self {
+            listener,
+            queue: Arc::new(queue),
+            data_dir: options.data_dir.clone(),
+            job_retention: options.job_retention,
+            compress_options,
+            policy: options.policy.clone(),
+            profiles: options.profiles.clone(),
+            rate_limiter: RateLimiter::new(),
+            metrics: Arc::new(Metrics::new()),
+            multi_progress,
+            active_requests: AtomicUsize::new(0),
+        })
+    }
+
+    /// Bound address (useful when listening on port 0)
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    /// Start the worker and handle requests until the process is stopped
+    pub fn run(self) -> Result<()> {
+        let worker = Worker {
+            queue: Arc::clone(&self.queue),
+            data_dir: self.data_dir.clone(),
+            job_retention: self.job_retention,
+            metrics: Arc::clone(&self.metrics),
+            multi_progress: self.multi_progress.clone(),
+            warm_pool: WarmPool::new(),
+        };
+        thread::spawn(move || worker.run());
+
+        let address = self.listener.local_addr()?;
+        info!("Listening on http://{address}");
+        let server = Arc::new(self);
+        for stream in server.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to accept a connection: {e}");
+                    continue;
+                }
+            };
+            if server.active_requests.fetch_add(1, Ordering::SeqCst) >= MAX_ACTIVE_REQUESTS {
+                server.active_requests.fetch_sub(1, Ordering::SeqCst);
+                send(
+                    &stream,
+                    api_error(503, "server_busy", "Too many requests in progress"),
+                );
+                continue;
+            }
+            let server = Arc::clone(&server);
+            thread::spawn(move || {
+                server.serve(stream);
+                server.active_requests.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        Ok(())
+    }
+
+    /// Read one request from `stream` and answer it
+    fn serve(&self, stream: TcpStream) {
+        let (Ok(remote_addr), Ok(())) = (
+            stream.peer_addr(),
+            stream.set_read_timeout(Some(read_timeout(&self.policy))),
+        ) else {
+            return;
+        };
+        let mut connection = BufReader::new(stream);
+        let response = match Request::read(&mut connection, remote_addr) {
+            Ok(mut request) => self.handle(&mut request),
+            Err(violation) => violation_response(violation),
+        };
+        send(connection.get_ref(), response);
+    }
+
+    fn handle(&self, request: &mut Request) -> ApiResponse {
+        let url = request.url.clone();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        if let Some(limit) = self.policy.requests_per_minute {
+            if path != "/metrics" {
+                if let Err(violation) =
+                    self.rate_limiter
+                        .check(request.remote_addr.ip(), limit, Instant::now())
+                {
+                    return violation_response(violation);
+                }
+            }
+        }
+
+        let result = match (&request.method, segments.as_slice()) {
+            (Method::Post, ["jobs"]) => self.submit(request, query),
+            (Method::Get, ["jobs", id]) => self.status(id),
+            (Method::Get, ["jobs", id, "result"]) => self.result(id),
+            (Method::Get, ["capabilities"]) => Ok(json(
+                200,
+                &Capabilities::detect(self.compress_options.ffmpeg_path.as_deref()),
+            )),
+            (Method::Get, ["profiles"]) => Ok(json(
+                200,
+                &serde_json::json!({
+                    "profiles": self.profiles.names().collect::<Vec<_>>(),
+                    "default_profile": self.profiles.default_profile(),
+                }),
+            )),
+            (Method::Get, ["metrics"]) => {
+                let body = self.metrics.render();
+                let length = body.len();
+                Ok(respond(
+                    200,
+                    "text/plain; version=0.0.4; charset=utf-8",
+                    Box::new(io::Cursor::new(body)),
+                    length,
+                ))
+            }
+            _ => Ok(api_error(404, "not_found", format!("No route for {path}"))),
+        };
+
+        result.unwrap_or_else(|e| {
+            error!("Request {path} failed: {e:#}");
+            api_error(500, error::error_code(&e), format!("{e:#}"))
+        })
+    }
+
+    fn submit(&self, request: &mut Request, query: &str) -> Result<ApiResponse> {
+        let options = match self.job_options(query) {
+            Ok(options) => options,
+            Err(violation) => return Ok(violation_response(violation)),
+        };
+        let length = request.body_length.unwrap_or_default() as u64;
+        if let Err(violation) = self.policy.check_upload_size(length) {
+            return Ok(violation_response(violation));
+        }
+
+        let id = self.queue.next_id()?;
+        let input = self.data_dir.join("uploads").join(format!("{id}.siq"));
+        let partial = input.with_extension("part");
+
+        let mut file = File::create(&partial).map_err(|source| SicomError::Io {
+            path: partial.clone(),
+            source,
+        })?;
+        if let Err(violation) = receive_upload(&mut request.body(), length, &mut file, &self.policy)
+        {
+            let _ = fs::remove_file(&partial);
+            return Ok(violation_response(violation));
+        }
+        fs::rename(&partial, &input).map_err(|source| SicomError::Io {
+            path: input.clone(),
+            source,
+        })?;
+
+        let output = self.data_dir.join("results").join(format!("{id}.siq"));
+        let job = self.queue.submit(id, input, output, options)?;
+        info!("Queued job {} ({} bytes)", job.id, job.input_size);
+        Ok(json(202, &JobStatus::from(&job)))
+    }
+
+    /// Options for a new job: the selected profile, then any quality parameters
+    fn job_options(&self, query: &str) -> Result<CompressOptions, PolicyViolation> {
+        let mut profile = None;
+        let parameters: Vec<&str> = query
+            .split('&')
+            .filter(|pair| match pair.strip_prefix("profile=") {
+                Some(name) => {
+                    profile = Some(name);
+                    false
+                }
+                None => !pair.is_empty(),
+            })
+            .collect();
+
+        let options = self.profiles.resolve(profile, &self.compress_options)?;
+        if !parameters.is_empty() && !self.profiles.allow_parameters {
+            return Err(PolicyViolation {
+                status: 400,
+                code: "parameter_not_allowed",
+                message: "Only profile selection is allowed on this server".to_string(),
+                retry_after: None,
+            });
+        }
+        self.policy.apply_query(&parameters.join("&"), options)
+    }
+
+    fn status(&self, id: &str) -> Result<ApiResponse> {
+        Ok(match self.queue.get(id)? {
+            Some(job) => json(200, &JobStatus::from(&job)),
+            None => api_error(404, "job_not_found", format!("Unknown job: {id}")),
+        })
+    }
+
+    fn result(&self, id: &str) -> Result<ApiResponse> {
+        let Some(job) = self.queue.get(id)? else {
+            return Ok(api_error(
+                404,
+                "job_not_found",
+                format!("Unknown job: {id}"),
+            ));
+        };
+        if job.state != JobState::Succeeded {
+            return Ok(api_error(
+                409,
+                "job_not_finished",
+                format!("Job {id} is {}", job.state.as_str()),
+            ));
+        }
+
+        let file = File::open(&job.output).map_err(|source| SicomError::Io {
+            path: job.output.clone(),
+            source,
+        })?;
+        let length = file
+            .metadata()
+            .map(|m| m.len() as usize)
+            .unwrap_or_default();
+        Ok(respond(
+            200,
+            "application/octet-stream",
+            Box::new(file),
+            length,
+        ))
+    }
+}
+
+/// Background thread compressing queued jobs one at a time
+struct Worker {
+    queue: Arc<JobQueue>,
+    data_dir: PathBuf,
+    job_retention: Duration,
+    metrics: Arc<Metrics>,
+    multi_progress: MultiProgress,
+    /// ffmpeg lookup and video probes kept between jobs
+    warm_pool: WarmPool,
+}
+
+impl Worker {
+    fn run(self) {
+        let mut pruned_at: Option<Instant> = None;
+        loop {
+            if pruned_at.is_none_or(|at| at.elapsed() >= PRUNE_INTERVAL) {
+                self.prune();
+                pruned_at = Some(Instant::now());
+            }
+            match self.queue.claim_next() {
+                Ok(Some(job)) => {
+                    if let Err(e) = self.process(&job) {
+                        error!("Failed to update job {}: {e:#}", job.id);
+                    }
+                }
+                Ok(None) => thread::sleep(IDLE_POLL),
+                Err(e) => {
+                    error!("Failed to read job queue: {e:#}");
+                    thread::sleep(IDLE_POLL);
+                }
+            }
+        }
+    }
+
+    /// Forget finished jobs past their retention and delete their files
+    fn prune(&self) {
+        match self.queue.prune(self.job_retention) {
+            Ok(jobs) => {
+                for job in jobs {
+                    debug!("Deleting expired job {}", job.id);
+                    let _ = fs::remove_file(&job.input);
+                    let _ = fs::remove_file(&job.output);
+                }
+            }
+            Err(e) => error!("Failed to prune job queue: {e:#}"),
+        }
+    }
+
+    fn process(&self, job: &Job) -> Result<()> {
+        info!("Compressing job {} (attempt {})", job.id, job.attempts);
+        let partial = self
+            .data_dir
+            .join("results")
+            .join(format!("{}.part", job.id));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            PackCompressor::new(job.options.clone())
+                .multi_progress(self.multi_progress.clone())
+                .warm_pool(self.warm_pool.clone())
+                .compress(job.input.clone(), Some(partial.clone()))
+        }))
+        .unwrap_or_else(|_| Err(anyhow!("Compression panicked")));
+
+        match result.and_then(|stats| {
+            fs::rename(&partial, &job.output).map_err(|source| SicomError::Io {
+                path: job.output.clone(),
+                source,
+            })?;
+            Ok(stats)
+        }) {
+            Ok(stats) => {
+                self.metrics.record_pack(&stats);
+                let output_size = fs::metadata(&job.output).map(|m| m.len()).unwrap_or(0);
+                self.queue.complete(&job.id, output_size)?;
+                let _ = fs::remove_file(&job.input);
+                info!("Job {} succeeded", job.id);
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                let code = error::error_code(&e);
+                self.metrics.record_failure(code);
+                let failure = JobFailure {
+                    code: code.to_string(),
+                    message: format!("{e:#}"),
+                };
+                let job = self.queue.fail(&job.id, failure, error::is_transient(&e))?;
+                if job.state == JobState::Failed {
+                    error!(
+                        "Job {} failed after {} attempts: {e:#}",
+                        job.id, job.attempts
+                    );
+                    let _ = fs::remove_file(&job.input);
+                } else {
+                    warn!("Job {} failed, will retry: {e:#}", job.id);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use indicatif::ProgressDrawTarget;
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Instant;
+
+    fn start(dir: &std::path::Path, policy: ApiPolicy) -> SocketAddr {
+        start_with_profiles(dir, policy, Profiles::default())
+    }
+
+    fn start_with_profiles(
+        dir: &std::path::Path,
+        policy: ApiPolicy,
+        profiles: Profiles,
+    ) -> SocketAddr {
+        let options = ServeOptions {
+            listen: "127.0.0.1:0".to_string(),
+            data_dir: dir.to_path_buf(),
+            max_attempts: 1,
+            retry_backoff: Duration::ZERO,
+            job_retention: Duration::from_secs(3600),
+            policy,
+            profiles,
+        };
+        let server = Server::bind(
+            &options,
+            CompressOptions::new().skip_video(true),
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        )
+        .unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        address
+    }
+
+    /// Minimal HTTP/1.1 client: returns the status and body
+    fn http(address: SocketAddr, method: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap();
+        let head = String::from_utf8_lossy(&response[..split]);
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, response[split + 4..].to_vec())
+    }
+
+    fn http_json(
+        address: SocketAddr,
+        method: &str,
+        path: &str,
+        body: &[u8],
+    ) -> (u16, serde_json::Value) {
+        let (status, body) = http(address, method, path, body);
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn wait_for_job(address: SocketAddr, id: &str) -> serde_json::Value {
+        let started = Instant::now();
+        loop {
+            let (_, job) = http_json(address, "GET", &format!("/jobs/{id}"), b"");
+            if job["state"] == "succeeded" || job["state"] == "failed" {
+                return job;
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "job never finished"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    fn pack_bytes() -> Vec<u8> {
+        test_support::pack_bytes(&[("content.xml", test_support::EMPTY_PACKAGE)])
+    }
+
+    #[test]
+    fn test_job_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let address = start(dir.path(), ApiPolicy::default());
+
+        let (status, job) = http_json(address, "POST", "/jobs", &pack_bytes());
+        assert_eq!(status, 202);
+        assert_eq!(job["state"], "queued");
+        let id = job["id"].as_str().unwrap().to_string();
+
+        let job = wait_for_job(address, &id);
+        assert_eq!(job["state"], "succeeded");
+        assert_eq!(job["attempts"], 1);
+        assert!(job.get("input").is_none());
+
+        let (status, body) = http(address, "GET", &format!("/jobs/{id}/result"), b"");
+        assert_eq!(status, 200);
+        assert!(body.starts_with(b"PK"));
+
+        let (_, metrics) = http(address, "GET", "/metrics", b"");
+        assert!(String::from_utf8_lossy(&metrics).contains("sicom_packs_processed_total 1\n"));
+
+        let (status, capabilities) = http_json(address, "GET", "/capabilities", b"");
+        assert_eq!(status, 200);
+        assert_eq!(capabilities["formats"][0]["kind"], "image");
+    }
+
+    #[test]
+    fn test_failed_job_and_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let address = start(dir.path(), ApiPolicy::default());
+
+        let (_, job) = http_json(address, "POST", "/jobs", b"not a zip");
+        let id = job["id"].as_str().unwrap().to_string();
+        let job = wait_for_job(address, &id);
+        assert_eq!(job["state"], "failed");
+        assert_eq!(job["error"]["code"], "archive_error");
+
+        let (status, body) = http_json(address, "GET", &format!("/jobs/{id}/result"), b"");
+        assert_eq!(status, 409);
+        assert_eq!(body["error"]["code"], "job_not_finished");
+
+        let (status, body) = http_json(address, "GET", "/jobs/unknown", b"");
+        assert_eq!(status, 404);
+        assert_eq!(body["error"]["code"], "job_not_found");
+    }
+
+    #[test]
+    fn test_policy_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = ApiPolicy {
+            requests_per_minute: Some(3),
+            max_upload_bytes: Some(16),
+            allowed_quality: 20..=80,
+            ..ApiPolicy::default()
+        };
+        let address = start(dir.path(), policy);
+
+        let (status, body) = http_json(address, "POST", "/jobs", &[0; 17]);
+        assert_eq!(status, 413);
+        assert_eq!(body["error"]["code"], "upload_too_large");
+
+        let (status, body) = http_json(address, "POST", "/jobs?quality=95", b"");
+        assert_eq!(status, 400);
+        assert_eq!(body["error"]["code"], "quality_not_allowed");
+
+        let (status, _) = http_json(address, "GET", "/jobs/unknown", b"");
+        assert_eq!(status, 404);
+        let (status, body) = http_json(address, "GET", "/jobs/unknown", b"");
+        assert_eq!(status, 429);
+        assert_eq!(body["error"]["code"], "rate_limited");
+
+        // Metrics stay reachable for monitoring
+        let (status, _) = http(address, "GET", "/metrics", b"");
+        assert_eq!(status, 200);
+    }
+
+    #[test]
+    fn test_stalled_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let address = start(dir.path(), ApiPolicy::default());
+
+        // An upload that never sends its body must not hold up other clients
+        let mut stalled = TcpStream::connect(address).unwrap();
+        write!(
+            stalled,
+            "POST /jobs HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1000\r\n\r\nPK"
+        )
+        .unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        let (status, body) = http_json(address, "GET", "/jobs/unknown", b"");
+        assert_eq!(status, 404);
+        assert_eq!(body["error"]["code"], "job_not_found");
+        drop(stalled);
+    }
+
+    #[test]
+    fn test_upload_without_body_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = ApiPolicy {
+            upload_timeout: Some(Duration::from_secs(1)),
+            ..ApiPolicy::default()
+        };
+        let address = start(dir.path(), policy);
+
+        // Headers only: the body never starts
+        let stalled = |length: usize| {
+            let started = Instant::now();
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            write!(
+                stream,
+                "POST /jobs HTTP/1.1\r\nHost: localhost\r\nContent-Length: {length}\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+            let mut response = Vec::new();
+            let _ = stream.read_to_end(&mut response);
+            assert!(started.elapsed() < Duration::from_secs(3));
+            String::from_utf8_lossy(&response).into_owned()
+        };
+        for length in [100, 100_000] {
+            let response = stalled(length);
+            assert!(response.starts_with("HTTP/1.1 408"), "{response}");
+            assert!(response.contains("upload_timeout"));
+        }
+        // The server keeps accepting connections afterwards
+        let (status, _) = http(address, "GET", "/jobs/unknown", b"");
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles: Profiles = serde_json::from_str(
+            r#"{"allow_parameters": false, "profiles": {"telegram": {"image_quality": 30}}}"#,
+        )
+        .unwrap();
+        let address = start_with_profiles(dir.path(), ApiPolicy::default(), profiles);
+
+        let (status, body) = http_json(address, "GET", "/profiles", b"");
+        assert_eq!(status, 200);
+        assert_eq!(body["profiles"], serde_json::json!(["telegram"]));
+
+        let (status, job) = http_json(address, "POST", "/jobs?profile=telegram", &pack_bytes());
+        assert_eq!(status, 202);
+        let job = wait_for_job(address, job["id"].as_str().unwrap());
+        assert_eq!(job["state"], "succeeded");
+
+        let (status, body) = http_json(address, "POST", "/jobs?profile=archive", b"");
+        assert_eq!(status, 400);
+        assert_eq!(body["error"]["code"], "unknown_profile");
+
+        let (status, body) = http_json(address, "POST", "/jobs?profile=telegram&quality=90", b"");
+        assert_eq!(status, 400);
+        assert_eq!(body["error"]["code"], "parameter_not_allowed");
+    }
+}
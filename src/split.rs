@@ -0,0 +1,238 @@
+use crate::error::SicomError;
+use anyhow::Result;
+use log::info;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Suffix of the first part, which `join_parts` expects
+const FIRST_PART_SUFFIX: &str = ".001";
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> SicomError + '_ {
+    |source| SicomError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// `path` with `suffix` appended to the file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Checksum list written next to the parts (pack.siq.blake3)
+pub fn checksum_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".blake3")
+}
+
+fn part_path(path: &Path, index: usize) -> PathBuf {
+    with_suffix(path, &format!(".{index:03}"))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Copy up to `limit` bytes, feeding them to every hasher
+fn copy_hashed(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    hashers: &mut [&mut blake3::Hasher],
+    limit: u64,
+) -> std::io::Result<u64> {
+    let mut buffer = vec![0; 64 * 1024];
+    let mut copied = 0;
+    while copied < limit {
+        let wanted = buffer
+            .len()
+            .min(usize::try_from(limit - copied).unwrap_or(usize::MAX));
+        let read = reader.read(&mut buffer[..wanted])?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..read])?;
+        for hasher in hashers.iter_mut() {
+            hasher.update(&buffer[..read]);
+        }
+        copied += read as u64;
+    }
+    Ok(copied)
+}
+
+/// Split a file into `part_size` parts (pack.siq.001, .002, ...) and remove it
+///
+/// Part checksums and the checksum of the whole file are written to
+/// `checksum_path` in b3sum format. A file that already fits into one part is left as it is.
+pub fn split_file(path: &Path, part_size: u64) -> Result<Vec<PathBuf>> {
+    if part_size == 0 {
+        return Err(SicomError::InvalidOptions("Part size must be above 0".to_string()).into());
+    }
+    let total_size = std::fs::metadata(path).map_err(io_error(path))?.len();
+    if total_size <= part_size {
+        info!("Output fits into one part, not splitting");
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut reader = BufReader::new(File::open(path).map_err(io_error(path))?);
+    let mut whole = blake3::Hasher::new();
+    let mut parts = Vec::new();
+    let mut checksums = String::new();
+    for index in 1..=total_size.div_ceil(part_size) {
+        let part = part_path(path, usize::try_from(index)?);
+        let mut writer = BufWriter::new(File::create(&part).map_err(io_error(&part))?);
+        let mut hasher = blake3::Hasher::new();
+        copy_hashed(
+            &mut reader,
+            &mut writer,
+            &mut [&mut hasher, &mut whole],
+            part_size,
+        )
+        .and_then(|_| writer.flush())
+        .map_err(io_error(&part))?;
+        checksums.push_str(&format!("{}  {}\n", hasher.finalize(), file_name(&part)));
+        parts.push(part);
+    }
+    checksums.push_str(&format!("{}  {}\n", whole.finalize(), file_name(path)));
+
+    let checksum_file = checksum_path(path);
+    std::fs::write(&checksum_file, checksums).map_err(io_error(&checksum_file))?;
+    std::fs::remove_file(path).map_err(io_error(path))?;
+    info!(
+        "Split {} into {} parts (checksums in {})",
+        path.display(),
+        parts.len(),
+        checksum_file.display()
+    );
+    Ok(parts)
+}
+
+/// Rejoin the parts written by `split_file`, given the first one (pack.siq.001)
+///
+/// Every part and the joined file are checked against the checksum list; the
+/// output defaults to the original file name and is removed on a mismatch.
+pub fn join_parts(first_part: &Path, output: Option<PathBuf>) -> Result<PathBuf> {
+    let base = first_part
+        .to_str()
+        .and_then(|path| path.strip_suffix(FIRST_PART_SUFFIX))
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            SicomError::InvalidOptions(format!(
+                "Expected the first part ({FIRST_PART_SUFFIX}), got {}",
+                first_part.display()
+            ))
+        })?;
+    let directory = base.parent().unwrap_or_else(|| Path::new(""));
+    let checksum_file = checksum_path(&base);
+
+    // One "<hash>  <file>" line per part, then one for the whole file
+    let listing = File::open(&checksum_file).map_err(io_error(&checksum_file))?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(listing).lines() {
+        let line = line.map_err(io_error(&checksum_file))?;
+        if let Some((hash, name)) = line.split_once("  ") {
+            let hash = blake3::Hash::from_hex(hash.trim())
+                .map_err(|_| SicomError::ChecksumMismatch(checksum_file.clone()))?;
+            entries.push((hash, name.to_string()));
+        }
+    }
+    let Some((whole_hash, _)) = entries.pop() else {
+        return Err(SicomError::ChecksumMismatch(checksum_file).into());
+    };
+
+    let output = output.unwrap_or_else(|| base.clone());
+    let mut writer = BufWriter::new(File::create(&output).map_err(io_error(&output))?);
+    let mut whole = blake3::Hasher::new();
+    let joined = (|| -> Result<()> {
+        for (hash, name) in &entries {
+            let part = directory.join(name);
+            let mut reader = BufReader::new(File::open(&part).map_err(io_error(&part))?);
+            let mut hasher = blake3::Hasher::new();
+            copy_hashed(
+                &mut reader,
+                &mut writer,
+                &mut [&mut hasher, &mut whole],
+                u64::MAX,
+            )
+            .map_err(io_error(&part))?;
+            if hasher.finalize() != *hash {
+                return Err(SicomError::ChecksumMismatch(part).into());
+            }
+        }
+        writer.flush().map_err(io_error(&output))?;
+        if whole.finalize() != whole_hash {
+            return Err(SicomError::ChecksumMismatch(output.clone()).into());
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = joined {
+        drop(writer);
+        let _ = std::fs::remove_file(&output);
+        return Err(e);
+    }
+    info!("Joined {} parts into {}", entries.len(), output.display());
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.siq");
+        let data = sample(10_000);
+        std::fs::write(&path, &data).unwrap();
+
+        let parts = split_file(&path, 4096).unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], dir.path().join("pack.siq.001"));
+        assert_eq!(
+            std::fs::metadata(&parts[2]).unwrap().len(),
+            10_000 - 2 * 4096
+        );
+        assert!(!path.exists());
+        let checksums = std::fs::read_to_string(checksum_path(&path)).unwrap();
+        assert_eq!(checksums.lines().count(), 4);
+        assert!(checksums.ends_with("  pack.siq\n"));
+
+        assert_eq!(join_parts(&parts[0], None).unwrap(), path);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+
+        // Fits into one part
+        let small = dir.path().join("small.siq");
+        std::fs::write(&small, b"tiny").unwrap();
+        assert_eq!(split_file(&small, 4096).unwrap(), [small.as_path()]);
+        assert!(small.exists());
+    }
+
+    #[test]
+    fn test_join_detects_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.siq");
+        std::fs::write(&path, sample(5_000)).unwrap();
+        let parts = split_file(&path, 2048).unwrap();
+
+        let mut part = std::fs::read(&parts[1]).unwrap();
+        part[10] ^= 0xff;
+        std::fs::write(&parts[1], part).unwrap();
+
+        let error = join_parts(&parts[0], None).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<SicomError>(),
+            Some(SicomError::ChecksumMismatch(part)) if part == &parts[1]
+        ));
+        assert!(!path.exists());
+
+        assert!(join_parts(&path, None).is_err());
+    }
+}
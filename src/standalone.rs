@@ -0,0 +1,154 @@
+use crate::error::SicomError;
+use crate::media::MediaKind;
+use crate::options::CompressOptions;
+use crate::progress::ProgressLogger;
+use crate::{build_registry, format_size, prepare_ffmpeg};
+use anyhow::{Result, anyhow};
+use indicatif::MultiProgress;
+use log::info;
+use std::path::{Path, PathBuf};
+
+/// Default output path next to the input, named after the processor output
+///
+/// `photo.jpg` becomes `photo.webp`; when the extension doesn't change
+/// (`intro.mp3`) a `_compressed` suffix keeps the original intact.
+fn default_output_path(input: &Path, planned_name: &str) -> PathBuf {
+    let planned = Path::new(planned_name);
+    if planned == input {
+        let stem = input
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        let file_name = match input.extension().and_then(|s| s.to_str()) {
+            Some(ext) => format!("{stem}_compressed.{ext}"),
+            None => format!("{stem}_compressed"),
+        };
+        input.with_file_name(file_name)
+    } else {
+        planned.to_path_buf()
+    }
+}
+
+/// Compress a single media file outside of a pack using the pack pipelines
+pub fn compress_media_file(
+    input: &Path,
+    output: Option<PathBuf>,
+    mut options: CompressOptions,
+    multi_progress: &MultiProgress,
+) -> Result<()> {
+    if !input.exists() {
+        return Err(SicomError::InputNotFound(input.to_path_buf()).into());
+    }
+    options.validate()?;
+
+    let input_name = input.to_string_lossy().to_string();
+    let data = std::fs::read(input).map_err(|source| SicomError::Io {
+        path: input.to_path_buf(),
+        source,
+    })?;
+    let original_size = data.len() as u64;
+
+    let ffmpeg_available = prepare_ffmpeg(&mut options);
+    let registry = build_registry(&options, ffmpeg_available);
+
+    let (processor, disabled_reason) = registry
+        .find_standalone(&input_name)
+        .ok_or_else(|| anyhow!("Unsupported media file: {input:?}"))?;
+    if processor.kind() == MediaKind::Video && !ffmpeg_available && !options.skip_video {
+        return Err(SicomError::FfmpegNotFound(options.ffmpeg_path.clone()).into());
+    }
+    if let Some(reason) = disabled_reason {
+        return Err(anyhow!(
+            "{} compression is disabled ({reason})",
+            processor.kind().label()
+        ));
+    }
+
+    let mut logger = ProgressLogger::new(1, multi_progress);
+    let result = processor.compress(&data, &input_name, &mut logger);
+    logger.finish();
+    let compressed = result.map_err(|source| SicomError::MediaEncode {
+        kind: processor.kind(),
+        name: input_name.clone(),
+        source,
+    })?;
+    let compressed_size = compressed.data.len() as u64;
+
+    if compressed_size >= original_size && !options.always_compress {
+        info!(
+            "Keeping original (compressed would be larger): {} vs {}",
+            format_size(original_size),
+            format_size(compressed_size)
+        );
+        return Ok(());
+    }
+
+    let output_path = output.unwrap_or_else(|| default_output_path(input, &compressed.filename));
+    std::fs::write(&output_path, &compressed.data).map_err(|source| SicomError::Io {
+        path: output_path.clone(),
+        source,
+    })?;
+
+    info!(
+        "Compressed to {}: {} -> {} ({:.1}% reduction)",
+        compressed.codec,
+        format_size(original_size),
+        format_size(compressed_size),
+        (1.0 - compressed_size as f64 / original_size as f64) * 100.0
+    );
+    info!("Output to: {output_path:?}");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::png;
+
+    #[test]
+    fn test_default_output_path() {
+        assert_eq!(
+            default_output_path(Path::new("assets/photo.jpg"), "assets/photo.webp"),
+            PathBuf::from("assets/photo.webp")
+        );
+        assert_eq!(
+            default_output_path(Path::new("assets/intro.mp3"), "assets/intro.mp3"),
+            PathBuf::from("assets/intro_compressed.mp3")
+        );
+        assert_eq!(
+            default_output_path(Path::new("photo.webp"), "photo.webp"),
+            PathBuf::from("photo_compressed.webp")
+        );
+    }
+
+    #[test]
+    fn test_compress_standalone_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("gradient.png");
+        let png = png(64, 64);
+        std::fs::write(&input, &png).unwrap();
+
+        compress_media_file(
+            &input,
+            None,
+            CompressOptions::new().skip_video(true),
+            &MultiProgress::new(),
+        )
+        .unwrap();
+
+        let output = std::fs::read(dir.path().join("gradient.webp")).unwrap();
+        assert_eq!(&output[8..12], b"WEBP");
+    }
+
+    #[test]
+    fn test_compress_standalone_unsupported() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("notes.txt");
+        std::fs::write(&input, b"hello").unwrap();
+
+        let result =
+            compress_media_file(&input, None, CompressOptions::new(), &MultiProgress::new());
+        assert!(result.is_err());
+    }
+}
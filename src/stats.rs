@@ -1,31 +1,64 @@
+use crate::media::MediaKind;
+use crate::verify::Problem;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Percentage saved going from `original` to `compressed` bytes
+///
+/// Negative when the output grew, 0 for empty input. The difference is taken
+/// in integers first so huge totals keep their precision.
+pub fn reduction_percent(original: u64, compressed: u64) -> f64 {
+    if original == 0 {
+        return 0.0;
+    }
+    let percent = |delta: u64| delta as f64 / original as f64 * 100.0;
+    if compressed <= original {
+        percent(original - compressed)
+    } else {
+        -percent(compressed - original)
+    }
+}
+
+/// Counters for one media kind
+///
+/// All counters saturate instead of wrapping, so very long batch runs
+/// can't overflow in release builds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MediaStats {
+    pub processed: u64,
+    pub skipped: u64,
+    pub kept_original: u64,
+    pub deduplicated: u64,
+    pub pruned: u64,
+    pub original_size: u64,
+    pub compressed_size: u64,
+}
+
+impl MediaStats {
+    pub fn compression_ratio(&self) -> f64 {
+        reduction_percent(self.original_size, self.compressed_size)
+    }
+}
+
 /// Statistics tracking for compression operations
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct CompressionStats {
-    // Image statistics
-    images_processed: u32,
-    images_skipped: u32,
-    images_kept_original: u32,
-    image_original_size: u64,
-    image_compressed_size: u64,
-
-    // Audio statistics
-    audio_processed: u32,
-    audio_skipped: u32,
-    audio_kept_original: u32,
-    audio_original_size: u64,
-    audio_compressed_size: u64,
-
-    // Video statistics
-    video_processed: u32,
-    video_skipped: u32,
-    video_kept_original: u32,
-    video_original_size: u64,
-    video_compressed_size: u64,
+    images: MediaStats,
+    audio: MediaStats,
+    video: MediaStats,
+    /// Audio re-encoded from WAV and FLAC, also counted in `audio`
+    lossless_audio: MediaStats,
+
+    // Time spent in each encoder call, by kind (for latency metrics)
+    encode_times: [Vec<Duration>; 3],
 
     // Overall statistics
     total_input_size: u64,
     total_output_size: u64,
-    total_updated_refs: u32,
+    total_updated_refs: u64,
+    /// content.xml references to files the input pack doesn't have
+    missing_references: Vec<Problem>,
 }
 
 impl CompressionStats {
@@ -33,177 +66,390 @@ impl CompressionStats {
         Self::default()
     }
 
-    // Image tracking methods
-    pub fn add_processed_image(&mut self, original_size: u64, compressed_size: u64) {
-        self.images_processed += 1;
-        self.image_original_size += original_size;
-        self.image_compressed_size += compressed_size;
-        self.total_input_size += original_size;
-        self.total_output_size += compressed_size;
+    pub const fn lossless_audio(&self) -> &MediaStats {
+        &self.lossless_audio
+    }
+
+    pub const fn media(&self, kind: MediaKind) -> &MediaStats {
+        match kind {
+            MediaKind::Image => &self.images,
+            MediaKind::Audio => &self.audio,
+            MediaKind::Video => &self.video,
+        }
+    }
+
+    const fn media_mut(&mut self, kind: MediaKind) -> &mut MediaStats {
+        match kind {
+            MediaKind::Image => &mut self.images,
+            MediaKind::Audio => &mut self.audio,
+            MediaKind::Video => &mut self.video,
+        }
+    }
+
+    fn add_totals(&mut self, input_size: u64, output_size: u64) {
+        self.total_input_size = self.total_input_size.saturating_add(input_size);
+        self.total_output_size = self.total_output_size.saturating_add(output_size);
+    }
+
+    // Media tracking methods
+    pub fn add_processed(&mut self, kind: MediaKind, original_size: u64, compressed_size: u64) {
+        let media = self.media_mut(kind);
+        media.processed = media.processed.saturating_add(1);
+        media.original_size = media.original_size.saturating_add(original_size);
+        media.compressed_size = media.compressed_size.saturating_add(compressed_size);
+        self.add_totals(original_size, compressed_size);
     }
 
-    pub fn add_kept_original_image(&mut self, size: u64) {
-        self.images_kept_original += 1;
-        self.image_original_size += size;
-        self.image_compressed_size += size;
-        self.total_input_size += size;
-        self.total_output_size += size;
+    /// Account audio already added with [`Self::add_processed`] that came from a lossless source
+    pub fn add_lossless_audio(&mut self, original_size: u64, compressed_size: u64) {
+        let media = &mut self.lossless_audio;
+        media.processed = media.processed.saturating_add(1);
+        media.original_size = media.original_size.saturating_add(original_size);
+        media.compressed_size = media.compressed_size.saturating_add(compressed_size);
     }
 
-    pub fn add_skipped_image(&mut self, size: u64) {
-        self.images_skipped += 1;
-        self.image_original_size += size;
-        self.image_compressed_size += size;
-        self.total_input_size += size;
-        self.total_output_size += size;
+    pub fn add_kept_original(&mut self, kind: MediaKind, size: u64) {
+        let media = self.media_mut(kind);
+        media.kept_original = media.kept_original.saturating_add(1);
+        self.add_unchanged(kind, size);
     }
 
-    // Audio tracking methods
-    pub fn add_processed_audio(&mut self, original_size: u64, compressed_size: u64) {
-        self.audio_processed += 1;
-        self.audio_original_size += original_size;
-        self.audio_compressed_size += compressed_size;
-        self.total_input_size += original_size;
-        self.total_output_size += compressed_size;
+    pub fn add_skipped(&mut self, kind: MediaKind, size: u64) {
+        let media = self.media_mut(kind);
+        media.skipped = media.skipped.saturating_add(1);
+        self.add_unchanged(kind, size);
     }
 
-    pub fn add_kept_original_audio(&mut self, size: u64) {
-        self.audio_kept_original += 1;
-        self.audio_original_size += size;
-        self.audio_compressed_size += size;
-        self.total_input_size += size;
-        self.total_output_size += size;
+    /// Account a media file copied to the output as-is
+    fn add_unchanged(&mut self, kind: MediaKind, size: u64) {
+        let media = self.media_mut(kind);
+        media.original_size = media.original_size.saturating_add(size);
+        media.compressed_size = media.compressed_size.saturating_add(size);
+        self.add_totals(size, size);
     }
 
-    pub fn add_skipped_audio(&mut self, size: u64) {
-        self.audio_skipped += 1;
-        self.audio_original_size += size;
-        self.audio_compressed_size += size;
-        self.total_input_size += size;
-        self.total_output_size += size;
+    /// Account media dropped in favour of a similar file (input only)
+    pub fn add_deduplicated(&mut self, kind: MediaKind, size: u64) {
+        let media = self.media_mut(kind);
+        media.deduplicated = media.deduplicated.saturating_add(1);
+        media.original_size = media.original_size.saturating_add(size);
+        self.add_totals(size, 0);
     }
 
-    // Video tracking methods
-    pub fn add_processed_video(&mut self, original_size: u64, compressed_size: u64) {
-        self.video_processed += 1;
-        self.video_original_size += original_size;
-        self.video_compressed_size += compressed_size;
-        self.total_input_size += original_size;
-        self.total_output_size += compressed_size;
+    /// Account media left out because nothing references it (input only)
+    pub fn add_pruned(&mut self, kind: MediaKind, size: u64) {
+        let media = self.media_mut(kind);
+        media.pruned = media.pruned.saturating_add(1);
+        media.original_size = media.original_size.saturating_add(size);
+        self.add_totals(size, 0);
     }
 
-    pub fn add_kept_original_video(&mut self, size: u64) {
-        self.video_kept_original += 1;
-        self.video_original_size += size;
-        self.video_compressed_size += size;
-        self.total_input_size += size;
-        self.total_output_size += size;
+    pub fn add_encode_time(&mut self, kind: MediaKind, elapsed: Duration) {
+        self.encode_times[kind as usize].push(elapsed);
     }
 
-    pub fn add_skipped_video(&mut self, size: u64) {
-        self.video_skipped += 1;
-        self.video_original_size += size;
-        self.video_compressed_size += size;
-        self.total_input_size += size;
-        self.total_output_size += size;
+    pub fn encode_times(&self, kind: MediaKind) -> &[Duration] {
+        &self.encode_times[kind as usize]
     }
 
     // Other file tracking
     pub fn add_other_file(&mut self, size: u64) {
-        self.total_input_size += size;
-        self.total_output_size += size;
+        self.add_totals(size, size);
     }
 
-    pub fn add_updated_refs(&mut self, count: u32) {
-        self.total_updated_refs += count;
+    /// Account a file the input doesn't have (output only)
+    pub fn add_extra_file(&mut self, size: u64) {
+        self.add_totals(0, size);
     }
 
-    // Calculation methods
-    pub fn total_compression_ratio(&self) -> f64 {
-        if self.total_input_size > 0 {
-            (1.0 - self.total_output_size as f64 / self.total_input_size as f64) * 100.0
-        } else {
-            0.0
-        }
+    pub fn add_updated_refs(&mut self, count: u64) {
+        self.total_updated_refs = self.total_updated_refs.saturating_add(count);
     }
 
-    pub fn image_compression_ratio(&self) -> f64 {
-        if self.image_original_size > 0 {
-            (1.0 - self.image_compressed_size as f64 / self.image_original_size as f64) * 100.0
-        } else {
-            0.0
+    /// Fold one event into the counters
+    pub fn apply(&mut self, event: &StatsEvent) {
+        match *event {
+            StatsEvent::FileProcessed {
+                kind,
+                original_size,
+                compressed_size,
+                lossless_source,
+            } => {
+                self.add_processed(kind, original_size, compressed_size);
+                if lossless_source {
+                    self.add_lossless_audio(original_size, compressed_size);
+                }
+            }
+            StatsEvent::FileSkipped { kind, size, reason } => match reason {
+                SkipReason::Skipped => self.add_skipped(kind, size),
+                SkipReason::KeptOriginal => self.add_kept_original(kind, size),
+                SkipReason::Deduplicated => self.add_deduplicated(kind, size),
+                SkipReason::Pruned => self.add_pruned(kind, size),
+            },
+            StatsEvent::OtherFile { size } => self.add_other_file(size),
+            StatsEvent::ExtraFile { size } => self.add_extra_file(size),
+            StatsEvent::RefUpdated { count } => self.add_updated_refs(count),
+            StatsEvent::StageTiming { kind, elapsed } => self.add_encode_time(kind, elapsed),
         }
     }
 
-    pub fn audio_compression_ratio(&self) -> f64 {
-        if self.audio_original_size > 0 {
-            (1.0 - self.audio_compressed_size as f64 / self.audio_original_size as f64) * 100.0
-        } else {
-            0.0
-        }
+    pub fn total_updated_refs(&self) -> u64 {
+        self.total_updated_refs
     }
 
-    pub fn video_compression_ratio(&self) -> f64 {
-        if self.video_original_size > 0 {
-            (1.0 - self.video_compressed_size as f64 / self.video_original_size as f64) * 100.0
-        } else {
-            0.0
-        }
+    pub fn missing_references(&self) -> &[Problem] {
+        &self.missing_references
     }
 
-    // Getter methods for public access to statistics
-    pub fn images_processed(&self) -> u32 {
-        self.images_processed
-    }
-    pub fn images_skipped(&self) -> u32 {
-        self.images_skipped
+    pub fn set_missing_references(&mut self, missing: Vec<Problem>) {
+        self.missing_references = missing;
     }
-    pub fn images_kept_original(&self) -> u32 {
-        self.images_kept_original
+
+    // Calculation methods
+    pub fn total_compression_ratio(&self) -> f64 {
+        reduction_percent(self.total_input_size, self.total_output_size)
     }
-    pub fn image_original_size(&self) -> u64 {
-        self.image_original_size
+
+    pub fn total_input_size(&self) -> u64 {
+        self.total_input_size
     }
-    pub fn image_compressed_size(&self) -> u64 {
-        self.image_compressed_size
+    pub fn total_output_size(&self) -> u64 {
+        self.total_output_size
     }
+}
 
-    pub fn audio_processed(&self) -> u32 {
-        self.audio_processed
-    }
-    pub fn audio_skipped(&self) -> u32 {
-        self.audio_skipped
+/// Why a media file didn't end up compressed in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Disabled, excluded or failed to compress; copied unchanged
+    Skipped,
+    /// Compressed, but the original was used (e.g. the result was larger)
+    KeptOriginal,
+    /// Dropped in favour of a near-identical file; nothing written
+    Deduplicated,
+    /// Unused media left out with `--prune-unused`; nothing written
+    Pruned,
+}
+
+/// What a worker reports to the [`StatsAggregator`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatsEvent {
+    /// Media replaced by its compressed version
+    FileProcessed {
+        kind: MediaKind,
+        original_size: u64,
+        compressed_size: u64,
+        /// Audio decoded from WAV or FLAC
+        lossless_source: bool,
+    },
+    FileSkipped {
+        kind: MediaKind,
+        size: u64,
+        reason: SkipReason,
+    },
+    /// A non-media file copied to the output
+    OtherFile { size: u64 },
+    /// A file written to the output that the input doesn't have
+    ExtraFile { size: u64 },
+    /// Media references rewritten in text entries
+    RefUpdated { count: u64 },
+    /// Time spent in one encoder call
+    StageTiming { kind: MediaKind, elapsed: Duration },
+}
+
+/// Handle workers send [`StatsEvent`]s through; cheap to clone
+#[derive(Debug, Clone)]
+pub struct StatsSender(Sender<StatsEvent>);
+
+impl StatsSender {
+    pub fn send(&self, event: StatsEvent) {
+        // The aggregator only stops once every sender is gone
+        let _ = self.0.send(event);
     }
-    pub fn audio_kept_original(&self) -> u32 {
-        self.audio_kept_original
+}
+
+/// Owner of a run's [`CompressionStats`]
+///
+/// Events from any number of workers are folded in one at a time on the
+/// aggregator's thread, which then hands the totals to `on_update` (the live
+/// progress display). [`Self::finish`] returns the same totals for the final
+/// summary, so the two can't disagree.
+pub struct StatsAggregator {
+    sender: StatsSender,
+    handle: JoinHandle<CompressionStats>,
+}
+
+impl StatsAggregator {
+    pub fn spawn(mut on_update: impl FnMut(&CompressionStats) + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel::<StatsEvent>();
+        let handle = thread::spawn(move || {
+            let mut stats = CompressionStats::new();
+            for event in receiver {
+                stats.apply(&event);
+                on_update(&stats);
+            }
+            stats
+        });
+        Self {
+            sender: StatsSender(sender),
+            handle,
+        }
     }
-    pub fn audio_original_size(&self) -> u64 {
-        self.audio_original_size
+
+    /// Another handle for a worker
+    pub fn sender(&self) -> StatsSender {
+        self.sender.clone()
     }
-    pub fn audio_compressed_size(&self) -> u64 {
-        self.audio_compressed_size
+
+    pub fn send(&self, event: StatsEvent) {
+        self.sender.send(event);
     }
 
-    pub fn video_processed(&self) -> u32 {
-        self.video_processed
+    /// Statistics of every event sent, once all other senders are dropped
+    pub fn finish(self) -> CompressionStats {
+        let Self { sender, handle } = self;
+        drop(sender);
+        handle
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
     }
-    pub fn video_skipped(&self) -> u32 {
-        self.video_skipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reduction_percent() {
+        assert_eq!(reduction_percent(0, 0), 0.0);
+        assert_eq!(reduction_percent(0, 500), 0.0);
+        assert_eq!(reduction_percent(1000, 250), 75.0);
+        assert_eq!(reduction_percent(1000, 1500), -50.0);
+        assert_eq!(reduction_percent(u64::MAX, 0), 100.0);
+        assert_eq!(reduction_percent(u64::MAX, u64::MAX), 0.0);
+        assert_eq!(
+            reduction_percent(1, u64::MAX),
+            -((u64::MAX - 1) as f64) * 100.0
+        );
     }
-    pub fn video_kept_original(&self) -> u32 {
-        self.video_kept_original
+
+    #[test]
+    fn test_reduction_percent_keeps_precision_for_large_totals() {
+        // 5 TB in, one byte saved: 1.0 - ratio would round to exactly 0
+        let original = 5_000_000_000_000;
+        assert!(reduction_percent(original, original - 1) > 0.0);
     }
-    pub fn video_original_size(&self) -> u64 {
-        self.video_original_size
+
+    #[test]
+    fn test_counters_saturate() {
+        let mut stats = CompressionStats::new();
+        stats.add_processed(MediaKind::Video, u64::MAX, u64::MAX - 10);
+        stats.add_processed(MediaKind::Video, 100, 50);
+        stats.add_other_file(u64::MAX);
+        stats.add_updated_refs(u64::MAX);
+        stats.add_updated_refs(1);
+
+        let video = stats.media(MediaKind::Video);
+        assert_eq!(video.processed, 2);
+        assert_eq!(video.original_size, u64::MAX);
+        assert_eq!(video.compressed_size, u64::MAX);
+        assert_eq!(stats.total_input_size(), u64::MAX);
+        assert_eq!(stats.total_output_size(), u64::MAX);
+        assert_eq!(stats.total_updated_refs, u64::MAX);
     }
-    pub fn video_compressed_size(&self) -> u64 {
-        self.video_compressed_size
+
+    #[test]
+    fn test_tracking_by_kind() {
+        let mut stats = CompressionStats::new();
+        stats.add_processed(MediaKind::Image, 1000, 400);
+        stats.add_kept_original(MediaKind::Image, 200);
+        stats.add_deduplicated(MediaKind::Image, 300);
+        stats.add_skipped(MediaKind::Audio, 500);
+        stats.add_other_file(100);
+
+        let images = stats.media(MediaKind::Image);
+        assert_eq!(images.processed, 1);
+        assert_eq!(images.kept_original, 1);
+        assert_eq!(images.deduplicated, 1);
+        assert_eq!(images.original_size, 1500);
+        assert_eq!(images.compressed_size, 600);
+        assert_eq!(images.compression_ratio(), 60.0);
+
+        assert_eq!(stats.media(MediaKind::Audio).skipped, 1);
+        assert_eq!(stats.media(MediaKind::Audio).compression_ratio(), 0.0);
+        assert_eq!(stats.media(MediaKind::Video), &MediaStats::default());
+
+        // Lossless sources are a breakdown of audio, not added to the totals
+        stats.add_processed(MediaKind::Audio, 1000, 100);
+        stats.add_lossless_audio(1000, 100);
+        assert_eq!(stats.lossless_audio().processed, 1);
+        assert_eq!(stats.lossless_audio().compression_ratio(), 90.0);
+        assert_eq!(stats.media(MediaKind::Audio).processed, 1);
+
+        assert_eq!(stats.total_input_size(), 3100);
+        assert_eq!(stats.total_output_size(), 1300);
     }
 
-    pub fn total_input_size(&self) -> u64 {
-        self.total_input_size
+    #[test]
+    fn test_encode_times_by_kind() {
+        let mut stats = CompressionStats::new();
+        stats.add_encode_time(MediaKind::Video, Duration::from_secs(3));
+        stats.add_encode_time(MediaKind::Video, Duration::from_secs(5));
+        stats.add_encode_time(MediaKind::Image, Duration::from_millis(20));
+
+        assert_eq!(stats.encode_times(MediaKind::Video).len(), 2);
+        assert_eq!(
+            stats.encode_times(MediaKind::Image),
+            [Duration::from_millis(20)]
+        );
+        assert!(stats.encode_times(MediaKind::Audio).is_empty());
     }
-    pub fn total_output_size(&self) -> u64 {
-        self.total_output_size
+
+    #[test]
+    fn test_aggregator_matches_live_updates() {
+        let live = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = live.clone();
+        let aggregator = StatsAggregator::spawn(move |stats| {
+            seen.lock()
+                .unwrap()
+                .push((stats.total_input_size(), stats.total_output_size()));
+        });
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let sender = aggregator.sender();
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        sender.send(StatsEvent::FileProcessed {
+                            kind: MediaKind::Audio,
+                            original_size: 100,
+                            compressed_size: 40,
+                            lossless_source: true,
+                        });
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        aggregator.send(StatsEvent::FileSkipped {
+            kind: MediaKind::Image,
+            size: 50,
+            reason: SkipReason::Deduplicated,
+        });
+        aggregator.send(StatsEvent::RefUpdated { count: 3 });
+        let stats = aggregator.finish();
+
+        assert_eq!(stats.media(MediaKind::Audio).processed, 100);
+        assert_eq!(stats.lossless_audio().processed, 100);
+        assert_eq!(stats.media(MediaKind::Image).deduplicated, 1);
+        assert_eq!(stats.total_updated_refs(), 3);
+        let live = live.lock().unwrap();
+        assert_eq!(live.len(), 102);
+        assert_eq!(
+            live.last(),
+            Some(&(stats.total_input_size(), stats.total_output_size()))
+        );
     }
 }
@@ -0,0 +1,169 @@
+use crate::error::SicomError;
+use crate::format_size;
+use crate::media::MediaKind;
+use crate::options::CompressOptions;
+use crate::pipeline::{compress_pack, default_output_path};
+use crate::stats::CompressionStats;
+use anyhow::Result;
+use indicatif::MultiProgress;
+use log::info;
+use std::path::PathBuf;
+
+/// Quality taken off the largest media kind per pass
+const QUALITY_STEP: u8 = 10;
+/// Lowest quality the search goes to
+const MIN_QUALITY: u8 = 10;
+
+/// Options for the next pass: the one of images and video taking up the most
+/// space in the output gets `QUALITY_STEP` less quality
+///
+/// None when both are at `MIN_QUALITY` (or absent from the pack).
+fn lower_largest(
+    options: &CompressOptions,
+    stats: &CompressionStats,
+) -> Option<(MediaKind, CompressOptions)> {
+    let (kind, quality) = [
+        (MediaKind::Video, options.video_quality),
+        (MediaKind::Image, options.image_quality),
+    ]
+    .into_iter()
+    .filter(|&(kind, quality)| quality > MIN_QUALITY && stats.media(kind).compressed_size > 0)
+    .max_by_key(|&(kind, _)| stats.media(kind).compressed_size)?;
+
+    let quality = quality.saturating_sub(QUALITY_STEP).max(MIN_QUALITY);
+    let options = match kind {
+        MediaKind::Video => options.clone().video_quality(quality),
+        _ => options.clone().image_quality(quality),
+    };
+    Some((kind, options))
+}
+
+/// Compress, lowering video and image quality pass by pass (largest
+/// contributor first) until the output is at most `max_size` bytes
+///
+/// Every pass compresses the whole pack again. An output that doesn't fit
+/// even at the lowest qualities is left in place and reported as an error.
+pub fn compress_to_size(
+    input_pack: PathBuf,
+    output_pack: Option<PathBuf>,
+    mut options: CompressOptions,
+    max_size: u64,
+    multi_progress: MultiProgress,
+) -> Result<CompressionStats> {
+    if options.lossless_only {
+        return Err(SicomError::InvalidOptions(
+            "--max-pack-size lowers quality and can't be used with --lossless-only".to_string(),
+        )
+        .into());
+    }
+    let output_pack = match output_pack {
+        Some(path) => path,
+        None => default_output_path(&input_pack)?,
+    };
+
+    loop {
+        let stats = compress_pack(
+            input_pack.clone(),
+            Some(output_pack.clone()),
+            options.clone(),
+            multi_progress.clone(),
+        )?;
+        let size = std::fs::metadata(&output_pack)
+            .map_err(|source| SicomError::Io {
+                path: output_pack.clone(),
+                source,
+            })?
+            .len();
+        if size <= max_size {
+            info!(
+                "Output is {}, within the {} limit",
+                format_size(size),
+                format_size(max_size)
+            );
+            return Ok(stats);
+        }
+
+        let Some((kind, lowered)) = lower_largest(&options, &stats) else {
+            return Err(SicomError::OutputTooLarge {
+                size,
+                limit: max_size,
+            }
+            .into());
+        };
+        options = lowered;
+        info!(
+            "Output is {}, over the {} limit; compressing again with {} quality {}",
+            format_size(size),
+            format_size(max_size),
+            kind.label(),
+            match kind {
+                MediaKind::Video => options.video_quality,
+                _ => options.image_quality,
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{encode_png, write_pack};
+    use indicatif::ProgressDrawTarget;
+
+    #[test]
+    fn test_lower_largest() {
+        let mut stats = CompressionStats::new();
+        stats.add_processed(MediaKind::Image, 5000, 1000);
+        stats.add_processed(MediaKind::Video, 50_000, 8000);
+        let options = CompressOptions::new().image_quality(40).video_quality(15);
+
+        // Video is largest, then hits the floor and images follow
+        let (kind, options) = lower_largest(&options, &stats).unwrap();
+        assert_eq!((kind, options.video_quality), (MediaKind::Video, 10));
+        let (kind, options) = lower_largest(&options, &stats).unwrap();
+        assert_eq!((kind, options.image_quality), (MediaKind::Image, 30));
+
+        let floor = options.image_quality(MIN_QUALITY);
+        assert!(lower_largest(&floor, &stats).is_none());
+        // Nothing to lower in a pack without images or video
+        assert!(lower_largest(&CompressOptions::new(), &CompressionStats::new()).is_none());
+    }
+
+    #[test]
+    fn test_compress_to_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("pack.siq");
+        let png = encode_png(&::image::RgbImage::from_fn(64, 64, |x, y| {
+            ::image::Rgb([((x * 7) ^ (y * 13)) as u8, (x * y) as u8, (x + y * 3) as u8])
+        }));
+        write_pack(
+            &input,
+            &[
+                (
+                    "content.xml",
+                    br#"<item type="image" isRef="True">a.png</item>"#.as_slice(),
+                ),
+                ("Images/a.png", png.as_slice()),
+            ],
+        );
+
+        let run = |max_size| {
+            compress_to_size(
+                input.clone(),
+                None,
+                CompressOptions::new()
+                    .skip_video(true)
+                    .always_compress(true),
+                max_size,
+                MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            )
+        };
+        assert!(run(u64::MAX).is_ok());
+        let error = run(1).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<SicomError>(),
+            Some(SicomError::OutputTooLarge { limit: 1, .. })
+        ));
+        assert!(default_output_path(&input).unwrap().exists());
+    }
+}
@@ -0,0 +1,121 @@
+use log::debug;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Shared budget of temporary disk space for in-flight video encodes
+///
+/// Each encode reserves its estimated footprint before touching the disk and
+/// waits while the pool is exhausted, so parallel encodes can't fill the disk
+/// halfway through. A request larger than the whole pool runs once the pool is
+/// idle instead of waiting forever.
+#[derive(Debug, Clone)]
+pub struct TempSpacePool {
+    capacity: u64,
+    state: Arc<(Mutex<u64>, Condvar)>,
+}
+
+/// Reserved temp space, returned to the pool when dropped
+#[derive(Debug)]
+pub struct TempSpaceReservation {
+    pool: TempSpacePool,
+    bytes: u64,
+}
+
+impl TempSpacePool {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            state: Arc::new((Mutex::new(0), Condvar::new())),
+        }
+    }
+
+    /// Temp space needed to encode a source: the source copy plus the output
+    pub const fn estimate(source_size: u64) -> u64 {
+        source_size.saturating_mul(2)
+    }
+
+    /// Bytes currently reserved by in-flight encodes
+    pub fn reserved(&self) -> u64 {
+        *self.state.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Block until `bytes` fit in the pool, then reserve them
+    pub fn reserve(&self, bytes: u64) -> TempSpaceReservation {
+        let (lock, available) = &*self.state;
+        let mut reserved = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let fits = |reserved: u64| reserved == 0 || reserved.saturating_add(bytes) <= self.capacity;
+
+        if !fits(*reserved) {
+            debug!(
+                "  Waiting for temp space: need {bytes} bytes, {} of {} reserved",
+                *reserved, self.capacity
+            );
+            reserved = available
+                .wait_while(reserved, |reserved| !fits(*reserved))
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        *reserved = reserved.saturating_add(bytes);
+
+        TempSpaceReservation {
+            pool: self.clone(),
+            bytes,
+        }
+    }
+}
+
+impl Drop for TempSpaceReservation {
+    fn drop(&mut self) {
+        let (lock, available) = &*self.pool.state;
+        let mut reserved = lock.lock().unwrap_or_else(|e| e.into_inner());
+        *reserved = reserved.saturating_sub(self.bytes);
+        available.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reservations_are_released() {
+        let pool = TempSpacePool::new(1000);
+        let first = pool.reserve(400);
+        let second = pool.reserve(600);
+        assert_eq!(pool.reserved(), 1000);
+
+        drop(first);
+        assert_eq!(pool.reserved(), 600);
+        drop(second);
+        assert_eq!(pool.reserved(), 0);
+    }
+
+    #[test]
+    fn test_reserve_waits_for_space() {
+        let pool = TempSpacePool::new(1000);
+        let first = pool.reserve(TempSpacePool::estimate(400));
+
+        let (sender, receiver) = mpsc::channel();
+        let waiting_pool = pool.clone();
+        let waiter = thread::spawn(move || {
+            let _reservation = waiting_pool.reserve(500);
+            sender.send(()).unwrap();
+        });
+
+        // 800 + 500 exceeds the pool - the second encode must wait
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(first);
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        waiter.join().unwrap();
+        assert_eq!(pool.reserved(), 0);
+    }
+
+    #[test]
+    fn test_oversized_request_runs_alone() {
+        let pool = TempSpacePool::new(100);
+        let reservation = pool.reserve(5000);
+        assert_eq!(pool.reserved(), 5000);
+        drop(reservation);
+    }
+}
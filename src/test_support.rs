@@ -0,0 +1,54 @@
+use std::fs::File;
+use std::io::{Cursor, Seek, Write};
+use std::path::Path;
+use zip::ZipWriter;
+use zip::write::FileOptions;
+
+/// Gradient test image; `shade` sets the red slope, so different shades give different images
+pub fn gradient(width: u32, height: u32, shade: u32) -> image::RgbImage {
+    image::RgbImage::from_fn(width, height, |x, y| {
+        image::Rgb([(x * shade) as u8, (y * 4) as u8, ((x + y) * 2) as u8])
+    })
+}
+
+/// PNG encoding of `image`
+pub fn encode_png(image: &image::RgbImage) -> Vec<u8> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    png
+}
+
+/// PNG of the default gradient
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+    encode_png(&gradient(width, height, 4))
+}
+
+fn write_entries<W: Write + Seek>(writer: W, entries: &[(&str, &[u8])]) -> W {
+    let mut zip = ZipWriter::new(writer);
+    for (name, data) in entries {
+        zip.start_file(*name, FileOptions::default()).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap()
+}
+
+/// Write a ZIP pack with `entries` (name, data) in order
+pub fn write_pack(path: &Path, entries: &[(&str, &[u8])]) {
+    write_entries(File::create(path).unwrap(), entries);
+}
+
+/// In-memory ZIP pack with `entries` (name, data) in order
+#[cfg(feature = "server")]
+pub fn pack_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    write_entries(Cursor::new(Vec::new()), entries).into_inner()
+}
+
+/// `content.xml` of a package without rounds or media
+pub const EMPTY_PACKAGE: &[u8] = b"<package name=\"Test\"></package>";
+
+/// Pack holding nothing but an empty package
+pub fn write_empty_pack(path: &Path) {
+    write_pack(path, &[("content.xml", EMPTY_PACKAGE)]);
+}
@@ -0,0 +1,269 @@
+use crate::error::SicomError;
+use crate::manifest::Manifest;
+use crate::options::CompressOptions;
+use crate::pipeline::{EntryMetadata, entry_options, open_pack, rewrite_references};
+use anyhow::{Context, Result};
+use log::info;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use zip::ZipWriter;
+
+/// Latin spelling of a lowercase Cyrillic or accented Latin letter
+fn latin(c: char) -> Option<&'static str> {
+    Some(match c {
+        'а' => "a",
+        'б' => "b",
+        'в' => "v",
+        'г' | 'ґ' => "g",
+        'д' => "d",
+        'е' | 'э' => "e",
+        'ё' => "yo",
+        'є' => "ye",
+        'ж' => "zh",
+        'з' => "z",
+        'и' | 'і' => "i",
+        'ї' => "yi",
+        'й' | 'ы' => "y",
+        'к' => "k",
+        'л' => "l",
+        'м' => "m",
+        'н' => "n",
+        'о' => "o",
+        'п' => "p",
+        'р' => "r",
+        'с' => "s",
+        'т' => "t",
+        'у' | 'ў' => "u",
+        'ф' => "f",
+        'х' => "kh",
+        'ц' => "ts",
+        'ч' => "ch",
+        'ш' => "sh",
+        'щ' => "shch",
+        'ъ' | 'ь' => "",
+        'ю' => "yu",
+        'я' => "ya",
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'č' => "c",
+        'ď' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ę' | 'ě' => "e",
+        'ì' | 'í' | 'î' | 'ï' => "i",
+        'ł' => "l",
+        'ñ' | 'ń' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ő' => "o",
+        'œ' => "oe",
+        'ř' => "r",
+        'ś' | 'š' => "s",
+        'ß' => "ss",
+        'ť' => "t",
+        'ù' | 'ú' | 'û' | 'ü' | 'ů' | 'ű' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
+
+/// ASCII transliteration of `text`; characters without one become `_`
+pub fn transliterate(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii() {
+            result.push(c);
+            continue;
+        }
+        let lower = c.to_lowercase().next().unwrap_or(c);
+        match latin(lower) {
+            // Capitals keep only their first letter capitalized (Ж -> Zh)
+            Some(spelling) if lower != c => {
+                let mut letters = spelling.chars();
+                if let Some(first) = letters.next() {
+                    result.push(first.to_ascii_uppercase());
+                    result.push_str(letters.as_str());
+                }
+            }
+            Some(spelling) => result.push_str(spelling),
+            None if c == '№' => result.push_str("No"),
+            None => result.push('_'),
+        }
+    }
+    result
+}
+
+/// Entry name without the extension of its filename
+pub fn entry_stem(entry_name: &str) -> &str {
+    let start = entry_name.rfind('/').map_or(0, |pos| pos + 1);
+    match entry_name[start..].rfind('.') {
+        Some(dot) if dot > 0 => &entry_name[..start + dot],
+        _ => entry_name,
+    }
+}
+
+/// ASCII name for an entry whose filename isn't ASCII once URL-decoded
+///
+/// The directory stays, the filename is transliterated and URL-encoded again
+/// if it was before. Stems (see [`entry_stem`]) for which `taken` returns true
+/// get a `-2`, `-3`, ... suffix, so two originals never end up on one name.
+pub fn ascii_entry_name(entry_name: &str, taken: impl Fn(&str) -> bool) -> Option<String> {
+    let start = entry_name.rfind('/').map_or(0, |pos| pos + 1);
+    let (directory, filename) = entry_name.split_at(start);
+    let decoded = urlencoding::decode(filename).unwrap_or_else(|_| filename.into());
+    if decoded.is_ascii() {
+        return None;
+    }
+
+    let (stem, extension) = match decoded.rfind('.') {
+        Some(dot) if dot > 0 => (&decoded[..dot], &decoded[dot..]),
+        _ => (decoded.as_ref(), ""),
+    };
+    let mut stem = transliterate(stem);
+    if stem.chars().all(|c| c == '_') {
+        stem = "media".to_string();
+    }
+    let extension = transliterate(extension);
+    let encode = |name: &str| {
+        if decoded == filename {
+            name.to_string()
+        } else {
+            urlencoding::encode(name).into_owned()
+        }
+    };
+
+    (1..)
+        .map(|n| match n {
+            1 => format!("{directory}{}", encode(&stem)),
+            n => format!("{directory}{}", encode(&format!("{stem}-{n}"))),
+        })
+        .find(|candidate| !taken(candidate))
+        .map(|candidate| format!("{candidate}{}", encode(&extension)))
+}
+
+/// Give transliterated entries of a pack back their original names, using the
+/// manifest written next to it; the output defaults to `<name>_restored.siq`
+pub fn restore_names(pack: &Path, output: Option<PathBuf>) -> Result<PathBuf> {
+    let manifest_path = Manifest::path_for(pack);
+    let names: BTreeMap<String, String> = Manifest::load(&manifest_path)
+        .map(|manifest| {
+            manifest
+                .entries
+                .into_values()
+                .filter_map(|entry| Some((entry.output, entry.transliterated_from?)))
+                .collect()
+        })
+        .filter(|names: &BTreeMap<_, _>| !names.is_empty())
+        .ok_or_else(|| {
+            SicomError::InvalidOptions(format!(
+                "No transliterated names recorded in {}",
+                manifest_path.display()
+            ))
+        })?;
+    let output = output.unwrap_or_else(|| {
+        let stem = pack.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = pack.extension().unwrap_or_default().to_string_lossy();
+        pack.with_file_name(format!("{stem}_restored.{extension}"))
+    });
+
+    // Packs refer to media by the decoded name
+    let decoded: Vec<(&str, String)> = names
+        .iter()
+        .map(|(ascii, original)| {
+            let original = urlencoding::decode(original).unwrap_or_else(|_| original.into());
+            (ascii.as_str(), original.into_owned())
+        })
+        .collect();
+    let references: Vec<(&str, &str)> = decoded
+        .iter()
+        .map(|(ascii, original)| (*ascii, original.as_str()))
+        .collect();
+
+    let mut archive = open_pack(pack)?;
+    let file = File::create(&output).map_err(|source| SicomError::Io {
+        path: output.clone(),
+        source,
+    })?;
+    let mut writer = ZipWriter::new(BufWriter::new(file));
+    let text_options = CompressOptions::new();
+    let mut renamed = 0;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let name = entry.name().to_string();
+        let source = EntryMetadata::of(&entry);
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read file: {name}"))?;
+
+        if name == "content.xml" || text_options.is_text_entry(&name) {
+            if let Ok(mut text) = String::from_utf8(data.clone()) {
+                rewrite_references(&mut text, &references);
+                data = text.into_bytes();
+            }
+        }
+        let name = match names.get(&name) {
+            Some(original) => {
+                renamed += 1;
+                original.clone()
+            }
+            None => name,
+        };
+
+        writer
+            .start_file(&name, source.apply(entry_options(&name, None)))
+            .with_context(|| format!("Failed to start file in output ZIP: {name}"))?;
+        writer
+            .write_all(&data)
+            .with_context(|| format!("Failed to write file: {name}"))?;
+    }
+    writer
+        .finish()
+        .with_context(|| "Failed to finalize output ZIP")?;
+    info!(
+        "Restored {renamed} original names into {}",
+        output.display()
+    );
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transliterate() {
+        assert_eq!(transliterate("Щука и ёж"), "Shchuka i yozh");
+        assert_eq!(transliterate("Їжак Ґанок"), "Yizhak Ganok");
+        assert_eq!(transliterate("Crème brûlée №5"), "Creme brulee No5");
+        assert_eq!(transliterate("东京"), "__");
+
+        assert_eq!(entry_stem("Images/a.b.png"), "Images/a.b");
+        assert_eq!(entry_stem("Images/.hidden"), "Images/.hidden");
+        assert_eq!(entry_stem("noext"), "noext");
+    }
+
+    #[test]
+    fn test_ascii_entry_name() {
+        let free = |_: &str| false;
+        // Percent-encoded names stay encoded: "Images/ВДНХ 1.webp"
+        assert_eq!(
+            ascii_entry_name("Images/%D0%92%D0%94%D0%9D%D0%A5%201.webp", free).as_deref(),
+            Some("Images/VDNKh%201.webp")
+        );
+        assert_eq!(
+            ascii_entry_name("Audio/гимн.mp3", free).as_deref(),
+            Some("Audio/gimn.mp3")
+        );
+        assert_eq!(
+            ascii_entry_name("Video/东京.mp4", free).as_deref(),
+            Some("Video/media.mp4")
+        );
+        assert_eq!(ascii_entry_name("Images/plain%20name.png", free), None);
+
+        let taken = |stem: &str| ["Audio/gimn", "Audio/gimn-2"].contains(&stem);
+        assert_eq!(
+            ascii_entry_name("Audio/гимн.mp3", taken).as_deref(),
+            Some("Audio/gimn-3.mp3")
+        );
+    }
+}
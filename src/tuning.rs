@@ -0,0 +1,294 @@
+use crate::image::{DEFAULT_EFFORT, ImageOptions, MAX_EFFORT};
+use crate::video::X265Preset;
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+/// File the benchmark results are stored in, inside [`config_dir`]
+pub const TUNING_FILE: &str = "tuning.json";
+
+/// Bumped when the benchmark changes, so older results are measured again
+const BENCHMARK_VERSION: u32 = 1;
+
+/// Encode times of a typical 4-core laptop, in milliseconds
+const TYPICAL_WEBP_MS: f64 = 50.0;
+const TYPICAL_LAME_MS: f64 = 40.0;
+const TYPICAL_X265_MS: f64 = 1500.0;
+
+/// Directory sicom keeps per-machine settings in: `$SICOM_CONFIG_DIR`, or
+/// `sicom` under `$XDG_CONFIG_HOME`, `~/.config` or `%APPDATA%`
+pub fn config_dir() -> Option<PathBuf> {
+    let var = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+    if let Some(dir) = var("SICOM_CONFIG_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    let base = var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var("APPDATA").map(PathBuf::from))
+        .or_else(|| var("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("sicom"))
+}
+
+/// How fast this machine encodes compared to a typical one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MachineClass {
+    Weak,
+    Typical,
+    Strong,
+}
+
+/// Timings of tiny test encodes on this machine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MachineBenchmark {
+    pub version: u32,
+    pub cpus: usize,
+    pub webp_ms: f64,
+    /// Missing without the `audio` feature
+    pub lame_ms: Option<f64>,
+    /// Missing without ffmpeg and libx265
+    pub x265_ms: Option<f64>,
+}
+
+/// Defaults picked for this machine; explicit flags and presets win over them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TunedDefaults {
+    pub image_effort: u8,
+    pub video_preset: X265Preset,
+}
+
+impl MachineBenchmark {
+    /// Run the test encodes, which take a second or two
+    pub fn measure(ffmpeg_path: Option<&Path>) -> Self {
+        Self {
+            version: BENCHMARK_VERSION,
+            cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            webp_ms: time_webp(),
+            lame_ms: time_lame(),
+            x265_ms: time_x265(ffmpeg_path),
+        }
+    }
+
+    /// Speed relative to a typical machine (2.0 encodes twice as fast),
+    /// averaged over the encoders measured
+    pub fn relative_speed(&self) -> f64 {
+        let ratios: Vec<f64> = [
+            Some((TYPICAL_WEBP_MS, self.webp_ms)),
+            self.lame_ms.map(|ms| (TYPICAL_LAME_MS, ms)),
+            self.x265_ms.map(|ms| (TYPICAL_X265_MS, ms)),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|(typical, measured)| typical / measured.max(1.0))
+        .collect();
+        ratios.iter().sum::<f64>() / ratios.len() as f64
+    }
+
+    pub fn class(&self) -> MachineClass {
+        let speed = self.relative_speed();
+        if speed < 0.5 || self.cpus <= 2 {
+            MachineClass::Weak
+        } else if speed >= 2.0 && self.cpus >= 8 {
+            MachineClass::Strong
+        } else {
+            MachineClass::Typical
+        }
+    }
+
+    /// Quicker video encodes and WebP effort on weak machines, the most WebP
+    /// effort on strong ones
+    pub fn tuned_defaults(&self) -> TunedDefaults {
+        match self.class() {
+            MachineClass::Weak => TunedDefaults {
+                image_effort: 2,
+                video_preset: X265Preset::Faster,
+            },
+            MachineClass::Typical => TunedDefaults {
+                image_effort: DEFAULT_EFFORT,
+                video_preset: X265Preset::Medium,
+            },
+            MachineClass::Strong => TunedDefaults {
+                image_effort: MAX_EFFORT,
+                video_preset: X265Preset::Medium,
+            },
+        }
+    }
+
+    fn load(path: &Path) -> Option<Self> {
+        let benchmark: Self = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+        (benchmark.version == BENCHMARK_VERSION).then_some(benchmark)
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Results stored in `dir`, benchmarking the machine first when there are none
+/// yet or `retune` asks for fresh ones; a failed save only costs a rerun
+pub fn load_or_measure(dir: &Path, retune: bool, ffmpeg_path: Option<&Path>) -> MachineBenchmark {
+    let path = dir.join(TUNING_FILE);
+    if !retune {
+        if let Some(benchmark) = MachineBenchmark::load(&path) {
+            debug!("Using machine benchmark from {}", path.display());
+            return benchmark;
+        }
+    }
+
+    info!("Benchmarking this machine to pick default settings...");
+    let benchmark = MachineBenchmark::measure(ffmpeg_path);
+    let defaults = benchmark.tuned_defaults();
+    info!(
+        "Machine class: {:?} (WebP effort {}, x265 preset {}), stored in {} (refresh with --retune)",
+        benchmark.class(),
+        defaults.image_effort,
+        defaults.video_preset.name(),
+        path.display()
+    );
+    if let Err(e) = benchmark.save(&path) {
+        warn!("Keeping the benchmark for this run only: {e:#}");
+    }
+    benchmark
+}
+
+/// Milliseconds taken by `run`
+fn time_ms(run: impl FnOnce()) -> f64 {
+    let started = Instant::now();
+    run();
+    started.elapsed().as_secs_f64() * 1000.0
+}
+
+/// 512x512 lossy WebP encode of a noisy gradient
+fn time_webp() -> f64 {
+    let mut noise = 0x2545_f491_u32;
+    let image = image::RgbImage::from_fn(512, 512, |x, y| {
+        noise ^= noise << 13;
+        noise ^= noise >> 17;
+        noise ^= noise << 5;
+        let grain = (noise % 32) as u8;
+        image::Rgb([(x / 2) as u8 ^ grain, (y / 2) as u8, grain.wrapping_mul(4)])
+    });
+    let mut png = Vec::new();
+    if image
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .is_err()
+    {
+        return f64::NAN;
+    }
+    let options = ImageOptions::new().quality(40);
+    time_ms(|| {
+        let _ = crate::image::compress_image(&png, &options);
+    })
+}
+
+/// Three seconds of a tone through the built-in MP3 encoder
+#[cfg(feature = "audio")]
+fn time_lame() -> Option<f64> {
+    let wav = crate::audio::synthetic_wav(3);
+    let options = crate::audio::AudioOptions::new();
+    let mut result = Ok(Vec::new());
+    let elapsed = time_ms(|| {
+        result = crate::audio::compress_audio(&wav, "benchmark.wav", &options, None);
+    });
+    result.ok().map(|_| elapsed)
+}
+
+#[cfg(not(feature = "audio"))]
+fn time_lame() -> Option<f64> {
+    None
+}
+
+/// One second of 640x360 test video through libx265
+fn time_x265(ffmpeg_path: Option<&Path>) -> Option<f64> {
+    if !cfg!(feature = "video") {
+        return None;
+    }
+    let program = ffmpeg_path.map_or_else(|| PathBuf::from("ffmpeg"), Path::to_path_buf);
+    let mut command = Command::new(program);
+    command.args([
+        "-hide_banner",
+        "-loglevel",
+        "error",
+        "-f",
+        "lavfi",
+        "-i",
+        "testsrc2=size=640x360:rate=25",
+        "-t",
+        "1",
+        "-c:v",
+        "libx265",
+        "-preset",
+        X265Preset::Medium.name(),
+        "-x265-params",
+        "log-level=error",
+        "-f",
+        "null",
+        "-",
+    ]);
+    let mut succeeded = false;
+    let elapsed = time_ms(|| {
+        succeeded = command.output().is_ok_and(|output| output.status.success());
+    });
+    succeeded.then_some(elapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn benchmark(cpus: usize, webp_ms: f64, x265_ms: Option<f64>) -> MachineBenchmark {
+        MachineBenchmark {
+            version: BENCHMARK_VERSION,
+            cpus,
+            webp_ms,
+            lame_ms: None,
+            x265_ms,
+        }
+    }
+
+    #[test]
+    fn test_machine_class() {
+        let typical = benchmark(4, TYPICAL_WEBP_MS, Some(TYPICAL_X265_MS));
+        assert_eq!(typical.relative_speed(), 1.0);
+        assert_eq!(typical.class(), MachineClass::Typical);
+        assert_eq!(typical.tuned_defaults().image_effort, DEFAULT_EFFORT);
+
+        let weak = benchmark(4, TYPICAL_WEBP_MS * 3.0, Some(TYPICAL_X265_MS * 4.0));
+        assert_eq!(weak.class(), MachineClass::Weak);
+        assert_eq!(weak.tuned_defaults().video_preset, X265Preset::Faster);
+        // Two cores are weak however fast they are
+        assert_eq!(benchmark(2, 1.0, None).class(), MachineClass::Weak);
+
+        let strong = benchmark(16, TYPICAL_WEBP_MS / 3.0, None);
+        assert_eq!(strong.class(), MachineClass::Strong);
+        assert_eq!(strong.tuned_defaults().image_effort, MAX_EFFORT);
+    }
+
+    #[test]
+    fn test_load_or_measure() {
+        let dir = tempfile::tempdir().unwrap();
+        let stored = benchmark(16, 10.0, None);
+        stored.save(&dir.path().join(TUNING_FILE)).unwrap();
+        assert_eq!(load_or_measure(dir.path(), false, None), stored);
+
+        // Results of an older benchmark are measured again
+        let mut outdated = stored.clone();
+        outdated.version = 0;
+        outdated.save(&dir.path().join(TUNING_FILE)).unwrap();
+        let measured = load_or_measure(dir.path(), false, None);
+        assert_eq!(measured.version, BENCHMARK_VERSION);
+        assert!(measured.webp_ms > 0.0);
+        let reloaded = MachineBenchmark::load(&dir.path().join(TUNING_FILE)).unwrap();
+        assert_eq!(reloaded.cpus, measured.cpus);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::error::SicomError;
+use crate::inspect::EntryIndex;
+use crate::media::{MediaKind, MediaRegistry};
+use crate::options::CompressOptions;
+use crate::pack::{self, MediaRef, Package};
+use crate::{build_registry, open_pack};
+use anyhow::{Context, Result, anyhow};
+use log::warn;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Broken media file or reference
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Problem {
+    pub entry: String,
+    pub message: String,
+}
+
+/// Outcome of checking a pack
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    /// Media files decoded (images, audio) or probed (video)
+    pub checked_media: usize,
+    /// Videos not probed because ffprobe is missing
+    pub unchecked_videos: usize,
+    /// Media references in content.xml
+    pub checked_references: usize,
+    /// Media nothing refers to (allowed, but dead weight)
+    pub unreferenced: Vec<String>,
+    pub problems: Vec<Problem>,
+}
+
+/// Whether `ffprobe` can be run
+fn ffprobe_available() -> bool {
+    std::process::Command::new("ffprobe")
+        .arg("-version")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// The package logo and every media reference of its questions
+pub(crate) fn media_references(package: &Package) -> impl Iterator<Item = &MediaRef> {
+    package.logo.iter().chain(
+        package
+            .questions()
+            .flat_map(|(_, _, question)| &question.media),
+    )
+}
+
+/// Problem for a reference `index` can't resolve, naming the entry it was
+/// probably meant for when only the URL encoding differs
+pub(crate) fn missing_reference(index: &EntryIndex, media: &MediaRef) -> Problem {
+    let message = match index.resolve_decoded(media) {
+        Some((entry, _)) => format!(
+            "referenced in content.xml as {:?}, which only matches {entry} once URL-decoded",
+            media.name
+        ),
+        None => "referenced in content.xml but missing from the pack".to_string(),
+    };
+    Problem {
+        entry: media.entry_name(),
+        message,
+    }
+}
+
+fn verify_archive(
+    archive: &mut ZipArchive<BufReader<File>>,
+    registry: &MediaRegistry,
+    probe_videos: bool,
+) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+
+    let mut xml = String::new();
+    archive
+        .by_name("content.xml")
+        .map_err(|_| anyhow!("No content.xml found in pack"))?
+        .read_to_string(&mut xml)
+        .with_context(|| "Failed to read content.xml as UTF-8")?;
+    let package = pack::parse_package(&xml)?;
+
+    let mut entries = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .with_context(|| format!("Failed to read file at index {i}"))?;
+        let file_name = file.name().to_string();
+        entries.push((file_name.clone(), file.size()));
+
+        let Some((processor, _)) = registry.find(&file_name) else {
+            continue;
+        };
+        if processor.kind() == MediaKind::Video && !probe_videos {
+            report.unchecked_videos += 1;
+            continue;
+        }
+        let mut data = Vec::new();
+        let checked = file
+            .read_to_end(&mut data)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| processor.check(&data, &file_name));
+        report.checked_media += 1;
+        if let Err(e) = checked {
+            report.problems.push(Problem {
+                entry: file_name,
+                message: format!("{e:#}"),
+            });
+        }
+    }
+
+    // Every reference must point at an entry; entries without references are only noted
+    let index = EntryIndex::new(entries.iter().map(|(name, size)| (name.as_str(), *size)));
+    let mut referenced = HashSet::new();
+    for media in media_references(&package) {
+        report.checked_references += 1;
+        match index.resolve(media) {
+            Some((entry, _)) => {
+                referenced.insert(entry.as_str());
+            }
+            None => report.problems.push(missing_reference(&index, media)),
+        }
+    }
+    report.unreferenced = entries
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .filter(|name| {
+            MediaKind::ALL
+                .iter()
+                .any(|kind| name.starts_with(kind.directory()))
+                && !referenced.contains(name)
+        })
+        .map(str::to_string)
+        .collect();
+
+    Ok(report)
+}
+
+fn print_report(report: &VerifyReport) {
+    println!(
+        "Checked {} media files and {} references",
+        report.checked_media, report.checked_references
+    );
+    if report.unchecked_videos > 0 {
+        println!(
+            "Skipped {} videos (ffprobe not found)",
+            report.unchecked_videos
+        );
+    }
+    if !report.unreferenced.is_empty() {
+        println!("Unreferenced media ({}):", report.unreferenced.len());
+        for entry in &report.unreferenced {
+            println!("  {entry}");
+        }
+    }
+    if report.problems.is_empty() {
+        println!("No problems found");
+    } else {
+        println!("Problems ({}):", report.problems.len());
+        for problem in &report.problems {
+            println!("  {}: {}", problem.entry, problem.message);
+        }
+    }
+}
+
+/// Decode every image and audio file, probe every video and check all media
+/// references of a pack; fails when anything is broken
+pub fn verify_pack(input_pack: &Path, json: bool) -> Result<()> {
+    if !input_pack.exists() {
+        return Err(SicomError::InputNotFound(input_pack.to_path_buf()).into());
+    }
+
+    let mut archive = open_pack(input_pack)?;
+    let probe_videos = ffprobe_available();
+    if !probe_videos {
+        warn!("ffprobe not found, videos are not checked");
+    }
+    let registry = build_registry(&CompressOptions::new(), true);
+    let report = verify_archive(&mut archive, &registry, probe_videos)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    if report.problems.is_empty() {
+        Ok(())
+    } else {
+        Err(SicomError::VerificationFailed(report.problems.len()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{encode_png, write_pack};
+
+    #[test]
+    fn test_verify_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.siq");
+
+        let png = encode_png(&::image::RgbImage::new(8, 8));
+        let xml = r#"<package name="P" logo="@logo.png"><rounds><round name="R"><themes><theme name="T"><questions><question price="100"><params><param name="question" type="content"><item type="image" isRef="True">broken.png</item><item type="audio" isRef="True">missing.mp3</item></param></params></question></questions></theme></themes></round></rounds></package>"#;
+
+        write_pack(
+            &path,
+            &[
+                ("content.xml", xml.as_bytes()),
+                ("Images/logo.png", png.as_slice()),
+                ("Images/broken.png", b"not an image".as_slice()),
+                ("Images/unused.png", png.as_slice()),
+            ],
+        );
+
+        let registry = build_registry(&CompressOptions::new(), false);
+        let report = verify_archive(&mut open_pack(&path).unwrap(), &registry, false).unwrap();
+        assert_eq!(report.checked_media, 3);
+        assert_eq!(report.checked_references, 3);
+        assert_eq!(report.unreferenced, ["Images/unused.png"]);
+        let problems: Vec<&str> = report
+            .problems
+            .iter()
+            .map(|problem| problem.entry.as_str())
+            .collect();
+        assert_eq!(problems, ["Images/broken.png", "Audio/missing.mp3"]);
+    }
+}
@@ -1,12 +1,26 @@
-#![allow(clippy::collapsible_if)]
-
+#![allow(clippy::collapsible_if, clippy::collapsible_match)]
+// Without ffmpeg and ffprobe, the helpers of the encode have no callers
+#![cfg_attr(not(feature = "video"), allow(dead_code, unused_imports))]
+
+use crate::error::SicomError;
+use crate::media::{
+    LongMediaPenalty, MediaInfo, MediaKind, MediaOutput, MediaPlan, MediaProcessor, RenditionOutput,
+};
+use crate::progress::ProgressLogger;
+use crate::tempspace::TempSpacePool;
+use crate::warm::WarmPool;
 use anyhow::{Context, Result, anyhow};
+#[cfg(feature = "video")]
 use ffmpeg_sidecar::command::FfmpegCommand;
+#[cfg(feature = "video")]
 use ffmpeg_sidecar::event::{FfmpegEvent, LogLevel};
-use log::{debug, warn};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use tempfile::NamedTempFile;
 
 /// Supported video formats
@@ -16,28 +30,538 @@ pub enum VideoFormat {
     Mov,
     Avi,
     Mkv,
+    Wmv,
+    Webm,
     // Future formats can be added here
 }
 
+/// Denoise strength applied before encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum VideoDenoise {
+    Light,
+    Medium,
+    Strong,
+}
+
+/// x265 speed/size trade-off; slower presets make smaller files at the same CRF
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum X265Preset {
+    Faster,
+    #[default]
+    Medium,
+    Slow,
+}
+
+impl X265Preset {
+    /// Name passed to ffmpeg's `-preset`
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Faster => "faster",
+            Self::Medium => "medium",
+            Self::Slow => "slow",
+        }
+    }
+}
+
+/// Widely supported codec to encode to instead of HEVC (`--video-compat`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum VideoCompat {
+    /// H.264 through libx264, 8-bit 4:2:0 High profile, which every browser plays
+    H264,
+}
+
+impl VideoCompat {
+    /// ffmpeg encoder name
+    pub const fn encoder(self) -> &'static str {
+        match self {
+            Self::H264 => "libx264",
+        }
+    }
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::H264 => "H.264",
+        }
+    }
+}
+
+/// How videos are processed (`--video-mode`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum VideoMode {
+    /// Re-encode at the CRF mapped from quality
+    #[default]
+    Reencode,
+    /// Copy the first video and audio stream into a faststart MP4 without re-encoding
+    Remux,
+}
+
+/// Resolution of an extra, scaled-down copy of a video (`--ladder`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Rendition {
+    #[cfg_attr(feature = "cli", value(name = "1080p"))]
+    #[serde(rename = "1080p")]
+    P1080,
+    #[cfg_attr(feature = "cli", value(name = "720p"))]
+    #[serde(rename = "720p")]
+    P720,
+    #[cfg_attr(feature = "cli", value(name = "480p"))]
+    #[serde(rename = "480p")]
+    P480,
+    #[cfg_attr(feature = "cli", value(name = "360p"))]
+    #[serde(rename = "360p")]
+    P360,
+}
+
+impl Rendition {
+    pub const fn height(self) -> u32 {
+        match self {
+            Self::P1080 => 1080,
+            Self::P720 => 720,
+            Self::P480 => 480,
+            Self::P360 => 360,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::P1080 => "1080p",
+            Self::P720 => "720p",
+            Self::P480 => "480p",
+            Self::P360 => "360p",
+        }
+    }
+
+    /// Entry name of this rendition of `entry`: `Video/clip.mp4` -> `Video/clip_720p.mp4`
+    pub fn entry_name(self, entry: &str) -> String {
+        match entry.rsplit_once('.') {
+            Some((stem, extension)) if !extension.contains('/') => {
+                format!("{stem}_{}.{extension}", self.name())
+            }
+            _ => format!("{entry}_{}", self.name()),
+        }
+    }
+}
+
+/// Display aspect ratio such as 16:9
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AspectRatio {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AspectRatio {
+    pub const fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+
+    /// Whether a frame of `width` x `height` already has this ratio (within 1%)
+    fn matches(self, (width, height): (u32, u32)) -> bool {
+        let target = f64::from(self.width) / f64::from(self.height);
+        let actual = f64::from(width) / f64::from(height.max(1));
+        (actual / target - 1.0).abs() <= 0.01
+    }
+}
+
+impl FromStr for AspectRatio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid aspect ratio {s:?}, expected e.g. 16:9");
+        let (width, height) = s.split_once(':').ok_or_else(invalid)?;
+        match (width.trim().parse(), height.trim().parse()) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok(Self::new(width, height)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for AspectRatio {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<AspectRatio> for String {
+    fn from(aspect: AspectRatio) -> Self {
+        aspect.to_string()
+    }
+}
+
+impl fmt::Display for AspectRatio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.width, self.height)
+    }
+}
+
+/// Settings for video compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VideoOptions {
+    /// Quality 1-100, mapped to an x265 CRF (or x264 with `compat`)
+    pub quality: u8,
+    pub denoise: Option<VideoDenoise>,
+    /// Scan the encoded video for truncation, black output and decode errors
+    pub verify_output: bool,
+    /// Lower quality for long videos (off when None)
+    pub long_media: Option<LongMediaPenalty>,
+    /// Drop the audio stream of videos whose audio is entirely silent
+    pub strip_silent_audio: bool,
+    /// Pad videos of other shapes to this aspect ratio over a blurred background
+    pub normalize_aspect: Option<AspectRatio>,
+    /// Copy the streams into the MP4 instead of re-encoding (lossless)
+    pub remux: bool,
+    pub preset: X265Preset,
+    /// Scale down to at most this many lines (never up)
+    pub max_height: Option<u32>,
+    /// Scale down to at most this many columns (never up)
+    pub max_width: Option<u32>,
+    /// Encode to this codec instead of HEVC
+    pub compat: Option<VideoCompat>,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        Self {
+            quality: 50,
+            denoise: None,
+            verify_output: false,
+            long_media: None,
+            strip_silent_audio: false,
+            normalize_aspect: None,
+            remux: false,
+            preset: X265Preset::Medium,
+            max_height: None,
+            max_width: None,
+            compat: None,
+        }
+    }
+}
+
+impl VideoOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub const fn quality(mut self, quality: u8) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    pub const fn denoise(mut self, denoise: Option<VideoDenoise>) -> Self {
+        self.denoise = denoise;
+        self
+    }
+
+    pub const fn verify_output(mut self, verify: bool) -> Self {
+        self.verify_output = verify;
+        self
+    }
+
+    pub const fn long_media(mut self, penalty: Option<LongMediaPenalty>) -> Self {
+        self.long_media = penalty;
+        self
+    }
+
+    pub const fn strip_silent_audio(mut self, strip: bool) -> Self {
+        self.strip_silent_audio = strip;
+        self
+    }
+
+    pub const fn normalize_aspect(mut self, aspect: Option<AspectRatio>) -> Self {
+        self.normalize_aspect = aspect;
+        self
+    }
+
+    pub const fn remux(mut self, remux: bool) -> Self {
+        self.remux = remux;
+        self
+    }
+
+    pub const fn preset(mut self, preset: X265Preset) -> Self {
+        self.preset = preset;
+        self
+    }
+
+    pub const fn max_height(mut self, height: Option<u32>) -> Self {
+        self.max_height = height;
+        self
+    }
+
+    pub const fn max_width(mut self, width: Option<u32>) -> Self {
+        self.max_width = width;
+        self
+    }
+
+    pub const fn compat(mut self, compat: Option<VideoCompat>) -> Self {
+        self.compat = compat;
+        self
+    }
+}
+
+/// Video pipeline: HEVC (or H.264 for compatibility) re-encode through ffmpeg
+pub struct VideoProcessor {
+    pub options: VideoOptions,
+    pub ffmpeg_path: Option<PathBuf>,
+    /// Temp space budget shared with other encodes
+    pub temp_space: Option<TempSpacePool>,
+    /// Scaled-down copies to encode besides the main output
+    pub ladder: Vec<Rendition>,
+    /// Probe results kept between jobs of long-running modes
+    pub warm_pool: Option<WarmPool>,
+}
+
+#[cfg(feature = "video")]
+impl MediaProcessor for VideoProcessor {
+    fn kind(&self) -> MediaKind {
+        MediaKind::Video
+    }
+
+    fn supported(&self, filename: &str) -> bool {
+        is_supported_video(filename)
+    }
+
+    fn analyze(&self, data: &[u8], filename: &str) -> MediaInfo {
+        let mut info = MediaInfo {
+            format: detect_video_format(filename).map(|format| format!("{format:?}")),
+            ..Default::default()
+        };
+
+        // ffprobe needs a file on disk
+        let Ok(mut temp) = NamedTempFile::with_suffix(get_file_extension(filename)) else {
+            return info;
+        };
+        if temp.write_all(data).is_ok() {
+            let metadata = probe_source(
+                data,
+                temp.path(),
+                self.ffmpeg_path.as_deref(),
+                self.warm_pool.as_ref(),
+            );
+            info.duration_seconds = metadata.duration_seconds;
+            info.width = metadata.display_size.map(|(width, _)| width);
+            info.height = metadata.display_size.map(|(_, height)| height);
+        }
+        info
+    }
+
+    fn plan(&self, _data: &[u8], filename: &str) -> Result<MediaPlan> {
+        detect_video_format(filename)
+            .ok_or_else(|| anyhow!("Unsupported video format: {}", filename))?;
+
+        // Video output size depends on content - only a real encode can tell
+        Ok(MediaPlan {
+            filename: output_filename(filename),
+            encoder: if self.options.remux {
+                REMUX_DESCRIPTION.to_string()
+            } else {
+                describe_quality(
+                    self.options.quality,
+                    self.options.denoise,
+                    self.options.preset,
+                    self.options.compat,
+                )
+            },
+            estimated_size: None,
+        })
+    }
+
+    fn check(&self, data: &[u8], filename: &str) -> Result<()> {
+        // ffprobe needs a file on disk
+        let mut temp = NamedTempFile::with_suffix(get_file_extension(filename))
+            .context("Failed to create temp file")?;
+        temp.write_all(data).context("Failed to write temp file")?;
+        let probe = ffprobe::ffprobe(temp.path()).map_err(|e| anyhow!("ffprobe failed: {e}"))?;
+        if !probe
+            .streams
+            .iter()
+            .any(|s| s.codec_type.as_ref().is_some_and(|t| t == "video"))
+        {
+            return Err(anyhow!("No video stream found"));
+        }
+        Ok(())
+    }
+
+    fn compress(
+        &self,
+        data: &[u8],
+        filename: &str,
+        progress: &mut ProgressLogger,
+    ) -> Result<MediaOutput> {
+        // Held until the temp files of this encode are gone
+        let _reservation = self.temp_space.as_ref().map(|pool| {
+            let reservation = pool.reserve(TempSpacePool::estimate(data.len() as u64));
+            debug!("  Temp space reserved: {} bytes in use", pool.reserved());
+            reservation
+        });
+
+        progress.start_video_progress(filename);
+        let result = encode_video(
+            data,
+            filename,
+            &self.options,
+            self.ffmpeg_path.as_deref(),
+            self.warm_pool.as_ref(),
+            progress,
+        );
+        progress.finish_video_progress();
+        let compressed = result?;
+
+        Ok(MediaOutput {
+            data: compressed,
+            filename: output_filename(filename),
+            codec: match self.options.compat {
+                _ if self.options.remux => "remux",
+                Some(compat) => compat.label(),
+                None => "HEVC",
+            },
+            renditions: self.encode_ladder(data, filename, progress),
+        })
+    }
+}
+
+#[cfg(feature = "video")]
+impl VideoProcessor {
+    /// Encode the ladder renditions below the source height from the source
+    ///
+    /// A failed rendition is left out with a warning; the main output stands.
+    fn encode_ladder(
+        &self,
+        data: &[u8],
+        filename: &str,
+        progress: &mut ProgressLogger,
+    ) -> Vec<RenditionOutput> {
+        if self.ladder.is_empty() || self.options.remux {
+            return Vec::new();
+        }
+        // The main output is at most as tall as the height cap
+        let source_height = self.analyze(data, filename).height.map(|height| {
+            self.options
+                .max_height
+                .map_or(height, |cap| height.min(cap))
+        });
+        let mut renditions = Vec::new();
+        for &rendition in &self.ladder {
+            if source_height.is_some_and(|height| rendition.height() >= height) {
+                debug!(
+                    "  Skipping {} rendition of {filename}: main output is not taller",
+                    rendition.name()
+                );
+                continue;
+            }
+            progress.start_video_progress(&format!("{filename} ({})", rendition.name()));
+            let result = encode_video(
+                data,
+                filename,
+                &self.options.max_height(Some(rendition.height())),
+                self.ffmpeg_path.as_deref(),
+                self.warm_pool.as_ref(),
+                progress,
+            );
+            progress.finish_video_progress();
+            match result {
+                Ok(data) => renditions.push(RenditionOutput { rendition, data }),
+                Err(e) => warn!("  {} rendition of {filename} failed: {e}", rendition.name()),
+            }
+        }
+        renditions
+    }
+}
+
+/// Without ffmpeg and ffprobe videos are recognized but not compressed
+#[cfg(not(feature = "video"))]
+impl MediaProcessor for VideoProcessor {
+    fn kind(&self) -> MediaKind {
+        MediaKind::Video
+    }
+
+    fn supported(&self, filename: &str) -> bool {
+        is_supported_video(filename)
+    }
+
+    fn analyze(&self, _data: &[u8], filename: &str) -> MediaInfo {
+        MediaInfo {
+            format: detect_video_format(filename).map(|format| format!("{format:?}")),
+            ..Default::default()
+        }
+    }
+
+    fn plan(&self, _data: &[u8], _filename: &str) -> Result<MediaPlan> {
+        Err(SicomError::MissingFeature("video").into())
+    }
+
+    fn check(&self, _data: &[u8], _filename: &str) -> Result<()> {
+        Err(SicomError::MissingFeature("video").into())
+    }
+
+    fn compress(
+        &self,
+        _data: &[u8],
+        _filename: &str,
+        _progress: &mut ProgressLogger,
+    ) -> Result<MediaOutput> {
+        Err(SicomError::MissingFeature("video").into())
+    }
+}
+
 /// Video metadata for progress calculation
-#[derive(Debug, Clone)]
-struct VideoMetadata {
+#[derive(Debug, Clone, Default)]
+pub(crate) struct VideoMetadata {
     total_frames: Option<u32>,
     duration_seconds: Option<f64>, // May not be available - be honest about it
     fps: Option<f32>,
+    has_audio: bool,
+    /// Codec of the first audio stream, as ffprobe names it
+    audio_codec: Option<String>,
+    /// Width and height as displayed (None when unknown or rotated by the container)
+    display_size: Option<(u32, u32)>,
+}
+
+/// Result of scanning a video for black frames and decode errors
+#[derive(Debug, Clone, Default)]
+struct VideoScan {
+    black_seconds: f64,
+    decode_errors: u32,
 }
 
+/// Allowed difference between source and encoded duration in seconds
+const DURATION_TOLERANCE_SECONDS: f64 = 1.0;
+
+/// Fraction of the duration that must be black to treat a video as "mostly black"
+const MOSTLY_BLACK_RATIO: f64 = 0.9;
+
+/// Frame rates above this are bogus metadata (high-speed footage is stored slowed down)
+const MAX_PLAUSIBLE_FPS: f32 = 240.0;
+
+/// Bitrate range a real video stream falls into, in bits per second; a duration
+/// implying a bitrate outside it doesn't match the container size
+const PLAUSIBLE_BITRATE: std::ops::RangeInclusive<f64> = 1_000.0..=500_000_000.0;
+
+/// silencedetect settings: anything quieter than -60 dB for half a second is silence
+const SILENCE_FILTER: &str = "silencedetect=noise=-60dB:d=0.5";
+
+/// Silence must start within this many seconds of the beginning to cover the whole track
+const SILENCE_START_TOLERANCE_SECONDS: f64 = 0.1;
+
+/// Video file extensions (lowercase) the pipeline compresses
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["mp4", "mov", "avi", "mkv", "wmv", "webm"];
+
 /// Check if a video file format is supported
 pub fn is_supported_video(filename: &str) -> bool {
     let path = Path::new(filename);
     path.extension()
         .and_then(|s| s.to_str())
-        .is_some_and(|ext| {
-            matches!(
-                ext.to_lowercase().as_str(),
-                "mp4" | "mov" | "avi" | "mkv" | "wmv" | "webm"
-            )
-        })
+        .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
 }
 
 /// Detect video format from file extension
@@ -50,6 +574,8 @@ fn detect_video_format(filename: &str) -> Option<VideoFormat> {
             "mov" => Some(VideoFormat::Mov),
             "avi" => Some(VideoFormat::Avi),
             "mkv" => Some(VideoFormat::Mkv),
+            "wmv" => Some(VideoFormat::Wmv),
+            "webm" => Some(VideoFormat::Webm),
             _ => None,
         })
 }
@@ -64,6 +590,32 @@ fn get_file_extension(filename: &str) -> String {
     }
 }
 
+/// Entry name of a processed video: every output is an MP4, since many
+/// clients can't play AVI, WMV or MKV
+pub fn output_filename(filename: &str) -> String {
+    let path = Path::new(filename);
+    let unchanged = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|current| current.eq_ignore_ascii_case("mp4"));
+    if unchanged {
+        filename.to_string()
+    } else {
+        path.with_extension("mp4").to_string_lossy().into_owned()
+    }
+}
+
+/// Audio codecs MP4 holds as they are
+const MP4_AUDIO_CODECS: &[&str] = &["aac", "mp3", "ac3", "eac3", "opus", "alac", "flac"];
+
+/// Bitrate of the AAC audio other codecs are encoded to
+const MP4_AAC_BITRATE: &str = "160k";
+
+/// Whether audio of this codec can be copied into MP4 (no audio at all can)
+fn mp4_takes_audio(codec: Option<&str>) -> bool {
+    codec.is_none_or(|codec| MP4_AUDIO_CODECS.contains(&codec))
+}
+
 /// Get FFmpeg input format string from video format
 fn get_ffmpeg_format(format: VideoFormat) -> &'static str {
     match format {
@@ -71,10 +623,13 @@ fn get_ffmpeg_format(format: VideoFormat) -> &'static str {
         VideoFormat::Mov => "mov",
         VideoFormat::Avi => "avi",
         VideoFormat::Mkv => "matroska",
+        VideoFormat::Wmv => "asf",
+        VideoFormat::Webm => "webm",
     }
 }
 
 /// Extract video metadata using ffprobe-rs for accurate progress calculation
+#[cfg(feature = "video")]
 fn extract_video_metadata(file_path: &Path, _ffmpeg_path: Option<&Path>) -> VideoMetadata {
     // Use ffprobe-rs to get structured video metadata
     let probe_result = ffprobe::ffprobe(file_path);
@@ -83,16 +638,39 @@ fn extract_video_metadata(file_path: &Path, _ffmpeg_path: Option<&Path>) -> Vide
         total_frames: None,
         duration_seconds: None, // Will be set from ffprobe if available
         fps: None,
+        has_audio: false,
+        audio_codec: None,
+        display_size: None,
     };
 
     match probe_result {
         Ok(probe_data) => {
+            let audio_stream = probe_data
+                .streams
+                .iter()
+                .find(|s| s.codec_type.as_ref().is_some_and(|t| t == "audio"));
+            metadata.has_audio = audio_stream.is_some();
+            metadata.audio_codec = audio_stream.and_then(|s| s.codec_name.clone());
+
             // Find the first video stream
             if let Some(video_stream) = probe_data
                 .streams
                 .iter()
                 .find(|s| s.codec_type.as_ref().is_some_and(|t| t == "video"))
             {
+                // A display matrix rotates the frames, so the coded size isn't what's shown
+                let rotated = video_stream
+                    .side_data_list
+                    .iter()
+                    .any(|side_data| side_data.side_data_type == "Display Matrix");
+                if let (Some(width), Some(height), false) =
+                    (video_stream.width, video_stream.height, rotated)
+                {
+                    if let (Ok(width), Ok(height)) = (u32::try_from(width), u32::try_from(height)) {
+                        metadata.display_size = Some((width, height));
+                    }
+                }
+
                 // Extract frame count (nb_frames)
                 if let Some(nb_frames_str) = &video_stream.nb_frames {
                     if let Ok(frames) = nb_frames_str.parse::<u32>() {
@@ -136,6 +714,9 @@ fn extract_video_metadata(file_path: &Path, _ffmpeg_path: Option<&Path>) -> Vide
         }
     }
 
+    let file_size = fs::metadata(file_path).map_or(0, |m| m.len());
+    let mut metadata = sanitize_metadata(metadata, file_size);
+
     // If nb_frames is not available but we have duration and fps, calculate it
     if metadata.total_frames.is_none() {
         if let (Some(duration), Some(fps)) = (metadata.duration_seconds, metadata.fps) {
@@ -146,6 +727,46 @@ fn extract_video_metadata(file_path: &Path, _ffmpeg_path: Option<&Path>) -> Vide
     metadata
 }
 
+/// Drop ffprobe values that can't be right for a file of `file_size` bytes,
+/// so progress and size math fall back instead of going haywire
+fn sanitize_metadata(mut metadata: VideoMetadata, file_size: u64) -> VideoMetadata {
+    if let Some(fps) = metadata.fps {
+        if !(fps.is_finite() && fps > 0.0 && fps <= MAX_PLAUSIBLE_FPS) {
+            debug!("Ignoring implausible frame rate: {fps}");
+            metadata.fps = None;
+        }
+    }
+
+    if let Some(duration) = metadata.duration_seconds {
+        let plausible = duration.is_finite()
+            && duration > 0.0
+            && (file_size == 0 || PLAUSIBLE_BITRATE.contains(&(file_size as f64 * 8.0 / duration)));
+        if !plausible {
+            debug!("Ignoring implausible duration: {duration}s for {file_size} bytes");
+            metadata.duration_seconds = None;
+        }
+    }
+
+    // A frame count far off duration x fps is as bogus as the values above
+    if let Some(frames) = metadata.total_frames {
+        let expected = match (metadata.duration_seconds, metadata.fps) {
+            (Some(duration), Some(fps)) => Some(duration * f64::from(fps)),
+            _ => None,
+        };
+        let implausible = frames == 0
+            || expected.is_some_and(|expected| {
+                let frames = f64::from(frames);
+                frames > expected * 2.0 + 1.0 || frames < expected / 2.0 - 1.0
+            });
+        if implausible {
+            debug!("Ignoring implausible frame count: {frames}");
+            metadata.total_frames = None;
+        }
+    }
+
+    metadata
+}
+
 /// Parse FFmpeg time string (e.g., "00:01:23.45") to seconds
 /// Handles both HH:MM:SS.MS and MM:SS.MS formats
 fn parse_ffmpeg_time_to_seconds(time_str: &str) -> Option<f64> {
@@ -201,10 +822,202 @@ fn calculate_video_progress(
     None
 }
 
+/// Parse the black segment duration from an FFmpeg blackdetect log line
+/// (e.g., "[blackdetect @ 0x...] black_start:0 black_end:2.5 black_duration:2.5")
+fn parse_black_duration(line: &str) -> Option<f64> {
+    line.split_whitespace()
+        .find_map(|token| token.strip_prefix("black_duration:"))
+        .and_then(|value| value.parse::<f64>().ok())
+}
+
+/// Running silencedetect state for the audio track of one encode
+#[derive(Debug, Clone, Default)]
+struct SilenceTracker {
+    /// Start of the silence still running, if any
+    open_start: Option<f64>,
+    /// A silence starting at the beginning ran for this long
+    leading_seconds: Option<f64>,
+}
+
+impl SilenceTracker {
+    /// Feed an FFmpeg silencedetect log line
+    /// (e.g., "[silencedetect @ 0x...] silence_end: 12.5 | silence_duration: 12.5")
+    fn push(&mut self, line: &str) {
+        let value = |key: &str| {
+            line.split_once(key)
+                .and_then(|(_, rest)| rest.split_whitespace().next())
+                .and_then(|value| value.parse::<f64>().ok())
+        };
+        if let Some(start) = value("silence_start:") {
+            self.open_start = Some(start);
+        } else if let Some(duration) = value("silence_duration:") {
+            if self
+                .open_start
+                .take()
+                .is_some_and(|start| start <= SILENCE_START_TOLERANCE_SECONDS)
+            {
+                self.leading_seconds = Some(duration);
+            }
+        }
+    }
+
+    /// Whether silence covers the track from start to end
+    fn entirely_silent(&self, duration_seconds: Option<f64>) -> bool {
+        // A silence that never ended lasted until the end of the stream
+        if self
+            .open_start
+            .is_some_and(|start| start <= SILENCE_START_TOLERANCE_SECONDS)
+        {
+            return true;
+        }
+        match (self.leading_seconds, duration_seconds) {
+            (Some(silent), Some(duration)) => duration - silent <= DURATION_TOLERANCE_SECONDS,
+            _ => false,
+        }
+    }
+}
+
+/// Check whether the black portion covers most of the video
+fn is_mostly_black(black_seconds: f64, duration_seconds: Option<f64>) -> bool {
+    duration_seconds
+        .is_some_and(|duration| duration > 0.0 && black_seconds / duration >= MOSTLY_BLACK_RATIO)
+}
+
+/// Ensure the encoded video did not lose a significant part of the source
+fn check_encoded_duration(source_seconds: Option<f64>, output_seconds: Option<f64>) -> Result<()> {
+    match (source_seconds, output_seconds) {
+        (Some(source), Some(output)) if source - output > DURATION_TOLERANCE_SECONDS => Err(
+            anyhow!("Encoded video is shorter than source: {output:.1}s vs {source:.1}s"),
+        ),
+        (Some(_), None) => Err(anyhow!("Encoded video duration could not be determined")),
+        _ => Ok(()),
+    }
+}
+
+/// Metadata of a source video written to `path`, from the warm pool when it has seen `data`
+#[cfg(feature = "video")]
+fn probe_source(
+    data: &[u8],
+    path: &Path,
+    ffmpeg_path: Option<&Path>,
+    warm_pool: Option<&WarmPool>,
+) -> VideoMetadata {
+    match warm_pool {
+        Some(pool) => pool.video_metadata(data, || extract_video_metadata(path, ffmpeg_path)),
+        None => extract_video_metadata(path, ffmpeg_path),
+    }
+}
+
+/// Decode the whole video with blackdetect enabled, counting black time and decode errors
+#[cfg(feature = "video")]
+fn scan_video(file_path: &Path, ffmpeg_path: Option<&Path>) -> Result<VideoScan> {
+    let mut ffmpeg_cmd = ffmpeg_path.map_or_else(FfmpegCommand::new, |path| {
+        FfmpegCommand::new_with_path(path)
+    });
+
+    ffmpeg_cmd
+        .input(file_path.to_string_lossy())
+        .args(["-vf", "blackdetect=d=0.1:pix_th=0.10", "-an", "-f", "null"])
+        .output("-");
+
+    let mut child = ffmpeg_cmd
+        .spawn()
+        .context("Failed to spawn ffmpeg scan process")?;
+    let iter = child.iter().context("Failed to create event iterator")?;
+
+    let mut scan = VideoScan::default();
+    for event in iter {
+        match event {
+            FfmpegEvent::Log(LogLevel::Error | LogLevel::Fatal, message) => {
+                debug!("FFmpeg scan: {}", message.trim());
+                scan.decode_errors += 1;
+            }
+            FfmpegEvent::Log(_, message) => {
+                if let Some(black) = parse_black_duration(&message) {
+                    scan.black_seconds += black;
+                }
+            }
+            FfmpegEvent::Done => break,
+            _ => {}
+        }
+    }
+
+    Ok(scan)
+}
+
+/// Verify an encoded video: duration must match the source (±1s), the output must
+/// decode cleanly and must not be mostly black unless the source is as well
+#[cfg(feature = "video")]
+fn verify_encoded_video(
+    input_path: &Path,
+    output_path: &Path,
+    source_metadata: &VideoMetadata,
+    ffmpeg_path: Option<&Path>,
+) -> Result<()> {
+    let output_metadata = extract_video_metadata(output_path, ffmpeg_path);
+    check_encoded_duration(
+        source_metadata.duration_seconds,
+        output_metadata.duration_seconds,
+    )?;
+
+    let output_scan = scan_video(output_path, ffmpeg_path)?;
+    if output_scan.decode_errors > 0 {
+        return Err(anyhow!(
+            "Encoded video has {} decode errors",
+            output_scan.decode_errors
+        ));
+    }
+
+    if is_mostly_black(output_scan.black_seconds, output_metadata.duration_seconds) {
+        // Only scan the source when needed - some question videos are intentionally black
+        let source_scan = scan_video(input_path, ffmpeg_path)?;
+        if !is_mostly_black(source_scan.black_seconds, source_metadata.duration_seconds) {
+            return Err(anyhow!(
+                "Encoded video is mostly black ({:.1}s black) while source is not",
+                output_scan.black_seconds
+            ));
+        }
+    }
+
+    debug!(
+        "Encoded video verified: {:.1}s black, no decode errors",
+        output_scan.black_seconds
+    );
+    Ok(())
+}
+
+/// Copy a video without its audio stream
+#[cfg(feature = "video")]
+fn strip_audio(input_path: &Path, output_path: &Path, ffmpeg_path: Option<&Path>) -> Result<()> {
+    let mut ffmpeg_cmd = ffmpeg_path.map_or_else(FfmpegCommand::new, |path| {
+        FfmpegCommand::new_with_path(path)
+    });
+
+    ffmpeg_cmd
+        .input(input_path.to_string_lossy())
+        .args(["-c", "copy", "-an", "-movflags", "+faststart", "-y"])
+        .output(output_path.to_string_lossy());
+
+    let mut child = ffmpeg_cmd
+        .spawn()
+        .context("Failed to spawn ffmpeg remux process")?;
+    let iter = child.iter().context("Failed to create event iterator")?;
+    for event in iter {
+        match event {
+            FfmpegEvent::Error(error_msg) if error_msg.trim() != "No streams found" => {
+                return Err(anyhow!("Failed to strip audio: {}", error_msg.trim()));
+            }
+            FfmpegEvent::Done => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 /// Map quality (1-100) to x265 CRF value (0-51)
 /// Lower CRF = higher quality, larger size
 /// Higher CRF = lower quality, smaller size
-fn quality_to_crf(quality: u8) -> u8 {
+pub fn quality_to_crf(quality: u8) -> u8 {
     // Ensure quality is in valid range
     let quality = quality.clamp(1, 100);
 
@@ -218,16 +1031,136 @@ fn quality_to_crf(quality: u8) -> u8 {
     }
 }
 
+/// x264 reaches the quality of an x265 encode at about this much lower a CRF
+const H264_CRF_OFFSET: u8 = 5;
+
+/// Map quality (1-100) to an x264 CRF value, matching the x265 mapping in
+/// visual quality (so quality 50 gives CRF 30 instead of 35)
+pub fn quality_to_h264_crf(quality: u8) -> u8 {
+    quality_to_crf(quality) - H264_CRF_OFFSET
+}
+
+/// Encoder description of `VideoOptions::remux`
+pub const REMUX_DESCRIPTION: &str = "stream copy into a faststart MP4 (remux)";
+
+/// Describe what the video settings mean in encoder terms
+pub fn describe_quality(
+    quality: u8,
+    denoise: Option<VideoDenoise>,
+    preset: X265Preset,
+    compat: Option<VideoCompat>,
+) -> String {
+    let mut description = match compat {
+        Some(compat) => format!(
+            "{} ({}) CRF {}, preset {}",
+            compat.label(),
+            compat.encoder(),
+            quality_to_h264_crf(quality),
+            preset.name()
+        ),
+        None => format!(
+            "HEVC (libx265) CRF {}, preset {}",
+            quality_to_crf(quality),
+            preset.name()
+        ),
+    };
+    if let Some(denoise) = denoise {
+        description.push_str(&format!(", filter {}", denoise_filter(denoise)));
+    }
+    description
+}
+
+/// Map denoise strength to an FFmpeg video filter
+/// Light and medium use the fast hqdn3d filter, strong uses the slower but
+/// much more effective nlmeans filter for heavy camcorder/low-light noise
+fn denoise_filter(denoise: VideoDenoise) -> &'static str {
+    match denoise {
+        VideoDenoise::Light => "hqdn3d=2:1.5:3:2.25",
+        VideoDenoise::Medium => "hqdn3d=4:3:6:4.5",
+        VideoDenoise::Strong => "nlmeans=s=3.0:p=7:r=15",
+    }
+}
+
+/// Filter graph padding `[0:v]` to `aspect` over a blurred, stretched copy of
+/// itself, labelled `[v]`; the frame is never cropped or scaled down
+fn aspect_filter(aspect: AspectRatio, denoise: Option<&str>, scale: Option<&str>) -> String {
+    let (w, h) = (aspect.width, aspect.height);
+    let canvas_width = format!("'trunc(max(iw,ih*{w}/{h})/2)*2'");
+    let canvas_height = format!("'trunc(max(ih,iw*{h}/{w})/2)*2'");
+    let denoise = denoise
+        .map(|filter| format!("{filter},"))
+        .unwrap_or_default();
+    let scale = scale.map(|filter| format!(",{filter}")).unwrap_or_default();
+    format!(
+        "[0:v]{denoise}split[bg][fg];\
+         [bg]scale=w={canvas_width}:h={canvas_height},boxblur=20:2[blur];\
+         [blur][fg]overlay=x=(W-w)/2:y=(H-h)/2,setsar=1{scale}[v]"
+    )
+}
+
+/// Scale filter fitting the frame within `max_width` x `max_height`, keeping
+/// the aspect ratio and even dimensions; smaller videos keep their size
+fn scale_filter(max_width: Option<u32>, max_height: Option<u32>) -> Option<String> {
+    match (max_width, max_height) {
+        (None, None) => None,
+        (None, Some(height)) => Some(format!("scale=-2:'min({height},ih)'")),
+        (Some(width), None) => Some(format!("scale='min({width},iw)':-2")),
+        (Some(width), Some(height)) => Some(format!(
+            "scale='min({width},iw)':'min({height},ih)':force_original_aspect_ratio=decrease:force_divisible_by=2"
+        )),
+    }
+}
+
+/// Compress a video to HEVC (H.265), or the `compat` codec, through ffmpeg,
+/// returning the encoded file
+///
+/// The container is detected from the `filename` extension (MP4, MOV, AVI, MKV)
+/// and kept for the output. `ffmpeg_path` of `None` runs `ffmpeg` from PATH.
+#[cfg(feature = "video")]
+pub fn compress_video(
+    data: &[u8],
+    filename: &str,
+    options: &VideoOptions,
+    ffmpeg_path: Option<&Path>,
+) -> Result<Vec<u8>> {
+    if !(1..=100).contains(&options.quality) {
+        return Err(SicomError::InvalidOptions(
+            "Video quality must be between 1 and 100".to_string(),
+        )
+        .into());
+    }
+    encode_video(
+        data,
+        filename,
+        options,
+        ffmpeg_path,
+        None,
+        &mut ProgressLogger::hidden(),
+    )
+}
+
+/// Compress a video, which needs the `video` feature
+#[cfg(not(feature = "video"))]
+pub fn compress_video(
+    _data: &[u8],
+    _filename: &str,
+    _options: &VideoOptions,
+    _ffmpeg_path: Option<&Path>,
+) -> Result<Vec<u8>> {
+    Err(SicomError::MissingFeature("video").into())
+}
+
 /// Compress video file using HEVC (H.265) encoding via ffmpeg-sidecar
-/// Returns (`compressed_data`, `original_size`, `compressed_size`)
 /// Logging is handled in real-time through the provided logger
-pub fn compress_video_file(
+#[cfg(feature = "video")]
+fn encode_video(
     data: &[u8],
     filename: &str,
-    quality: u8,
+    options: &VideoOptions,
     ffmpeg_path: Option<&Path>,
-    logger: &mut crate::ProgressLogger,
-) -> Result<(Vec<u8>, u64, u64)> {
+    warm_pool: Option<&WarmPool>,
+    logger: &mut ProgressLogger,
+) -> Result<Vec<u8>> {
     let original_size = data.len() as u64;
 
     // Detect video format
@@ -278,7 +1211,7 @@ pub fn compress_video_file(
     }
 
     // Extract video metadata for accurate progress calculation
-    let metadata = extract_video_metadata(input_path, ffmpeg_path);
+    let metadata = probe_source(data, input_path, ffmpeg_path, warm_pool);
 
     // Log video metadata for debugging
     if let Some(frames) = metadata.total_frames {
@@ -287,12 +1220,26 @@ pub fn compress_video_file(
         debug!("Video metadata: frame count unavailable, using fallback progress");
     }
 
-    let output_temp = NamedTempFile::with_suffix(&file_extension)
-        .context("Failed to create temporary output file")?;
+    // Every output goes into MP4, whatever the source container
+    let output_temp =
+        NamedTempFile::with_suffix(".mp4").context("Failed to create temporary output file")?;
     let output_path = output_temp.path().to_path_buf();
 
-    // Calculate CRF from quality
-    let crf = quality_to_crf(quality);
+    // Calculate CRF from quality, lowered for long videos
+    let quality = options.long_media.map_or(options.quality, |penalty| {
+        penalty.apply(options.quality, metadata.duration_seconds)
+    });
+    if quality != options.quality {
+        debug!(
+            "Long video ({:.0}s): quality {} -> {quality}",
+            metadata.duration_seconds.unwrap_or_default(),
+            options.quality
+        );
+    }
+    let crf = match options.compat {
+        Some(VideoCompat::H264) => quality_to_h264_crf(quality),
+        None => quality_to_crf(quality),
+    };
 
     // Setup ffmpeg command
     let mut ffmpeg_cmd = ffmpeg_path.map_or_else(FfmpegCommand::new, |path| {
@@ -305,23 +1252,103 @@ pub fn compress_video_file(
     // Log video processing
     debug!("Processing video: {filename}");
 
-    ffmpeg_cmd
-        .input(input_path.to_string_lossy()) // Input file with auto-detection
-        .args([
+    ffmpeg_cmd.input(input_path.to_string_lossy()); // Input file with auto-detection
+
+    // Videos already in the target shape skip the padding filter
+    let normalize_aspect = options.normalize_aspect.filter(|aspect| {
+        !metadata
+            .display_size
+            .is_some_and(|size| aspect.matches(size))
+    });
+
+    // Scaling for ladder renditions runs last, on the final frame
+    let scale = scale_filter(options.max_width, options.max_height);
+    if options.remux {
+        debug!("Remuxing video without re-encoding");
+    } else if let Some(aspect) = normalize_aspect {
+        // Optional denoising runs on the source frames, before they are split for padding
+        debug!("Padding video to {aspect}");
+        let filter = aspect_filter(
+            aspect,
+            options.denoise.map(denoise_filter),
+            scale.as_deref(),
+        );
+        ffmpeg_cmd.args(["-filter_complex", &filter, "-map", "[v]", "-map", "0:a:0?"]);
+    } else {
+        // Optional denoising before encoding (noise is expensive to encode)
+        if let Some(denoise) = options.denoise {
+            debug!("Applying {denoise:?} denoise filter");
+        }
+        let filters: Vec<&str> = options
+            .denoise
+            .map(denoise_filter)
+            .into_iter()
+            .chain(scale.as_deref())
+            .collect();
+        if !filters.is_empty() {
+            ffmpeg_cmd.args(["-vf", &filters.join(",")]);
+        }
+    }
+
+    if options.remux {
+        // Re-encoding the audio would make the remux lossy
+        if !mp4_takes_audio(metadata.audio_codec.as_deref()) {
+            return Err(anyhow!(
+                "{} audio can't be copied into MP4 without re-encoding",
+                metadata.audio_codec.as_deref().unwrap_or("unknown")
+            ));
+        }
+        // First video and audio stream only: data, subtitle and attachment
+        // streams often can't go into MP4 and players ignore them anyway
+        ffmpeg_cmd.args(["-map", "0:v:0", "-map", "0:a:0?", "-c", "copy"]);
+    } else if let Some(compat) = options.compat {
+        ffmpeg_cmd.args([
+            "-c:v",
+            compat.encoder(),
+            "-crf",
+            &crf.to_string(),
+            "-preset",
+            options.preset.name(),
+            // 10-bit and 4:4:4 sources would otherwise end up in profiles browsers can't decode
+            "-pix_fmt",
+            "yuv420p",
+            "-profile:v",
+            "high",
+        ]);
+    } else {
+        ffmpeg_cmd.args([
             "-c:v",
             "libx265", // Use HEVC/H.265 encoder
             "-crf",
             &crf.to_string(), // Quality setting
             "-preset",
-            "medium", // Encoding speed vs compression trade-off
-            "-c:a",
-            "copy", // Copy audio stream without re-encoding
+            options.preset.name(), // Encoding speed vs compression trade-off
+        ]);
+    }
+    if !options.remux {
+        if mp4_takes_audio(metadata.audio_codec.as_deref()) {
+            ffmpeg_cmd.args(["-c:a", "copy"]); // Copy audio stream without re-encoding
+        } else {
+            // WMA, Vorbis and PCM audio of AVI/WMV/MKV sources can't go into MP4
+            ffmpeg_cmd.args(["-c:a", "aac", "-b:a", MP4_AAC_BITRATE]);
+        }
+    }
+
+    ffmpeg_cmd
+        .args([
             "-movflags",
             "+faststart", // Optimize for web streaming
             "-y",         // Overwrite output file if it exists
         ])
         .output(output_path.to_string_lossy()); // Output file
 
+    // Detect silent audio in the same pass through a second, discarded output
+    if metadata.has_audio {
+        ffmpeg_cmd
+            .args(["-map", "0:a:0", "-af", SILENCE_FILTER, "-f", "null"])
+            .output("-");
+    }
+
     // Execute FFmpeg with real-time event processing
     let mut child = ffmpeg_cmd
         .spawn()
@@ -331,14 +1358,21 @@ pub fn compress_video_file(
 
     let mut has_error = false;
     let mut error_message = String::new();
+    let mut silence = SilenceTracker::default();
 
     for event in iter {
+        // Events arrive at least with every progress update, so this reacts within a second
+        if logger.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("Cancelled while encoding {filename}"));
+        }
         match event {
             FfmpegEvent::Log(LogLevel::Warning | LogLevel::Error | LogLevel::Fatal, message) => {
                 // Filter for warnings and errors only
                 debug!("FFmpeg: {}", message.trim());
             }
-            FfmpegEvent::Log(_, _) => {} // Ignore Info and Unknown levels
+            FfmpegEvent::Log(_, message) => silence.push(&message), // silencedetect logs at info level
             FfmpegEvent::Error(error_msg) => {
                 // Ignore spurious "No streams found" error that occurs after successful processing
                 if error_msg.trim() != "No streams found" {
@@ -371,17 +1405,32 @@ pub fn compress_video_file(
         return Err(anyhow!("FFmpeg execution failed: {}", error_message));
     }
 
+    // Catch silent encode failures (truncated or black output) before trusting the result
+    if options.verify_output {
+        verify_encoded_video(input_path, &output_path, &metadata, ffmpeg_path)?;
+    }
+
+    if metadata.has_audio && silence.entirely_silent(metadata.duration_seconds) {
+        if options.strip_silent_audio {
+            info!("  Silent audio track in {filename}, dropping it");
+            let stripped_temp = NamedTempFile::with_suffix(".mp4")
+                .context("Failed to create temporary output file")?;
+            strip_audio(&output_path, stripped_temp.path(), ffmpeg_path)?;
+            return fs::read(stripped_temp.path()).context("Failed to read compressed video data");
+        }
+        info!("  Silent audio track in {filename} (drop it with --strip-silent-audio)");
+    }
+
     // Read compressed data from output file
     let compressed_data = fs::read(&output_path).context("Failed to read compressed video data")?;
-    let compressed_size = compressed_data.len() as u64;
 
     // Clean up temporary files automatically when they go out of scope
     // Both input_temp and output_temp will be cleaned up at function end
 
-    Ok((compressed_data, original_size, compressed_size))
+    Ok(compressed_data)
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "video"))]
 mod tests {
     use super::*;
 
@@ -407,7 +1456,7 @@ mod tests {
             Some(VideoFormat::Avi)
         );
         assert_eq!(detect_video_format("test.mkv"), Some(VideoFormat::Mkv));
-        assert_eq!(detect_video_format("test.wmv"), None);
+        assert_eq!(detect_video_format("test.wmv"), Some(VideoFormat::Wmv));
         assert_eq!(detect_video_format("test.txt"), None);
     }
 
@@ -429,6 +1478,17 @@ mod tests {
         assert_eq!(quality_to_crf(80), 25); // Higher quality
     }
 
+    #[test]
+    fn test_h264_compat() {
+        assert_eq!(quality_to_h264_crf(1), 46);
+        assert_eq!(quality_to_h264_crf(50), 30);
+        assert_eq!(quality_to_h264_crf(100), 13);
+        assert_eq!(
+            describe_quality(50, None, X265Preset::Medium, Some(VideoCompat::H264)),
+            "H.264 (libx264) CRF 30, preset medium"
+        );
+    }
+
     #[test]
     fn test_get_file_extension() {
         assert_eq!(get_file_extension("video.mp4"), ".mp4");
@@ -444,6 +1504,196 @@ mod tests {
         assert_eq!(get_ffmpeg_format(VideoFormat::Mov), "mov");
         assert_eq!(get_ffmpeg_format(VideoFormat::Avi), "avi");
         assert_eq!(get_ffmpeg_format(VideoFormat::Mkv), "matroska");
+        assert_eq!(get_ffmpeg_format(VideoFormat::Wmv), "asf");
+    }
+
+    #[test]
+    fn test_output_filename() {
+        assert_eq!(output_filename("Video/clip.mkv"), "Video/clip.mp4");
+        assert_eq!(output_filename("Video/%D0%BA.wmv"), "Video/%D0%BA.mp4");
+        assert_eq!(output_filename("Video/clip.MP4"), "Video/clip.MP4");
+    }
+
+    #[test]
+    fn test_mp4_takes_audio() {
+        assert!(mp4_takes_audio(Some("aac")));
+        assert!(mp4_takes_audio(None));
+        assert!(!mp4_takes_audio(Some("wmav2")));
+        assert!(!mp4_takes_audio(Some("pcm_s16le")));
+    }
+
+    #[test]
+    fn test_denoise_filter() {
+        assert_eq!(denoise_filter(VideoDenoise::Light), "hqdn3d=2:1.5:3:2.25");
+        assert_eq!(denoise_filter(VideoDenoise::Medium), "hqdn3d=4:3:6:4.5");
+        assert!(denoise_filter(VideoDenoise::Strong).starts_with("nlmeans"));
+    }
+
+    #[test]
+    fn test_parse_black_duration() {
+        assert_eq!(
+            parse_black_duration(
+                "[blackdetect @ 0x55d] black_start:0 black_end:2.5 black_duration:2.5"
+            ),
+            Some(2.5)
+        );
+        assert_eq!(parse_black_duration("frame=  100 fps=25"), None);
+        assert_eq!(parse_black_duration("black_duration:abc"), None);
+    }
+
+    #[test]
+    fn test_aspect_ratio() {
+        let aspect: AspectRatio = "16:9".parse().unwrap();
+        assert_eq!(aspect, AspectRatio::new(16, 9));
+        assert_eq!(aspect.to_string(), "16:9");
+        assert!(aspect.matches((1920, 1080)));
+        assert!(aspect.matches((854, 480)));
+        assert!(!aspect.matches((1080, 1920)));
+        assert!(!aspect.matches((640, 480)));
+
+        assert!("16x9".parse::<AspectRatio>().is_err());
+        assert!("0:9".parse::<AspectRatio>().is_err());
+        assert!("wide".parse::<AspectRatio>().is_err());
+
+        assert_eq!(serde_json::to_string(&aspect).unwrap(), r#""16:9""#);
+        assert_eq!(
+            serde_json::from_str::<AspectRatio>(r#""4:3""#).unwrap(),
+            AspectRatio::new(4, 3)
+        );
+        assert!(serde_json::from_str::<AspectRatio>(r#""4-3""#).is_err());
+    }
+
+    #[test]
+    fn test_aspect_filter() {
+        let filter = aspect_filter(AspectRatio::new(16, 9), None, None);
+        assert!(filter.starts_with("[0:v]split[bg][fg];[bg]scale="));
+        assert!(filter.contains("w='trunc(max(iw,ih*16/9)/2)*2'"));
+        assert!(filter.contains("h='trunc(max(ih,iw*9/16)/2)*2'"));
+        assert!(filter.ends_with("overlay=x=(W-w)/2:y=(H-h)/2,setsar=1[v]"));
+
+        let denoised = aspect_filter(AspectRatio::new(16, 9), Some("hqdn3d=2:1.5:3:2.25"), None);
+        assert!(denoised.starts_with("[0:v]hqdn3d=2:1.5:3:2.25,split[bg][fg];"));
+
+        let scale = scale_filter(None, Some(720));
+        let scaled = aspect_filter(AspectRatio::new(16, 9), None, scale.as_deref());
+        assert!(scaled.ends_with("setsar=1,scale=-2:'min(720,ih)'[v]"));
+    }
+
+    #[test]
+    fn test_scale_filter() {
+        assert_eq!(scale_filter(None, None), None);
+        assert_eq!(
+            scale_filter(Some(1280), None).as_deref(),
+            Some("scale='min(1280,iw)':-2")
+        );
+        assert_eq!(
+            scale_filter(Some(1280), Some(720)).as_deref(),
+            Some(
+                "scale='min(1280,iw)':'min(720,ih)':force_original_aspect_ratio=decrease:force_divisible_by=2"
+            )
+        );
+    }
+
+    #[test]
+    fn test_rendition_entry_name() {
+        assert_eq!(
+            Rendition::P720.entry_name("Video/clip.mp4"),
+            "Video/clip_720p.mp4"
+        );
+        assert_eq!(
+            Rendition::P360.entry_name("Video/v1.0/clip"),
+            "Video/v1.0/clip_360p"
+        );
+        assert_eq!(Rendition::P1080.height(), 1080);
+    }
+
+    #[test]
+    fn test_silence_tracker() {
+        let track = |lines: &[&str]| {
+            let mut tracker = SilenceTracker::default();
+            for line in lines {
+                tracker.push(line);
+            }
+            tracker
+        };
+
+        // Silence from the start that never ends
+        let open = track(&["[silencedetect @ 0x55d] silence_start: 0"]);
+        assert!(open.entirely_silent(Some(30.0)));
+        assert!(open.entirely_silent(None));
+
+        // Silence ended at the end of the stream
+        let closed = track(&[
+            "[silencedetect @ 0x55d] silence_start: 0",
+            "[silencedetect @ 0x55d] silence_end: 29.8 | silence_duration: 29.8",
+        ]);
+        assert!(closed.entirely_silent(Some(30.0)));
+        assert!(!closed.entirely_silent(Some(60.0)));
+        assert!(!closed.entirely_silent(None));
+
+        // Pauses in an otherwise audible track
+        let pauses = track(&[
+            "[silencedetect @ 0x55d] silence_start: 0",
+            "[silencedetect @ 0x55d] silence_end: 2 | silence_duration: 2",
+            "[silencedetect @ 0x55d] silence_start: 10.5",
+        ]);
+        assert!(!pauses.entirely_silent(Some(30.0)));
+
+        assert!(!track(&["frame=  100 fps=25"]).entirely_silent(Some(30.0)));
+    }
+
+    #[test]
+    fn test_is_mostly_black() {
+        assert!(is_mostly_black(9.5, Some(10.0)));
+        assert!(!is_mostly_black(2.0, Some(10.0)));
+        assert!(!is_mostly_black(5.0, None));
+        assert!(!is_mostly_black(0.0, Some(0.0)));
+    }
+
+    #[test]
+    fn test_check_encoded_duration() {
+        assert!(check_encoded_duration(Some(30.0), Some(30.0)).is_ok());
+        assert!(check_encoded_duration(Some(30.0), Some(29.2)).is_ok()); // Within tolerance
+        assert!(check_encoded_duration(Some(30.0), Some(31.0)).is_ok()); // Longer is fine
+        assert!(check_encoded_duration(Some(30.0), Some(20.0)).is_err()); // Truncated
+        assert!(check_encoded_duration(Some(30.0), None).is_err()); // Unreadable output
+        assert!(check_encoded_duration(None, Some(30.0)).is_ok()); // Unknown source
+    }
+
+    #[test]
+    fn test_sanitize_metadata() {
+        let metadata = |total_frames, duration_seconds, fps| VideoMetadata {
+            total_frames,
+            duration_seconds,
+            fps,
+            has_audio: false,
+            audio_codec: None,
+            display_size: None,
+        };
+        let size = 5_000_000; // 5 MB
+
+        let sane = sanitize_metadata(metadata(Some(750), Some(30.0), Some(25.0)), size);
+        assert_eq!(sane.total_frames, Some(750));
+        assert_eq!(sane.duration_seconds, Some(30.0));
+        assert_eq!(sane.fps, Some(25.0));
+
+        let bogus_fps = sanitize_metadata(metadata(None, Some(30.0), Some(10000.0)), size);
+        assert_eq!(bogus_fps.fps, None);
+        assert_eq!(bogus_fps.duration_seconds, Some(30.0));
+
+        // 10 hours in 1 MB is ~0.2 kbps; zero duration is no duration
+        let ten_hours = sanitize_metadata(metadata(None, Some(36000.0), Some(25.0)), 1_000_000);
+        assert_eq!(ten_hours.duration_seconds, None);
+        let zero = sanitize_metadata(metadata(None, Some(0.0), Some(25.0)), size);
+        assert_eq!(zero.duration_seconds, None);
+        let nan = sanitize_metadata(metadata(None, Some(f64::NAN), Some(25.0)), size);
+        assert_eq!(nan.duration_seconds, None);
+
+        // Frame count way off duration x fps
+        let frames = sanitize_metadata(metadata(Some(900_000), Some(30.0), Some(25.0)), size);
+        assert_eq!(frames.total_frames, None);
+        let zero_frames = sanitize_metadata(metadata(Some(0), Some(30.0), Some(25.0)), size);
+        assert_eq!(zero_frames.total_frames, None);
     }
 
     #[test]
@@ -470,6 +1720,9 @@ mod tests {
             total_frames: Some(1000),
             duration_seconds: Some(40.0),
             fps: Some(25.0),
+            has_audio: false,
+            audio_codec: None,
+            display_size: None,
         };
 
         assert_eq!(
@@ -505,6 +1758,9 @@ mod tests {
             total_frames: None,
             duration_seconds: Some(60.0), // 1 minute video
             fps: Some(30.0),
+            has_audio: false,
+            audio_codec: None,
+            display_size: None,
         };
 
         assert_eq!(
@@ -540,6 +1796,9 @@ mod tests {
             total_frames: None,
             duration_seconds: Some(30.0),
             fps: None,
+            has_audio: false,
+            audio_codec: None,
+            display_size: None,
         };
 
         // Invalid time format should return None for indeterminate progress
@@ -557,6 +1816,9 @@ mod tests {
             total_frames: None,
             duration_seconds: None,
             fps: None,
+            has_audio: false,
+            audio_codec: None,
+            display_size: None,
         };
 
         assert_eq!(
@@ -582,6 +1844,9 @@ mod tests {
             total_frames: Some(150),
             duration_seconds: Some(5.0),
             fps: Some(30.0),
+            has_audio: false,
+            audio_codec: None,
+            display_size: None,
         };
 
         assert_eq!(
@@ -602,6 +1867,9 @@ mod tests {
             total_frames: Some(2880),
             duration_seconds: Some(120.0),
             fps: Some(24.0),
+            has_audio: false,
+            audio_codec: None,
+            display_size: None,
         };
 
         assert_eq!(
@@ -630,6 +1898,9 @@ mod tests {
             total_frames: None,
             duration_seconds: Some(10.0),
             fps: Some(25.0),
+            has_audio: false,
+            audio_codec: None,
+            display_size: None,
         };
 
         // Manually calculate frames as extract_video_metadata would do
@@ -0,0 +1,102 @@
+use crate::video::VideoMetadata;
+use log::debug;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Probe results kept at most; the cache starts over once it's full
+const MAX_PROBES: usize = 1024;
+
+/// State long-running modes (serve, daemon) keep warm between jobs
+///
+/// Clones share the pool. It remembers whether ffmpeg was found, so jobs
+/// don't look it up again, and the ffprobe results of video sources by
+/// content, so a video seen before (or probed again for its ladder) skips
+/// ffprobe.
+#[derive(Debug, Clone, Default)]
+pub struct WarmPool {
+    ffmpeg: Arc<Mutex<HashMap<Option<PathBuf>, bool>>>,
+    probes: Arc<Mutex<HashMap<blake3::Hash, VideoMetadata>>>,
+}
+
+impl WarmPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether ffmpeg at `path` (PATH with `None`) is available, running `detect` only once
+    pub fn ffmpeg_available(&self, path: Option<&Path>, detect: impl FnOnce() -> bool) -> bool {
+        let key = path.map(Path::to_path_buf);
+        if let Some(&available) = self
+            .ffmpeg
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+        {
+            return available;
+        }
+        let available = detect();
+        self.ffmpeg
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, available);
+        available
+    }
+
+    /// Metadata of the video `data`, running `probe` only for unseen content
+    #[cfg_attr(not(feature = "video"), allow(dead_code))]
+    pub(crate) fn video_metadata(
+        &self,
+        data: &[u8],
+        probe: impl FnOnce() -> VideoMetadata,
+    ) -> VideoMetadata {
+        let hash = blake3::hash(data);
+        if let Some(metadata) = self
+            .probes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&hash)
+        {
+            debug!("  Reusing video probe from an earlier encode");
+            return metadata.clone();
+        }
+        let metadata = probe();
+        let mut probes = self.probes.lock().unwrap_or_else(|e| e.into_inner());
+        if probes.len() >= MAX_PROBES {
+            probes.clear();
+        }
+        probes.insert(hash, metadata.clone());
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_checks_run_once() {
+        let pool = WarmPool::new();
+        let shared = pool.clone();
+        let lookups = Cell::new(0);
+        let detect = || {
+            lookups.set(lookups.get() + 1);
+            true
+        };
+        assert!(pool.ffmpeg_available(None, detect));
+        assert!(shared.ffmpeg_available(None, detect));
+        assert!(!pool.ffmpeg_available(Some(Path::new("/missing/ffmpeg")), || false));
+        assert_eq!(lookups.get(), 1);
+
+        let probes = Cell::new(0);
+        let probe = || {
+            probes.set(probes.get() + 1);
+            VideoMetadata::default()
+        };
+        pool.video_metadata(b"video", probe);
+        shared.video_metadata(b"video", probe);
+        pool.video_metadata(b"other video", probe);
+        assert_eq!(probes.get(), 2);
+    }
+}
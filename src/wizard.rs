@@ -0,0 +1,251 @@
+use crate::audio::AudioOptions;
+use crate::options::CompressOptions;
+use crate::{format_size, parse_size};
+use anyhow::{Result, anyhow};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Where the pack will be played, which sets how much quality matters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Venue {
+    /// Streamed to players over the internet: smallest download wins
+    Online,
+    /// Played from a laptop or a local network
+    Local,
+    /// Projected for a live audience, where artifacts show
+    BigScreen,
+}
+
+/// What the user told the wizard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answers {
+    pub venue: Venue,
+    pub care_about_video: bool,
+    /// Per-file upload limit of the service the pack is shared through
+    pub upload_limit: Option<u64>,
+}
+
+/// Settings derived from the answers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    pub image_quality: u8,
+    pub audio_quality: u8,
+    pub video_quality: u8,
+    /// Part size for `--split-output`
+    pub split_output: Option<u64>,
+}
+
+/// Video quality added when the user cares about video
+const VIDEO_QUALITY_BONUS: u8 = 15;
+
+impl Plan {
+    pub fn from_answers(answers: &Answers) -> Self {
+        let (image_quality, audio_quality, video_quality) = match answers.venue {
+            Venue::Online => (40, 70, 40),
+            Venue::Local => (40, 85, 50),
+            Venue::BigScreen => (60, 85, 60),
+        };
+        let video_quality = if answers.care_about_video {
+            video_quality + VIDEO_QUALITY_BONUS
+        } else {
+            video_quality
+        };
+        Self {
+            image_quality,
+            audio_quality,
+            video_quality,
+            split_output: answers.upload_limit,
+        }
+    }
+
+    pub fn options(&self) -> CompressOptions {
+        CompressOptions::new()
+            .image_quality(self.image_quality)
+            .audio(AudioOptions::new().quality(self.audio_quality))
+            .video_quality(self.video_quality)
+    }
+
+    /// The equivalent `sicom compress` invocation, for running without the wizard
+    pub fn command_line(&self, input_pack: &Path) -> String {
+        let mut command = format!(
+            "sicom compress {} --image-quality {} --audio-quality {} --video-quality {}",
+            shell_word(&input_pack.to_string_lossy()),
+            self.image_quality,
+            self.audio_quality,
+            self.video_quality
+        );
+        if let Some(limit) = self.split_output {
+            command.push_str(&format!(" --split-output {}", limit_argument(limit)));
+        }
+        command
+    }
+}
+
+/// Quote a word for a POSIX shell unless it's plain
+fn shell_word(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// Size argument in the largest unit that represents it exactly
+fn limit_argument(bytes: u64) -> String {
+    for (unit, multiplier) in [("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10)] {
+        if bytes.is_multiple_of(multiplier) {
+            return format!("{}{unit}", bytes / multiplier);
+        }
+    }
+    bytes.to_string()
+}
+
+/// Ask until the answer is accepted by `parse`, which explains rejections
+fn prompt<T>(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    parse: impl Fn(&str) -> std::result::Result<T, String>,
+) -> Result<T> {
+    loop {
+        write!(output, "{question} ")?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(anyhow!("Wizard cancelled"));
+        }
+        match parse(line.trim()) {
+            Ok(value) => return Ok(value),
+            Err(message) => writeln!(output, "  {message}")?,
+        }
+    }
+}
+
+/// Ask for the pack to compress (quotes from drag and drop are removed)
+pub fn ask_pack(input: &mut impl BufRead, output: &mut impl Write) -> Result<PathBuf> {
+    prompt(input, output, "Pack to compress (.siq):", |answer| {
+        let path = PathBuf::from(answer.trim_matches(['"', '\'']));
+        if path.is_file() {
+            Ok(path)
+        } else {
+            Err(format!("No such file: {}", path.display()))
+        }
+    })
+}
+
+/// Ask yes/no, `default` on an empty answer
+pub fn confirm(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    default: bool,
+) -> Result<bool> {
+    let hint = if default { "[Y/n]" } else { "[y/N]" };
+    prompt(
+        input,
+        output,
+        &format!("{question} {hint}:"),
+        |answer| match answer.to_ascii_lowercase().as_str() {
+            "" => Ok(default),
+            "y" | "yes" => Ok(true),
+            "n" | "no" => Ok(false),
+            _ => Err("Please answer y or n".to_string()),
+        },
+    )
+}
+
+/// Ask the wizard questions on `input`, writing prompts to `output`
+pub fn ask(input: &mut impl BufRead, output: &mut impl Write) -> Result<Answers> {
+    writeln!(output, "Where will the pack be played?")?;
+    writeln!(output, "  1) Online, streamed to players")?;
+    writeln!(output, "  2) From a laptop or local network")?;
+    writeln!(output, "  3) On a big screen at a live event")?;
+    let venue = prompt(input, output, "Choose 1-3 [2]:", |answer| match answer {
+        "1" => Ok(Venue::Online),
+        "" | "2" => Ok(Venue::Local),
+        "3" => Ok(Venue::BigScreen),
+        _ => Err("Please enter 1, 2 or 3".to_string()),
+    })?;
+
+    let care_about_video = confirm(
+        input,
+        output,
+        "Do you care about video quality more than size?",
+        false,
+    )?;
+
+    let upload_limit = prompt(
+        input,
+        output,
+        "Per-file upload size limit, e.g. 100MB (empty for none):",
+        |answer| match answer {
+            "" => Ok(None),
+            answer => match parse_size(answer)? {
+                0 => Err("The limit must be above 0".to_string()),
+                bytes => Ok(Some(bytes)),
+            },
+        },
+    )?;
+    if let Some(limit) = upload_limit {
+        writeln!(
+            output,
+            "  Packs over {} will be split into parts",
+            format_size(limit)
+        )?;
+    }
+
+    Ok(Answers {
+        venue,
+        care_about_video,
+        upload_limit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ask() {
+        let mut output = Vec::new();
+        let answers = ask(&mut "4\n3\nmaybe\ny\n100MB\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            answers,
+            Answers {
+                venue: Venue::BigScreen,
+                care_about_video: true,
+                upload_limit: Some(100 << 20),
+            }
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Please enter 1, 2 or 3"));
+        assert!(output.contains("Please answer y or n"));
+
+        // Defaults on empty answers, cancelled on end of input
+        let answers = ask(&mut "\n\n\n".as_bytes(), &mut Vec::new()).unwrap();
+        assert_eq!(answers.venue, Venue::Local);
+        assert!(!answers.care_about_video && answers.upload_limit.is_none());
+        assert!(ask(&mut "1\n".as_bytes(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_plan() {
+        let plan = Plan::from_answers(&Answers {
+            venue: Venue::Online,
+            care_about_video: true,
+            upload_limit: Some(50 << 20),
+        });
+        assert_eq!(plan.video_quality, 55);
+        assert_eq!(plan.options().audio.quality, 70);
+        assert_eq!(
+            plan.command_line(Path::new("my pack.siq")),
+            "sicom compress 'my pack.siq' --image-quality 40 --audio-quality 70 --video-quality 55 --split-output 50MB"
+        );
+        assert_eq!(limit_argument(3 << 10), "3KB");
+        assert_eq!(limit_argument(1536), "1536");
+    }
+}